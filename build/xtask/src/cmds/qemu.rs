@@ -9,7 +9,15 @@ use crate::profile::Profile;
 use crate::tracing::OutputOptions;
 use crate::{Options, qemu};
 use clap::{Parser, ValueHint};
-use std::path::PathBuf;
+use std::fs::File;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+
+/// The size of the scratch disk attached for the kernel tests.
+const SCRATCH_DISK_SIZE: u64 = 1024 * 1024;
+/// The serial number of the scratch disk, the kernel tests only ever write to a disk with this
+/// serial number.
+const SCRATCH_DISK_SERIAL: &str = "k23-scratch";
 
 #[derive(Debug, Parser)]
 pub struct Cmd {
@@ -27,10 +35,48 @@ impl Cmd {
 
         let image = crate::build::build_loader(&opts, output, &profile, &self.kernel)?;
 
-        let mut child = qemu::spawn(&self.qemu_opts, profile, &image, true, &[])?;
+        let scratch_disk = self.kernel.with_extension("scratch.img");
+        create_scratch_disk(&scratch_disk)?;
+        // The network device sends its frames to a UDP socket it also receives from, so every
+        // frame the kernel sends is received again and the network tests can talk to themselves.
+        let loopback_port = free_udp_port()?;
+
+        // devices exercised by the kernel tests
+        let device_args = [
+            "-drive".to_string(),
+            format!(
+                "file={},if=none,format=raw,id=scratch",
+                scratch_disk.display()
+            ),
+            "-device".to_string(),
+            format!("virtio-blk-device,drive=scratch,serial={SCRATCH_DISK_SERIAL}"),
+            "-device".to_string(),
+            "virtio-rng-device".to_string(),
+            "-netdev".to_string(),
+            format!(
+                "socket,id=net0,udp=127.0.0.1:{loopback_port},localaddr=127.0.0.1:{loopback_port}"
+            ),
+            "-device".to_string(),
+            "virtio-net-device,netdev=net0".to_string(),
+        ];
+
+        let mut child = qemu::spawn(&self.qemu_opts, profile, &image, true, &device_args)?;
 
         child.0.wait()?;
 
         Ok(())
     }
 }
+
+/// Returns a currently unused UDP port on the host.
+fn free_udp_port() -> crate::Result<u16> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    Ok(socket.local_addr()?.port())
+}
+
+/// Create a zeroed scratch disk image at `path`, replacing any previous one.
+fn create_scratch_disk(path: &Path) -> crate::Result<()> {
+    let file = File::create(path)?;
+    file.set_len(SCRATCH_DISK_SIZE)?;
+    Ok(())
+}
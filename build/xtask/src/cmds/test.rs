@@ -43,7 +43,7 @@ impl Cmd {
         cargo.build_std(true);
         let mut cmd = cargo.into_cmd();
 
-        let (var, val) = cargo_qemu_runner_env(&profile)?;
+        let (var, val) = cargo_qemu_runner_env(&profile, &self.qemu_opts)?;
         cmd.env(var, val);
 
         cmd.args(["--", "--"]);
@@ -93,18 +93,24 @@ fn parse_secs(s: &str) -> color_eyre::Result<Duration> {
         .context("not a valid number of seconds")
 }
 
-pub fn cargo_qemu_runner_env(profile: &Profile) -> crate::Result<(&'static str, String)> {
+pub fn cargo_qemu_runner_env(
+    profile: &Profile,
+    qemu: &qemu::QemuOptions,
+) -> crate::Result<(&'static str, String)> {
     // The produced target artifact cannot be run on the host, so we proactively set the
     // runner to the
     let runner_env_var = match profile.arch {
         Architecture::Riscv64 => "CARGO_TARGET_RISCV64GC_K23_NONE_KERNEL_RUNNER",
     };
 
-    Ok((
-        runner_env_var,
-        format!(
-            "cargo xtask __qemu {}",
-            profile.file_path.canonicalize()?.display()
-        ),
-    ))
+    let mut runner = format!(
+        "cargo xtask __qemu {}",
+        profile.file_path.canonicalize()?.display()
+    );
+    // the runner spawns its own QEMU, so it has to be told which machine variant to boot
+    if qemu.aia {
+        runner.push_str(" --aia");
+    }
+
+    Ok((runner_env_var, runner))
 }
@@ -19,6 +19,9 @@ pub struct QemuOptions {
     /// The TCP port to listen for debug connections on.
     #[clap(long, default_value = "1234")]
     pub gdb_port: u16,
+    /// Use the Advanced Interrupt Architecture (APLIC and IMSIC) instead of the PLIC.
+    #[clap(long)]
+    pub aia: bool,
     /// Extra arguments passed to QEMU.
     #[clap(raw = true)]
     pub qemu_args: Vec<String>,
@@ -33,10 +36,16 @@ pub fn spawn(
 ) -> crate::Result<KillOnDrop> {
     let mut cmd = match profile.arch {
         Architecture::Riscv64 => {
+            let machine = if qemu.aia {
+                "virt,aia=aplic-imsic"
+            } else {
+                "virt"
+            };
+
             let mut cmd = Command::new("qemu-system-riscv64");
             cmd.args([
                 "-machine",
-                "virt",
+                machine,
                 "-cpu",
                 "rv64",
                 "-m",
@@ -13,14 +13,16 @@
 //! - `call_with_setjmp`, `setjmp`, `longjmp`, `JumpBuf`, `JumpBufStruct` for setjmp/longjmp functionality
 //! - `init`, `per_cpu_init_early`, `per_cpu_init_late` for initialization
 //! - `cpu_park`, `cpu_park_timeout` for parking a CPU
+//! - `cpu_offline`, `cpu_online`, `cpu_suspend` for CPU hotplug and deep idle states
+//! - `cpu_stop`, `is_cpu_stopped`, `system_reset` for shutting down and rebooting the system
 //! - `with_user_memory_access` for temporarily enabling kernel access to userspace memory
 //! - `mb`, `rmb`, `wmb` for memory barriers
 //! - `set_thread_ptr`, `get_stack_pointer`, `get_next_older_pc_from_fp`, `assert_fp_is_aligned` for
 //!   WASM stack support
 //! - `device::cpu::init`, `device::cpu::with_cpu_info` for CPU initialization
-//! - `invalidate_range`, `is_kernel_address`, `AddressSpace`, `KERNEL_ASPACE_BASE`,
-//!   `USER_ASPACE_BASE`, `PAGE_SHIFT`, `CANONICAL_ADDRESS_MASK`, `PAGE_SIZE`, `DEFAULT_ASID` to
-//!   support the virtual memory subsystem
+//! - `invalidate_range`, `invalidate_all`, `is_kernel_address`, `AddressSpace`,
+//!   `KERNEL_ASPACE_BASE`, `USER_ASPACE_BASE`, `PAGE_SHIFT`, `CANONICAL_ADDRESS_MASK`, `PAGE_SIZE`,
+//!   `DEFAULT_ASID` to support the virtual memory subsystem
 
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "riscv64")] {
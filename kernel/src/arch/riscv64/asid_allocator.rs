@@ -5,77 +5,338 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+//! Address Space Identifier (ASID) management.
+//!
+//! ASIDs are assigned lazily when an address space is activated. Each address space keeps an
+//! *ASID context* which is the combination of the ASID and the *generation* it was allocated in.
+//! When all ASIDs are exhausted a new generation is started: the allocation bitmap is reset, every
+//! CPU is asked to flush its TLB before activating its next address space, and address spaces from
+//! older generations get reassigned an ASID on their next activation. ASIDs that are currently active
+//! on any CPU are carried over into the new generation so running address spaces aren't disturbed.
+//!
+//! This is the same scheme that Linux uses on arm64 and RISC-V.
+//!
+//! On hardware without ASID support every address space uses [`DEFAULT_ASID`] and the caller
+//! is responsible for flushing the TLB when switching between address spaces (see [`has_asids`]).
+//! The same applies if the hardware supports too few ASIDs to always find a free one after a
+//! rollover, i.e. no more ASIDs than there are CPUs.
+
+use crate::CPUID;
+use crate::arch::DEFAULT_ASID;
+use crate::counter;
+use crate::metrics::Counter;
+use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::arch::asm;
 use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use riscv::satp;
-use spin::OnceLock;
+use spin::{Mutex, OnceLock};
+
+/// The ASID occupies the lower bits of an ASID context, the generation the upper bits.
+const GENERATION_SHIFT: u32 = u16::BITS;
+const ASID_MASK: u64 = (1 << GENERATION_SHIFT) - 1;
+const FIRST_GENERATION: u64 = 1 << GENERATION_SHIFT;
 
-// FIXME: A OnceLock to store a u16? yikes
-static MAX_ASID: OnceLock<u16> = OnceLock::new();
+static ASIDS: OnceLock<Asids> = OnceLock::new();
+static ASID_ROLLOVERS: Counter = counter!("asid-rollovers");
 
 pub fn init() {
     // Determine the number of supported ASID bits. The ASID is a "WARL" (Write Any Values, Reads Legal Values)
     // so we can write all 1s to and see which ones "stick".
     // Safety: register access
-    unsafe {
+    let max_asid = unsafe {
         let orig = satp::read();
         satp::set(orig.mode(), 0xFFFF, orig.ppn());
         let max_asid = satp::read().asid();
         satp::set(orig.mode(), orig.asid(), orig.ppn());
+        max_asid
+    };
 
+    let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+    let num_cpus = usize::try_from(usize::BITS - cpu_mask.leading_zeros()).unwrap();
+
+    // The ASIDs active on any CPU are carried over into a new generation, so there has to be at
+    // least one more ASID than there are CPUs for a rollover to always free up an ASID.
+    let has_asids = usize::from(max_asid) > num_cpus;
+    if max_asid == 0 {
+        tracing::warn!("hardware doesn't support ASIDs, falling back to flushing on every switch");
+    } else if !has_asids {
+        tracing::warn!(
+            "only {max_asid} ASIDs for {num_cpus} CPUs, falling back to flushing on every switch"
+        );
+    } else {
         tracing::trace!("supported ASID bits: {} {max_asid}", max_asid.count_ones());
-        MAX_ASID.get_or_init(|| max_asid);
     }
+
+    ASIDS.get_or_init(|| Asids {
+        has_asids,
+        generation: AtomicU64::new(FIRST_GENERATION),
+        active: (0..num_cpus).map(|_| AtomicU64::new(0)).collect(),
+        flush_pending: AtomicUsize::new(0),
+        allocator: Mutex::new(AsidAllocator::new(max_asid, num_cpus)),
+    });
+}
+
+/// Returns `true` if the hardware supports address space identifiers.
+///
+/// If this returns `false`, all address spaces share [`DEFAULT_ASID`] and the TLB must be flushed
+/// whenever the calling CPU switches address spaces.
+pub fn has_asids() -> bool {
+    ASIDS.get().unwrap().has_asids
+}
+
+/// Returns the ASID encoded in the given ASID context.
+pub fn asid_of(context: u64) -> u16 {
+    u16::try_from(context & ASID_MASK).unwrap()
 }
 
-pub struct AsidAllocator {
-    bitmap: Vec<u8>,
+/// Prepare the calling CPU for activating the address space with the given ASID `context`.
+///
+/// If the context is from an older generation a new ASID will be assigned and written back into
+/// `context`. Returns the ASID the caller should use when activating the address space.
+pub fn activate(context: &AtomicU64) -> u16 {
+    let asids = ASIDS.get().unwrap();
+    if !asids.has_asids {
+        return DEFAULT_ASID;
+    }
+
+    let cpuid = CPUID.get();
+    let active = &asids.active[cpuid];
+
+    // Fast path: the address space has an ASID from the current generation and no rollover happened
+    // since the last activation on this CPU (which would have reset `active` to zero).
+    let ctx = context.load(Ordering::Relaxed);
+    let old_active = active.load(Ordering::Relaxed);
+    if old_active != 0
+        && asids.is_current_generation(ctx)
+        && active
+            .compare_exchange(old_active, ctx, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        return asid_of(ctx);
+    }
+
+    let mut allocator = asids.allocator.lock();
+    let mut ctx = context.load(Ordering::Relaxed);
+    if !asids.is_current_generation(ctx) {
+        ctx = allocator.new_context(asids, ctx);
+        context.store(ctx, Ordering::Relaxed);
+    }
+
+    let this_cpu = 1 << cpuid;
+    if asids.flush_pending.fetch_and(!this_cpu, Ordering::Relaxed) & this_cpu != 0 {
+        local_flush_all();
+    }
+
+    active.store(ctx, Ordering::Relaxed);
+    drop(allocator);
+
+    asid_of(ctx)
+}
+
+/// Invalidate all address translation caches of the calling CPU.
+pub fn local_flush_all() {
+    // Safety: inline assembly
+    unsafe {
+        asm!("sfence.vma zero, zero");
+    }
+}
+
+struct Asids {
+    has_asids: bool,
+    /// The current generation, always a multiple of `FIRST_GENERATION`.
+    generation: AtomicU64,
+    /// The ASID context currently active on each CPU or zero if a rollover happened since the
+    /// CPU last activated an address space.
+    active: Box<[AtomicU64]>,
+    /// Bitmask of CPUs that need to flush their TLB before activating their next address space.
+    flush_pending: AtomicUsize,
+    allocator: Mutex<AsidAllocator>,
+}
+
+impl Asids {
+    fn is_current_generation(&self, context: u64) -> bool {
+        context & !ASID_MASK == self.generation.load(Ordering::Relaxed)
+    }
+}
+
+struct AsidAllocator {
+    bitmap: Vec<u64>,
+    max_asid: u16,
     last: u16,
+    /// The ASID context each CPU had active at the time of the last rollover. These ASIDs are carried
+    /// over into the new generation.
+    reserved: Box<[u64]>,
 }
 
 impl fmt::Debug for AsidAllocator {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AsidAllocator")
+            .field("max_asid", &self.max_asid)
             .field("last", &self.last)
             .finish_non_exhaustive()
     }
 }
 
 impl AsidAllocator {
-    pub fn new() -> Self {
-        let max_asid = *MAX_ASID.get().unwrap();
-        let bitmap_size = (max_asid as usize + 1) / 8;
+    fn new(max_asid: u16, num_cpus: usize) -> Self {
+        let bitmap_size = (usize::from(max_asid) + 1).div_ceil(64);
 
-        Self {
+        let mut this = Self {
             bitmap: vec![0; bitmap_size],
-            last: 2,
+            max_asid,
+            last: DEFAULT_ASID,
+            reserved: vec![0; num_cpus].into_boxed_slice(),
+        };
+        // the kernel address space always uses the default ASID
+        this.set(DEFAULT_ASID);
+        this
+    }
+
+    fn new_context(&mut self, asids: &Asids, old_context: u64) -> u64 {
+        let generation = asids.generation.load(Ordering::Relaxed);
+
+        if old_context != 0 {
+            let asid = asid_of(old_context);
+            let new_context = generation | u64::from(asid);
+
+            // If the ASID was active on some CPU during the last rollover, we carried it over and
+            // can keep using it.
+            if self.update_reserved(old_context, new_context) {
+                return new_context;
+            }
+
+            // If nobody else claimed our old ASID in the new generation yet we can keep using it too.
+            if !self.is_set(asid) {
+                self.set(asid);
+                return new_context;
+            }
+        }
+
+        let asid = match self.find_free() {
+            Some(asid) => asid,
+            None => {
+                self.rollover(asids);
+                // at most one ASID per CPU is carried over and `init` only uses ASIDs if there are
+                // more of them than CPUs
+                self.find_free()
+                    .expect("rollover should always free up an ASID")
+            }
+        };
+
+        asids.generation.load(Ordering::Relaxed) | u64::from(asid)
+    }
+
+    /// Start a new generation.
+    fn rollover(&mut self, asids: &Asids) {
+        tracing::debug!("ASIDs exhausted, starting new generation");
+        ASID_ROLLOVERS.increment(1);
+
+        asids
+            .generation
+            .fetch_add(FIRST_GENERATION, Ordering::Relaxed);
+
+        self.bitmap.fill(0);
+        self.set(DEFAULT_ASID);
+
+        let mut all_cpus = 0;
+        for (cpu, active) in asids.active.iter().enumerate() {
+            let mut context = active.swap(0, Ordering::Relaxed);
+            // If this CPU already saw a rollover but didn't activate anything since then, its
+            // previously reserved ASID is still the one in use.
+            if context == 0 {
+                context = self.reserved[cpu];
+            }
+
+            self.set(asid_of(context));
+            self.reserved[cpu] = context;
+            all_cpus |= 1 << cpu;
+        }
+
+        // Stale translations from the previous generation must be flushed on all CPUs before they
+        // activate an address space of the new generation.
+        asids.flush_pending.store(all_cpus, Ordering::Relaxed);
+    }
+
+    fn update_reserved(&mut self, old_context: u64, new_context: u64) -> bool {
+        let mut hit = false;
+        for reserved in &mut self.reserved {
+            if *reserved == old_context {
+                *reserved = new_context;
+                hit = true;
+            }
         }
+        hit
     }
 
-    pub fn alloc(&mut self) -> Option<u16> {
-        for asid in self.last + 1..u16::MAX {
+    fn find_free(&mut self) -> Option<u16> {
+        let candidates = (self.last + 1..=self.max_asid).chain(1..=self.last);
+        for asid in candidates {
             if !self.is_set(asid) {
                 self.set(asid);
+                self.last = asid;
                 return Some(asid);
             }
         }
         None
     }
 
-    pub fn free(&mut self, asid: u16) {
-        debug_assert!(self.is_set(asid));
-        self.unset(asid);
-    }
-
-    fn is_set(&self, index: u16) -> bool {
-        let byte = self.bitmap[index as usize / 8];
-        (byte & 1 << (index % 8)) != 0
+    fn is_set(&self, asid: u16) -> bool {
+        let word = self.bitmap[usize::from(asid) / 64];
+        (word & 1 << (asid % 64)) != 0
     }
-    fn set(&mut self, index: u16) {
-        self.bitmap[index as usize / 8] |= 1 << (index % 8);
+    fn set(&mut self, asid: u16) {
+        self.bitmap[usize::from(asid) / 64] |= 1 << (asid % 64);
     }
-    fn unset(&mut self, index: u16) {
-        self.bitmap[index as usize / 8] &= !(1 << (index % 8));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ktest::test]
+    async fn rollover_carries_over_active_asids() {
+        // two CPUs sharing the ASIDs 1..=3
+        let asids = Asids {
+            has_asids: true,
+            generation: AtomicU64::new(FIRST_GENERATION),
+            active: (0..2).map(|_| AtomicU64::new(0)).collect(),
+            flush_pending: AtomicUsize::new(0),
+            allocator: Mutex::new(AsidAllocator::new(3, 2)),
+        };
+        let mut allocator = asids.allocator.lock();
+
+        let contexts: Vec<_> = (0..3).map(|_| allocator.new_context(&asids, 0)).collect();
+        for (context, asid) in contexts.iter().zip(1..) {
+            assert!(asids.is_current_generation(*context));
+            assert_eq!(asid_of(*context), asid);
+        }
+        // the first address space is active on CPU 0
+        asids.active[0].store(contexts[0], Ordering::Relaxed);
+
+        // all ASIDs are taken, so the next allocation starts a new generation
+        let rollovers = ASID_ROLLOVERS.get().unwrap_or(0);
+        let context = allocator.new_context(&asids, 0);
+        assert_eq!(ASID_ROLLOVERS.get(), Some(rollovers + 1));
+        assert_eq!(
+            asids.generation.load(Ordering::Relaxed),
+            2 * FIRST_GENERATION
+        );
+        assert!(asids.is_current_generation(context));
+        assert_eq!(asid_of(context), 2);
+        // every CPU has to flush before activating an address space of the new generation
+        assert_eq!(asids.flush_pending.load(Ordering::Relaxed), 0b11);
+
+        // the active address space keeps its ASID
+        let carried_over = allocator.new_context(&asids, contexts[0]);
+        assert!(asids.is_current_generation(carried_over));
+        assert_eq!(asid_of(carried_over), 1);
+
+        // while inactive ones whose ASID has been reused get a new one
+        let reassigned = allocator.new_context(&asids, contexts[1]);
+        assert!(asids.is_current_generation(reassigned));
+        assert_eq!(asid_of(reassigned), 3);
     }
 }
@@ -0,0 +1,385 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for the RISC-V Advanced Interrupt Architecture (AIA).
+//!
+//! With the AIA, wired interrupts are collected by the APLIC (Advanced Platform-Level Interrupt
+//! Controller), which - in MSI delivery mode - forwards them as message-signaled interrupts to the
+//! IMSIC (Incoming MSI Controller) interrupt file of the hart they are routed to. Every hart has
+//! its own S-level interrupt file, accessed through the `siselect`/`sireg` and `stopei` CSRs.
+//!
+//! APLIC source numbers are used as MSI identities, so an interrupt has the same number no matter
+//! which hart it is routed to. All identities are enabled in every interrupt file, routing and
+//! masking happen exclusively in the APLIC, which any hart can program.
+//!
+//! QEMU's `virt` machine uses the AIA when started with `-M virt,aia=aplic-imsic`.
+
+use crate::device_tree::{Device, DeviceTree, IrqSource};
+use crate::driver;
+use crate::irq::{InterruptController, IrqClaim, TriggerMode};
+use crate::mem::{Mmap, VirtualAddress};
+use alloc::vec::Vec;
+use anyhow::{Context, bail, ensure};
+use core::num::NonZero;
+use core::str::FromStr;
+use riscv::{sireg, siselect, stopei};
+use spin::OnceLock;
+
+const DOMAINCFG: usize = 0x0000;
+const SOURCECFG: usize = 0x0004;
+const IN_CLRIP: usize = 0x1d00;
+const SETIENUM: usize = 0x1edc;
+const CLRIENUM: usize = 0x1fdc;
+const SETIPNUM_LE: usize = 0x2000;
+const TARGET: usize = 0x3004;
+
+const DOMAINCFG_IE: u32 = 1 << 8;
+const DOMAINCFG_DM_MSI: u32 = 1 << 2;
+const SOURCECFG_SM_MASK: u32 = 0x7;
+const SOURCECFG_SM_EDGE1: u32 = 4;
+const SOURCECFG_SM_EDGE0: u32 = 5;
+/// Active-high level-sensitive source mode, which is what all devices of QEMU's `virt` machine use.
+const SOURCECFG_SM_LEVEL1: u32 = 6;
+const SOURCECFG_SM_LEVEL0: u32 = 7;
+const TARGET_HART_INDEX_SHIFT: u32 = 18;
+
+const EIDELIVERY: usize = 0x70;
+const EITHRESHOLD: usize = 0x72;
+const EIE0: usize = 0xc0;
+
+/// The S-mode external interrupt cause, used to tell S-level from M-level IMSIC and APLIC nodes.
+const SUPERVISOR_EXTERNAL: u32 = 9;
+
+static APLIC: OnceLock<Aplic> = OnceLock::new();
+
+/// The AIA handle of a CPU.
+#[derive(Debug)]
+pub struct Aia {
+    aplic: &'static Aplic,
+}
+
+/// The S-level APLIC interrupt domain, shared by all CPUs.
+#[derive(Debug)]
+struct Aplic {
+    mmap: Mmap,
+    num_sources: u32,
+    /// The IMSIC hart index of every CPU, indexed by CPU id.
+    hart_indices: Vec<Option<u32>>,
+}
+
+/// Returns whether the device tree describes an AIA that delivers interrupts through IMSICs.
+pub fn is_present(devtree: &DeviceTree) -> bool {
+    find_imsic(devtree).is_some()
+}
+
+impl Aia {
+    #[cold]
+    pub fn new(devtree: &DeviceTree) -> crate::Result<Aia> {
+        let imsic = find_imsic(devtree).context("no S-level IMSIC found")?;
+        let num_ids = imsic
+            .property("riscv,num-ids")
+            .context("IMSIC is missing `riscv,num-ids`")?
+            .as_u32()?;
+
+        let aplic = APLIC.get_or_try_init(|| Aplic::new(devtree, imsic))?;
+        ensure!(
+            num_ids >= aplic.num_sources,
+            "IMSIC supports fewer interrupt identities than the APLIC has sources"
+        );
+
+        let aia = Aia { aplic };
+        aia.init_interrupt_file();
+        Ok(aia)
+    }
+
+    /// Restore the calling hart's interrupt file after the hart was stopped or suspended, which
+    /// might have reset it.
+    pub fn resume(&mut self) {
+        self.init_interrupt_file();
+    }
+
+    fn init_interrupt_file(&self) {
+        // deliver every identity the APLIC can send us, which ones actually arrive is decided by
+        // the APLIC
+        for id in 1..=self.aplic.num_sources {
+            set_interrupt_enabled(id, true);
+        }
+        write_indirect(EITHRESHOLD, 0);
+        write_indirect(EIDELIVERY, 1);
+    }
+}
+
+impl InterruptController for Aia {
+    fn irq_claim(&mut self) -> Option<IrqClaim> {
+        let id = stopei::claim().identity();
+        // Safety: the identity was just claimed from the interrupt file
+        NonZero::new(id).map(|raw| unsafe { IrqClaim::from_raw(raw) })
+    }
+
+    fn irq_complete(&mut self, _claim: IrqClaim) {
+        // claiming through `stopei` already cleared the pending bit, and level-sensitive sources
+        // are re-triggered when they are unmasked again
+    }
+
+    fn irq_mask(&mut self, irq_num: u32, cpuid: usize) {
+        self.aplic.check_source(irq_num);
+        // a source can only be routed to one hart, don't mask it if someone else took it over
+        if self.aplic.target(irq_num) == self.aplic.target_for(irq_num, cpuid) {
+            self.aplic.write(CLRIENUM, irq_num);
+        }
+    }
+
+    fn irq_unmask(&mut self, irq_num: u32, cpuid: usize) {
+        self.aplic.check_source(irq_num);
+        self.aplic.write(
+            target_offset(irq_num),
+            self.aplic.target_for(irq_num, cpuid),
+        );
+        self.aplic.write(SETIENUM, irq_num);
+
+        // in MSI delivery mode the pending bit of a level-sensitive source is only set when its
+        // input becomes active, so a source that stayed active while it was masked would be lost
+        if self.aplic.is_level_triggered(irq_num) && self.aplic.input_active(irq_num) {
+            self.aplic.write(SETIPNUM_LE, irq_num);
+        }
+    }
+
+    fn irq_set_priority(&mut self, _irq_num: u32, _priority: u32) -> crate::Result<()> {
+        bail!("IMSIC interrupts are prioritized by their number and can't be given a priority")
+    }
+
+    fn irq_set_trigger(&mut self, irq_num: u32, mode: TriggerMode) -> crate::Result<()> {
+        ensure!(
+            irq_num > 0 && irq_num <= self.aplic.num_sources,
+            "APLIC has no interrupt source {irq_num}"
+        );
+        let source_mode = match mode {
+            TriggerMode::EdgeRising => SOURCECFG_SM_EDGE1,
+            TriggerMode::EdgeFalling => SOURCECFG_SM_EDGE0,
+            TriggerMode::LevelHigh => SOURCECFG_SM_LEVEL1,
+            TriggerMode::LevelLow => SOURCECFG_SM_LEVEL0,
+        };
+        self.aplic.write(sourcecfg_offset(irq_num), source_mode);
+        Ok(())
+    }
+}
+
+impl Aplic {
+    fn new(devtree: &DeviceTree, imsic: &Device) -> crate::Result<Self> {
+        let soc = devtree.find_by_path("/soc").expect("missing /soc node");
+        let dev = soc
+            .children()
+            .filter(|dev| dev.is_compatible(["riscv,aplic"]))
+            .find(|dev| {
+                dev.property("msi-parent")
+                    .and_then(|prop| prop.as_u32().ok())
+                    .is_some_and(|phandle| Some(phandle) == imsic.phandle)
+            })
+            .context("no APLIC delivers to the S-level IMSIC")?;
+
+        let num_sources = dev
+            .property("riscv,num-sources")
+            .context("APLIC is missing `riscv,num-sources`")?
+            .as_u32()?;
+
+        let this = Self {
+            mmap: driver::map_reg(dev, 0, "APLIC")?,
+            num_sources,
+            hart_indices: hart_indices(devtree, imsic),
+        };
+
+        this.write(DOMAINCFG, 0);
+        for irq_num in 1..=num_sources {
+            this.write(CLRIENUM, irq_num);
+            this.write(sourcecfg_offset(irq_num), SOURCECFG_SM_LEVEL1);
+        }
+        this.write(DOMAINCFG, DOMAINCFG_IE | DOMAINCFG_DM_MSI);
+
+        Ok(this)
+    }
+
+    fn check_source(&self, irq_num: u32) {
+        assert!(irq_num > 0 && irq_num <= self.num_sources);
+    }
+
+    fn target(&self, irq_num: u32) -> u32 {
+        self.read(target_offset(irq_num))
+    }
+
+    /// Returns the target register value that sends `irq_num` to the CPU `cpuid`.
+    fn target_for(&self, irq_num: u32, cpuid: usize) -> u32 {
+        let hart_index = self
+            .hart_indices
+            .get(cpuid)
+            .copied()
+            .flatten()
+            .unwrap_or_else(|| panic!("CPU {cpuid} has no IMSIC interrupt file"));
+
+        (hart_index << TARGET_HART_INDEX_SHIFT) | irq_num
+    }
+
+    fn is_level_triggered(&self, irq_num: u32) -> bool {
+        let source_mode = self.read(sourcecfg_offset(irq_num)) & SOURCECFG_SM_MASK;
+        matches!(source_mode, SOURCECFG_SM_LEVEL1 | SOURCECFG_SM_LEVEL0)
+    }
+
+    /// Returns whether the rectified input of the source is active, i.e. the input value after
+    /// inverting active-low sources.
+    fn input_active(&self, irq_num: u32) -> bool {
+        let word = self.read(IN_CLRIP + usize::try_from(irq_num / 32).unwrap() * 4);
+        word & (1 << (irq_num % 32)) != 0
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        #[expect(clippy::cast_ptr_alignment, reason = "MMIO regions are page aligned")]
+        let ptr = self.register(offset).as_ptr().cast::<u32>();
+        // Safety: the register is within the mapped MMIO region of the APLIC
+        unsafe { ptr.read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        #[expect(clippy::cast_ptr_alignment, reason = "MMIO regions are page aligned")]
+        let ptr = self.register(offset).as_mut_ptr().cast::<u32>();
+        // Safety: the register is within the mapped MMIO region of the APLIC
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn register(&self, offset: usize) -> VirtualAddress {
+        self.mmap.range().start.checked_add(offset).unwrap()
+    }
+}
+
+fn sourcecfg_offset(irq_num: u32) -> usize {
+    SOURCECFG + usize::try_from(irq_num - 1).unwrap() * 4
+}
+
+fn target_offset(irq_num: u32) -> usize {
+    TARGET + usize::try_from(irq_num - 1).unwrap() * 4
+}
+
+/// Returns the S-level IMSIC, which signals S-mode external interrupts to the harts.
+fn find_imsic(devtree: &DeviceTree) -> Option<&Device<'_>> {
+    let soc = devtree.find_by_path("/soc")?;
+    soc.children()
+        .filter(|dev| dev.is_compatible(["riscv,imsics"]))
+        .find(|dev| {
+            dev.interrupts_extended(devtree)
+                .is_some_and(|mut interrupts| {
+                    interrupts.any(|(_, irq)| matches!(irq, IrqSource::C1(SUPERVISOR_EXTERNAL)))
+                })
+        })
+}
+
+/// Returns the hart index of every CPU, which is the position of the CPU's interrupt file in the
+/// `interrupts-extended` property of the IMSIC.
+fn hart_indices(devtree: &DeviceTree, imsic: &Device) -> Vec<Option<u32>> {
+    let mut indices = Vec::new();
+    let Some(interrupts) = imsic.interrupts_extended(devtree) else {
+        return indices;
+    };
+    let cpus = devtree
+        .find_by_path("/cpus")
+        .expect("required /cpus node not in device tree");
+
+    for (hart_index, (hlic, _)) in interrupts.enumerate() {
+        let Some(cpuid) = cpus
+            .children()
+            .filter(|cpu| cpu.name.name == "cpu")
+            .find(|cpu| {
+                cpu.children().any(|child| {
+                    child.name.name == "interrupt-controller" && child.phandle == hlic.phandle
+                })
+            })
+            .and_then(|cpu| usize::from_str(cpu.name.unit_address?).ok())
+        else {
+            continue;
+        };
+
+        if indices.len() <= cpuid {
+            indices.resize(cpuid + 1, None);
+        }
+        indices[cpuid] = Some(u32::try_from(hart_index).unwrap());
+    }
+
+    indices
+}
+
+/// Enable or disable the interrupt identity `id` in the calling hart's interrupt file.
+fn set_interrupt_enabled(id: u32, enabled: bool) {
+    let id = usize::try_from(id).unwrap();
+    // on RV64 only the even-numbered `eie` registers exist, each covering 64 identities
+    let reg = EIE0 + (id / 64) * 2;
+    let bit = 1 << (id % 64);
+
+    let value = read_indirect(reg);
+    write_indirect(reg, if enabled { value | bit } else { value & !bit });
+}
+
+fn read_indirect(reg: usize) -> usize {
+    siselect::set(reg);
+    sireg::read()
+}
+
+fn write_indirect(reg: usize, value: usize) {
+    siselect::set(reg);
+    sireg::set(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CPUID;
+    use crate::irq::{IRQ_COUNTS, Irq};
+    use crate::scheduler::scheduler;
+    use crate::time::{Duration, timeout};
+
+    #[ktest::test]
+    async fn delivers_routed_interrupt() {
+        // the test runner only boots with the AIA when started with `--aia`
+        let Some(aplic) = APLIC.get() else {
+            return;
+        };
+
+        // none of the devices of QEMU's `virt` machine use the last source
+        let irq = Irq::new(aplic.num_sources);
+        irq.set_trigger(TriggerMode::EdgeRising).unwrap();
+
+        // route the interrupt to another CPU if there is one
+        let online = scheduler().online_cpus();
+        let target = (0..usize::BITS as usize)
+            .find(|cpuid| *cpuid != CPUID.get() && online & (1 << cpuid) != 0)
+            .unwrap_or(CPUID.get());
+        irq.set_affinity(Some(1 << target)).unwrap();
+
+        let index = usize::try_from(irq.as_u32()).unwrap();
+        let delivered_before = IRQ_COUNTS.get_for(target, index).unwrap_or(0);
+
+        let mut waiter = scheduler().spawn(irq.next_event());
+        // The waiter might not have started waiting yet when we raise the interrupt, in which case
+        // the interrupt is delivered but nobody is woken. Keep raising it until the waiter sees it.
+        let res = timeout(Duration::from_secs(10), async {
+            loop {
+                aplic.write(SETIPNUM_LE, irq.as_u32());
+                if let Ok(res) = timeout(Duration::from_millis(10), &mut waiter).await {
+                    break res;
+                }
+            }
+        })
+        .await
+        .expect("routed interrupt was never received");
+        res.unwrap().unwrap();
+
+        assert_eq!(
+            aplic.target(irq.as_u32()),
+            aplic.target_for(irq.as_u32(), target)
+        );
+        assert!(IRQ_COUNTS.get_for(target, index).unwrap_or(0) > delivered_before);
+
+        irq.set_affinity(None).unwrap();
+        irq.set_trigger(TriggerMode::LevelHigh).unwrap();
+    }
+}
@@ -8,7 +8,6 @@
 use crate::CPUID;
 use crate::arch::device;
 use crate::device_tree::DeviceTree;
-use crate::irq::InterruptController;
 use crate::time::clock::Ticks;
 use crate::time::{Clock, NANOS_PER_SEC};
 use anyhow::{Context, bail};
@@ -29,7 +28,7 @@ pub struct Cpu {
     pub cbop_block_size: Option<usize>,
     pub cboz_block_size: Option<usize>,
     pub cbom_block_size: Option<usize>,
-    pub plic: RefCell<device::plic::Plic>,
+    pub irq_ctl: RefCell<device::IrqController>,
     pub clock: Clock,
 }
 
@@ -76,6 +75,7 @@ bitflags! {
         const SSTVECD = 1 << 37;
         const SVADU = 1 << 38;
         const SVVPTC = 1 << 39;
+        const ZKR = 1 << 40;
     }
 }
 
@@ -85,7 +85,7 @@ impl fmt::Display for Cpu {
         writeln!(f, "{:<17} : {:?}", "CBOP BLOCK SIZE", self.cbop_block_size)?;
         writeln!(f, "{:<17} : {:?}", "CBOZ BLOCK SIZE", self.cboz_block_size)?;
         writeln!(f, "{:<17} : {:?}", "CBOM BLOCK SIZE", self.cbom_block_size)?;
-        writeln!(f, "{:<17} : {:?}", "PLIC", self.plic)?;
+        writeln!(f, "{:<17} : {:?}", "IRQ CONTROLLER", self.irq_ctl)?;
         writeln!(f, "{:<17} : {}", "CLOCK", self.clock)?;
 
         Ok(())
@@ -152,11 +152,17 @@ pub fn init(devtree: &DeviceTree) -> crate::Result<()> {
         .unwrap();
     tracing::trace!("CPU interrupt controller: {:?}", hlic_node);
 
-    let mut plic = device::plic::Plic::new(devtree, hlic_node)?;
-    plic.irq_unmask(10);
+    let irq_ctl = device::IrqController::new(devtree, hlic_node)?;
 
     let tick_duration = Duration::from_nanos(NANOS_PER_SEC / timebase_frequency);
     let clock = Clock::new(tick_duration, || Ticks(riscv::register::time::read64()));
+    // with Sstc we can program the timer ourselves instead of trapping into the firmware for
+    // every deadline
+    let clock = if extensions.contains(RiscvExtensions::SSTC) {
+        clock.with_timer("sstc", arm_timer_sstc)
+    } else {
+        clock.with_timer("sbi", arm_timer_sbi)
+    };
 
     debug_assert_eq!(
         clock.ticks_to_duration(Ticks(timebase_frequency)),
@@ -174,7 +180,7 @@ pub fn init(devtree: &DeviceTree) -> crate::Result<()> {
             cbop_block_size,
             cboz_block_size,
             cbom_block_size,
-            plic: RefCell::new(plic),
+            irq_ctl: RefCell::new(irq_ctl),
         };
         tracing::debug!("\n{info_}");
 
@@ -184,6 +190,16 @@ pub fn init(devtree: &DeviceTree) -> crate::Result<()> {
     Ok(())
 }
 
+/// Program the timer interrupt through the SBI timer extension.
+fn arm_timer_sbi(deadline: Ticks) {
+    riscv::sbi::time::set_timer(deadline.0).unwrap();
+}
+
+/// Program the timer interrupt by writing the `stimecmp` CSR directly (Sstc extension).
+fn arm_timer_sstc(deadline: Ticks) {
+    riscv::register::stimecmp::set(usize::try_from(deadline.0).unwrap());
+}
+
 pub fn parse_riscv_extensions(strs: fdt::StringList) -> crate::Result<RiscvExtensions> {
     let mut out = RiscvExtensions::empty();
 
@@ -229,6 +245,7 @@ pub fn parse_riscv_extensions(strs: fdt::StringList) -> crate::Result<RiscvExten
             "sstvecd" => RiscvExtensions::SSTVECD,
             "svadu" => RiscvExtensions::SVADU,
             "svvptc" => RiscvExtensions::SVVPTC,
+            "zkr" => RiscvExtensions::ZKR,
             ext => {
                 bail!("unknown RISCV extension {}", ext);
             }
@@ -243,3 +260,44 @@ impl fmt::Display for RiscvExtensions {
         bitflags::parser::to_writer(self, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::scheduler;
+    use crate::time::{self, Instant};
+
+    const ITERATIONS: u32 = 1000;
+
+    /// Compares the cost of programming the timer interrupt through the SBI and through Sstc.
+    #[ktest::test]
+    async fn arm_timer_benchmark() {
+        let sbi = measure(arm_timer_sbi);
+        tracing::info!("arming the timer through SBI takes {sbi:?}");
+
+        if with_cpu(|cpu| cpu.extensions.contains(RiscvExtensions::SSTC)) {
+            let sstc = measure(arm_timer_sstc);
+            tracing::info!("arming the timer through Sstc takes {sstc:?}");
+        }
+
+        let mode = with_cpu(|cpu| cpu.clock.timer_mode());
+        tracing::info!("active timer mode: {}", mode.unwrap());
+    }
+
+    /// Returns the average time `arm` takes, with interrupts disabled to keep the timer interrupt
+    /// out of the measurement.
+    fn measure(arm: fn(Ticks)) -> Duration {
+        riscv::interrupt::with_disabled(|| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                arm(Ticks(u64::MAX));
+            }
+            let elapsed = start.elapsed();
+
+            // restore the deadline of the CPU-local timer we just overwrote
+            time::arm_timer_interrupt(scheduler().cpu_local_timer().next_deadline());
+
+            elapsed / ITERATIONS
+        })
+    }
+}
@@ -5,5 +5,84 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+pub mod aia;
 pub mod cpu;
 pub mod plic;
+
+use crate::device_tree::{Device, DeviceTree};
+use crate::irq::{InterruptController, IrqClaim, TriggerMode};
+
+/// The external interrupt controller of a CPU.
+///
+/// Machines that implement the Advanced Interrupt Architecture use the [`aia::Aia`], all others the
+/// [`plic::Plic`].
+#[derive(Debug)]
+pub enum IrqController {
+    Plic(plic::Plic),
+    Aia(aia::Aia),
+}
+
+impl IrqController {
+    #[cold]
+    pub fn new(devtree: &DeviceTree, hlic_node: &Device) -> crate::Result<Self> {
+        if aia::is_present(devtree) {
+            Ok(Self::Aia(aia::Aia::new(devtree)?))
+        } else {
+            Ok(Self::Plic(plic::Plic::new(devtree, hlic_node)?))
+        }
+    }
+
+    /// Restore the calling CPU's interrupt controller state after the CPU was stopped or
+    /// suspended.
+    pub fn resume(&mut self) {
+        match self {
+            // all PLIC state lives in the PLIC itself, which isn't affected
+            Self::Plic(_) => {}
+            Self::Aia(aia) => aia.resume(),
+        }
+    }
+}
+
+impl InterruptController for IrqController {
+    fn irq_claim(&mut self) -> Option<IrqClaim> {
+        match self {
+            Self::Plic(plic) => plic.irq_claim(),
+            Self::Aia(aia) => aia.irq_claim(),
+        }
+    }
+
+    fn irq_complete(&mut self, claim: IrqClaim) {
+        match self {
+            Self::Plic(plic) => plic.irq_complete(claim),
+            Self::Aia(aia) => aia.irq_complete(claim),
+        }
+    }
+
+    fn irq_mask(&mut self, irq_num: u32, cpuid: usize) {
+        match self {
+            Self::Plic(plic) => plic.irq_mask(irq_num, cpuid),
+            Self::Aia(aia) => aia.irq_mask(irq_num, cpuid),
+        }
+    }
+
+    fn irq_unmask(&mut self, irq_num: u32, cpuid: usize) {
+        match self {
+            Self::Plic(plic) => plic.irq_unmask(irq_num, cpuid),
+            Self::Aia(aia) => aia.irq_unmask(irq_num, cpuid),
+        }
+    }
+
+    fn irq_set_priority(&mut self, irq_num: u32, priority: u32) -> crate::Result<()> {
+        match self {
+            Self::Plic(plic) => plic.irq_set_priority(irq_num, priority),
+            Self::Aia(aia) => aia.irq_set_priority(irq_num, priority),
+        }
+    }
+
+    fn irq_set_trigger(&mut self, irq_num: u32, mode: TriggerMode) -> crate::Result<()> {
+        match self {
+            Self::Plic(plic) => plic.irq_set_trigger(irq_num, mode),
+            Self::Aia(aia) => aia.irq_set_trigger(irq_num, mode),
+        }
+    }
+}
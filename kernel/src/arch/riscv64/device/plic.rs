@@ -7,28 +7,42 @@
 
 use crate::arch::PAGE_SIZE;
 use crate::device_tree::{Device, DeviceTree, IrqSource};
-use crate::irq::{InterruptController, IrqClaim};
+use crate::irq::{DEFAULT_PRIORITY, InterruptController, IrqClaim, TriggerMode};
 use crate::mem::{
     AddressRangeExt, AddressSpaceRegion, Permissions, PhysicalAddress, with_kernel_aspace,
 };
 use crate::util::either::Either;
 use alloc::string::ToString;
+use alloc::vec::Vec;
+use anyhow::{bail, ensure};
 use core::alloc::Layout;
 use core::mem::{MaybeUninit, offset_of};
 use core::num::NonZero;
 use core::ops::{BitAnd, BitOr, Not};
 use core::ptr;
 use core::range::Range;
+use core::str::FromStr;
 use fallible_iterator::FallibleIterator;
+use spin::{Mutex, Once};
 use static_assertions::const_assert_eq;
 
 const MAX_CONTEXTS: usize = 64;
+/// The highest priority supported by the PLIC, priority 0 means "never interrupt".
+const MAX_PRIORITY: u32 = 7;
+
+/// Serializes updates of the enable bits, which are shared between contexts of different CPUs.
+static ENABLE_LOCK: Mutex<()> = Mutex::new(());
+/// Source priorities are shared by all CPUs, so they are only reset by the first one.
+static PRIORITIES_INIT: Once = Once::new();
 
 #[derive(Debug)]
 pub struct Plic {
     /// The MMIO registers of the PLIC.
     regs: *mut PlicRegs,
+    /// The S-mode context of the calling CPU.
     context: usize,
+    /// The S-mode context of every CPU, indexed by CPU id.
+    contexts: Vec<Option<usize>>,
     /// The number of external interrupts supported by this controller.
     ///
     /// <https://github.com/torvalds/linux/blob/5bfc75d92efd494db37f5c4c173d3639d4772966/Documentation/devicetree/bindings/interrupt-controller/sifive%2Cplic-1.0.0.yaml#L69>
@@ -82,27 +96,28 @@ impl Plic {
         let (context, dev) = soc
             .children()
             .filter(|dev| dev.is_compatible(["sifive,plic-1.0.0", "riscv,plic0"]))
-            .find_map(|dev| {
-                let interrupts = if let Some(interrupts) = dev.interrupts_extended(devtree) {
-                    Either::Left(interrupts)
-                } else if let Some(interrupts) = dev.interrupts(devtree) {
-                    Either::Right(interrupts)
-                } else {
-                    return None;
-                };
-
-                for (context, (parent, _)) in interrupts
-                    .enumerate()
-                    .filter(|(_, (_, irq))| is_supervisor_source(irq))
-                {
-                    if parent.phandle == hlic_node.phandle {
-                        return Some((context, dev));
-                    }
-                }
+            .find_map(|dev| Some((find_context(devtree, dev, hlic_node)?, dev)))
+            .unwrap();
 
-                None
+        let contexts = devtree
+            .find_by_path("/cpus")
+            .expect("required /cpus node not in device tree")
+            .children()
+            .filter(|cpu| cpu.name.name == "cpu")
+            .filter_map(|cpu| {
+                let cpuid = usize::from_str(cpu.name.unit_address?).ok()?;
+                let hlic_node = cpu
+                    .children()
+                    .find(|c| c.name.name == "interrupt-controller")?;
+                Some((cpuid, find_context(devtree, dev, hlic_node)))
             })
-            .unwrap();
+            .fold(Vec::new(), |mut contexts, (cpuid, context)| {
+                if contexts.len() <= cpuid {
+                    contexts.resize(cpuid + 1, None);
+                }
+                contexts[cpuid] = context;
+                contexts
+            });
 
         let mmio_range = {
             let reg = dev.regs().unwrap().next()?.unwrap();
@@ -143,12 +158,47 @@ impl Plic {
         // an MMIO region, so it's inherently unsafe.
         unsafe { regs.as_mut().unwrap().set_priority_threshold(context, 0) };
 
+        PRIORITIES_INIT.call_once(|| {
+            // Safety: see above
+            let regs = unsafe { regs.as_mut().unwrap() };
+            for irq in 1..=ndev {
+                regs.set_priority(NonZero::new(irq).unwrap(), DEFAULT_PRIORITY);
+            }
+        });
+
         Ok(Plic {
             regs,
             context,
+            contexts,
             ndev,
         })
     }
+
+    fn context_of(&self, cpuid: usize) -> usize {
+        self.contexts
+            .get(cpuid)
+            .copied()
+            .flatten()
+            .unwrap_or_else(|| panic!("CPU {cpuid} has no PLIC context"))
+    }
+}
+
+/// Returns the PLIC context that delivers S-mode external interrupts to the CPU with the given
+/// local interrupt controller.
+fn find_context(devtree: &DeviceTree, dev: &Device, hlic_node: &Device) -> Option<usize> {
+    let interrupts = if let Some(interrupts) = dev.interrupts_extended(devtree) {
+        Either::Left(interrupts)
+    } else if let Some(interrupts) = dev.interrupts(devtree) {
+        Either::Right(interrupts)
+    } else {
+        return None;
+    };
+
+    interrupts
+        .enumerate()
+        .filter(|(_, (_, irq))| is_supervisor_source(irq))
+        .find(|(_, (parent, _))| parent.phandle == hlic_node.phandle)
+        .map(|(context, _)| context)
 }
 
 impl InterruptController for Plic {
@@ -166,22 +216,44 @@ impl InterruptController for Plic {
         regs.complete(self.context, claim);
     }
 
-    fn irq_mask(&mut self, irq_num: u32) {
+    fn irq_mask(&mut self, irq_num: u32, cpuid: usize) {
         assert!(irq_num > 0 && irq_num as usize <= self.ndev);
+        let context = self.context_of(cpuid);
         // Safety: constructor ensures this is valid, but at the end of the day, this is writing to
         // an MMIO region, so it's inherently unsafe.
         let regs = unsafe { self.regs.as_mut().unwrap() };
-        regs.set_priority(NonZero::new(irq_num as usize).unwrap(), 1);
-        regs.enable(self.context, NonZero::new(irq_num as usize).unwrap(), false);
+        let _guard = ENABLE_LOCK.lock();
+        regs.enable(context, NonZero::new(irq_num as usize).unwrap(), false);
     }
 
-    fn irq_unmask(&mut self, irq_num: u32) {
+    fn irq_unmask(&mut self, irq_num: u32, cpuid: usize) {
         assert!(irq_num as usize <= self.ndev);
+        let context = self.context_of(cpuid);
+        // Safety: constructor ensures this is valid, but at the end of the day, this is writing to
+        // an MMIO region, so it's inherently unsafe.
+        let regs = unsafe { self.regs.as_mut().unwrap() };
+        let _guard = ENABLE_LOCK.lock();
+        regs.enable(context, NonZero::new(irq_num as usize).unwrap(), true);
+    }
+
+    fn irq_set_priority(&mut self, irq_num: u32, priority: u32) -> crate::Result<()> {
+        ensure!(
+            irq_num > 0 && irq_num as usize <= self.ndev,
+            "PLIC has no interrupt source {irq_num}"
+        );
+        ensure!(
+            (1..=MAX_PRIORITY).contains(&priority),
+            "PLIC priorities range from 1 to {MAX_PRIORITY}"
+        );
         // Safety: constructor ensures this is valid, but at the end of the day, this is writing to
         // an MMIO region, so it's inherently unsafe.
         let regs = unsafe { self.regs.as_mut().unwrap() };
-        regs.set_priority(NonZero::new(irq_num as usize).unwrap(), 1);
-        regs.enable(self.context, NonZero::new(irq_num as usize).unwrap(), true);
+        regs.set_priority(NonZero::new(irq_num as usize).unwrap(), priority);
+        Ok(())
+    }
+
+    fn irq_set_trigger(&mut self, _irq_num: u32, _mode: TriggerMode) -> crate::Result<()> {
+        bail!("the trigger mode of PLIC interrupt sources is fixed by the platform")
     }
 }
 
@@ -321,7 +393,9 @@ impl<T> MmioReg<T> {
 #[expect(clippy::match_like_matches_macro, reason = "its cleaner this way")]
 fn is_supervisor_source(addr: &IrqSource) -> bool {
     match addr {
-        IrqSource::C1(u32::MAX) | IrqSource::C3(u32::MAX, _, _) => false,
+        IrqSource::C1(u32::MAX) | IrqSource::C2(u32::MAX, _) | IrqSource::C3(u32::MAX, _, _) => {
+            false
+        }
         IrqSource::C1(11) => false,
         _ => true,
     }
@@ -5,16 +5,23 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use crate::CPUID;
+use crate::arch::riscv64::asid_allocator;
 use crate::arch::{mb, wmb};
+use crate::counter;
 use crate::mem::flush::Flush;
 use crate::mem::frame_alloc::{Frame, FrameAllocator};
 use crate::mem::{PhysicalAddress, VirtualAddress};
+use crate::metrics::Counter;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::bitflags;
+use core::arch::asm;
 use core::num::NonZeroUsize;
 use core::ptr::NonNull;
 use core::range::{Range, RangeInclusive};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::{fmt, slice};
 use riscv::satp;
 use riscv::sbi::rfence::sfence_vma_asid;
@@ -58,6 +65,10 @@ const_assert_eq!(CANONICAL_ADDRESS_MASK, 0xffffffc000000000);
 pub const PAGE_TABLE_ENTRIES: usize = 512;
 pub const PAGE_TABLE_LEVELS: usize = 3; // L0, L1, L2 Sv39
 pub const PAGE_ENTRY_SHIFT: usize = (PAGE_TABLE_ENTRIES - 1).count_ones() as usize;
+/// The size of a "huge" page i.e. the page size of the first page table level above the base page
+/// size. Large mappings are opportunistically backed by pages of this size to reduce TLB pressure.
+pub const HUGE_PAGE_SIZE: usize = PAGE_SIZE << PAGE_ENTRY_SHIFT;
+const_assert_eq!(HUGE_PAGE_SIZE, 0x20_0000);
 /// On `RiscV` targets the page table entry's physical address bits are shifted 2 bits to the right.
 const PTE_PPN_SHIFT: usize = 2;
 
@@ -86,26 +97,130 @@ pub const fn is_kernel_address(virt: VirtualAddress) -> bool {
     KERNEL_ASPACE_RANGE.start.get() <= virt.get() && virt.get() < KERNEL_ASPACE_RANGE.end.get()
 }
 
+/// Flushes spanning more than this number of pages will invalidate the entire address space instead
+/// of issuing one `sfence.vma` per page.
+const MAX_RANGED_FLUSH_PAGES: usize = 64;
+
+static TLB_SHOOTDOWNS_SENT: Counter = counter!("tlb-shootdowns-sent");
+static TLB_SHOOTDOWNS_AVOIDED: Counter = counter!("tlb-shootdowns-avoided");
+
 /// Invalidate address translation caches for the given `address_range` in the given `address_space`.
 ///
+/// Only the CPUs in `active_cpus` - the CPUs on which the address space is (or was recently)
+/// active - are flushed. The calling CPU is flushed locally through `sfence.vma` and all other CPUs
+/// receive a single remote fence request through SBI, which is skipped entirely if there are no
+/// other CPUs.
+///
+/// The mask is read only after the page table changes are visible, so a CPU that activates the
+/// address space concurrently either shows up in the mask or only ever walks the new page tables
+/// (see [`AddressSpace::activate`](crate::mem::ArchAddressSpace::activate)).
+///
 /// # Errors
 ///
 /// Should return an error if the underlying operation failed and the caches could not be invalidated.
-pub fn invalidate_range(asid: u16, address_range: Range<VirtualAddress>) -> crate::Result<()> {
-    mb();
-
+pub fn invalidate_range(
+    asid: u16,
+    active_cpus: &AtomicUsize,
+    address_range: Range<VirtualAddress>,
+) -> crate::Result<()> {
     let base_addr = address_range.start.get();
     let size = address_range
         .end
         .checked_sub_addr(address_range.start)
         .unwrap();
-    sfence_vma_asid(0, usize::MAX, base_addr, size, asid)?;
+    if size > MAX_RANGED_FLUSH_PAGES * PAGE_SIZE {
+        invalidate(asid, active_cpus, 0, usize::MAX)
+    } else {
+        invalidate(asid, active_cpus, base_addr, size)
+    }
+}
+
+/// Invalidate *all* address translation caches for the given `address_space`.
+///
+/// Unlike [`invalidate_range`] - which only needs to drop the leaf entries of the range - this also
+/// drops any cached non-leaf entries of the address space, so it must be used before page table
+/// frames are freed. Otherwise, other CPUs might keep walking page tables that have already been
+/// released and reused.
+///
+/// # Errors
+///
+/// Should return an error if the underlying operation failed and the caches could not be invalidated.
+pub fn invalidate_all(asid: u16, active_cpus: &AtomicUsize) -> crate::Result<()> {
+    invalidate(asid, active_cpus, 0, usize::MAX)
+}
+
+fn invalidate(
+    asid: u16,
+    active_cpus: &AtomicUsize,
+    base_addr: usize,
+    size: usize,
+) -> crate::Result<()> {
+    mb();
+    let cpu_mask = active_cpus.load(Ordering::SeqCst);
+
+    let this_cpu = cpu_bit(CPUID.get());
+    if cpu_mask & this_cpu != 0 {
+        local_sfence_vma(asid, base_addr, size);
+    }
+
+    let remote_cpus = cpu_mask & !this_cpu;
+    if remote_cpus != 0 {
+        sfence_vma_asid(remote_cpus, 0, base_addr, size, asid)?;
+        TLB_SHOOTDOWNS_SENT.increment(1);
+    } else {
+        TLB_SHOOTDOWNS_AVOIDED.increment(1);
+    }
 
     mb();
 
     Ok(())
 }
 
+/// Returns the bit of the CPU `cpuid` in CPU masks.
+///
+/// # Panics
+///
+/// Panics if `cpuid` doesn't fit into a CPU mask.
+fn cpu_bit(cpuid: usize) -> usize {
+    assert!(
+        cpuid < usize::BITS as usize,
+        "CPU id {cpuid} doesn't fit into a CPU mask"
+    );
+    1 << cpuid
+}
+
+/// Invalidate address translation caches for the given range on the calling CPU only.
+///
+/// A `size` of `usize::MAX` invalidates all translations of the address space.
+fn local_sfence_vma(asid: u16, base_addr: usize, size: usize) {
+    // The kernel address space uses global mappings which are not affected by `sfence.vma` with
+    // a non-zero `rs2` register so we need to use `zero` for it.
+    let asid = usize::from(asid);
+    let is_kernel = asid == usize::from(DEFAULT_ASID);
+
+    if size == usize::MAX {
+        // Safety: inline assembly
+        unsafe {
+            if is_kernel {
+                asm!("sfence.vma zero, zero");
+            } else {
+                asm!("sfence.vma zero, {asid}", asid = in(reg) asid);
+            }
+        }
+    } else {
+        for addr in (base_addr..base_addr.checked_add(size).unwrap()).step_by(PAGE_SIZE) {
+            // Safety: inline assembly
+            unsafe {
+                if is_kernel {
+                    asm!("sfence.vma {addr}, zero", addr = in(reg) addr);
+                } else {
+                    asm!("sfence.vma {addr}, {asid}", addr = in(reg) addr, asid = in(reg) asid);
+                }
+            }
+        }
+    }
+}
+
 /// Return the page size for the given page table level.
 ///
 /// # Panics
@@ -145,6 +260,16 @@ fn can_map_at_level(
     virt.is_aligned_to(page_size) && phys.is_aligned_to(page_size) && remaining_bytes >= page_size
 }
 
+/// Return whether the range starting at `virt` spanning `remaining_bytes` fully covers the page at
+/// the given `level` that `virt` falls into.
+///
+/// This is used to determine whether a large page leaf can be operated on as a whole or needs to be
+/// split first.
+fn covers_page(virt: VirtualAddress, remaining_bytes: usize, lvl: usize) -> bool {
+    let page_size = page_size_for_level(lvl);
+    virt.is_aligned_to(page_size) && remaining_bytes >= page_size
+}
+
 fn get_active_pgtable(expected_asid: u16) -> PhysicalAddress {
     let satp = satp::read();
     assert_eq!(satp.asid(), expected_asid);
@@ -156,13 +281,20 @@ fn get_active_pgtable(expected_asid: u16) -> PhysicalAddress {
 pub struct AddressSpace {
     root_pgtable: PhysicalAddress,
     wired_frames: Vec<Frame>,
-    asid: u16,
+    /// The ASID context of this address space, assigned lazily on activation (see
+    /// [`asid_allocator`]). `None` for the kernel address space which always uses [`DEFAULT_ASID`].
+    asid_context: Option<AtomicU64>,
+    /// Bitmask of CPUs on which this address space is (or was recently) active. Since TLB entries
+    /// are tagged with the ASID they can outlive the activation, so bits are never cleared here.
+    ///
+    /// Shared with every [`Flush`] of this address space, which reads it when flushing.
+    active_cpus: Arc<AtomicUsize>,
 }
 
 impl crate::mem::ArchAddressSpace for AddressSpace {
     type Flags = PTEFlags;
 
-    fn new(asid: u16, frame_alloc: &FrameAllocator) -> crate::Result<(Self, Flush)>
+    fn new(frame_alloc: &FrameAllocator) -> crate::Result<(Self, Flush)>
     where
         Self: Sized,
     {
@@ -188,13 +320,15 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
 
         mb();
 
+        let active_cpus = Arc::new(AtomicUsize::new(0));
         let this = Self {
-            asid,
+            asid_context: Some(AtomicU64::new(0)),
             root_pgtable: root_pgtable.addr(),
             wired_frames: vec![root_pgtable],
+            active_cpus: active_cpus.clone(),
         };
 
-        Ok((this, Flush::empty(asid)))
+        Ok((this, Flush::empty(DEFAULT_ASID, active_cpus)))
     }
 
     fn from_active(asid: u16) -> (Self, Flush)
@@ -203,13 +337,17 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
     {
         let root_pgtable = get_active_pgtable(asid);
 
+        // The active address space is the kernel address space that every CPU booted into
+        let active_cpus = Arc::new(AtomicUsize::new(crate::BOOT_INFO.get().unwrap().cpu_mask));
+
         let this = Self {
-            asid,
+            asid_context: None,
             root_pgtable,
             wired_frames: vec![],
+            active_cpus: active_cpus.clone(),
         };
 
-        (this, Flush::empty(asid))
+        (this, Flush::empty(asid, active_cpus))
     }
 
     unsafe fn map_contiguous(
@@ -272,12 +410,21 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
                 if can_map_at_level(virt, phys, remaining_bytes, lvl) {
                     let page_size = page_size_for_level(lvl);
 
-                    // This PTE is vacant AND we can map at this level
+                    // If this PTE currently points to a sub-table, we're promoting a range previously
+                    // mapped through smaller pages to a single larger page. The sub-table (and any
+                    // tables below it) are now unreachable so we need to release them. Other CPUs
+                    // might still be walking them though, so they are only freed once `flush` has
+                    // invalidated the whole address space.
+                    if pte.is_valid() && !pte.is_leaf() {
+                        self.free_pgtable(pte.get_address_and_flags().0, lvl - 1, flush);
+                    }
+
+                    // This PTE is vacant (or we just freed its sub-table) AND we can map at this level
                     // mark this PTE as a valid leaf node pointing to the physical frame
                     pte.replace_address_and_flags(phys, PTEFlags::VALID | flags);
 
                     flush.extend_range(
-                        self.asid,
+                        self.asid(),
                         Range::from(virt..virt.checked_add(page_size).unwrap()),
                     )?;
                     virt = virt.checked_add(page_size).unwrap();
@@ -287,6 +434,10 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
                 } else if pte.is_valid() && !pte.is_leaf() {
                     // This PTE is an internal node pointing to another page table
                     pgtable = self.pgtable_ptr_from_phys(pte.get_address_and_flags().0);
+                } else if pte.is_valid() {
+                    // This PTE is a large page leaf that we're only remapping a part of, split it up
+                    // so we can descend to the next level without clobbering the rest of the page.
+                    pgtable = self.split_leaf(frame_alloc, pte, lvl)?;
                 } else {
                     // The current PTE is vacant, but we couldn't map at this level (because the
                     // page size was too large, or the request wasn't sufficiently aligned or
@@ -312,6 +463,7 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
 
     unsafe fn update_flags(
         &mut self,
+        frame_alloc: &FrameAllocator,
         mut virt: VirtualAddress,
         len: NonZeroUsize,
         new_flags: Self::Flags,
@@ -347,7 +499,11 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
                 };
                 let page_size = page_size_for_level(lvl);
 
-                if pte.is_valid() && pte.is_leaf() {
+                if pte.is_valid() && pte.is_leaf() && !covers_page(virt, remaining_bytes, lvl) {
+                    // The requested range only partially covers this large page, so we need to split
+                    // it into a sub-table of smaller pages first and then continue to descend.
+                    pgtable = self.split_leaf(frame_alloc, pte, lvl)?;
+                } else if pte.is_valid() && pte.is_leaf() {
                     // We reached the previously mapped leaf node that we want to edit
                     // firstly, ensure that this operation only removes permissions never adds any
                     // and secondly mask out the old permissions replacing them with the new. This must
@@ -363,7 +519,7 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
                     );
 
                     flush.extend_range(
-                        self.asid,
+                        self.asid(),
                         Range::from(virt..virt.checked_add(page_size).unwrap()),
                     )?;
                     virt = virt.checked_add(page_size).unwrap();
@@ -385,6 +541,7 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
 
     unsafe fn unmap(
         &mut self,
+        frame_alloc: &FrameAllocator,
         mut virt: VirtualAddress,
         len: NonZeroUsize,
         flush: &mut Flush,
@@ -405,6 +562,7 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
         // if by doing so the parent has become empty in which case we also need to unmap the parent.
         while remaining_bytes > 0 {
             self.unmap_inner(
+                frame_alloc,
                 self.pgtable_ptr_from_phys(self.root_pgtable),
                 &mut virt,
                 &mut remaining_bytes,
@@ -430,7 +588,9 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
 
             if pte.is_valid() && pte.is_leaf() {
                 let (addr, flags) = pte.get_address_and_flags();
-                return Some((addr, flags));
+                // the leaf might map a huge page, so add the offset into whatever page we found
+                let offset = virt.get() & (page_size_for_level(lvl) - 1);
+                return Some((addr.checked_add(offset).unwrap(), flags));
             } else if pte.is_valid() {
                 // This PTE is an internal node pointing to another page table
                 pgtable = self.pgtable_ptr_from_phys(pte.get_address_and_flags().0);
@@ -447,22 +607,52 @@ impl crate::mem::ArchAddressSpace for AddressSpace {
     }
 
     unsafe fn activate(&self) {
+        // Announce ourselves before switching, so a concurrent page table change either includes
+        // this CPU in its flush or is visible to every page table walk after the switch.
+        self.active_cpus
+            .fetch_or(cpu_bit(CPUID.get()), Ordering::SeqCst);
+        mb();
+
+        let asid = self
+            .asid_context
+            .as_ref()
+            .map_or(DEFAULT_ASID, asid_allocator::activate);
+        let ppn = self.root_pgtable.get() >> 12_i32;
+
         // Safety: register access
         unsafe {
-            let ppn = self.root_pgtable.get() >> 12_i32;
-            satp::set(satp::Mode::Sv39, self.asid, ppn);
+            let switched = satp::read().ppn() != ppn;
+            satp::set(satp::Mode::Sv39, asid, ppn);
+
+            // Without ASIDs, translations of the previous address space would otherwise remain
+            // visible in the TLB.
+            if switched && !asid_allocator::has_asids() {
+                asid_allocator::local_flush_all();
+            }
         }
         mb();
     }
 
     fn new_flush(&self) -> Flush {
-        Flush::empty(self.asid)
+        Flush::empty(self.asid(), self.active_cpus.clone())
     }
 }
 
 impl AddressSpace {
+    /// The ASID this address space was last assigned.
+    ///
+    /// Address spaces that were never activated (or only in an older ASID generation) report a
+    /// stale ASID here, but their translations were flushed when the generation rolled over so
+    /// invalidating the stale ASID is harmless.
+    fn asid(&self) -> u16 {
+        self.asid_context.as_ref().map_or(DEFAULT_ASID, |context| {
+            asid_allocator::asid_of(context.load(Ordering::Relaxed))
+        })
+    }
+
     fn unmap_inner(
         &mut self,
+        frame_alloc: &FrameAllocator,
         pgtable: NonNull<PageTableEntry>,
         virt: &mut VirtualAddress,
         remaining_bytes: &mut usize,
@@ -474,14 +664,19 @@ impl AddressSpace {
         let pte = unsafe { pgtable.add(index).as_mut() };
         let page_size = page_size_for_level(lvl);
 
-        if pte.is_valid() && pte.is_leaf() {
+        if pte.is_valid() && pte.is_leaf() && !covers_page(*virt, *remaining_bytes, lvl) {
+            // We're only unmapping part of a large page, split it up into smaller pages and
+            // unmap those instead.
+            let pgtable = self.split_leaf(frame_alloc, pte, lvl)?;
+            self.unmap_inner(frame_alloc, pgtable, virt, remaining_bytes, lvl - 1, flush)?;
+        } else if pte.is_valid() && pte.is_leaf() {
             self.wired_frames
                 .retain(|wired| wired.addr() != pte.get_address_and_flags().0);
             // The PTE is mapped, so go ahead and clear it unmapping the frame
             pte.clear();
 
             flush.extend_range(
-                self.asid,
+                self.asid(),
                 Range::from(*virt..virt.checked_add(page_size).unwrap()),
             )?;
             *virt = virt.checked_add(page_size).unwrap();
@@ -489,7 +684,7 @@ impl AddressSpace {
         } else if pte.is_valid() {
             // This PTE is an internal node pointing to another page table
             let pgtable = self.pgtable_ptr_from_phys(pte.get_address_and_flags().0);
-            self.unmap_inner(pgtable, virt, remaining_bytes, lvl - 1, flush)?;
+            self.unmap_inner(frame_alloc, pgtable, virt, remaining_bytes, lvl - 1, flush)?;
 
             // The recursive descend above might have unmapped the last child of this PTE in which
             // case we need to unmap it as well
@@ -501,16 +696,85 @@ impl AddressSpace {
 
             if !is_still_populated {
                 let frame = pte.get_address_and_flags().0;
-                self.wired_frames.retain(|wired| wired.addr() != frame);
                 pte.clear();
+                self.retire_pgtable(frame, flush);
             }
         } else {
-            *remaining_bytes = remaining_bytes.saturating_sub(page_size);
+            // Nothing is mapped here, skip ahead to the next entry
+            let next = virt.align_down(page_size).checked_add(page_size).unwrap();
+            let skipped = next.checked_sub_addr(*virt).unwrap();
+            *virt = next;
+            *remaining_bytes = remaining_bytes.saturating_sub(skipped);
         }
 
         Ok(())
     }
 
+    /// Split the large page leaf `pte` at level `lvl` into a newly allocated sub-table of
+    /// next-level leaves that together map the same physical memory with the same flags.
+    ///
+    /// Returns a pointer to the new sub-table. Note that splitting doesn't change the translation
+    /// so no TLB flush is required, the caller will flush the entries it changes afterward.
+    fn split_leaf(
+        &mut self,
+        frame_alloc: &FrameAllocator,
+        pte: &mut PageTableEntry,
+        lvl: usize,
+    ) -> crate::Result<NonNull<PageTableEntry>> {
+        debug_assert!(pte.is_valid() && pte.is_leaf());
+        debug_assert!(lvl > 0, "cannot split a base page");
+
+        let (phys, flags) = pte.get_address_and_flags();
+        let sub_page_size = page_size_for_level(lvl - 1);
+
+        let frame = frame_alloc.alloc_one_zeroed()?;
+        let sub_pgtable = self.pgtable_ptr_from_phys(frame.addr());
+        for index in 0..PAGE_TABLE_ENTRIES {
+            // Safety: index is always within one page
+            let sub_pte = unsafe { sub_pgtable.add(index).as_mut() };
+            sub_pte
+                .replace_address_and_flags(phys.checked_add(index * sub_page_size).unwrap(), flags);
+        }
+
+        mb();
+
+        pte.replace_address_and_flags(frame.addr(), PTEFlags::VALID);
+        self.wired_frames.push(frame);
+
+        Ok(sub_pgtable)
+    }
+
+    /// Release the page table at `phys` (which is a level `lvl` table) and all tables below it.
+    ///
+    /// This does *not* free the physical memory mapped by any leaf entries, those are owned
+    /// by the VMOs that mapped them.
+    fn free_pgtable(&mut self, phys: PhysicalAddress, lvl: usize, flush: &mut Flush) {
+        if lvl > 0 {
+            let pgtable = self.pgtable_ptr_from_phys(phys);
+            for index in 0..PAGE_TABLE_ENTRIES {
+                // Safety: index is always within one page
+                let pte = unsafe { pgtable.add(index).as_ref() };
+                if pte.is_valid() && !pte.is_leaf() {
+                    self.free_pgtable(pte.get_address_and_flags().0, lvl - 1, flush);
+                }
+            }
+        }
+
+        self.retire_pgtable(phys, flush);
+    }
+
+    /// Hand the no longer referenced page table at `phys` over to `flush`, which frees it after
+    /// invalidating the address space on all CPUs that might still be walking it.
+    fn retire_pgtable(&mut self, phys: PhysicalAddress, flush: &mut Flush) {
+        if let Some(pos) = self
+            .wired_frames
+            .iter()
+            .position(|wired| wired.addr() == phys)
+        {
+            flush.retire_pgtable(self.wired_frames.swap_remove(pos));
+        }
+    }
+
     fn pgtable_ptr_from_phys(&self, phys: PhysicalAddress) -> NonNull<PageTableEntry> {
         NonNull::new(
             KERNEL_ASPACE_RANGE
@@ -630,3 +894,56 @@ impl From<crate::mem::Permissions> for PTEFlags {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::ArchAddressSpace;
+    use crate::mem::frame_alloc::FRAME_ALLOC;
+
+    #[ktest::test]
+    async fn unmap_skips_holes() {
+        let frame_alloc = FRAME_ALLOC.get().unwrap();
+        let (mut aspace, mut flush) = AddressSpace::new(frame_alloc).unwrap();
+
+        let base = USER_ASPACE_RANGE.start;
+        let large_page = page_size_for_level(1);
+        // a hole of a single page between the first two mappings and a hole spanning a whole
+        // level 1 entry before the last one
+        let offsets = [0, 2 * PAGE_SIZE, 2 * large_page];
+        let frames: Vec<_> = offsets
+            .iter()
+            .map(|_| frame_alloc.alloc_one().unwrap())
+            .collect();
+
+        for (offset, frame) in offsets.iter().zip(&frames) {
+            // Safety: the address space is fresh and never activated
+            unsafe {
+                aspace
+                    .map_contiguous(
+                        frame_alloc,
+                        base.checked_add(*offset).unwrap(),
+                        frame.addr(),
+                        NonZeroUsize::new(PAGE_SIZE).unwrap(),
+                        PTEFlags::READ | PTEFlags::WRITE,
+                        &mut flush,
+                    )
+                    .unwrap();
+            }
+        }
+
+        let len = NonZeroUsize::new(2 * large_page + PAGE_SIZE).unwrap();
+        // Safety: the address space is fresh and never activated
+        unsafe {
+            aspace.unmap(frame_alloc, base, len, &mut flush).unwrap();
+        }
+        flush.flush().unwrap();
+
+        for offset in offsets {
+            let virt = base.checked_add(offset).unwrap();
+            // Safety: we only read the page tables
+            let mapping = unsafe { aspace.query(virt) };
+            assert!(mapping.is_none(), "{virt:?} is still mapped");
+        }
+    }
+}
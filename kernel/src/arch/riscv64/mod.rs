@@ -9,22 +9,31 @@ mod asid_allocator;
 pub mod device;
 mod mem;
 mod setjmp_longjmp;
+mod suspend;
 mod trap_handler;
 
 use crate::device_tree::DeviceTree;
 use crate::mem::VirtualAddress;
-pub use asid_allocator::AsidAllocator;
+use crate::shutdown::Action;
 use core::arch::asm;
+use device::cpu::{RiscvExtensions, with_cpu};
 pub use mem::{
-    AddressSpace, CANONICAL_ADDRESS_MASK, DEFAULT_ASID, KERNEL_ASPACE_RANGE, PAGE_SHIFT, PAGE_SIZE,
-    USER_ASPACE_RANGE, invalidate_range, is_kernel_address,
+    AddressSpace, CANONICAL_ADDRESS_MASK, DEFAULT_ASID, HUGE_PAGE_SIZE, KERNEL_ASPACE_RANGE,
+    PAGE_SHIFT, PAGE_SIZE, USER_ASPACE_RANGE, invalidate_all, invalidate_range, is_kernel_address,
 };
+use riscv::sbi::hsm::HartState;
+use riscv::sbi::srst::{ResetReason, ResetType};
+use riscv::seed::Opst;
 use riscv::sstatus::FS;
-use riscv::{interrupt, scounteren, sie, sstatus};
+use riscv::{interrupt, sbi, scounteren, seed, sie, sstatus};
 pub use setjmp_longjmp::{JmpBuf, JmpBufStruct, call_with_setjmp, longjmp};
+pub use suspend::{cpu_offline, cpu_online, cpu_suspend};
 
 pub const STACK_ALIGNMENT: usize = 16;
 
+/// How often to poll the `Zkr` entropy source while it reports that no entropy is available yet.
+const SEED_RETRIES: usize = 1000;
+
 /// Global RISC-V specific initialization.
 #[cold]
 pub fn init_early() {
@@ -60,6 +69,7 @@ pub fn per_cpu_init_early() {
 #[cold]
 pub fn per_cpu_init_late(devtree: &DeviceTree) -> crate::Result<()> {
     device::cpu::init(devtree)?;
+    crate::irq::per_cpu_init();
 
     // Safety: register access
     unsafe {
@@ -137,6 +147,46 @@ pub fn rmb() {
     }
 }
 
+/// Fill `buf` with entropy from the CPU's hardware entropy source.
+///
+/// Returns the number of bytes written, which is `0` if the CPU has no entropy source (i.e. doesn't
+/// implement the `Zkr` extension) or the source failed.
+pub fn hardware_entropy(buf: &mut [u8]) -> usize {
+    if !with_cpu(|cpu| cpu.extensions.contains(RiscvExtensions::ZKR)) {
+        return 0;
+    }
+
+    let mut filled = 0;
+    let mut retries = 0;
+    while filled < buf.len() {
+        let seed = seed::read();
+        match seed.opst() {
+            Opst::Es16 => {
+                let bytes = seed.entropy().to_ne_bytes();
+                let len = bytes.len().min(buf.len() - filled);
+                buf[filled..filled + len].copy_from_slice(&bytes[..len]);
+                filled += len;
+            }
+            Opst::Bist | Opst::Wait if retries < SEED_RETRIES => {
+                retries += 1;
+                core::hint::spin_loop();
+            }
+            Opst::Bist | Opst::Wait => break,
+            Opst::Dead => {
+                tracing::warn!("hardware entropy source failed");
+                break;
+            }
+        }
+    }
+
+    filled
+}
+
+/// Returns a fast-moving hardware counter for timing measurements.
+pub fn timestamp() -> u64 {
+    riscv::register::time::read64()
+}
+
 /// Suspend the calling cpu indefinitely.
 ///
 /// # Safety
@@ -147,6 +197,48 @@ pub unsafe fn cpu_park() {
     unsafe { asm!("wfi") }
 }
 
+/// Stop the calling cpu for good, handing it back to the SBI implementation.
+pub fn cpu_stop() -> ! {
+    interrupt::disable();
+
+    if let Err(err) = sbi::hsm::hart_stop() {
+        tracing::warn!("failed to stop CPU through SBI, parking it instead: {err}");
+    }
+
+    loop {
+        // Safety: interrupts are disabled and this cpu has nothing left to do
+        unsafe { cpu_park() }
+    }
+}
+
+/// Returns whether the cpu `cpuid` has been stopped through [`cpu_stop`].
+pub fn is_cpu_stopped(cpuid: usize) -> bool {
+    matches!(sbi::hsm::hart_get_status(cpuid), Ok(HartState::Stopped))
+}
+
+/// Shut down or reboot the whole system.
+///
+/// On success this function doesn't return.
+///
+/// # Errors
+///
+/// Returns an error if the SBI implementation doesn't support the requested reset.
+pub fn system_reset(action: Action, failure: bool) -> crate::Result<()> {
+    let reset_type = match action {
+        Action::Shutdown => ResetType::Shutdown,
+        Action::ColdReboot => ResetType::ColdReboot,
+        Action::WarmReboot => ResetType::WarmReboot,
+    };
+    let reason = if failure {
+        ResetReason::SystemFailure
+    } else {
+        ResetReason::NoReason
+    };
+
+    sbi::srst::system_reset(reset_type, reason)?;
+    Ok(())
+}
+
 /// Send an interrupt to a parked cpu waking it up.
 ///
 /// # Safety
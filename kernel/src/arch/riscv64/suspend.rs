@@ -0,0 +1,270 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Stopping and suspending CPUs through the SBI Hart State Management extension.
+//!
+//! Both stopping a hart and suspending it non-retentively lose its register state. When the hart
+//! is started or resumed again it begins executing at a physical address with the MMU turned off,
+//! much like the secondary harts started by the loader. Before handing the hart to the firmware we
+//! therefore save the calling context with `setjmp` and record everything needed to get back to it
+//! in the hart's [`ResumeContext`]. The [`resume_trampoline`] turns the MMU back on, restores the
+//! thread pointer and `sstatus` and then `longjmp`s into the saved context, which makes
+//! [`cpu_offline`] and [`cpu_suspend`] return as if the hart never left. The remaining per-CPU
+//! state (trap vector, counters, interrupt enables, timer deadline) is restored from Rust
+//! afterwards.
+
+use super::{call_with_setjmp, longjmp, trap_handler};
+use crate::CPUID;
+use crate::arch::device::cpu::with_cpu;
+use crate::mem::{ArchAddressSpace, PhysicalAddress, VirtualAddress, with_kernel_aspace};
+use crate::scheduler::scheduler;
+use crate::time;
+use alloc::format;
+use anyhow::Context;
+use core::arch::{asm, naked_asm};
+use core::mem::offset_of;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::sbi::hsm::{self, SuspendType};
+use riscv::{interrupt, sbi, scounteren, sie, sstatus};
+
+const MAX_HARTS: usize = usize::BITS as usize;
+
+static RESUME_CONTEXTS: [ResumeContext; MAX_HARTS] = [const { ResumeContext::new() }; MAX_HARTS];
+
+/// Everything the [`resume_trampoline`] needs to get a hart back into the kernel.
+///
+/// The trampoline reads this with the MMU turned off, so a context must not straddle a page
+/// boundary, which the alignment guarantees.
+#[repr(C, align(64))]
+struct ResumeContext {
+    /// The virtual address of [`resume_entry`].
+    entry: AtomicUsize,
+    satp: AtomicUsize,
+    sstatus: AtomicUsize,
+    tp: AtomicUsize,
+    /// The address of the `JmpBufStruct` holding the saved context.
+    jmp_buf: AtomicUsize,
+}
+
+/// Take the calling CPU offline until another CPU brings it back through [`cpu_online`].
+///
+/// This returns once the CPU is back online.
+///
+/// # Errors
+///
+/// Returns an error if the firmware refused to stop the CPU.
+pub fn cpu_offline() -> crate::Result<()> {
+    suspend_with(|_, _| hsm::hart_stop())
+}
+
+/// Bring the CPU `cpuid`, which must have been stopped through [`cpu_offline`], back online.
+///
+/// # Errors
+///
+/// Returns an error if the firmware refused to start the CPU.
+pub fn cpu_online(cpuid: usize) -> crate::Result<()> {
+    let (trampoline, context) = resume_addresses(cpuid)?;
+    hsm::hart_start(cpuid, trampoline.get(), context.get())?;
+    Ok(())
+}
+
+/// Put the calling CPU into non-retentive suspend until an interrupt arrives.
+///
+/// Unlike [`cpu_park`](super::cpu_park) this lets the platform power down the CPU, at the cost
+/// of a much more expensive wakeup.
+///
+/// # Errors
+///
+/// Returns an error if the firmware doesn't support non-retentive suspend.
+pub fn cpu_suspend() -> crate::Result<()> {
+    suspend_with(|trampoline, context| {
+        hsm::hart_suspend(SuspendType::DefaultNonRetentive, trampoline, context)
+    })
+}
+
+/// Save the calling context and hand the hart to the firmware through `f`, which is called with
+/// the physical addresses of the [`resume_trampoline`] and the hart's [`ResumeContext`].
+fn suspend_with(f: impl FnOnce(usize, usize) -> Result<(), sbi::Error>) -> crate::Result<()> {
+    let cpuid = CPUID.get();
+    let (trampoline, context_phys) = resume_addresses(cpuid)?;
+    let context = &RESUME_CONTEXTS[cpuid];
+
+    let interrupts_enabled = sstatus::read().sie();
+    interrupt::disable();
+
+    let mut res = Ok(());
+    let resumed = call_with_setjmp(|jmp_buf| {
+        context.save(ptr::from_ref(jmp_buf).addr());
+        // If this succeeds the hart comes back through the trampoline, which `longjmp`s out of
+        // here instead of returning
+        res = f(trampoline.get(), context_phys.get());
+        0
+    }) != 0;
+
+    if resumed {
+        restore_cpu_state();
+    }
+
+    if interrupts_enabled {
+        // Safety: interrupts were enabled when we were called
+        unsafe { interrupt::enable() }
+    }
+
+    res?;
+    Ok(())
+}
+
+/// Restore the per-CPU state that doesn't survive stopping or suspending the hart.
+fn restore_cpu_state() {
+    trap_handler::init();
+
+    // Safety: register access
+    unsafe {
+        scounteren::set_cy();
+        scounteren::set_tm();
+        scounteren::set_ir();
+
+        sie::set_ssie();
+        sie::set_stie();
+        sie::set_seie();
+    }
+
+    with_cpu(|cpu| cpu.irq_ctl.borrow_mut().resume());
+
+    // `stimecmp` is lost along with the rest of the hart's state when using Sstc
+    time::arm_timer_interrupt(scheduler().cpu_local_timer().next_deadline());
+}
+
+/// Returns the physical addresses of the [`resume_trampoline`] and the [`ResumeContext`] of the
+/// CPU `cpuid`.
+fn resume_addresses(cpuid: usize) -> crate::Result<(PhysicalAddress, PhysicalAddress)> {
+    let context = RESUME_CONTEXTS
+        .get(cpuid)
+        .with_context(|| format!("invalid CPU id {cpuid}"))?;
+
+    Ok((
+        virt_to_phys(resume_trampoline as usize)?,
+        virt_to_phys(ptr::from_ref(context).addr())?,
+    ))
+}
+
+fn virt_to_phys(addr: usize) -> crate::Result<PhysicalAddress> {
+    let virt = VirtualAddress::new(addr).unwrap();
+
+    with_kernel_aspace(|aspace| {
+        // Safety: we only read the page tables
+        unsafe { aspace.lock().arch.query(virt) }
+    })
+    .map(|(phys, _)| phys)
+    .with_context(|| format!("{virt} is not mapped"))
+}
+
+impl ResumeContext {
+    const fn new() -> Self {
+        Self {
+            entry: AtomicUsize::new(0),
+            satp: AtomicUsize::new(0),
+            sstatus: AtomicUsize::new(0),
+            tp: AtomicUsize::new(0),
+            jmp_buf: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record the calling hart's state, must be called with interrupts disabled.
+    fn save(&self, jmp_buf: usize) {
+        let (satp, sstatus, tp): (usize, usize, usize);
+        // Safety: inline assembly
+        unsafe {
+            asm!(
+                "csrr {satp}, satp",
+                "csrr {sstatus}, sstatus",
+                "mv {tp}, tp",
+                satp = out(reg) satp,
+                sstatus = out(reg) sstatus,
+                tp = out(reg) tp,
+                options(nomem, nostack)
+            );
+        }
+
+        self.entry.store(resume_entry as usize, Ordering::Relaxed);
+        self.satp.store(satp, Ordering::Relaxed);
+        self.sstatus.store(sstatus, Ordering::Relaxed);
+        self.tp.store(tp, Ordering::Relaxed);
+        self.jmp_buf.store(jmp_buf, Ordering::Release);
+    }
+}
+
+/// Entry point of harts started through [`cpu_online`] or resumed from [`cpu_suspend`].
+///
+/// The firmware jumps here with the MMU turned off, `a0` holding the hart id and `a1` the physical
+/// address of the hart's [`ResumeContext`]. Once the MMU is turned on, the next instruction fetch
+/// from our physical address faults and traps into [`resume_entry`], which we installed as the trap
+/// vector beforehand. Should the fetch not fault, we jump there explicitly.
+#[naked]
+unsafe extern "C" fn resume_trampoline() -> ! {
+    // Safety: inline assembly
+    unsafe {
+        naked_asm! {
+            // FIXME this is a workaround for bug in rustc/llvm
+            //  https://github.com/rust-lang/rust/issues/80608#issuecomment-1094267279
+            ".attribute arch, \"rv64gc\"",
+
+            // Mask all interrupts in case the firmware left them on.
+            "csrc   sstatus, 1 << 1",
+            "csrw   sie, zero",
+
+            // Load everything we need while the context is still accessible through its physical
+            // address
+            "ld     t0, {entry}(a1)",
+            "ld     t1, {satp}(a1)",
+            "ld     t2, {sstatus}(a1)",
+            "ld     t3, {tp}(a1)",
+            "ld     t4, {jmp_buf}(a1)",
+
+            // Turn on the MMU
+            "csrw   stvec, t0",
+            "sfence.vma",
+            "csrw   satp, t1",
+            "sfence.vma",
+            "jr     t0",
+
+            entry = const offset_of!(ResumeContext, entry),
+            satp = const offset_of!(ResumeContext, satp),
+            sstatus = const offset_of!(ResumeContext, sstatus),
+            tp = const offset_of!(ResumeContext, tp),
+            jmp_buf = const offset_of!(ResumeContext, jmp_buf),
+        }
+    }
+}
+
+/// Continuation of the [`resume_trampoline`] at its virtual address.
+///
+/// Expects the saved `sstatus` in `t2`, the thread pointer in `t3` and the `JmpBufStruct` in `t4`.
+#[naked]
+unsafe extern "C" fn resume_entry() -> ! {
+    // Safety: inline assembly
+    unsafe {
+        naked_asm! {
+            // FIXME this is a workaround for bug in rustc/llvm
+            //  https://github.com/rust-lang/rust/issues/80608#issuecomment-1094267279
+            ".attribute arch, \"rv64gc\"",
+            ".align 4",
+
+            // Restoring `sstatus` first also turns the FPU back on, which `longjmp` needs to
+            // restore the floating point registers
+            "csrw   sstatus, t2",
+            "mv     tp, t3",
+
+            "mv     a0, t4",
+            "li     a1, 1",
+            "tail   {longjmp}",
+
+            longjmp = sym longjmp,
+        }
+    }
+}
@@ -10,7 +10,7 @@ use crate::arch::device::cpu::with_cpu;
 use crate::backtrace::Backtrace;
 use crate::mem::VirtualAddress;
 use crate::scheduler::scheduler;
-use crate::{TRAP_STACK_SIZE_PAGES, irq};
+use crate::{TRAP_STACK_SIZE_PAGES, irq, lockup};
 use alloc::boxed::Box;
 use core::arch::{asm, naked_asm};
 use core::cell::Cell;
@@ -18,7 +18,7 @@ use core::ops::DerefMut;
 use cpu_local::cpu_local;
 use riscv::scause::{Exception, Interrupt, Trap};
 use riscv::{load_fp, load_gp, save_fp, save_gp};
-use riscv::{sbi, scause, sepc, sip, sscratch, sstatus, stval, stvec};
+use riscv::{scause, sepc, sip, sscratch, sstatus, stval, stvec};
 
 cpu_local! {
     static IN_TRAP: Cell<bool> = Cell::new(false);
@@ -289,26 +289,40 @@ extern "C-unwind" fn default_trap_handler(
     'handler: {
         match cause {
             Trap::Interrupt(Interrupt::SupervisorSoft) => {
-                // Just a nop, software interrupts are only used as wakeup calls
+                // Software interrupts are used as wakeup calls, and by the lockup detector to
+                // request a backtrace of this CPU
                 // TODO this should be an specialized routine in the trap vector
 
                 // Safety: register access
                 unsafe {
                     sip::clear_ssoft();
                 }
+
+                if let Some(request) = lockup::take_backtrace_request() {
+                    tracing::error!("LOCKUP: backtrace of CPU {}", crate::CPUID.get());
+                    log_backtrace(frame, epc);
+
+                    if request.panic {
+                        unwind_interrupted(frame, epc, "CPU stalled");
+                    }
+                }
             }
             Trap::Interrupt(Interrupt::SupervisorTimer) => {
-                if let (_, Some(next_deadline)) = scheduler().cpu_local_timer().turn() {
-                    // Timer interrupts are always IPIs used for sleeping
-                    sbi::time::set_timer(next_deadline.ticks.0).unwrap();
-                } else {
-                    // Timer interrupts are always IPIs used for sleeping
-                    sbi::time::set_timer(u64::MAX).unwrap();
+                scheduler().cpu_local_timer().turn_and_arm();
+
+                if let Some(long_poll) = lockup::check() {
+                    log_backtrace(frame, epc);
+
+                    if long_poll.panic {
+                        unwind_interrupted(frame, epc, "task polled for too long");
+                    }
                 }
             }
             Trap::Interrupt(Interrupt::SupervisorExternal) => with_cpu(|cpu| {
-                let mut plic = cpu.plic.borrow_mut();
-                irq::trigger_irq(plic.deref_mut());
+                crate::entropy::add_interrupt_timing();
+
+                let mut irq_ctl = cpu.irq_ctl.borrow_mut();
+                irq::trigger_irq(irq_ctl.deref_mut());
             }),
             Trap::Exception(
                 Exception::LoadPageFault
@@ -375,6 +389,40 @@ fn handle_kernel_exception(
     unsafe { panic_unwind::begin_unwind(payload, regs, epc.checked_add(1).unwrap().get()) };
 }
 
+/// Unwind the interrupted code on behalf of the lockup detector.
+///
+/// If the interrupted code is a task stuck in `poll`, the scheduler will catch the panic and
+/// complete the task with an error just like any other panicking task.
+fn unwind_interrupted(frame: &TrapFrame, epc: VirtualAddress, reason: &'static str) -> ! {
+    let mut regs = unwind2::Registers {
+        gp: frame.gp,
+        fp: frame.fp,
+    };
+    regs.gp[2] = sscratch::read();
+
+    // FIXME it would be great to get rid of the allocation here :/
+    let payload = Box::new(reason);
+
+    IN_TRAP.set(false);
+
+    // begin a panic on the original stack
+    // Safety: we saved the register state at the beginning of the trap handler
+    unsafe { panic_unwind::begin_unwind(payload, regs, epc.checked_add(1).unwrap().get()) };
+}
+
+fn log_backtrace(frame: &TrapFrame, epc: VirtualAddress) {
+    let mut regs = unwind2::Registers {
+        gp: frame.gp,
+        fp: frame.fp,
+    };
+    regs.gp[2] = sscratch::read();
+
+    match Backtrace::<32>::from_registers(regs, epc.checked_add(1).unwrap()) {
+        Ok(backtrace) => tracing::error!("{backtrace}"),
+        Err(err) => tracing::error!("failed to capture backtrace: {err:?}"),
+    }
+}
+
 fn handle_recursive_fault(frame: &TrapFrame, epc: VirtualAddress) -> ! {
     let mut regs = unwind2::Registers {
         gp: frame.gp,
@@ -7,7 +7,11 @@
 
 use crate::backtrace::BacktraceStyle;
 use crate::device_tree::DeviceTree;
-use crate::tracing::Filter;
+use crate::hotplug;
+use crate::lockup;
+use crate::net;
+use crate::scheduler;
+use crate::tracing::{Filter, LogOutput};
 use core::str::FromStr;
 
 pub fn parse(devtree: &DeviceTree) -> crate::Result<Bootargs> {
@@ -22,7 +26,12 @@ pub fn parse(devtree: &DeviceTree) -> crate::Result<Bootargs> {
 #[derive(Default)]
 pub struct Bootargs {
     pub log: Filter,
+    pub log_output: LogOutput,
     pub backtrace: BacktraceStyle,
+    pub lockup: lockup::Config,
+    pub sched: scheduler::deterministic::Mode,
+    pub net: net::Config,
+    pub idle: hotplug::IdleMode,
 }
 
 impl FromStr for Bootargs {
@@ -30,7 +39,12 @@ impl FromStr for Bootargs {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut log = None;
+        let mut log_output = None;
         let mut backtrace = None;
+        let mut lockup = None;
+        let mut sched = None;
+        let mut net = None;
+        let mut idle = None;
 
         let parts = s.trim().split(';');
         for part in parts {
@@ -38,14 +52,39 @@ impl FromStr for Bootargs {
                 log = Some(Filter::from_str(current)?);
             }
 
+            if let Some(current) = part.strip_prefix("log_output=") {
+                log_output = Some(LogOutput::from_str(current)?);
+            }
+
             if let Some(current) = part.strip_prefix("backtrace=") {
                 backtrace = Some(BacktraceStyle::from_str(current)?);
             }
+
+            if let Some(current) = part.strip_prefix("lockup=") {
+                lockup = Some(lockup::Config::from_str(current)?);
+            }
+
+            if let Some(current) = part.strip_prefix("sched=") {
+                sched = Some(scheduler::deterministic::Mode::from_str(current)?);
+            }
+
+            if let Some(current) = part.strip_prefix("net=") {
+                net = Some(net::Config::from_str(current)?);
+            }
+
+            if let Some(current) = part.strip_prefix("idle=") {
+                idle = Some(hotplug::IdleMode::from_str(current)?);
+            }
         }
 
         Ok(Self {
             log: log.unwrap_or_default(),
+            log_output: log_output.unwrap_or_default(),
             backtrace: backtrace.unwrap_or_default(),
+            lockup: lockup.unwrap_or_default(),
+            sched: sched.unwrap_or_default(),
+            net: net.unwrap_or_default(),
+            idle: idle.unwrap_or_default(),
         })
     }
 }
@@ -114,6 +114,11 @@ impl<T: Send> CpuLocal<T> {
         self.get_inner(Cpu::new(cpuid))
     }
 
+    /// Returns the element for the cpu `cpuid`, if it exists.
+    pub fn get_for(&self, cpuid: usize) -> Option<&T> {
+        self.get_inner(Cpu::new(cpuid))
+    }
+
     /// Returns the element for the current cpu, or creates it if it doesn't
     /// exist.
     pub fn get_or<F>(&self, create: F) -> &T
@@ -124,6 +129,26 @@ impl<T: Send> CpuLocal<T> {
         unsafe { self.get_or_try(|| Ok::<T, ()>(create())).unwrap_unchecked() }
     }
 
+    /// Returns the element for the cpu `cpuid`, or creates it if it doesn't
+    /// exist.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the cpu `cpuid` doesn't access this `CpuLocal` at
+    /// the same time, e.g. because it is parked.
+    pub unsafe fn get_or_for<F>(&self, cpuid: usize, create: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        let cpu = Cpu::new(cpuid);
+
+        if let Some(val) = self.get_inner(cpu) {
+            return val;
+        }
+
+        self.insert(cpu, create())
+    }
+
     /// Returns the element for the current cpu, or creates it if it doesn't
     /// exist. If `create` fails, that error is returned and no element is
     /// added.
@@ -5,6 +5,8 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use alloc::string::String;
+use alloc::vec::Vec;
 use bumpalo::Bump;
 use core::ffi::CStr;
 use core::ptr::NonNull;
@@ -77,6 +79,10 @@ pub struct Property<'a> {
 unsafe impl Send for DeviceTree {}
 // Safety: `DeviceTree`s accessor methods allow non-mutable access.
 unsafe impl Sync for DeviceTree {}
+// Safety: `Device`s accessor methods allow non-mutable access.
+unsafe impl Send for Device<'_> {}
+// Safety: `Device`s accessor methods allow non-mutable access.
+unsafe impl Sync for Device<'_> {}
 
 impl fmt::Debug for DeviceTree {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -202,10 +208,16 @@ impl fmt::Debug for Device<'_> {
 }
 
 impl<'a> Device<'a> {
-    /// Returns `true` if this device is usable, i.e. its reported status property is "okay".
+    /// Returns `true` if this device is usable, i.e. its reported status property is "okay" or
+    /// missing.
+    ///
+    /// As mandated by the devicetree specification, a node without a `status` property is
+    /// available, and the legacy spelling "ok" is accepted as well. Nodes with any other status,
+    /// such as "disabled" or "fail", are skipped by the driver registry when probing, see
+    /// [`crate::driver`].
     pub fn is_available(&self) -> bool {
-        self.properties()
-            .any(|prop| prop.inner.name == "status" && prop.inner.raw == b"okay")
+        self.property("status")
+            .is_none_or(|prop| matches!(prop.as_str(), Ok("okay" | "ok")))
     }
 
     /// Matches the device `compatible` string against the given list of strings.
@@ -245,6 +257,30 @@ impl<'a> Device<'a> {
         self.properties().find(|prop| prop.inner.name == name)
     }
 
+    /// Returns the full path of this device, e.g. `/soc/serial@10000000`.
+    pub fn path(&self) -> String {
+        let mut components = Vec::new();
+        let mut node = self;
+        while let Some(parent) = node.parent() {
+            components.push(&node.name);
+            node = parent;
+        }
+
+        let mut path = String::new();
+        for name in components.iter().rev() {
+            path.push('/');
+            path.push_str(name.name);
+            if let Some(unit_address) = name.unit_address {
+                path.push('@');
+                path.push_str(unit_address);
+            }
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
     /// Returns the device with the given path starting from this device.
     pub fn find_by_path(&self, path: &str) -> Option<&Device> {
         let mut node = self;
@@ -444,6 +480,7 @@ impl<'a> Iterator for Properties<'a> {
 #[derive(Debug)]
 pub enum IrqSource {
     C1(u32),
+    C2(u32, u32),
     C3(u32, u32, u32),
 }
 
@@ -489,6 +526,7 @@ fn interrupt_address(
 ) -> Option<IrqSource> {
     match interrupt_cells {
         1 => Some(IrqSource::C1(iter.next()?)),
+        2 if let Ok([a, b]) = iter.next_chunk() => Some(IrqSource::C2(a, b)),
         3 if let Ok([a, b, c]) = iter.next_chunk() => Some(IrqSource::C3(a, b, c)),
         _ => None,
     }
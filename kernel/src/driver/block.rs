@@ -0,0 +1,42 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::future::Future;
+
+/// A device that stores data in fixed-size blocks, such as a disk.
+pub trait BlockDevice: Send + Sync {
+    /// The size of a single block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// The total number of blocks of the device.
+    fn num_blocks(&self) -> u64;
+
+    /// Whether the device rejects writes.
+    fn is_read_only(&self) -> bool;
+
+    /// Read consecutive blocks starting at `block` into `buf`.
+    ///
+    /// The length of `buf` must be a multiple of [`Self::block_size`].
+    fn read_blocks(
+        &self,
+        block: u64,
+        buf: &mut [u8],
+    ) -> impl Future<Output = crate::Result<()>> + Send;
+
+    /// Write `buf` to consecutive blocks starting at `block`.
+    ///
+    /// The length of `buf` must be a multiple of [`Self::block_size`]. The data is not guaranteed
+    /// to be persisted until a subsequent [`Self::flush`] completes.
+    fn write_blocks(
+        &self,
+        block: u64,
+        buf: &[u8],
+    ) -> impl Future<Output = crate::Result<()>> + Send;
+
+    /// Wait until all completed writes have been persisted.
+    fn flush(&self) -> impl Future<Output = crate::Result<()>> + Send;
+}
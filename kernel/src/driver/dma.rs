@@ -0,0 +1,99 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::arch;
+use crate::mem::frame_alloc::{FRAME_ALLOC, FrameList};
+use crate::mem::{PhysicalAddress, VirtualAddress};
+use core::alloc::Layout;
+use core::{fmt, slice};
+
+/// A physically contiguous, zero-initialized buffer that devices can access through DMA.
+///
+/// The buffer is allocated directly from the frame allocator and accessed through the physmap,
+/// so it is always page aligned and its size is rounded up to whole pages.
+pub struct DmaBuffer {
+    _frames: FrameList,
+    phys: PhysicalAddress,
+    virt: VirtualAddress,
+    len: usize,
+}
+
+// Safety: the buffer exclusively owns its frames
+unsafe impl Send for DmaBuffer {}
+// Safety: the buffer exclusively owns its frames and only hands out shared slices through `&self`
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    /// Allocate a new buffer of at least `len` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is not enough physically contiguous memory available.
+    pub fn new_zeroed(len: usize) -> crate::Result<Self> {
+        let layout = Layout::from_size_align(len.max(1), arch::PAGE_SIZE)?.pad_to_align();
+        let frames = FRAME_ALLOC.get().unwrap().alloc_contiguous_zeroed(layout)?;
+        let phys = frames.first().unwrap().addr();
+
+        Ok(Self {
+            _frames: frames,
+            phys,
+            virt: VirtualAddress::from_phys(phys).unwrap(),
+            len,
+        })
+    }
+
+    /// The physical address of the byte at `offset`, as seen by devices.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is out of bounds.
+    pub fn phys(&self, offset: usize) -> PhysicalAddress {
+        assert!(offset < self.len);
+        self.phys.checked_add(offset).unwrap()
+    }
+
+    /// Returns a raw pointer to the byte at `offset`.
+    ///
+    /// This is meant for memory that is shared with a device while it is in use, such as the
+    /// rings of a virtqueue, which must be accessed with volatile reads and writes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is out of bounds.
+    pub fn as_ptr(&self, offset: usize) -> *mut u8 {
+        assert!(offset < self.len);
+        self.virt.checked_add(offset).unwrap().as_mut_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // Safety: the buffer is valid for `len` bytes
+        unsafe { slice::from_raw_parts(self.virt.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // Safety: the buffer is valid for `len` bytes and we have exclusive access
+        unsafe { slice::from_raw_parts_mut(self.virt.as_mut_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl fmt::Debug for DmaBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DmaBuffer")
+            .field("phys", &self.phys)
+            .field("virt", &self.virt)
+            .field("len", &self.len)
+            .finish()
+    }
+}
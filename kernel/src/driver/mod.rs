@@ -0,0 +1,366 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Device-tree driven device drivers.
+//!
+//! Drivers are declared in their respective modules with the [`driver!`] macro, which places them
+//! in the `k23_drivers` linker section so no central list of drivers has to be maintained:
+//!
+//! ```rust,ignore
+//! use crate::driver;
+//! use crate::driver::{Driver, ProbeFuture};
+//! use crate::device_tree::Device;
+//!
+//! driver!(static MY_DEVICE = Driver::new("my-device", &["vendor,my-device"], probe));
+//!
+//! fn probe(dev: &'static Device<'static>) -> ProbeFuture {
+//!     Box::pin(async move {
+//!         let mmap = driver::map_reg(dev, 0, "my-device")?;
+//!         let irq = driver::irq(dev, 0)?;
+//!         Ok(Some(Arc::new(MyDevice::new(mmap, irq)) as _))
+//!     })
+//! }
+//! ```
+//!
+//! After the scheduler is up, [`init`] walks the device tree once and binds every available node
+//! to the first driver that declares a matching `compatible` string. Whether a node is available
+//! (see [`Device::is_available`]) is checked here at probe time, so `probe` functions are only
+//! ever called for enabled devices and don't need to check the `status` property themselves. The state returned from a
+//! driver's `probe` function is kept alongside the binding and can be retrieved by other parts of
+//! the kernel through [`find`] or [`wait_for`].
+//!
+//! Devices the kernel needs before the scheduler is running, such as the interrupt controller and
+//! the RTC, are still initialized directly during boot.
+
+pub mod block;
+pub mod dma;
+pub mod net;
+mod ns16550a;
+pub mod tty;
+pub mod virtio;
+
+use crate::arch;
+use crate::device_tree::{Device, DeviceTree, IrqSource, device_tree};
+use crate::irq::{Irq, TriggerMode};
+use crate::mem::{Mmap, PhysicalAddress, with_kernel_aspace};
+use crate::scheduler::Scheduler;
+use crate::sync::WaitQueue;
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::{Context, bail};
+use core::any::Any;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr::addr_of;
+use core::range::Range;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use fallible_iterator::FallibleIterator;
+use spin::{Once, RwLock};
+
+pub use ns16550a::Uart;
+
+/// The future returned by a driver's probe function, resolving to the driver-specific state of
+/// the bound device, or `None` if the driver doesn't want to bind to the device after all (e.g.
+/// because a generic transport node turned out to have no device behind it).
+pub type ProbeFuture = Pin<Box<dyn Future<Output = crate::Result<Option<DriverData>>> + Send>>;
+/// The driver-specific state of a bound device.
+pub type DriverData = Arc<dyn Any + Send + Sync>;
+
+static BOUND: RwLock<Vec<BoundDevice>> = RwLock::new(Vec::new());
+static BOUND_CHANGED: WaitQueue = WaitQueue::new();
+static PROBED: AtomicBool = AtomicBool::new(false);
+
+/// Declares a new driver.
+#[macro_export]
+macro_rules! driver {
+    (static $name:ident = $driver:expr) => {
+        #[used(linker)]
+        #[unsafe(link_section = "k23_drivers")]
+        static $name: $crate::driver::Driver = $driver;
+    };
+}
+
+/// A device driver, declared through the [`driver!`] macro.
+pub struct Driver {
+    /// The name of the driver, used in diagnostics.
+    pub name: &'static str,
+    /// The `compatible` strings of the device tree nodes this driver can bind to.
+    pub compatible: &'static [&'static str],
+    probe: fn(&'static Device<'static>) -> ProbeFuture,
+    remove: Option<fn(&BoundDevice)>,
+}
+
+/// A device tree node that has been bound to a driver.
+#[derive(Clone)]
+pub struct BoundDevice {
+    /// The device tree node of the device.
+    pub device: &'static Device<'static>,
+    pub driver: &'static Driver,
+    /// The state returned from the driver's probe function.
+    pub data: DriverData,
+}
+
+impl Driver {
+    pub const fn new(
+        name: &'static str,
+        compatible: &'static [&'static str],
+        probe: fn(&'static Device<'static>) -> ProbeFuture,
+    ) -> Self {
+        Self {
+            name,
+            compatible,
+            probe,
+            remove: None,
+        }
+    }
+
+    /// Sets the function that is called when a device is unbound from this driver, e.g. because
+    /// the system is shutting down.
+    pub const fn with_remove(self, remove: fn(&BoundDevice)) -> Self {
+        Self {
+            remove: Some(remove),
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Driver")
+            .field("name", &self.name)
+            .field("compatible", &self.compatible)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for BoundDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoundDevice")
+            .field("device", &self.device.path())
+            .field("driver", &self.driver.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for BoundDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.device.path(), self.driver.name)
+    }
+}
+
+/// Returns all drivers that are compiled into the kernel.
+pub fn drivers() -> &'static [Driver] {
+    #[used(linker)]
+    #[unsafe(link_section = "k23_drivers")]
+    static mut LINKME_PLEASE: [Driver; 0] = [];
+
+    unsafe extern "C" {
+        #[allow(improper_ctypes)]
+        static __start_k23_drivers: Driver;
+        #[allow(improper_ctypes)]
+        static __stop_k23_drivers: Driver;
+    }
+
+    let start = addr_of!(__start_k23_drivers);
+    let stop = addr_of!(__stop_k23_drivers);
+    let len = (stop.addr() - start.addr()) / size_of::<Driver>();
+
+    // Safety: the linker places all `Driver`s of the `k23_drivers` section between these symbols
+    unsafe { slice::from_raw_parts(start, len) }
+}
+
+/// Bind all available devices of the device tree to their drivers.
+///
+/// Probing happens in a background task, in device tree order. Use [`wait_for`] to wait for a
+/// particular device.
+pub fn init(devtree: &'static DeviceTree, sched: &'static Scheduler) {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        sched.build_task().name("driver-probe").spawn(async move {
+            probe_all(devtree).await;
+
+            PROBED.store(true, Ordering::Release);
+            BOUND_CHANGED.wake_all();
+        });
+    });
+}
+
+async fn probe_all(devtree: &'static DeviceTree) {
+    let drivers = drivers();
+    tracing::debug!("{} drivers registered", drivers.len());
+
+    // collect the matches upfront, the device tree iterators can't be held across await points
+    let matches: Vec<_> = devtree
+        .descendants()
+        .filter(|(_, dev)| dev.is_available())
+        .filter_map(|(_, dev)| {
+            let driver = drivers
+                .iter()
+                .find(|driver| dev.is_compatible(driver.compatible.iter().copied()))?;
+            Some((dev, driver))
+        })
+        .collect();
+
+    for (dev, driver) in matches {
+        match (driver.probe)(dev).await {
+            Ok(None) => {}
+            Ok(Some(data)) => {
+                tracing::info!("bound {} to driver {}", dev.path(), driver.name);
+
+                BOUND.write().push(BoundDevice {
+                    device: dev,
+                    driver,
+                    data,
+                });
+                BOUND_CHANGED.wake_all();
+            }
+            Err(err) => {
+                tracing::error!(
+                    "failed to probe {} with driver {}: {err}",
+                    dev.path(),
+                    driver.name
+                );
+            }
+        }
+    }
+}
+
+/// Returns a snapshot of all devices that are currently bound to a driver.
+pub fn bound_devices() -> Vec<BoundDevice> {
+    BOUND.read().clone()
+}
+
+/// Returns the driver state of the first bound device whose state is of type `T`.
+pub fn find<T: Any + Send + Sync>() -> Option<Arc<T>> {
+    BOUND
+        .read()
+        .iter()
+        .find_map(|bound| bound.data.clone().downcast::<T>().ok())
+}
+
+/// Waits until a device whose driver state is of type `T` is bound, see [`find`].
+///
+/// Returns `None` if probing finished without binding such a device.
+pub async fn wait_for<T: Any + Send + Sync>() -> Option<Arc<T>> {
+    BOUND_CHANGED
+        .wait_for_value(|| {
+            find::<T>()
+                .map(Some)
+                .or_else(|| PROBED.load(Ordering::Acquire).then_some(None))
+        })
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Waits until all devices of the device tree have been probed.
+pub async fn wait_for_probe() {
+    // the queue is never closed
+    let _ = BOUND_CHANGED
+        .wait_for(|| PROBED.load(Ordering::Acquire))
+        .await;
+}
+
+/// Unbind all devices from their drivers, in reverse order of binding.
+pub fn unbind_all() {
+    let bound = core::mem::take(&mut *BOUND.write());
+
+    for bound in bound.iter().rev() {
+        tracing::debug!("unbinding {bound}");
+        if let Some(remove) = bound.driver.remove {
+            remove(bound);
+        }
+    }
+}
+
+/// Map the `index`th region of the `reg` property of `dev` into the kernel address space.
+///
+/// # Errors
+///
+/// Returns an error if the device has no such region or the region couldn't be mapped.
+pub fn map_reg(dev: &Device<'_>, index: usize, name: &str) -> crate::Result<Mmap> {
+    let reg = dev
+        .regs()
+        .context("device has no `reg` property")?
+        .nth(index)?
+        .with_context(|| alloc::format!("device has no `reg` region {index}"))?;
+    let size = reg.size.context("`reg` region has no size")?;
+
+    with_kernel_aspace(|aspace| {
+        // FIXME: this is gross, we're using the PhysicalAddress as an alignment utility :/
+        let size = PhysicalAddress::new(size)
+            .checked_align_up(arch::PAGE_SIZE)
+            .unwrap()
+            .get();
+
+        let range_phys = {
+            let start = PhysicalAddress::new(reg.starting_address);
+            Range::from(start..start.checked_add(size).unwrap())
+        };
+
+        Mmap::new_phys(
+            aspace.clone(),
+            range_phys,
+            size,
+            arch::PAGE_SIZE,
+            Some(name.to_string()),
+        )
+    })
+}
+
+/// Resolve the `index`th entry of the `interrupts` property of `dev` into an [`Irq`] handle.
+///
+/// # Errors
+///
+/// Returns an error if the device has no such interrupt or the interrupt specifier is not
+/// supported.
+pub fn irq(dev: &Device<'_>, index: usize) -> crate::Result<Irq> {
+    let (_, source) = dev
+        .interrupts(device_tree())
+        .context("device has no `interrupts` property")?
+        .nth(index)
+        .with_context(|| alloc::format!("device has no interrupt {index}"))?;
+
+    match source {
+        IrqSource::C1(irq_num) => Ok(Irq::new(irq_num)),
+        // the second cell of two-cell specifiers (used by the APLIC) is the trigger type
+        IrqSource::C2(irq_num, flags) => {
+            let irq = Irq::new(irq_num);
+            if let Some(mode) = TriggerMode::from_devicetree_flags(flags) {
+                irq.set_trigger(mode)?;
+            }
+            Ok(irq)
+        }
+        IrqSource::C3(..) => bail!("three-cell interrupt specifiers are not supported"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ktest::test]
+    async fn registry() {
+        assert!(drivers().iter().any(|driver| driver.name == "ns16550a"));
+    }
+
+    #[ktest::test]
+    async fn binds_uart() {
+        let uart = wait_for::<Uart>().await;
+        assert!(uart.is_some());
+        assert!(
+            bound_devices()
+                .iter()
+                .any(|bound| bound.device.is_compatible(["ns16550a"]))
+        );
+    }
+}
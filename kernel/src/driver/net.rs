@@ -0,0 +1,33 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use alloc::vec::Vec;
+use core::future::Future;
+
+/// A device that sends and receives Ethernet frames, such as a network card.
+///
+/// Frames never include the trailing frame check sequence, computing and checking it is up to the
+/// device.
+pub trait NetDevice: Send + Sync {
+    /// The MAC address of the device.
+    fn mac_address(&self) -> [u8; 6];
+
+    /// The size of the largest frame the device can send or receive, including the Ethernet
+    /// header.
+    fn max_frame_size(&self) -> usize;
+
+    /// Take the next received frame, if there is one.
+    fn try_receive(&self) -> Option<Vec<u8>>;
+
+    /// Wait for the next received frame.
+    ///
+    /// Dropping the returned future before it completed never loses a frame.
+    fn receive(&self) -> impl Future<Output = crate::Result<Vec<u8>>> + Send;
+
+    /// Send a single frame.
+    fn transmit(&self, frame: &[u8]) -> impl Future<Output = crate::Result<()>> + Send;
+}
@@ -0,0 +1,266 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Driver for 16550 compatible UARTs.
+//!
+//! Both directions are buffered and interrupt driven: The `uart-irq` task moves received bytes
+//! from the receive FIFO into a ring buffer, and refills the transmit FIFO from the transmit ring
+//! buffer whenever the transmitter runs empty. Writers never spin on the line status register, so
+//! a slow terminal only stalls the tasks waiting for buffer space instead of the whole hart.
+
+use crate::device_tree::Device;
+use crate::driver;
+use crate::driver::{Driver, DriverData, ProbeFuture};
+use crate::irq::Irq;
+use crate::mem::Mmap;
+use crate::scheduler::scheduler;
+use crate::sync::WaitQueue;
+use crate::task::Priority;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use anyhow::Context;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use uart_16550::{InterruptFlags, SerialPort};
+
+driver!(static NS16550A = Driver::new("ns16550a", &["ns16550a"], probe));
+
+/// The number of bytes that can be queued for transmission.
+const TX_BUFFER_SIZE: usize = 16 * 1024;
+/// The number of received bytes that may wait for a reader, further bytes are dropped.
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// A 16550 compatible UART.
+pub struct Uart {
+    inner: Mutex<Inner>,
+    /// Woken when bytes are added to the receive buffer.
+    rx_ready: WaitQueue,
+    /// Woken when space becomes available in the transmit buffer.
+    tx_space: WaitQueue,
+    /// The number of bytes [`Uart::write_lossy`] had to drop because the transmit buffer was full.
+    dropped: AtomicUsize,
+    irq: Irq,
+    _mmap: Mmap,
+}
+
+struct Inner {
+    port: SerialPort,
+    tx: VecDeque<u8>,
+    rx: VecDeque<u8>,
+}
+
+fn probe(dev: &'static Device<'static>) -> ProbeFuture {
+    Box::pin(async move {
+        let clock_freq = dev
+            .property("clock-frequency")
+            .context("missing `clock-frequency` property")?
+            .as_u32()?;
+        let mmap = driver::map_reg(dev, 0, "UART-16550")?;
+        let irq = driver::irq(dev, 0)?;
+
+        // Safety: info comes from device tree
+        let port = unsafe { SerialPort::new(mmap.range().start.get(), clock_freq, 115200) };
+
+        let uart = Arc::new(Uart {
+            inner: Mutex::new(Inner {
+                port,
+                tx: VecDeque::with_capacity(TX_BUFFER_SIZE),
+                rx: VecDeque::with_capacity(RX_BUFFER_SIZE),
+            }),
+            rx_ready: WaitQueue::new(),
+            tx_space: WaitQueue::new(),
+            dropped: AtomicUsize::new(0),
+            irq,
+            _mmap: mmap,
+        });
+
+        let weak = Arc::downgrade(&uart);
+        scheduler()
+            .build_task()
+            .name("uart-irq")
+            .priority(Priority::Realtime)
+            .spawn(async move {
+                loop {
+                    let Some(uart) = weak.upgrade() else {
+                        break;
+                    };
+                    uart.handle_interrupt();
+                    drop(uart);
+
+                    if irq.next_event().await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+        crate::tracing::register_uart(uart.clone());
+
+        Ok(Some(uart as DriverData))
+    })
+}
+
+impl Uart {
+    fn handle_interrupt(&self) {
+        let mut inner = self.inner.lock();
+
+        // the receive FIFO has to be drained even if the buffer is full, otherwise the interrupt
+        // stays asserted
+        let mut received = false;
+        while let Some(byte) = inner.port.try_recv() {
+            if inner.rx.len() < RX_BUFFER_SIZE {
+                inner.rx.push_back(byte);
+                received = true;
+            }
+        }
+        let transmitted = inner.transmit();
+        drop(inner);
+
+        if received {
+            self.rx_ready.wake_all();
+        }
+        if transmitted {
+            self.tx_space.wake_all();
+        }
+    }
+
+    /// Read received bytes into `buf`, waiting until at least one byte is available.
+    ///
+    /// Returns the number of bytes read.
+    pub async fn read(&self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+
+        self.rx_ready
+            .wait_for_value(|| {
+                let mut inner = self.inner.lock();
+                let len = buf.len().min(inner.rx.len());
+                for (dst, src) in buf.iter_mut().zip(inner.rx.drain(..len)) {
+                    *dst = src;
+                }
+                (len > 0).then_some(len)
+            })
+            .await
+            // the queue is never closed
+            .unwrap_or(0)
+    }
+
+    /// Queue all of `buf` for transmission, waiting for space in the transmit buffer as necessary.
+    pub async fn write(&self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let Ok(len) = self
+                .tx_space
+                .wait_for_value(|| {
+                    let len = self.try_write(buf);
+                    (len > 0).then_some(len)
+                })
+                .await
+            else {
+                // the queue is never closed
+                return;
+            };
+            buf = &buf[len..];
+        }
+    }
+
+    /// Queue as much of `buf` for transmission as fits into the transmit buffer, without waiting.
+    ///
+    /// Returns the number of bytes queued.
+    pub fn try_write(&self, buf: &[u8]) -> usize {
+        self.inner.lock().queue(buf)
+    }
+
+    /// Queue `buf` for transmission without ever waiting, dropping whatever doesn't fit into the
+    /// transmit buffer.
+    ///
+    /// This is meant for log output, which may be produced while the calling hart already holds the
+    /// UART lock (e.g. from a trap handler), so output is dropped in that case too.
+    pub fn write_lossy(&self, buf: &[u8]) {
+        let queued = self
+            .inner
+            .try_lock()
+            .map_or(0, |mut inner| inner.queue(buf));
+
+        if queued < buf.len() {
+            self.dropped
+                .fetch_add(buf.len() - queued, Ordering::Relaxed);
+        }
+    }
+
+    /// Transmit all buffered output, spinning until the transmit FIFO has accepted it.
+    ///
+    /// This doesn't rely on the transmit interrupt, so it also works once interrupts are no longer
+    /// serviced, e.g. while the system shuts down.
+    pub fn flush_blocking(&self) {
+        let mut inner = self.inner.lock();
+        while !inner.tx.is_empty() {
+            if !inner.transmit() {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Like [`Self::flush_blocking`], but gives up right away if the UART is locked.
+    ///
+    /// This is meant for flushing from a panic, which might have happened while the calling hart
+    /// held the UART lock, so waiting for it could deadlock.
+    ///
+    /// Returns whether all buffered output was transmitted.
+    pub fn try_flush_blocking(&self) -> bool {
+        let Some(mut inner) = self.inner.try_lock() else {
+            return false;
+        };
+        while !inner.tx.is_empty() {
+            if !inner.transmit() {
+                core::hint::spin_loop();
+            }
+        }
+        true
+    }
+
+    /// The number of bytes [`Self::write_lossy`] had to drop so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Inner {
+    fn queue(&mut self, buf: &[u8]) -> usize {
+        let len = buf.len().min(TX_BUFFER_SIZE - self.tx.len());
+        self.tx.extend(&buf[..len]);
+        self.transmit();
+        len
+    }
+
+    /// Move buffered output into the transmit FIFO, keeping the transmit interrupt enabled for as
+    /// long as there is output left.
+    ///
+    /// Returns whether any output was moved.
+    fn transmit(&mut self) -> bool {
+        let len = self.port.fill_fifo(self.tx.as_slices().0);
+        self.tx.drain(..len);
+
+        let mut interrupts = InterruptFlags::RECEIVED;
+        interrupts.set(InterruptFlags::TRANSMIT_EMPTY, !self.tx.is_empty());
+        if self.port.interrupts() != interrupts {
+            self.port.set_interrupts(interrupts);
+        }
+
+        len > 0
+    }
+}
+
+impl fmt::Debug for Uart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Uart")
+            .field("irq", &self.irq)
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Interactive terminals on top of a [`Uart`].
+//!
+//! The [`LineDiscipline`] turns the raw bytes received from a terminal into lines of input: It
+//! echoes typed characters, handles backspace, recalls earlier lines with the up and down arrow
+//! keys, and abandons the current line on Ctrl-C.
+
+use crate::driver::Uart;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// The number of lines kept in the history.
+const HISTORY_LEN: usize = 32;
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const ESC: u8 = 0x1b;
+const DELETE: u8 = 0x7f;
+/// Moves the cursor back over the last character and erases it.
+const ERASE: &[u8] = b"\x08 \x08";
+
+/// The result of line editing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// A line was entered, without the line terminator.
+    Line(String),
+    /// The user pressed Ctrl-C, the line being edited was discarded.
+    Interrupt,
+}
+
+/// A terminal on top of a UART.
+#[derive(Debug)]
+pub struct Tty {
+    uart: Arc<Uart>,
+    discipline: LineDiscipline,
+    /// Received bytes that haven't been processed yet, e.g. when several lines were pasted at once.
+    pending: VecDeque<u8>,
+}
+
+impl Tty {
+    pub fn new(uart: Arc<Uart>) -> Self {
+        Self {
+            uart,
+            discipline: LineDiscipline::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Wait for the next line of input, echoing it back to the terminal as it is typed.
+    pub async fn read_line(&mut self) -> Input {
+        let mut echo = Vec::new();
+        loop {
+            while let Some(byte) = self.pending.pop_front() {
+                if let Some(input) = self.discipline.feed(byte, &mut echo) {
+                    self.uart.write(&echo).await;
+                    return input;
+                }
+            }
+            self.uart.write(&echo).await;
+            echo.clear();
+
+            let mut buf = [0; 64];
+            let len = self.uart.read(&mut buf).await;
+            self.pending.extend(&buf[..len]);
+        }
+    }
+}
+
+/// Line editing state of a terminal.
+#[derive(Debug, Default)]
+pub struct LineDiscipline {
+    line: String,
+    history: VecDeque<String>,
+    /// The history entry currently shown, `None` while editing a new line.
+    history_pos: Option<usize>,
+    /// The new line that was being edited before moving into the history.
+    saved: String,
+    escape: Escape,
+    /// Whether the previous byte was a carriage return, so a directly following line feed is part
+    /// of the same line terminator.
+    after_cr: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// An ESC was received.
+    Esc,
+    /// Inside a control sequence (`ESC [`), waiting for its final byte.
+    Csi,
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process a single received byte, appending whatever should be echoed to the terminal to
+    /// `echo`.
+    ///
+    /// Returns the input once a line has been completed or editing was interrupted.
+    pub fn feed(&mut self, byte: u8, echo: &mut Vec<u8>) -> Option<Input> {
+        let after_cr = core::mem::take(&mut self.after_cr);
+
+        match self.escape {
+            Escape::None => {}
+            Escape::Esc => {
+                self.escape = if byte == b'[' {
+                    Escape::Csi
+                } else {
+                    Escape::None
+                };
+                return None;
+            }
+            Escape::Csi => {
+                // parameter and intermediate bytes come before the final byte
+                if (0x40..=0x7e).contains(&byte) {
+                    self.escape = Escape::None;
+                    match byte {
+                        b'A' => self.history_prev(echo),
+                        b'B' => self.history_next(echo),
+                        _ => {}
+                    }
+                }
+                return None;
+            }
+        }
+
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                self.after_cr = byte == b'\r';
+                echo.extend_from_slice(b"\r\n");
+
+                let line = self.finish_line();
+                if !line.trim().is_empty() && self.history.back() != Some(&line) {
+                    if self.history.len() == HISTORY_LEN {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(line.clone());
+                }
+                Some(Input::Line(line))
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    echo.extend_from_slice(ERASE);
+                }
+                None
+            }
+            CTRL_C => {
+                echo.extend_from_slice(b"^C\r\n");
+                self.finish_line();
+                Some(Input::Interrupt)
+            }
+            ESC => {
+                self.escape = Escape::Esc;
+                None
+            }
+            b' '..=b'~' => {
+                self.line.push(char::from(byte));
+                echo.push(byte);
+                None
+            }
+            // ignore all other control characters and anything that isn't ASCII
+            _ => None,
+        }
+    }
+
+    fn finish_line(&mut self) -> String {
+        self.history_pos = None;
+        self.saved.clear();
+        core::mem::take(&mut self.line)
+    }
+
+    fn history_prev(&mut self, echo: &mut Vec<u8>) {
+        let pos = match self.history_pos {
+            None if self.history.is_empty() => return,
+            None => {
+                self.saved = core::mem::take(&mut self.line);
+                self.history.len() - 1
+            }
+            Some(0) => return,
+            Some(pos) => pos - 1,
+        };
+
+        self.history_pos = Some(pos);
+        self.replace_line(self.history[pos].clone(), echo);
+    }
+
+    fn history_next(&mut self, echo: &mut Vec<u8>) {
+        let Some(pos) = self.history_pos else {
+            return;
+        };
+
+        if pos + 1 < self.history.len() {
+            self.history_pos = Some(pos + 1);
+            self.replace_line(self.history[pos + 1].clone(), echo);
+        } else {
+            self.history_pos = None;
+            let saved = core::mem::take(&mut self.saved);
+            self.replace_line(saved, echo);
+        }
+    }
+
+    fn replace_line(&mut self, line: String, echo: &mut Vec<u8>) {
+        // erasing character by character works without knowing where the line starts on screen
+        for _ in 0..self.line.len() {
+            echo.extend_from_slice(ERASE);
+        }
+        echo.extend_from_slice(line.as_bytes());
+        self.line = line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_all(discipline: &mut LineDiscipline, input: &[u8]) -> (Vec<Input>, Vec<u8>) {
+        let mut echo = Vec::new();
+        let inputs = input
+            .iter()
+            .filter_map(|&byte| discipline.feed(byte, &mut echo))
+            .collect();
+        (inputs, echo)
+    }
+
+    fn line(s: &str) -> Input {
+        Input::Line(String::from(s))
+    }
+
+    #[ktest::test]
+    async fn echo_and_backspace() {
+        let mut discipline = LineDiscipline::new();
+
+        let (inputs, echo) = feed_all(&mut discipline, b"lsx\x7f -l\r\n");
+        assert_eq!(inputs, [line("ls -l")]);
+        assert_eq!(echo, b"lsx\x08 \x08 -l\r\n");
+
+        // backspace on an empty line doesn't erase the prompt
+        let (inputs, echo) = feed_all(&mut discipline, b"\x08\x7f\n");
+        assert_eq!(inputs, [line("")]);
+        assert_eq!(echo, b"\r\n");
+    }
+
+    #[ktest::test]
+    async fn interrupt() {
+        let mut discipline = LineDiscipline::new();
+
+        let (inputs, echo) = feed_all(&mut discipline, b"panic\x03help\r");
+        assert_eq!(inputs, [Input::Interrupt, line("help")]);
+        assert_eq!(echo, b"panic^C\r\nhelp\r\n");
+    }
+
+    #[ktest::test]
+    async fn history() {
+        let mut discipline = LineDiscipline::new();
+        feed_all(&mut discipline, b"one\rtwo\rtwo\r");
+
+        // duplicate lines are only recorded once
+        let (inputs, _) = feed_all(&mut discipline, b"\x1b[A\x1b[A\r");
+        assert_eq!(inputs, [line("one")]);
+
+        // moving past the newest entry restores the line that was being edited
+        let (inputs, echo) = feed_all(&mut discipline, b"x\x1b[A\x1b[B\r");
+        assert_eq!(inputs, [line("x")]);
+        assert_eq!(echo, b"x\x08 \x08one\x08 \x08\x08 \x08\x08 \x08x\r\n");
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! VirtIO block devices, see section 5.2 of the VirtIO specification.
+
+use crate::driver::block::BlockDevice;
+use crate::driver::dma::DmaBuffer;
+use crate::driver::virtio::{Request, Transport, VirtQueue, spawn_irq_handler};
+use crate::irq::Irq;
+use crate::mem::{KIB, MIB};
+use alloc::string::String;
+use alloc::sync::Arc;
+use anyhow::{bail, ensure};
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+/// The capacity and all requests are always expressed in 512-byte sectors, regardless of the
+/// device's actual block size.
+const SECTOR_SIZE: usize = 512;
+const HEADER_SIZE: usize = 16;
+const QUEUE_SIZE: u16 = 128;
+/// The largest transfer issued as a single request, larger transfers are split up.
+const MAX_TRANSFER: usize = 64 * KIB;
+/// The maximum length of the device's serial number.
+const ID_SIZE: usize = 20;
+
+/// A VirtIO block device.
+#[derive(Debug)]
+pub struct VirtioBlk {
+    transport: Transport,
+    queue: VirtQueue,
+    /// The capacity of the device in sectors.
+    capacity: u64,
+    features: u64,
+}
+
+enum Data<'a> {
+    None,
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl VirtioBlk {
+    pub(super) fn new(transport: Transport, irq: Irq) -> crate::Result<Arc<Self>> {
+        let features = transport.negotiate_features(VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH)?;
+        let queue = transport.setup_queue(0, QUEUE_SIZE)?;
+        let capacity = transport.config_u64(0);
+        transport.driver_ok();
+
+        let this = Arc::new(Self {
+            transport,
+            queue,
+            capacity,
+            features,
+        });
+        tracing::info!(
+            "virtio-blk: {capacity} sectors ({} MiB){}",
+            capacity / u64::try_from(MIB / SECTOR_SIZE).unwrap(),
+            if this.is_read_only() {
+                ", read-only"
+            } else {
+                ""
+            }
+        );
+
+        spawn_irq_handler("virtio-blk-irq", irq, Arc::downgrade(&this), |this| {
+            this.transport.ack_interrupt();
+            this.queue.process_used();
+        });
+
+        Ok(this)
+    }
+
+    /// Returns the serial number of the device, which is empty if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device doesn't support querying its serial number.
+    pub async fn serial(&self) -> crate::Result<String> {
+        let mut id = [0; ID_SIZE];
+        self.request(VIRTIO_BLK_T_GET_ID, 0, Data::Read(&mut id))
+            .await?;
+
+        // the serial number is only NUL terminated if it is shorter than the maximum length
+        let len = id.iter().position(|b| *b == 0).unwrap_or(ID_SIZE);
+        Ok(String::from_utf8_lossy(&id[..len]).into_owned())
+    }
+
+    fn check_range(&self, block: u64, len: usize) -> crate::Result<()> {
+        ensure!(
+            len % SECTOR_SIZE == 0,
+            "buffer length {len} is not a multiple of the block size"
+        );
+        let blocks = u64::try_from(len / SECTOR_SIZE).unwrap();
+        ensure!(
+            block
+                .checked_add(blocks)
+                .is_some_and(|end| end <= self.capacity),
+            "blocks {block}..{} are out of range",
+            block.saturating_add(blocks)
+        );
+        Ok(())
+    }
+
+    async fn request(&self, kind: u32, sector: u64, data: Data<'_>) -> crate::Result<()> {
+        let data_len = match &data {
+            Data::None => 0,
+            Data::Read(buf) => buf.len(),
+            Data::Write(buf) => buf.len(),
+        };
+        let status = HEADER_SIZE + data_len;
+
+        let mut buf = DmaBuffer::new_zeroed(status + 1)?;
+        let bytes = buf.as_mut_slice();
+        bytes[0..4].copy_from_slice(&kind.to_le_bytes());
+        bytes[8..16].copy_from_slice(&sector.to_le_bytes());
+        if let Data::Write(src) = &data {
+            bytes[HEADER_SIZE..status].copy_from_slice(src);
+        }
+        // the device always writes the status, make sure we notice if it doesn't
+        bytes[status] = u8::MAX;
+
+        let request = match &data {
+            Data::None => Request::new(buf).readable(0, HEADER_SIZE),
+            Data::Read(_) => Request::new(buf)
+                .readable(0, HEADER_SIZE)
+                .writable(HEADER_SIZE, data_len),
+            Data::Write(_) => Request::new(buf)
+                .readable(0, HEADER_SIZE)
+                .readable(HEADER_SIZE, data_len),
+        }
+        .writable(status, 1);
+
+        let (request, _) = self.queue.submit(&self.transport, request).await?;
+        let bytes = request.buf().as_slice();
+
+        match bytes[status] {
+            VIRTIO_BLK_S_OK => {}
+            VIRTIO_BLK_S_IOERR => bail!("I/O error accessing sector {sector}"),
+            VIRTIO_BLK_S_UNSUPP => bail!("unsupported request type {kind}"),
+            other => bail!("unknown request status {other}"),
+        }
+
+        if let Data::Read(dst) = data {
+            dst.copy_from_slice(&bytes[HEADER_SIZE..status]);
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.capacity
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.features & VIRTIO_BLK_F_RO != 0
+    }
+
+    async fn read_blocks(&self, block: u64, buf: &mut [u8]) -> crate::Result<()> {
+        self.check_range(block, buf.len())?;
+
+        let mut sector = block;
+        for chunk in buf.chunks_mut(MAX_TRANSFER) {
+            let len = chunk.len();
+            self.request(VIRTIO_BLK_T_IN, sector, Data::Read(chunk))
+                .await?;
+            sector += u64::try_from(len / SECTOR_SIZE).unwrap();
+        }
+
+        Ok(())
+    }
+
+    async fn write_blocks(&self, block: u64, buf: &[u8]) -> crate::Result<()> {
+        ensure!(!self.is_read_only(), "device is read-only");
+        self.check_range(block, buf.len())?;
+
+        let mut sector = block;
+        for chunk in buf.chunks(MAX_TRANSFER) {
+            self.request(VIRTIO_BLK_T_OUT, sector, Data::Write(chunk))
+                .await?;
+            sector += u64::try_from(chunk.len() / SECTOR_SIZE).unwrap();
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> crate::Result<()> {
+        // without a write cache every completed write is already persisted
+        if self.features & VIRTIO_BLK_F_FLUSH == 0 {
+            return Ok(());
+        }
+
+        self.request(VIRTIO_BLK_T_FLUSH, 0, Data::None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// The serial number of the scratch disk the test runner attaches.
+    const SCRATCH_SERIAL: &str = "k23-scratch";
+
+    #[ktest::test]
+    async fn read_write() {
+        let blk = driver::wait_for::<VirtioBlk>()
+            .await
+            .expect("the test runner attaches a virtio-blk device");
+
+        // never write to any disk other than the scratch disk
+        assert_eq!(blk.serial().await.unwrap(), SCRATCH_SERIAL);
+        assert!(!blk.is_read_only());
+
+        let last = blk.num_blocks() - 1;
+        let pattern: Vec<u8> = (0..SECTOR_SIZE)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect();
+        blk.write_blocks(last, &pattern).await.unwrap();
+        blk.flush().await.unwrap();
+
+        let mut readback = vec![0; SECTOR_SIZE];
+        blk.read_blocks(last, &mut readback).await.unwrap();
+        assert_eq!(readback, pattern);
+    }
+
+    #[ktest::test]
+    async fn out_of_range() {
+        let blk = driver::wait_for::<VirtioBlk>()
+            .await
+            .expect("the test runner attaches a virtio-blk device");
+
+        let mut buf = vec![0; SECTOR_SIZE];
+        assert!(blk.read_blocks(blk.num_blocks(), &mut buf).await.is_err());
+        assert!(blk.read_blocks(0, &mut buf[..100]).await.is_err());
+    }
+}
@@ -0,0 +1,334 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! VirtIO devices over the MMIO transport.
+//!
+//! QEMU's `virt` machine exposes a number of `virtio,mmio` slots in the device tree, most of which
+//! are empty unless a device is attached to them (e.g. with `-device virtio-blk-device`). The
+//! `virtio-mmio` driver binds to every slot, reads the device type and hands the slot off to the
+//! driver for that device type.
+//!
+//! Both the legacy (version 1) and the modern (version 2) register layout are supported, QEMU uses
+//! the legacy layout unless `-global virtio-mmio.force-legacy=false` is given.
+//!
+//! See the [VirtIO specification](https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html).
+
+mod blk;
+mod net;
+mod queue;
+mod rng;
+
+use crate::device_tree::Device;
+use crate::driver::{Driver, DriverData, ProbeFuture};
+use crate::irq::Irq;
+use crate::mem::{Mmap, PhysicalAddress, VirtualAddress};
+use crate::scheduler::scheduler;
+use crate::task::Priority;
+use crate::{arch, driver};
+use alloc::boxed::Box;
+use alloc::sync::Weak;
+use anyhow::{bail, ensure};
+use core::fmt;
+
+pub use blk::VirtioBlk;
+pub use net::VirtioNet;
+pub use queue::{Request, VirtQueue};
+pub use rng::VirtioRng;
+
+driver!(static VIRTIO_MMIO = Driver::new("virtio-mmio", &["virtio,mmio"], probe));
+
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const CONFIG_GENERATION: usize = 0x0fc;
+const CONFIG: usize = 0x100;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// The device conforms to the VirtIO 1.0+ specification, required by the modern transport.
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const DEVICE_TYPE_NET: u32 = 1;
+const DEVICE_TYPE_BLOCK: u32 = 2;
+const DEVICE_TYPE_ENTROPY: u32 = 4;
+
+fn probe(dev: &'static Device<'static>) -> ProbeFuture {
+    Box::pin(async move {
+        let transport = Transport::new(driver::map_reg(dev, 0, "virtio-mmio")?)?;
+        let irq = driver::irq(dev, 0)?;
+
+        match transport.device_id() {
+            // an empty slot
+            0 => Ok(None),
+            DEVICE_TYPE_NET => Ok(Some(VirtioNet::new(transport, irq)? as DriverData)),
+            DEVICE_TYPE_BLOCK => Ok(Some(VirtioBlk::new(transport, irq)? as DriverData)),
+            DEVICE_TYPE_ENTROPY => Ok(Some(VirtioRng::new(transport, irq)? as DriverData)),
+            device_id => {
+                tracing::debug!(
+                    "no driver for virtio device type {device_id} at {}",
+                    dev.path()
+                );
+                Ok(None)
+            }
+        }
+    })
+}
+
+/// Spawn a task that calls `handle` for every interrupt of `irq`, until the device is dropped.
+fn spawn_irq_handler<D>(name: &str, irq: Irq, device: Weak<D>, handle: fn(&D))
+where
+    D: Send + Sync + 'static,
+{
+    scheduler()
+        .build_task()
+        .name(name)
+        .priority(Priority::Realtime)
+        .spawn(async move {
+            loop {
+                let Some(device) = device.upgrade() else {
+                    break;
+                };
+                handle(&device);
+                drop(device);
+
+                if irq.next_event().await.is_err() {
+                    break;
+                }
+            }
+        });
+}
+
+/// The registers of a VirtIO MMIO device.
+pub struct Transport {
+    mmap: Mmap,
+    version: u32,
+}
+
+impl Transport {
+    fn new(mmap: Mmap) -> crate::Result<Self> {
+        let mut this = Self { mmap, version: 0 };
+
+        ensure!(
+            this.read(MAGIC_VALUE) == MAGIC,
+            "invalid virtio-mmio magic value"
+        );
+        this.version = this.read(VERSION);
+        ensure!(
+            matches!(this.version, 1 | 2),
+            "unsupported virtio-mmio version {}",
+            this.version
+        );
+
+        Ok(this)
+    }
+
+    /// The type of the device behind this transport, `0` if there is none.
+    pub fn device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    /// Reset the device and negotiate the features the driver supports.
+    ///
+    /// Returns the negotiated features, i.e. the subset of `supported` the device offers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device doesn't accept the negotiated features.
+    pub fn negotiate_features(&self, supported: u64) -> crate::Result<u64> {
+        self.write(STATUS, 0);
+        self.write(STATUS, STATUS_ACKNOWLEDGE);
+        self.write(STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let mut device_features = u64::from(self.read(DEVICE_FEATURES));
+        if !self.is_legacy() {
+            self.write(DEVICE_FEATURES_SEL, 1);
+            device_features |= u64::from(self.read(DEVICE_FEATURES)) << 32_i32;
+        }
+
+        let supported = if self.is_legacy() {
+            supported
+        } else {
+            supported | VIRTIO_F_VERSION_1
+        };
+        let features = device_features & supported;
+
+        self.write(DRIVER_FEATURES_SEL, 0);
+        #[expect(clippy::cast_possible_truncation, reason = "we want the low half")]
+        self.write(DRIVER_FEATURES, features as u32);
+        if !self.is_legacy() {
+            self.write(DRIVER_FEATURES_SEL, 1);
+            self.write(DRIVER_FEATURES, u32::try_from(features >> 32_i32).unwrap());
+
+            self.write(
+                STATUS,
+                STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK,
+            );
+            if self.read(STATUS) & STATUS_FEATURES_OK == 0 {
+                self.write(STATUS, STATUS_FAILED);
+                bail!("device rejected features {features:#x}");
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Set up the queue with the given index with at most `max_size` descriptors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device has no such queue or the queue memory couldn't be allocated.
+    pub fn setup_queue(&self, index: u16, max_size: u16) -> crate::Result<VirtQueue> {
+        self.write(QUEUE_SEL, u32::from(index));
+
+        let in_use = if self.is_legacy() {
+            self.read(QUEUE_PFN) != 0
+        } else {
+            self.read(QUEUE_READY) != 0
+        };
+        ensure!(!in_use, "queue {index} is already in use");
+
+        let max = self.read(QUEUE_NUM_MAX);
+        ensure!(max != 0, "queue {index} is not available");
+        // split virtqueues must have a power-of-two size
+        let size: u32 = 1 << u32::from(max_size).min(max).ilog2();
+        let queue = VirtQueue::new(index, u16::try_from(size).unwrap())?;
+
+        self.write(QUEUE_NUM, size);
+        if self.is_legacy() {
+            let page_size = u32::try_from(arch::PAGE_SIZE).unwrap();
+            self.write(GUEST_PAGE_SIZE, page_size);
+            self.write(QUEUE_ALIGN, page_size);
+            self.write(
+                QUEUE_PFN,
+                u32::try_from(queue.desc_phys().get() / arch::PAGE_SIZE)?,
+            );
+        } else {
+            self.write_phys(QUEUE_DESC_LOW, queue.desc_phys());
+            self.write_phys(QUEUE_DRIVER_LOW, queue.avail_phys());
+            self.write_phys(QUEUE_DEVICE_LOW, queue.used_phys());
+            self.write(QUEUE_READY, 1);
+        }
+
+        Ok(queue)
+    }
+
+    /// Tell the device that the driver is fully set up.
+    pub fn driver_ok(&self) {
+        let status = self.read(STATUS);
+        self.write(STATUS, status | STATUS_DRIVER_OK);
+    }
+
+    /// Reset the device, afterward it won't access any queue memory anymore.
+    pub fn reset(&self) {
+        self.write(STATUS, 0);
+    }
+
+    /// Notify the device that new buffers are available in the given queue.
+    pub fn notify(&self, queue: u16) {
+        // make sure the device sees the updated rings before the notification
+        arch::mb();
+        self.write(QUEUE_NOTIFY, u32::from(queue));
+    }
+
+    /// Acknowledge all pending interrupts of the device, returning the interrupt status.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+        status
+    }
+
+    /// Read a field of the device-specific configuration space.
+    pub fn config_u8(&self, offset: usize) -> u8 {
+        let ptr = self.register(CONFIG + offset).as_ptr();
+        // Safety: the register is within the mapped MMIO region of the device
+        unsafe { ptr.read_volatile() }
+    }
+
+    /// Read a field of the device-specific configuration space.
+    pub fn config_u32(&self, offset: usize) -> u32 {
+        self.read(CONFIG + offset)
+    }
+
+    /// Read a field of the device-specific configuration space.
+    ///
+    /// The field is read in two halves, which is retried until the device reports a consistent
+    /// configuration.
+    pub fn config_u64(&self, offset: usize) -> u64 {
+        loop {
+            let generation = (!self.is_legacy()).then(|| self.read(CONFIG_GENERATION));
+            let low = self.read(CONFIG + offset);
+            let high = self.read(CONFIG + offset + 4);
+
+            if generation.is_none_or(|generation| generation == self.read(CONFIG_GENERATION)) {
+                return (u64::from(high) << 32_i32) | u64::from(low);
+            }
+        }
+    }
+
+    fn write_phys(&self, offset: usize, addr: PhysicalAddress) {
+        let addr = u64::try_from(addr.get()).unwrap();
+        #[expect(clippy::cast_possible_truncation, reason = "we want the low half")]
+        self.write(offset, addr as u32);
+        self.write(offset + 4, u32::try_from(addr >> 32_i32).unwrap());
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        #[expect(clippy::cast_ptr_alignment, reason = "MMIO regions are page aligned")]
+        let ptr = self.register(offset).as_ptr().cast::<u32>();
+        // Safety: the register is within the mapped MMIO region of the device
+        unsafe { ptr.read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        #[expect(clippy::cast_ptr_alignment, reason = "MMIO regions are page aligned")]
+        let ptr = self.register(offset).as_mut_ptr().cast::<u32>();
+        // Safety: the register is within the mapped MMIO region of the device
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn register(&self, offset: usize) -> VirtualAddress {
+        self.mmap.range().start.checked_add(offset).unwrap()
+    }
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transport")
+            .field("version", &self.version)
+            .field("device_id", &self.device_id())
+            .finish_non_exhaustive()
+    }
+}
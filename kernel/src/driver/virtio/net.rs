@@ -0,0 +1,218 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! VirtIO network devices, see section 5.1 of the VirtIO specification.
+
+use crate::driver::dma::DmaBuffer;
+use crate::driver::net::NetDevice;
+use crate::driver::virtio::{Request, Transport, VIRTIO_F_VERSION_1, VirtQueue, spawn_irq_handler};
+use crate::irq::Irq;
+use crate::net;
+use crate::scheduler::scheduler;
+use crate::sync::WaitQueue;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::{ensure, format_err};
+use core::fmt;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use spin::Mutex;
+
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 64;
+
+/// The size of `virtio_net_hdr` for legacy devices that don't use mergeable receive buffers.
+const LEGACY_HEADER_SIZE: usize = 10;
+/// The size of `virtio_net_hdr` for modern devices, which always includes `num_buffers`.
+const HEADER_SIZE: usize = 12;
+/// The largest Ethernet frame without FCS. We don't negotiate any offloads, so the device never
+/// hands us larger frames.
+const MAX_FRAME_SIZE: usize = 1514;
+/// The number of receive buffers that are kept available to the device.
+const RX_BUFFERS: usize = 32;
+/// The number of received frames that may wait for the network stack, further frames are dropped.
+const RX_BACKLOG: usize = 64;
+
+/// A VirtIO network device.
+pub struct VirtioNet {
+    transport: Transport,
+    rx_queue: VirtQueue,
+    tx_queue: VirtQueue,
+    mac: [u8; 6],
+    header_size: usize,
+    /// Received frames that haven't been picked up yet.
+    rx: Mutex<VecDeque<Vec<u8>>>,
+    /// Woken when a frame is added to `rx`.
+    rx_ready: WaitQueue,
+}
+
+impl VirtioNet {
+    pub(super) fn new(transport: Transport, irq: Irq) -> crate::Result<Arc<Self>> {
+        let features = transport.negotiate_features(VIRTIO_NET_F_MAC)?;
+        ensure!(
+            features & VIRTIO_NET_F_MAC != 0,
+            "device doesn't provide a MAC address"
+        );
+        let rx_queue = transport.setup_queue(RX_QUEUE, QUEUE_SIZE)?;
+        let tx_queue = transport.setup_queue(TX_QUEUE, QUEUE_SIZE)?;
+        let mac = core::array::from_fn(|i| transport.config_u8(i));
+        transport.driver_ok();
+
+        let this = Arc::new(Self {
+            transport,
+            rx_queue,
+            tx_queue,
+            mac,
+            header_size: if features & VIRTIO_F_VERSION_1 != 0 {
+                HEADER_SIZE
+            } else {
+                LEGACY_HEADER_SIZE
+            },
+            rx: Mutex::new(VecDeque::new()),
+            rx_ready: WaitQueue::new(),
+        });
+        tracing::info!("virtio-net: MAC address {}", MacAddress(mac));
+
+        spawn_irq_handler("virtio-net-irq", irq, Arc::downgrade(&this), |this| {
+            this.transport.ack_interrupt();
+            this.rx_queue.process_used();
+            this.tx_queue.process_used();
+        });
+        // the receive buffers have to stay with the device for as long as it is running, so this
+        // task keeps the device alive
+        scheduler()
+            .build_task()
+            .name("virtio-net-rx")
+            .spawn(this.clone().receive_loop());
+
+        net::register(this.clone());
+
+        Ok(this)
+    }
+
+    /// Keep [`RX_BUFFERS`] receive buffers available to the device and collect the frames it
+    /// writes into them.
+    async fn receive_loop(self: Arc<Self>) {
+        let mut in_flight = FuturesUnordered::new();
+        for _ in 0..RX_BUFFERS {
+            match DmaBuffer::new_zeroed(self.header_size + MAX_FRAME_SIZE) {
+                Ok(buf) => in_flight.push(self.receive_into(buf)),
+                Err(err) => tracing::warn!("failed to allocate virtio-net receive buffer: {err}"),
+            }
+        }
+
+        while let Some(res) = in_flight.next().await {
+            match res {
+                Ok((buf, frame)) => {
+                    self.deliver(frame);
+                    in_flight.push(self.receive_into(buf));
+                }
+                Err(err) => tracing::error!("virtio-net receive failed: {err}"),
+            }
+        }
+
+        tracing::error!("virtio-net ran out of receive buffers");
+    }
+
+    async fn receive_into(&self, buf: DmaBuffer) -> crate::Result<(DmaBuffer, Vec<u8>)> {
+        let len = buf.len();
+        let (request, used) = self
+            .rx_queue
+            .submit(&self.transport, Request::new(buf).writable(0, len))
+            .await?;
+
+        let used = usize::try_from(used)?.clamp(self.header_size, len);
+        let frame = request.buf().as_slice()[self.header_size..used].to_vec();
+
+        Ok((request.into_buf(), frame))
+    }
+
+    fn deliver(&self, frame: Vec<u8>) {
+        if frame.is_empty() {
+            return;
+        }
+
+        let mut rx = self.rx.lock();
+        if rx.len() >= RX_BACKLOG {
+            tracing::trace!("virtio-net receive backlog full, dropping frame");
+            return;
+        }
+        rx.push_back(frame);
+        drop(rx);
+
+        self.rx_ready.wake();
+    }
+}
+
+impl NetDevice for VirtioNet {
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn max_frame_size(&self) -> usize {
+        MAX_FRAME_SIZE
+    }
+
+    fn try_receive(&self) -> Option<Vec<u8>> {
+        self.rx.lock().pop_front()
+    }
+
+    async fn receive(&self) -> crate::Result<Vec<u8>> {
+        Ok(self
+            .rx_ready
+            .wait_for_value(|| self.rx.lock().pop_front())
+            .await?)
+    }
+
+    async fn transmit(&self, frame: &[u8]) -> crate::Result<()> {
+        ensure!(
+            frame.len() <= MAX_FRAME_SIZE,
+            "frame of {} bytes exceeds the maximum frame size",
+            frame.len()
+        );
+
+        // an all-zero header requests neither checksum offloading nor segmentation
+        let len = self.header_size + frame.len();
+        let mut buf = DmaBuffer::new_zeroed(len)?;
+        buf.as_mut_slice()[self.header_size..len].copy_from_slice(frame);
+
+        self.tx_queue
+            .submit(&self.transport, Request::new(buf).readable(0, len))
+            .await
+            .map_err(|err| format_err!("failed to transmit frame: {err}"))?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for VirtioNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtioNet")
+            .field("transport", &self.transport)
+            .field("mac", &MacAddress(self.mac))
+            .finish_non_exhaustive()
+    }
+}
+
+struct MacAddress([u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
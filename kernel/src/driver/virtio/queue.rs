@@ -0,0 +1,424 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Split virtqueues, see section 2.7 of the VirtIO specification.
+//!
+//! A queue consists of three parts that live in a single physically contiguous [`DmaBuffer`]:
+//! the descriptor table, the available ring (driver to device) and the used ring (device to
+//! driver). Free descriptors are kept in a list that is linked through their `next` fields, so
+//! taking a run of descriptors from the free list already links them up into a chain.
+//!
+//! Every [`Request`] owns the buffer it describes, and a request that is abandoned by its
+//! submitter stays with the queue until the device has completed it. This guarantees the device
+//! never writes into memory that has been freed.
+
+use crate::arch;
+use crate::driver::dma::DmaBuffer;
+use crate::driver::virtio::Transport;
+use crate::mem::PhysicalAddress;
+use crate::sync::WaitQueue;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use anyhow::ensure;
+use core::fmt;
+use spin::Mutex;
+
+const DESC_SIZE: usize = 16;
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// A split virtqueue.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    mem: DmaBuffer,
+    avail_offset: usize,
+    used_offset: usize,
+    inner: Mutex<Inner>,
+    /// Woken when descriptors are returned to the free list.
+    free: WaitQueue,
+    /// Woken when the device completed requests.
+    done: WaitQueue,
+}
+
+struct Inner {
+    free_head: u16,
+    num_free: u16,
+    /// Our copy of the available ring's index.
+    avail_idx: u16,
+    /// The index of the next used ring entry we haven't processed yet.
+    last_used_idx: u16,
+    /// The in-flight requests, indexed by the head descriptor of their chain.
+    slots: Box<[Slot]>,
+}
+
+#[derive(Default)]
+struct Slot {
+    request: Option<Request>,
+    /// The number of bytes written by the device, set once the request completed.
+    used_len: Option<u32>,
+    /// Set if the submitter stopped waiting for the request.
+    orphaned: bool,
+}
+
+/// A request for a device, consisting of a buffer and the segments of it that make up the
+/// descriptor chain.
+#[derive(Debug)]
+pub struct Request {
+    buf: DmaBuffer,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    offset: usize,
+    len: usize,
+    device_writable: bool,
+}
+
+impl Request {
+    pub fn new(buf: DmaBuffer) -> Self {
+        Self {
+            buf,
+            segments: Vec::new(),
+        }
+    }
+
+    /// Append a segment of the buffer that the device reads from.
+    pub fn readable(mut self, offset: usize, len: usize) -> Self {
+        self.push(offset, len, false);
+        self
+    }
+
+    /// Append a segment of the buffer that the device writes to.
+    pub fn writable(mut self, offset: usize, len: usize) -> Self {
+        self.push(offset, len, true);
+        self
+    }
+
+    pub fn buf(&self) -> &DmaBuffer {
+        &self.buf
+    }
+
+    pub fn buf_mut(&mut self) -> &mut DmaBuffer {
+        &mut self.buf
+    }
+
+    /// Returns the buffer of this request so it can be reused for another request.
+    pub fn into_buf(self) -> DmaBuffer {
+        self.buf
+    }
+
+    fn push(&mut self, offset: usize, len: usize, device_writable: bool) {
+        assert!(len > 0 && offset + len <= self.buf.len());
+        self.segments.push(Segment {
+            offset,
+            len,
+            device_writable,
+        });
+    }
+}
+
+impl VirtQueue {
+    pub(super) fn new(index: u16, size: u16) -> crate::Result<Self> {
+        let num = usize::from(size);
+        let avail_offset = DESC_SIZE * num;
+        // the legacy transport requires the used ring to be page aligned
+        let used_offset = (avail_offset + 6 + 2 * num).next_multiple_of(arch::PAGE_SIZE);
+        let mem = DmaBuffer::new_zeroed(used_offset + 6 + 8 * num)?;
+
+        let this = Self {
+            index,
+            size,
+            mem,
+            avail_offset,
+            used_offset,
+            inner: Mutex::new(Inner {
+                free_head: 0,
+                num_free: size,
+                avail_idx: 0,
+                last_used_idx: 0,
+                slots: (0..size).map(|_| Slot::default()).collect(),
+            }),
+            free: WaitQueue::new(),
+            done: WaitQueue::new(),
+        };
+
+        for idx in 0..size {
+            this.write_u16(desc_offset(idx) + 14, idx.wrapping_add(1));
+        }
+
+        Ok(this)
+    }
+
+    /// The index of this queue within its device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// The number of descriptors in this queue.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub(super) fn desc_phys(&self) -> PhysicalAddress {
+        self.mem.phys(0)
+    }
+
+    pub(super) fn avail_phys(&self) -> PhysicalAddress {
+        self.mem.phys(self.avail_offset)
+    }
+
+    pub(super) fn used_phys(&self) -> PhysicalAddress {
+        self.mem.phys(self.used_offset)
+    }
+
+    /// Submit `request` to the device and wait until the device completed it.
+    ///
+    /// Returns the request together with the number of bytes the device wrote into its writable
+    /// segments. If the returned future is dropped before the request completed, the request is
+    /// dropped once the device is done with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request has no segments or more segments than the queue has
+    /// descriptors.
+    pub async fn submit(
+        &self,
+        transport: &Transport,
+        request: Request,
+    ) -> crate::Result<(Request, u32)> {
+        let num_segments = request.segments.len();
+        ensure!(
+            num_segments > 0 && num_segments <= usize::from(self.size),
+            "invalid number of segments {num_segments}"
+        );
+
+        let mut request = Some(request);
+        let head = self
+            .free
+            .wait_for_value(|| self.try_push(&mut request))
+            .await?;
+        transport.notify(self.index);
+
+        let mut orphan = OrphanGuard {
+            queue: self,
+            head: Some(head),
+        };
+        let completion = self
+            .done
+            .wait_for_value(|| self.take_completion(head))
+            .await?;
+        orphan.head = None;
+
+        Ok(completion)
+    }
+
+    /// Process the requests the device completed, must be called after every interrupt.
+    pub fn process_used(&self) {
+        let mut inner = self.inner.lock();
+        let mut completed = false;
+        let mut freed = false;
+
+        loop {
+            let used_idx = self.read_u16(self.used_offset + 2);
+            if used_idx == inner.last_used_idx {
+                break;
+            }
+            // only read the ring entry after observing the index update
+            arch::rmb();
+
+            let elem = self.used_offset + 4 + 8 * usize::from(inner.last_used_idx % self.size);
+            let head = u16::try_from(self.read_u32(elem)).unwrap();
+            let len = self.read_u32(elem + 4);
+            inner.last_used_idx = inner.last_used_idx.wrapping_add(1);
+
+            let slot = &mut inner.slots[usize::from(head)];
+            if slot.orphaned {
+                // nobody is waiting for the request anymore, so we can free it right away
+                *slot = Slot::default();
+                self.free_chain(&mut inner, head);
+                freed = true;
+            } else {
+                slot.used_len = Some(len);
+                completed = true;
+            }
+        }
+        drop(inner);
+
+        if completed {
+            self.done.wake_all();
+        }
+        if freed {
+            self.free.wake_all();
+        }
+    }
+
+    fn try_push(&self, request: &mut Option<Request>) -> Option<u16> {
+        let mut inner = self.inner.lock();
+        let req = request.as_ref().unwrap();
+        let num_segments = u16::try_from(req.segments.len()).unwrap();
+        if inner.num_free < num_segments {
+            return None;
+        }
+
+        let head = inner.free_head;
+        let mut idx = head;
+        for (i, segment) in req.segments.iter().enumerate() {
+            let mut flags = if segment.device_writable {
+                DESC_F_WRITE
+            } else {
+                0
+            };
+            if i + 1 < req.segments.len() {
+                flags |= DESC_F_NEXT;
+            }
+
+            let desc = desc_offset(idx);
+            let addr = req.buf.phys(segment.offset).get();
+            self.write_u64(desc, u64::try_from(addr).unwrap());
+            self.write_u32(desc + 8, u32::try_from(segment.len).unwrap());
+            self.write_u16(desc + 12, flags);
+            // the free list is linked through `next`, so the chain is already linked up
+            idx = self.read_u16(desc + 14);
+        }
+        inner.free_head = idx;
+        inner.num_free -= num_segments;
+        inner.slots[usize::from(head)] = Slot {
+            request: request.take(),
+            used_len: None,
+            orphaned: false,
+        };
+
+        let avail_idx = inner.avail_idx;
+        self.write_u16(
+            self.avail_offset + 4 + 2 * usize::from(avail_idx % self.size),
+            head,
+        );
+        // the device must see the descriptors and the ring entry before the index update
+        arch::wmb();
+        inner.avail_idx = avail_idx.wrapping_add(1);
+        self.write_u16(self.avail_offset + 2, inner.avail_idx);
+
+        Some(head)
+    }
+
+    fn take_completion(&self, head: u16) -> Option<(Request, u32)> {
+        let mut inner = self.inner.lock();
+        let slot = &mut inner.slots[usize::from(head)];
+        let used_len = slot.used_len.take()?;
+        let request = slot.request.take().unwrap();
+        self.free_chain(&mut inner, head);
+        drop(inner);
+
+        self.free.wake_all();
+        Some((request, used_len))
+    }
+
+    /// Return the descriptor chain starting at `head` to the free list.
+    fn free_chain(&self, inner: &mut Inner, head: u16) {
+        let mut idx = head;
+        let mut count = 1;
+        while self.read_u16(desc_offset(idx) + 12) & DESC_F_NEXT != 0 {
+            idx = self.read_u16(desc_offset(idx) + 14);
+            count += 1;
+        }
+
+        self.write_u16(desc_offset(idx) + 14, inner.free_head);
+        inner.free_head = head;
+        inner.num_free += count;
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        #[expect(
+            clippy::cast_ptr_alignment,
+            reason = "virtqueue fields are naturally aligned"
+        )]
+        let ptr = self.mem.as_ptr(offset).cast::<u16>();
+        // Safety: the field is within the queue memory
+        unsafe { ptr.read_volatile() }
+    }
+
+    fn write_u16(&self, offset: usize, value: u16) {
+        #[expect(
+            clippy::cast_ptr_alignment,
+            reason = "virtqueue fields are naturally aligned"
+        )]
+        let ptr = self.mem.as_ptr(offset).cast::<u16>();
+        // Safety: the field is within the queue memory
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        #[expect(
+            clippy::cast_ptr_alignment,
+            reason = "virtqueue fields are naturally aligned"
+        )]
+        let ptr = self.mem.as_ptr(offset).cast::<u32>();
+        // Safety: the field is within the queue memory
+        unsafe { ptr.read_volatile() }
+    }
+
+    fn write_u32(&self, offset: usize, value: u32) {
+        #[expect(
+            clippy::cast_ptr_alignment,
+            reason = "virtqueue fields are naturally aligned"
+        )]
+        let ptr = self.mem.as_ptr(offset).cast::<u32>();
+        // Safety: the field is within the queue memory
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn write_u64(&self, offset: usize, value: u64) {
+        #[expect(
+            clippy::cast_ptr_alignment,
+            reason = "virtqueue fields are naturally aligned"
+        )]
+        let ptr = self.mem.as_ptr(offset).cast::<u64>();
+        // Safety: the field is within the queue memory
+        unsafe { ptr.write_volatile(value) }
+    }
+}
+
+impl fmt::Debug for VirtQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtQueue")
+            .field("index", &self.index)
+            .field("size", &self.size)
+            .field("mem", &self.mem)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Hands a request over to the queue if its submitter stops waiting for it.
+struct OrphanGuard<'a> {
+    queue: &'a VirtQueue,
+    head: Option<u16>,
+}
+
+impl Drop for OrphanGuard<'_> {
+    fn drop(&mut self) {
+        let Some(head) = self.head else {
+            return;
+        };
+
+        let mut inner = self.queue.inner.lock();
+        let slot = &mut inner.slots[usize::from(head)];
+        if slot.used_len.is_some() {
+            *slot = Slot::default();
+            self.queue.free_chain(&mut inner, head);
+            drop(inner);
+            self.queue.free.wake_all();
+        } else {
+            slot.orphaned = true;
+        }
+    }
+}
+
+fn desc_offset(idx: u16) -> usize {
+    DESC_SIZE * usize::from(idx)
+}
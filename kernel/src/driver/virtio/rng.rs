@@ -0,0 +1,87 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! VirtIO entropy devices, see section 5.4 of the VirtIO specification.
+
+use crate::driver::dma::DmaBuffer;
+use crate::driver::virtio::{Request, Transport, VirtQueue, spawn_irq_handler};
+use crate::irq::Irq;
+use alloc::sync::Arc;
+
+const QUEUE_SIZE: u16 = 8;
+
+/// A VirtIO entropy device.
+#[derive(Debug)]
+pub struct VirtioRng {
+    transport: Transport,
+    queue: VirtQueue,
+}
+
+impl VirtioRng {
+    pub(super) fn new(transport: Transport, irq: Irq) -> crate::Result<Arc<Self>> {
+        // the device defines no feature bits
+        transport.negotiate_features(0)?;
+        let queue = transport.setup_queue(0, QUEUE_SIZE)?;
+        transport.driver_ok();
+
+        let this = Arc::new(Self { transport, queue });
+        tracing::info!("virtio-rng: entropy source available");
+
+        spawn_irq_handler("virtio-rng-irq", irq, Arc::downgrade(&this), |this| {
+            this.transport.ack_interrupt();
+            this.queue.process_used();
+        });
+
+        Ok(this)
+    }
+
+    /// Fill `buf` with random bytes from the device.
+    ///
+    /// Returns the number of bytes written, the device may provide fewer bytes than requested.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request couldn't be submitted to the device.
+    pub async fn read(&self, buf: &mut [u8]) -> crate::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len();
+        let (request, used) = self
+            .queue
+            .submit(
+                &self.transport,
+                Request::new(DmaBuffer::new_zeroed(len)?).writable(0, len),
+            )
+            .await?;
+
+        let used = usize::try_from(used)?.min(len);
+        buf[..used].copy_from_slice(&request.buf().as_slice()[..used]);
+
+        Ok(used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ktest::test]
+    async fn read() {
+        let rng = crate::driver::wait_for::<VirtioRng>()
+            .await
+            .expect("the test runner attaches a virtio-rng device");
+
+        let mut a = [0; 64];
+        let mut b = [0; 64];
+        let len = rng.read(&mut a).await.unwrap();
+        assert!(len > 0 && len <= a.len());
+        rng.read(&mut b).await.unwrap();
+        assert_ne!(a, b);
+    }
+}
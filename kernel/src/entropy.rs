@@ -0,0 +1,294 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! The kernel entropy pool.
+//!
+//! All kernel randomness is derived from a single pool. It is seeded with the `rng-seed` the loader
+//! found in the device tree and then reseeded every [`RESEED_INTERVAL`] by the `entropy-reseed`
+//! task, which gathers fresh entropy from
+//!
+//! - a VirtIO entropy device (QEMU `-device virtio-rng-device`),
+//! - the CPU's hardware entropy source (the RISC-V `Zkr` extension), and
+//! - the timing jitter of external interrupts.
+//!
+//! Input is XOR-folded into an accumulator that is only mixed into the pool key on reseed. Output is
+//! produced by a ChaCha20 generator keyed with the pool key, which is replaced with fresh generator
+//! output on every draw, so earlier output can't be reconstructed from the current pool state.
+//!
+//! One-off randomness is drawn through [`getrandom`], which waits until the pool has been seeded
+//! from a source that is actually random. Long-lived generators, such as the one used for address
+//! space layout randomization, use a [`ReseedingRng`] which picks up fresh pool state after every
+//! reseed, except in [deterministic scheduling mode](crate::scheduler::deterministic).
+
+use crate::driver::virtio::VirtioRng;
+use crate::scheduler::{Scheduler, deterministic};
+use crate::sync::WaitQueue;
+use crate::time::{Duration, interval};
+use crate::{arch, driver};
+use anyhow::ensure;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use spin::{Mutex, Once};
+
+const SEED_LEN: usize = 32;
+/// How often the pool is reseeded from the hardware entropy sources.
+const RESEED_INTERVAL: Duration = Duration::from_secs(60);
+
+static POOL: Mutex<Pool> = Mutex::new(Pool::new());
+/// Incremented on every reseed, used by [`ReseedingRng`] to notice that it should reseed too.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Whether the pool has been seeded from a source that is actually random.
+static SEEDED: AtomicBool = AtomicBool::new(false);
+/// Woken once the pool has been seeded.
+static READY: WaitQueue = WaitQueue::new();
+/// Accumulated interrupt timestamps, folded into the pool on reseed.
+static JITTER: AtomicU64 = AtomicU64::new(0);
+
+struct Pool {
+    key: [u8; SEED_LEN],
+    input: [u8; SEED_LEN],
+    input_pos: usize,
+}
+
+impl Pool {
+    const fn new() -> Self {
+        Self {
+            key: [0; SEED_LEN],
+            input: [0; SEED_LEN],
+            input_pos: 0,
+        }
+    }
+
+    fn mix(&mut self, data: &[u8]) {
+        for byte in data {
+            self.input[self.input_pos] ^= byte;
+            self.input_pos = (self.input_pos + 1) % SEED_LEN;
+        }
+    }
+
+    fn reseed(&mut self) {
+        for (key, input) in self.key.iter_mut().zip(&mut self.input) {
+            *key ^= core::mem::take(input);
+        }
+        // run the key through the generator once so the input isn't visible in the key directly
+        let mut rng = ChaCha20Rng::from_seed(self.key);
+        rng.fill_bytes(&mut self.key);
+    }
+
+    /// Returns a seed for an output generator, replacing the pool key in the process.
+    fn next_seed(&mut self) -> [u8; SEED_LEN] {
+        let mut rng = ChaCha20Rng::from_seed(self.key);
+        let mut seed = [0; SEED_LEN];
+        rng.fill_bytes(&mut self.key);
+        rng.fill_bytes(&mut seed);
+        seed
+    }
+}
+
+/// Seed the pool with the seed passed on by the loader.
+///
+/// An all-zero seed means that the loader didn't find any entropy, in which case [`getrandom`]
+/// blocks until the first reseed from a hardware entropy source.
+pub fn init(boot_seed: [u8; SEED_LEN]) {
+    let mut pool = POOL.lock();
+    pool.mix(&boot_seed);
+    pool.reseed();
+    drop(pool);
+
+    if boot_seed != [0; SEED_LEN] {
+        SEEDED.store(true, Ordering::Release);
+    }
+}
+
+/// Spawn the task that periodically reseeds the pool.
+pub fn start(sched: &'static Scheduler) {
+    static START: Once = Once::new();
+
+    START.call_once(|| {
+        sched
+            .build_task()
+            .name("entropy-reseed")
+            .spawn(reseed_loop());
+    });
+}
+
+async fn reseed_loop() {
+    // the entropy devices are only known once all drivers have been probed
+    driver::wait_for_probe().await;
+
+    let mut interval = interval(RESEED_INTERVAL);
+    loop {
+        interval.tick().await;
+        reseed_from_sources().await;
+    }
+}
+
+async fn reseed_from_sources() {
+    let mut buf = [0; SEED_LEN];
+    // only full seeds from the hardware sources count towards seeding the pool, interrupt timings
+    // are mixed in for good measure but are too predictable to be relied upon
+    let mut credited = false;
+
+    if let Some(rng) = driver::find::<VirtioRng>() {
+        match rng.read(&mut buf).await {
+            Ok(len) => {
+                add_entropy(&buf[..len]);
+                credited |= len == SEED_LEN;
+            }
+            Err(err) => tracing::warn!("failed to read from virtio-rng: {err}"),
+        }
+    }
+
+    let len = arch::hardware_entropy(&mut buf);
+    add_entropy(&buf[..len]);
+    credited |= len == SEED_LEN;
+
+    add_entropy(&JITTER.swap(0, Ordering::Relaxed).to_ne_bytes());
+    add_entropy(&arch::timestamp().to_ne_bytes());
+
+    reseed(credited);
+}
+
+fn reseed(credited: bool) {
+    POOL.lock().reseed();
+    GENERATION.fetch_add(1, Ordering::Release);
+
+    if credited && !SEEDED.swap(true, Ordering::AcqRel) {
+        tracing::debug!("entropy pool seeded from hardware entropy sources");
+        READY.wake_all();
+    }
+}
+
+/// Mix `data` into the pool, it takes effect with the next reseed.
+///
+/// The data isn't credited as entropy, so callers may pass in anything that is hard to predict
+/// from the outside.
+pub fn add_entropy(data: &[u8]) {
+    POOL.lock().mix(data);
+}
+
+/// Record the arrival time of an interrupt.
+///
+/// This is called from the trap handler, so it must not take any locks.
+pub fn add_interrupt_timing() {
+    let now = arch::timestamp();
+    let _ = JITTER.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |acc| {
+        Some(acc.rotate_left(7) ^ now)
+    });
+}
+
+/// Fill `buf` with random bytes, waiting until the pool has been seeded.
+pub async fn getrandom(buf: &mut [u8]) {
+    // the queue is never closed
+    let _ = READY.wait_for(|| SEEDED.load(Ordering::Acquire)).await;
+    fill_bytes(buf);
+}
+
+/// Fill `buf` with random bytes without waiting for the pool to be seeded.
+///
+/// # Errors
+///
+/// Returns an error if the pool hasn't been seeded yet.
+pub fn try_fill_bytes(buf: &mut [u8]) -> crate::Result<()> {
+    ensure!(
+        SEEDED.load(Ordering::Acquire),
+        "the entropy pool hasn't been seeded yet"
+    );
+    fill_bytes(buf);
+    Ok(())
+}
+
+fn fill_bytes(buf: &mut [u8]) {
+    let seed = POOL.lock().next_seed();
+    ChaCha20Rng::from_seed(seed).fill_bytes(buf);
+}
+
+/// A ChaCha20 generator that reseeds itself from the entropy pool whenever the pool has been
+/// reseeded.
+///
+/// In deterministic scheduling mode the generator never reseeds, so everything derived from it
+/// (such as address space layouts) stays reproducible for a given seed.
+#[derive(Debug)]
+pub struct ReseedingRng {
+    rng: ChaCha20Rng,
+    generation: u64,
+}
+
+impl ReseedingRng {
+    /// Create a generator seeded from `rng`, it switches over to the entropy pool with the next
+    /// reseed.
+    pub fn from_rng(rng: &mut impl RngCore) -> Self {
+        Self {
+            rng: ChaCha20Rng::from_rng(rng),
+            generation: GENERATION.load(Ordering::Acquire),
+        }
+    }
+
+    fn reseed_if_needed(&mut self) {
+        if deterministic::seed().is_some() {
+            return;
+        }
+
+        let generation = GENERATION.load(Ordering::Acquire);
+        if generation != self.generation {
+            self.rng = ChaCha20Rng::from_seed(POOL.lock().next_seed());
+            self.generation = generation;
+        }
+    }
+}
+
+impl RngCore for ReseedingRng {
+    fn next_u32(&mut self) -> u32 {
+        self.reseed_if_needed();
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.reseed_if_needed();
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        self.reseed_if_needed();
+        self.rng.fill_bytes(dst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ktest::test]
+    async fn getrandom_differs() {
+        // without an `rng-seed`, virtio-rng or `Zkr` the pool is never seeded and `getrandom` would
+        // block forever
+        if !SEEDED.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut a = [0; 64];
+        let mut b = [0; 64];
+        getrandom(&mut a).await;
+        getrandom(&mut b).await;
+        assert_ne!(a, b);
+    }
+
+    #[ktest::test]
+    async fn reseeding_rng_follows_pool() {
+        let mut a = ReseedingRng::from_rng(&mut ChaCha20Rng::seed_from_u64(0));
+        let mut b = ChaCha20Rng::from_rng(&mut ChaCha20Rng::seed_from_u64(0));
+        assert_eq!(a.next_u64(), b.next_u64());
+
+        reseed(false);
+        if deterministic::seed().is_some() {
+            assert_eq!(a.next_u64(), b.next_u64());
+        } else {
+            assert_ne!(a.next_u64(), b.next_u64());
+        }
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! CPU hotplug and deep idle states.
+//!
+//! [`offline`] marks a CPU as offline in the scheduler and reroutes interrupts away from it. The
+//! CPU's worker notices the next time it looks for work, hands its queued tasks and sleeping timers
+//! over to the other CPUs and stops the CPU through the firmware (see [`stop_this_cpu`]). [`online`]
+//! restarts it, at which point the worker picks up where it left off. Taking a CPU offline and back
+//! online is mostly useful to test how the rest of the kernel copes with a changing set of CPUs.
+//!
+//! Independently of that, idle CPUs can be put into non-retentive suspend instead of just waiting
+//! for an interrupt when there is nothing to do for a while, see [`IdleMode`].
+
+use crate::arch::device::cpu::with_cpu;
+use crate::metrics::Counter;
+use crate::scheduler::scheduler;
+use crate::time::{Deadline, Duration, Instant, sleep};
+use crate::{BOOT_INFO, CPUID, arch, counter, irq, lockup};
+use anyhow::{bail, ensure};
+use core::fmt;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// How long [`online`] waits for a CPU that is going offline to actually stop.
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+/// The minimum time until the next timer deadline for an idle CPU to be suspended instead of
+/// waiting for an interrupt.
+const SUSPEND_THRESHOLD: Duration = Duration::from_millis(10);
+
+/// Serializes stopping a CPU against bringing it back online.
+static LOCK: spin::Mutex<()> = spin::Mutex::new(());
+/// Bitmask of the CPUs that are stopped or about to be stopped through the firmware.
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+static IDLE_MODE: AtomicU8 = AtomicU8::new(IdleMode::Wait as u8);
+static SUSPENDS: Counter = counter!("cpu-suspends");
+
+/// What an idle CPU does while waiting for work.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdleMode {
+    /// Wait for an interrupt, keeping all CPU state.
+    #[default]
+    Wait,
+    /// Suspend the CPU non-retentively if the next timer deadline is far enough away, allowing the
+    /// platform to power it down.
+    Suspend,
+}
+
+/// The hotplug state of a CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuState {
+    Online,
+    /// The CPU has been marked offline but hasn't stopped yet.
+    GoingOffline,
+    Offline,
+}
+
+pub fn init(idle_mode: IdleMode) {
+    set_idle_mode(idle_mode);
+}
+
+pub fn idle_mode() -> IdleMode {
+    match IDLE_MODE.load(Ordering::Relaxed) {
+        0 => IdleMode::Wait,
+        _ => IdleMode::Suspend,
+    }
+}
+
+pub fn set_idle_mode(mode: IdleMode) {
+    IDLE_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the number of times the CPU `cpuid` has been suspended while idle.
+pub fn suspend_count(cpuid: usize) -> u64 {
+    SUSPENDS.get_for(cpuid).unwrap_or(0)
+}
+
+/// Returns the hotplug state of the CPU `cpuid`.
+pub fn state(cpuid: usize) -> CpuState {
+    if scheduler().is_cpu_online(cpuid) {
+        CpuState::Online
+    } else if STOPPED.load(Ordering::Acquire) & (1 << cpuid) != 0 && arch::is_cpu_stopped(cpuid) {
+        CpuState::Offline
+    } else {
+        CpuState::GoingOffline
+    }
+}
+
+/// Take the CPU `cpuid` offline.
+///
+/// This returns right away, the CPU stops once its worker has handed over its tasks.
+///
+/// # Errors
+///
+/// Returns an error if the CPU doesn't exist, is already offline, is the last online CPU or the
+/// system is shutting down.
+pub fn offline(cpuid: usize) -> crate::Result<()> {
+    let sched = scheduler();
+    let cpu_mask = BOOT_INFO.get().unwrap().cpu_mask;
+
+    let _guard = LOCK.lock();
+    ensure!(
+        crate::scheduler::deterministic::seed().is_none(),
+        "CPU hotplug isn't supported in deterministic scheduling mode"
+    );
+    ensure!(
+        cpuid < usize::BITS as usize && cpu_mask & (1 << cpuid) != 0,
+        "CPU {cpuid} doesn't exist"
+    );
+    ensure!(sched.is_cpu_online(cpuid), "CPU {cpuid} is already offline");
+    ensure!(
+        sched.online_cpus() != 1 << cpuid,
+        "CPU {cpuid} is the last online CPU"
+    );
+    ensure!(!sched.is_shutting_down(), "the system is shutting down");
+
+    tracing::info!("taking CPU {cpuid} offline...");
+    sched.set_cpu_online(cpuid, false);
+    irq::reroute_from(cpuid);
+
+    Ok(())
+}
+
+/// Bring the CPU `cpuid`, previously taken offline through [`offline`], back online.
+///
+/// # Errors
+///
+/// Returns an error if the CPU is already online, didn't stop in time or the firmware failed to
+/// start it.
+pub async fn online(cpuid: usize) -> crate::Result<()> {
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while !try_online(cpuid)? {
+        ensure!(
+            Instant::now() <= deadline,
+            "CPU {cpuid} didn't stop in time"
+        );
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    Ok(())
+}
+
+/// Bring all offline CPUs back online, so they can take part in shutting down the system.
+pub fn online_all() {
+    let cpu_mask = BOOT_INFO.get().unwrap().cpu_mask;
+
+    // the system is shutting down, so we can't wait for timers here
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    for cpuid in (0..usize::BITS as usize).filter(|cpuid| cpu_mask & (1 << cpuid) != 0) {
+        while !scheduler().is_cpu_online(cpuid) {
+            match try_online(cpuid) {
+                Ok(true) => {}
+                Ok(false) if Instant::now() <= deadline => core::hint::spin_loop(),
+                Ok(false) => {
+                    tracing::warn!("CPU {cpuid} didn't stop in time, not bringing it back online");
+                    break;
+                }
+                Err(err) => {
+                    tracing::warn!("failed to bring CPU {cpuid} back online: {err}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Try to bring the CPU `cpuid` back online.
+///
+/// Returns `false` if the CPU is still on its way to being stopped by the firmware and can't be
+/// started yet, in which case the caller should try again a bit later.
+fn try_online(cpuid: usize) -> crate::Result<bool> {
+    let sched = scheduler();
+
+    let _guard = LOCK.lock();
+    ensure!(
+        cpuid < usize::BITS as usize && !sched.is_cpu_online(cpuid),
+        "CPU {cpuid} isn't offline"
+    );
+
+    if STOPPED.load(Ordering::Acquire) & (1 << cpuid) == 0 {
+        // the CPU didn't get around to stopping yet, cancelling the request is enough
+        sched.set_cpu_online(cpuid, true);
+        return Ok(true);
+    }
+
+    // `stop_this_cpu` releases the lock right before handing the CPU to the firmware
+    if !arch::is_cpu_stopped(cpuid) {
+        return Ok(false);
+    }
+
+    tracing::info!("bringing CPU {cpuid} online...");
+    STOPPED.fetch_and(!(1 << cpuid), Ordering::AcqRel);
+    sched.set_cpu_online(cpuid, true);
+    if let Err(err) = arch::cpu_online(cpuid) {
+        STOPPED.fetch_or(1 << cpuid, Ordering::AcqRel);
+        sched.set_cpu_online(cpuid, false);
+        return Err(err);
+    }
+
+    Ok(true)
+}
+
+/// Stop the calling CPU, which has been marked offline, returning once it is back online.
+///
+/// Called by the CPU's worker after it handed over all its work.
+///
+/// # Errors
+///
+/// Returns an error if the firmware failed to stop the CPU, in which case it is marked online
+/// again.
+pub(crate) fn stop_this_cpu() -> crate::Result<()> {
+    let cpuid = CPUID.get();
+    let sched = scheduler();
+
+    {
+        let _guard = LOCK.lock();
+        if sched.is_cpu_online(cpuid) {
+            // the request has been cancelled in the meantime
+            return Ok(());
+        }
+        if sched.is_shutting_down() {
+            sched.set_cpu_online(cpuid, true);
+            return Ok(());
+        }
+        STOPPED.fetch_or(1 << cpuid, Ordering::AcqRel);
+    }
+
+    if let Err(err) = arch::cpu_offline() {
+        let _guard = LOCK.lock();
+        STOPPED.fetch_and(!(1 << cpuid), Ordering::AcqRel);
+        sched.set_cpu_online(cpuid, true);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Wait for an interrupt on the calling CPU, which has nothing to do until `next_deadline`.
+///
+/// Depending on the [`IdleMode`] the CPU is suspended if the deadline is far enough away.
+pub fn wait_for_interrupt(next_deadline: Option<Deadline>) {
+    if idle_mode() == IdleMode::Suspend && should_suspend(next_deadline) {
+        match arch::cpu_suspend() {
+            Ok(()) => {
+                SUSPENDS.increment(1);
+                return;
+            }
+            Err(err) => {
+                tracing::warn!("failed to suspend CPU, falling back to waiting: {err}");
+                set_idle_mode(IdleMode::Wait);
+            }
+        }
+    }
+
+    // Safety: the worker has nothing to do, and any interrupt wakes us up again
+    unsafe {
+        arch::cpu_park();
+    }
+}
+
+fn should_suspend(next_deadline: Option<Deadline>) -> bool {
+    let deadline = next_deadline.map_or(u64::MAX, |deadline| deadline.ticks.0);
+    let deadline = lockup::clamp_timer_deadline(deadline);
+
+    with_cpu(|cpu| {
+        let Ok(threshold) = cpu.clock.duration_to_ticks(SUSPEND_THRESHOLD) else {
+            return false;
+        };
+        deadline.saturating_sub(cpu.clock.now_ticks().0) >= threshold.0
+    })
+}
+
+impl FromStr for IdleMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wait" => Ok(IdleMode::Wait),
+            "suspend" => Ok(IdleMode::Suspend),
+            _ => bail!("unknown idle mode `{s}`"),
+        }
+    }
+}
+
+impl fmt::Display for IdleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IdleMode::Wait => "wait",
+            IdleMode::Suspend => "suspend",
+        })
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CpuState::Online => "online",
+            CpuState::GoingOffline => "going offline",
+            CpuState::Offline => "offline",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ktest::test]
+    async fn offline_and_online() {
+        if crate::scheduler::deterministic::seed().is_some() {
+            // all CPUs are driven by a single one, so they can't be stopped individually
+            assert!(offline(CPUID.get()).is_err());
+            return;
+        }
+
+        let cpu_mask = BOOT_INFO.get().unwrap().cpu_mask;
+        let Some(cpuid) = (0..usize::BITS as usize)
+            .find(|cpuid| *cpuid != CPUID.get() && cpu_mask & (1 << cpuid) != 0)
+        else {
+            // nothing to test on single-CPU systems
+            return;
+        };
+
+        offline(cpuid).unwrap();
+        assert!(offline(cpuid).is_err());
+        while state(cpuid) != CpuState::Offline {
+            sleep(Duration::from_millis(1)).await;
+        }
+
+        online(cpuid).await.unwrap();
+        assert_eq!(state(cpuid), CpuState::Online);
+        let ran_on = scheduler()
+            .spawn_on(cpuid, async { CPUID.get() })
+            .await
+            .unwrap();
+        assert_eq!(ran_on, cpuid);
+    }
+}
@@ -5,19 +5,65 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+//! External interrupt management.
+//!
+//! Drivers wait for their interrupts through [`Irq::next_event`], which enables the interrupt at
+//! the controller for as long as a task waits for it. Every interrupt can be configured with
+//!
+//! - a priority, which decides which interrupt is handled first when several are pending,
+//! - the set of CPUs it may be delivered to, and
+//! - the trigger mode of its source, if the controller supports configuring it.
+//!
+//! Unless an interrupt is restricted to a single CPU, it is routed to one of the idle CPUs in its
+//! set, preferring the one that has handled the fewest interrupts so far. Only if all of them are
+//! busy is it routed to the waiting CPU itself. This keeps interrupt handling off busy CPUs and
+//! spreads it evenly across the rest.
+//!
+//! Every delivered interrupt is counted per interrupt and per CPU in [`IRQ_COUNTS`].
+
+use crate::arch::device::IrqController;
 use crate::arch::device::cpu::with_cpu;
-use crate::sync;
+use crate::metrics::{Counter, CounterArray};
+use crate::scheduler::scheduler;
 use crate::sync::WaitQueue;
+use crate::{CPUID, counter, counter_array, sync};
 use alloc::sync::Arc;
+use anyhow::{bail, ensure};
+use core::fmt;
 use core::num::NonZero;
+use core::str::FromStr;
 use hashbrown::HashMap;
 use spin::{LazyLock, RwLock};
 
+/// The number of interrupts tracked in [`IRQ_COUNTS`], enough for all PLIC and APLIC sources.
+pub const NUM_IRQS: usize = 1024;
+/// The priority of interrupts that weren't given one explicitly.
+pub const DEFAULT_PRIORITY: u32 = 1;
+
+/// The number of times each interrupt was delivered, per CPU.
+pub static IRQ_COUNTS: CounterArray<NUM_IRQS> = counter_array!("irqs", NUM_IRQS);
+/// The number of interrupts delivered to each CPU.
+static IRQS_HANDLED: Counter = counter!("irqs-handled");
+
 pub trait InterruptController {
     fn irq_claim(&mut self) -> Option<IrqClaim>;
     fn irq_complete(&mut self, claim: IrqClaim);
-    fn irq_mask(&mut self, irq_num: u32);
-    fn irq_unmask(&mut self, irq_num: u32);
+    /// Stop delivering `irq_num` to the CPU `cpuid`.
+    fn irq_mask(&mut self, irq_num: u32, cpuid: usize);
+    /// Start delivering `irq_num` to the CPU `cpuid`, which doesn't have to be the calling CPU.
+    fn irq_unmask(&mut self, irq_num: u32, cpuid: usize);
+    /// Set the priority of `irq_num`, interrupts with a higher priority are handled first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the controller doesn't support the priority.
+    fn irq_set_priority(&mut self, irq_num: u32, priority: u32) -> crate::Result<()>;
+    /// Configure how the source of `irq_num` signals interrupts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the controller doesn't support the trigger mode.
+    fn irq_set_trigger(&mut self, irq_num: u32, mode: TriggerMode) -> crate::Result<()>;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -32,10 +78,170 @@ impl IrqClaim {
     }
 }
 
+/// How an interrupt source signals interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    EdgeRising,
+    EdgeFalling,
+    LevelHigh,
+    LevelLow,
+}
+
+impl TriggerMode {
+    /// Returns the trigger mode for the `IRQ_TYPE_*` flags of a device tree interrupt specifier,
+    /// `None` if the specifier doesn't name one.
+    pub fn from_devicetree_flags(flags: u32) -> Option<Self> {
+        match flags & 0xf {
+            1 => Some(Self::EdgeRising),
+            2 => Some(Self::EdgeFalling),
+            4 => Some(Self::LevelHigh),
+            8 => Some(Self::LevelLow),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TriggerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::EdgeRising => "edge-rising",
+            Self::EdgeFalling => "edge-falling",
+            Self::LevelHigh => "level-high",
+            Self::LevelLow => "level-low",
+        })
+    }
+}
+
+impl FromStr for TriggerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "edge-rising" => Ok(Self::EdgeRising),
+            "edge-falling" => Ok(Self::EdgeFalling),
+            "level-high" => Ok(Self::LevelHigh),
+            "level-low" => Ok(Self::LevelLow),
+            _ => bail!(
+                "invalid trigger mode {s:?}, expected one of edge-rising, edge-falling, level-high, level-low"
+            ),
+        }
+    }
+}
+
+/// The configuration of an interrupt, `None` fields are left at their defaults.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IrqConfig {
+    pub priority: Option<u32>,
+    /// Bitmask of the CPUs the interrupt may be delivered to.
+    pub cpus: Option<usize>,
+    pub trigger: Option<TriggerMode>,
+}
+
+/// A handle to an external interrupt line, usually resolved from a device's `interrupts` property
+/// through [`driver::irq`](crate::driver::irq).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Irq(u32);
+
+impl Irq {
+    pub const fn new(irq_num: u32) -> Self {
+        Self(irq_num)
+    }
+
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Wait for the next interrupt on this line, see [`next_event`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interrupt line was closed.
+    pub async fn next_event(self) -> Result<(), sync::Closed> {
+        next_event(self.0).await
+    }
+
+    /// Returns the configuration of this interrupt.
+    pub fn config(self) -> IrqConfig {
+        CONFIGS.read().get(&self.0).copied().unwrap_or_default()
+    }
+
+    /// Set the priority of this interrupt, higher priorities are handled first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interrupt controller doesn't support the priority.
+    pub fn set_priority(self, priority: u32) -> crate::Result<()> {
+        with_irq_ctl(|irq_ctl| irq_ctl.irq_set_priority(self.0, priority))?;
+        CONFIGS.write().entry(self.0).or_default().priority = Some(priority);
+        Ok(())
+    }
+
+    /// Configure how the source of this interrupt signals interrupts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interrupt controller doesn't support the trigger mode.
+    pub fn set_trigger(self, mode: TriggerMode) -> crate::Result<()> {
+        with_irq_ctl(|irq_ctl| irq_ctl.irq_set_trigger(self.0, mode))?;
+        CONFIGS.write().entry(self.0).or_default().trigger = Some(mode);
+        Ok(())
+    }
+
+    /// Restrict this interrupt to the CPUs in the given bitmask, or allow it on all CPUs if
+    /// `None`.
+    ///
+    /// Takes effect the next time a task starts waiting for the interrupt.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mask includes CPUs that aren't online or no CPUs at all.
+    pub fn set_affinity(self, cpus: Option<usize>) -> crate::Result<()> {
+        if let Some(cpus) = cpus {
+            let online = scheduler().online_cpus();
+            ensure!(cpus != 0, "the CPU set must not be empty");
+            ensure!(
+                cpus & !online == 0,
+                "CPU set {cpus:#b} includes CPUs that aren't online (online {online:#b})"
+            );
+        }
+        CONFIGS.write().entry(self.0).or_default().cpus = cpus;
+        Ok(())
+    }
+
+    /// Returns the bitmask of CPUs this interrupt may be delivered to.
+    pub fn affinity(self) -> usize {
+        self.config()
+            .cpus
+            .unwrap_or_else(|| crate::BOOT_INFO.get().unwrap().cpu_mask)
+    }
+}
+
+/// The tasks waiting for an interrupt and the CPU it was last routed to.
+struct Waiters {
+    queue: Arc<WaitQueue>,
+    target: usize,
+}
+
 // hashbrown doesn't have a good const constructor, therefore the `LazyLock`
-static QUEUES: LazyLock<RwLock<HashMap<u32, Arc<WaitQueue>>>> =
+static WAITERS: LazyLock<RwLock<HashMap<u32, Waiters>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+/// The configuration of every interrupt that has been configured, see [`Irq::config`].
+static CONFIGS: LazyLock<RwLock<HashMap<u32, IrqConfig>>> =
     LazyLock::new(|| RwLock::new(HashMap::new()));
 
+fn with_irq_ctl<R>(f: impl FnOnce(&mut IrqController) -> R) -> R {
+    with_cpu(|cpu| f(&mut cpu.irq_ctl.borrow_mut()))
+}
+
+/// Perform the per-CPU initialization of the interrupt statistics.
+///
+/// This has to run before interrupts are enabled on the calling CPU, so that counting interrupts
+/// never allocates from within the trap handler.
+pub fn per_cpu_init() {
+    IRQ_COUNTS.init_cpu();
+    IRQS_HANDLED.increment(0);
+}
+
 pub fn trigger_irq(irq_ctl: &mut dyn InterruptController) {
     let Some(claim) = irq_ctl.irq_claim() else {
         // Spurious interrupt
@@ -45,29 +251,206 @@ pub fn trigger_irq(irq_ctl: &mut dyn InterruptController) {
     // acknowledge the interrupt as fast as possible
     irq_ctl.irq_complete(claim);
 
-    let queues = QUEUES.read();
-    if let Some(queue) = queues.get(&claim.as_u32()) {
-        queue.wake_all();
+    if let Ok(index) = usize::try_from(claim.as_u32())
+        && index < NUM_IRQS
+    {
+        IRQ_COUNTS.increment(index, 1);
+    }
+    IRQS_HANDLED.increment(1);
+
+    let waiters = WAITERS.read();
+    if let Some(waiters) = waiters.get(&claim.as_u32()) {
+        waiters.queue.wake_all();
+    }
+}
+
+/// Wake the tasks waiting for interrupts routed to the CPU `cpuid`, so they route their interrupts
+/// anew.
+///
+/// Called when the CPU `cpuid` goes offline, so no interrupt stays routed to it.
+pub fn reroute_from(cpuid: usize) {
+    tracing::debug!("rerouting interrupts away from CPU {cpuid}");
+
+    for waiters in WAITERS.read().values() {
+        if waiters.target == cpuid {
+            waiters.queue.wake_all();
+        }
     }
 }
 
 pub async fn next_event(irq_num: u32) -> Result<(), sync::Closed> {
-    with_cpu(|cpu| cpu.plic.borrow_mut().irq_unmask(irq_num));
+    // remember the CPU, so the interrupt is masked again on the same CPU even if the routing
+    // changes or the task migrates while waiting
+    let cpuid = select_target(Irq::new(irq_num).affinity());
+    with_irq_ctl(|irq_ctl| irq_ctl.irq_unmask(irq_num, cpuid));
 
     let wait = {
-        let mut queues = QUEUES.write();
-        let wait = queues
-            .entry(irq_num)
-            .or_insert_with(|| Arc::new(WaitQueue::new()))
-            .wait_owned();
+        let mut waiters = WAITERS.write();
+        let entry = waiters.entry(irq_num).or_insert_with(|| Waiters {
+            queue: Arc::new(WaitQueue::new()),
+            target: cpuid,
+        });
+        entry.target = cpuid;
+        let wait = entry.queue.wait_owned();
         // don't hold the RwLock guard across the await point
-        drop(queues);
+        drop(waiters);
         wait
     };
 
     let res = wait.await;
 
-    with_cpu(|cpu| cpu.plic.borrow_mut().irq_mask(irq_num));
+    with_irq_ctl(|irq_ctl| irq_ctl.irq_mask(irq_num, cpuid));
 
     res
 }
+
+/// Pick the CPU from the `cpus` bitmask an interrupt should be delivered to.
+fn select_target(cpus: usize) -> usize {
+    let this_cpu = CPUID.get();
+    let sched = scheduler();
+    // never route interrupts to offline CPUs, unless the interrupt isn't allowed anywhere else
+    let cpus = match cpus & sched.online_cpus() {
+        0 => sched.online_cpus(),
+        cpus => cpus,
+    };
+    let candidates = (0..usize::BITS as usize).filter(|cpuid| cpus & (1 << cpuid) != 0);
+
+    if let Some(cpuid) = least_loaded(candidates.clone().filter(|cpuid| sched.is_cpu_idle(*cpuid)))
+    {
+        cpuid
+    } else if cpus & (1 << this_cpu) != 0 {
+        this_cpu
+    } else {
+        least_loaded(candidates).expect("interrupt CPU set is empty")
+    }
+}
+
+/// Returns the CPU that has handled the fewest interrupts so far.
+fn least_loaded(cpuids: impl Iterator<Item = usize>) -> Option<usize> {
+    cpuids.min_by_key(|cpuid| IRQS_HANDLED.get_for(*cpuid).unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::pin::pin;
+    use futures::FutureExt;
+
+    /// An interrupt controller that has at most one pending interrupt.
+    struct FakeController {
+        pending: Option<u32>,
+    }
+
+    impl InterruptController for FakeController {
+        fn irq_claim(&mut self) -> Option<IrqClaim> {
+            let raw = NonZero::new(self.pending.take()?)?;
+            // Safety: the fake interrupt is claimed exactly once
+            Some(unsafe { IrqClaim::from_raw(raw) })
+        }
+
+        fn irq_complete(&mut self, _claim: IrqClaim) {}
+        fn irq_mask(&mut self, _irq_num: u32, _cpuid: usize) {}
+        fn irq_unmask(&mut self, _irq_num: u32, _cpuid: usize) {}
+
+        fn irq_set_priority(&mut self, _irq_num: u32, _priority: u32) -> crate::Result<()> {
+            Ok(())
+        }
+
+        fn irq_set_trigger(&mut self, _irq_num: u32, _mode: TriggerMode) -> crate::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[ktest::test]
+    async fn counts_delivered_interrupts() {
+        // keep real interrupts from being counted on this CPU while we check the counters
+        riscv::interrupt::with_disabled(|| {
+            let cpuid = CPUID.get();
+            // no device uses the last tracked interrupt
+            let index = NUM_IRQS - 1;
+            let irq_num = u32::try_from(index).unwrap();
+
+            let counts_before = IRQ_COUNTS.get_for(cpuid, index).unwrap_or(0);
+            let handled_before = IRQS_HANDLED.get_for(cpuid).unwrap_or(0);
+
+            trigger_irq(&mut FakeController {
+                pending: Some(irq_num),
+            });
+            assert_eq!(IRQ_COUNTS.get_for(cpuid, index), Some(counts_before + 1));
+            assert_eq!(IRQS_HANDLED.get_for(cpuid), Some(handled_before + 1));
+
+            // spurious interrupts aren't counted
+            trigger_irq(&mut FakeController { pending: None });
+            assert_eq!(IRQ_COUNTS.get_for(cpuid, index), Some(counts_before + 1));
+            assert_eq!(IRQS_HANDLED.get_for(cpuid), Some(handled_before + 1));
+
+            // interrupts beyond the tracked range only count towards the CPU total
+            trigger_irq(&mut FakeController {
+                pending: Some(u32::try_from(NUM_IRQS).unwrap()),
+            });
+            assert_eq!(IRQ_COUNTS.get_for(cpuid, index), Some(counts_before + 1));
+            assert_eq!(IRQS_HANDLED.get_for(cpuid), Some(handled_before + 2));
+        });
+    }
+
+    #[ktest::test]
+    async fn reroute_wakes_only_affected_waiters() {
+        // interrupt numbers no controller has, so no real interrupt wakes the waiters
+        let lines = [(u32::MAX - 1, 0), (u32::MAX - 2, 1)];
+        let queues = lines.map(|(irq_num, target)| {
+            let queue = Arc::new(WaitQueue::new());
+            WAITERS.write().insert(
+                irq_num,
+                Waiters {
+                    queue: queue.clone(),
+                    target,
+                },
+            );
+            queue
+        });
+
+        let [on_0, on_1] = &queues;
+        let mut wait_0 = pin!(on_0.wait_owned());
+        let mut wait_1 = pin!(on_1.wait_owned());
+        assert!(wait_0.as_mut().now_or_never().is_none());
+        assert!(wait_1.as_mut().now_or_never().is_none());
+
+        reroute_from(1);
+        assert!(wait_0.as_mut().now_or_never().is_none());
+        assert_eq!(wait_1.as_mut().now_or_never(), Some(Ok(())));
+
+        for (irq_num, _) in lines {
+            WAITERS.write().remove(&irq_num);
+        }
+    }
+
+    #[ktest::test]
+    async fn set_affinity() {
+        let irq = Irq::new(u32::MAX);
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        assert_eq!(irq.affinity(), cpu_mask);
+
+        irq.set_affinity(Some(1 << CPUID.get())).unwrap();
+        assert_eq!(irq.affinity(), 1 << CPUID.get());
+        assert_eq!(select_target(irq.affinity()), CPUID.get());
+
+        irq.set_affinity(None).unwrap();
+        assert_eq!(irq.affinity(), cpu_mask);
+
+        irq.set_affinity(Some(0)).unwrap_err();
+        irq.set_affinity(Some(!cpu_mask)).unwrap_err();
+    }
+
+    #[ktest::test]
+    async fn trigger_mode_from_devicetree() {
+        assert_eq!(
+            TriggerMode::from_devicetree_flags(4),
+            Some(TriggerMode::LevelHigh)
+        );
+        assert_eq!(TriggerMode::from_devicetree_flags(0), None);
+        assert_eq!(
+            "edge-falling".parse::<TriggerMode>().unwrap(),
+            TriggerMode::EdgeFalling
+        );
+    }
+}
@@ -0,0 +1,430 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Lockup detection for the cooperative scheduler.
+//!
+//! Since tasks are never preempted, a single future that doesn't return from `poll` stalls its CPU
+//! forever. The lockup detector watches for two conditions:
+//!
+//! - **Long polls**: A task has been polling for longer than [`Config::poll_threshold`]. This is
+//!   detected by the stuck CPU itself in its timer interrupt, which reports the task and a backtrace
+//!   of the interrupted code.
+//! - **Stalls**: A CPU hasn't been back to its scheduling loop for longer than
+//!   [`Config::stall_threshold`], for example because it is spinning with interrupts disabled. This
+//!   is detected by the other CPUs, which request a backtrace from the stuck CPU through an IPI.
+//!
+//! To make sure the checks run regularly, timer interrupts are programmed to fire at least every
+//! [`Config::check_interval`] while the detector is enabled. Since that wakes up idle CPUs too,
+//! the detector is disabled by default.
+//!
+//! The detector is configured through the `lockup` bootarg, see [`Config`].
+
+use crate::arch;
+use crate::arch::device::cpu::with_cpu;
+use crate::task::{Id, trace};
+use crate::time::Instant;
+use alloc::boxed::Box;
+use anyhow::{bail, format_err};
+use core::panic::Location;
+use core::ptr;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::OnceLock;
+
+static LOCKUP: OnceLock<Lockup> = OnceLock::new();
+
+/// Configuration of the lockup detector.
+///
+/// Parsed from the `lockup` bootarg, which is either `off`, `on` or a comma-separated list of
+/// `poll=<ms>`, `stall=<ms>` and `panic`, e.g. `lockup=poll=500,stall=2000,panic`. Any value
+/// other than `off` enables the detector.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Config {
+    /// Whether the lockup detector is enabled at all.
+    pub enabled: bool,
+    /// How long a single poll of a task may take before it is reported.
+    pub poll_threshold: Duration,
+    /// How long a CPU may go without returning to its scheduling loop before it is reported.
+    pub stall_threshold: Duration,
+    /// Whether to panic instead of just reporting a lockup.
+    ///
+    /// The panic always happens on the stuck CPU: For long polls it unwinds the stuck task, so it
+    /// completes with a [`JoinError`](crate::task::JoinError) instead of taking down the whole
+    /// system. Stalled CPUs unwind whatever they were running once they take the IPI requesting
+    /// their backtrace.
+    pub panic: bool,
+}
+
+/// A task that has been polling for longer than [`Config::poll_threshold`].
+#[derive(Debug)]
+pub struct LongPoll {
+    pub task: Id,
+    /// The source location that spawned the task.
+    pub location: &'static Location<'static>,
+    pub duration: Duration,
+    /// Whether the task should be unwound, see [`Config::panic`].
+    pub panic: bool,
+}
+
+/// A request from another CPU to print a backtrace because the calling CPU is stalled.
+#[derive(Debug)]
+pub struct BacktraceRequest {
+    /// Whether the interrupted code should be unwound, see [`Config::panic`].
+    pub panic: bool,
+}
+
+struct Lockup {
+    config: Config,
+    cpus: Box<[CpuState]>,
+}
+
+#[derive(Default)]
+struct CpuState {
+    /// The time the CPU last passed through its scheduling loop, in nanoseconds since boot. `0` if
+    /// the CPU hasn't started scheduling yet.
+    heartbeat: AtomicU64,
+    /// Whether the CPU is idle and waiting for an interrupt, in which case it can't be stalled.
+    parked: AtomicBool,
+    /// The time the current poll started, in nanoseconds since boot. `0` if the CPU isn't polling.
+    poll_started: AtomicU64,
+    /// The id of the task currently being polled.
+    task: AtomicU64,
+    /// The spawn location of the task currently being polled.
+    location: AtomicPtr<Location<'static>>,
+    /// Set once a stall of this CPU has been reported by another CPU, so it isn't reported
+    /// repeatedly. Cleared by the next heartbeat.
+    stall_reported: AtomicBool,
+    /// Set once this CPU has reported the current poll as too long. Cleared when the next poll
+    /// starts.
+    poll_reported: AtomicBool,
+    /// Set by other CPUs to request this CPU to print a backtrace.
+    backtrace_requested: AtomicBool,
+    /// Set by other CPUs alongside `backtrace_requested` to request this CPU to panic.
+    panic_requested: AtomicBool,
+}
+
+/// Initialize the lockup detector for the given set of CPUs.
+pub fn init(config: Config, cpu_mask: usize) {
+    if !config.enabled {
+        tracing::debug!("lockup detector disabled");
+        return;
+    }
+
+    let num_cpus = usize::try_from(usize::BITS - cpu_mask.leading_zeros()).unwrap();
+
+    LOCKUP.get_or_init(|| {
+        tracing::debug!(
+            "lockup detector enabled poll_threshold={:?} stall_threshold={:?} panic={}",
+            config.poll_threshold,
+            config.stall_threshold,
+            config.panic
+        );
+
+        Lockup {
+            config,
+            cpus: (0..num_cpus).map(|_| CpuState::default()).collect(),
+        }
+    });
+}
+
+/// Record that the calling CPU passed through its scheduling loop.
+pub fn heartbeat() {
+    if let Some(cpu) = this_cpu() {
+        cpu.heartbeat.store(now_nanos(), Ordering::Relaxed);
+        cpu.stall_reported.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Record that the calling CPU is about to go idle.
+pub fn park() {
+    if let Some(cpu) = this_cpu() {
+        cpu.parked.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Record that the calling CPU woke up from being idle.
+pub fn unpark() {
+    if let Some(cpu) = this_cpu() {
+        cpu.parked.store(false, Ordering::Relaxed);
+        heartbeat();
+    }
+}
+
+/// Record that the calling CPU starts polling the given task.
+pub fn poll_start(task: Id, location: &'static Location<'static>) {
+    if let Some(cpu) = this_cpu() {
+        cpu.task.store(task.as_u64(), Ordering::Relaxed);
+        cpu.location
+            .store(ptr::from_ref(location).cast_mut(), Ordering::Relaxed);
+        cpu.poll_reported.store(false, Ordering::Relaxed);
+        cpu.poll_started.store(now_nanos(), Ordering::Release);
+    }
+}
+
+/// Record that the calling CPU finished polling a task.
+pub fn poll_end() {
+    if let Some(cpu) = this_cpu() {
+        cpu.poll_started.store(0, Ordering::Release);
+        heartbeat();
+    }
+}
+
+/// Returns the deadline (in ticks) the timer interrupt should be programmed with, given the next
+/// deadline of the CPU-local timer, so the lockup checks run at least every
+/// [`Config::check_interval`].
+pub fn clamp_timer_deadline(deadline: u64) -> u64 {
+    let Some(lockup) = LOCKUP.get() else {
+        return deadline;
+    };
+
+    with_cpu(|cpu| {
+        let Ok(interval) = cpu.clock.duration_to_ticks(lockup.config.check_interval()) else {
+            return deadline;
+        };
+
+        deadline.min(cpu.clock.now_ticks().0.saturating_add(interval.0))
+    })
+}
+
+/// Run the lockup checks from the calling CPU's timer interrupt.
+///
+/// Returns `Some` if the interrupted code is a task that has been polling for too long. The caller
+/// is expected to print a backtrace of the interrupted code and, if [`LongPoll::panic`] is set,
+/// unwind it.
+///
+/// Stalled CPUs are asked to print their own backtrace (and panic if [`Config::panic`] is set)
+/// through an IPI, see [`take_backtrace_request`].
+pub fn check() -> Option<LongPoll> {
+    let lockup = LOCKUP.get()?;
+    let now = now_nanos();
+    let this_cpuid = crate::CPUID.get();
+
+    for (cpuid, cpu) in lockup.cpus.iter().enumerate() {
+        if cpuid == this_cpuid {
+            continue;
+        }
+
+        let heartbeat = cpu.heartbeat.load(Ordering::Relaxed);
+        if heartbeat == 0 || cpu.parked.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let stalled_for = Duration::from_nanos(now.saturating_sub(heartbeat));
+        if stalled_for > lockup.config.stall_threshold
+            && !cpu.stall_reported.swap(true, Ordering::Relaxed)
+        {
+            tracing::error!(
+                "LOCKUP: CPU {cpuid} hasn't scheduled for {stalled_for:?}, last polling task {}",
+                cpu.task.load(Ordering::Relaxed)
+            );
+
+            cpu.panic_requested
+                .store(lockup.config.panic, Ordering::Relaxed);
+            cpu.backtrace_requested.store(true, Ordering::Release);
+            // Safety: the software interrupt handler is non-disruptive
+            unsafe {
+                arch::cpu_unpark(cpuid);
+            }
+        }
+    }
+
+    let cpu = &lockup.cpus[this_cpuid];
+    let poll_started = cpu.poll_started.load(Ordering::Acquire);
+    if poll_started == 0 {
+        return None;
+    }
+
+    let duration = Duration::from_nanos(now.saturating_sub(poll_started));
+    if duration <= lockup.config.poll_threshold || cpu.poll_reported.swap(true, Ordering::Relaxed) {
+        return None;
+    }
+
+    // Safety: the pointer was created from a `&'static Location` in `poll_start`
+    let location = unsafe { &*cpu.location.load(Ordering::Relaxed) };
+    let task = Id::from_u64(cpu.task.load(Ordering::Relaxed));
+
+    // the task is still being polled on this CPU, so it is also the current task
+    trace::with_current_task(|current| {
+        let name = current.and_then(|(_, name)| name).unwrap_or("<unnamed>");
+        tracing::error!(
+            "LOCKUP: task {task} ({name}) spawned at {location} has been polling for {duration:?} on CPU {this_cpuid}"
+        );
+    });
+
+    Some(LongPoll {
+        task,
+        location,
+        duration,
+        panic: lockup.config.panic,
+    })
+}
+
+/// Returns `Some` if another CPU requested a backtrace of the calling CPU.
+///
+/// Called from the software interrupt handler, the caller is expected to print a backtrace of the
+/// interrupted code and, if [`BacktraceRequest::panic`] is set, unwind it.
+pub fn take_backtrace_request() -> Option<BacktraceRequest> {
+    let cpu = this_cpu()?;
+    cpu.backtrace_requested
+        .swap(false, Ordering::Acquire)
+        .then(|| BacktraceRequest {
+            panic: cpu.panic_requested.swap(false, Ordering::Relaxed),
+        })
+}
+
+fn this_cpu() -> Option<&'static CpuState> {
+    LOCKUP.get()?.cpus.get(crate::CPUID.get())
+}
+
+fn now_nanos() -> u64 {
+    let now = Instant::now().duration_since(Instant::ZERO);
+    // reserve `0` for "never"
+    u64::try_from(now.as_nanos()).unwrap_or(u64::MAX).max(1)
+}
+
+// === impl Config ===
+
+impl Config {
+    /// How often the lockup checks run.
+    pub fn check_interval(&self) -> Duration {
+        self.poll_threshold.min(self.stall_threshold) / 2
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_threshold: Duration::from_secs(1),
+            stall_threshold: Duration::from_secs(5),
+            panic: false,
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Config {
+            enabled: true,
+            ..Config::default()
+        };
+
+        match s {
+            "off" => {
+                config.enabled = false;
+                return Ok(config);
+            }
+            "on" => return Ok(config),
+            _ => {}
+        }
+
+        for part in s.split(',') {
+            let part = part.trim();
+            if part == "panic" {
+                config.panic = true;
+            } else if let Some(ms) = part.strip_prefix("poll=") {
+                config.poll_threshold = Duration::from_millis(ms.parse()?);
+            } else if let Some(ms) = part.strip_prefix("stall=") {
+                config.stall_threshold = Duration::from_millis(ms.parse()?);
+            } else {
+                bail!("unknown lockup option `{part}`");
+            }
+        }
+
+        if config.poll_threshold.is_zero() || config.stall_threshold.is_zero() {
+            return Err(format_err!("lockup thresholds must be non-zero"));
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::scheduler;
+    use core::hint;
+
+    #[ktest::test]
+    async fn parse_config() {
+        let off = Config::from_str("off").unwrap();
+        assert!(!off.enabled);
+
+        let on = Config::from_str("on").unwrap();
+        assert_eq!(
+            on,
+            Config {
+                enabled: true,
+                ..Config::default()
+            }
+        );
+
+        let config = Config::from_str("poll=500, stall=2000,panic").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                enabled: true,
+                poll_threshold: Duration::from_millis(500),
+                stall_threshold: Duration::from_millis(2000),
+                panic: true,
+            }
+        );
+        assert_eq!(config.check_interval(), Duration::from_millis(250));
+
+        // options that aren't given keep their defaults
+        let config = Config::from_str("stall=100").unwrap();
+        assert_eq!(config.poll_threshold, Config::default().poll_threshold);
+        assert_eq!(config.stall_threshold, Duration::from_millis(100));
+        assert!(!config.panic);
+
+        Config::from_str("poll=0").unwrap_err();
+        Config::from_str("stall=0").unwrap_err();
+        Config::from_str("poll=fast").unwrap_err();
+        Config::from_str("verbose").unwrap_err();
+    }
+
+    #[ktest::test]
+    async fn reports_long_poll() {
+        // The detector can't be turned off again, so this leaves it enabled for the tests that run
+        // after us. Other long polls are only reported, not unwound.
+        init(
+            Config {
+                enabled: true,
+                poll_threshold: Duration::from_millis(500),
+                stall_threshold: Duration::from_secs(60),
+                panic: false,
+            },
+            crate::BOOT_INFO.get().unwrap().cpu_mask,
+        );
+        let config = LOCKUP.get().unwrap().config;
+        assert!(!config.panic, "lockup bootarg would unwind this test");
+
+        // we're being polled right now, but the poll started before the detector was enabled
+        let (task, location) = {
+            let current = scheduler().current_task().unwrap();
+            (current.id(), current.location())
+        };
+        poll_start(task, location);
+        let cpu = this_cpu().unwrap();
+        // rearm the timer so the checks run while we're spinning
+        scheduler().cpu_local_timer().turn_and_arm();
+
+        let deadline = Instant::now() + config.poll_threshold * 2;
+        while !cpu.poll_reported.load(Ordering::Relaxed) && Instant::now() < deadline {
+            hint::spin_loop();
+        }
+
+        assert!(
+            cpu.poll_reported.load(Ordering::Relaxed),
+            "polling for {:?} wasn't reported",
+            config.poll_threshold * 2
+        );
+    }
+}
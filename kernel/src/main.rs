@@ -34,16 +34,23 @@ mod backtrace;
 mod bootargs;
 mod cpu_local;
 mod device_tree;
+mod driver;
+mod entropy;
+mod hotplug;
 mod irq;
+mod lockup;
 mod mem;
 mod metrics;
+mod net;
 mod scheduler;
 mod shell;
+mod shutdown;
 mod sync;
 mod task;
 #[cfg(test)]
 mod tests;
 mod time;
+mod topology;
 mod tracing;
 mod util;
 mod wasm;
@@ -65,7 +72,7 @@ use mem::PhysicalAddress;
 use mem::frame_alloc;
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
-use spin::{Once, OnceLock};
+use spin::{Barrier, Once, OnceLock};
 
 /// The size of the stack in pages
 pub const STACK_SIZE_PAGES: u32 = 256; // TODO find a lower more appropriate value
@@ -114,6 +121,10 @@ fn _start(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) -> ! {
         if backtrace.frames_omitted {
             tracing::warn!("Stack trace was larger than backtrace buffer, omitted some frames.");
         }
+
+        if let Some(seed) = scheduler::deterministic::seed() {
+            tracing::error!("replay this schedule with `sched=deterministic:{seed:#x}`");
+        }
     });
 
     // Unwinding expects at least one landing pad in the callstack, but capturing all unwinds that
@@ -124,11 +135,14 @@ fn _start(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) -> ! {
     });
 
     match res {
-        Ok(_) => arch::exit(0),
+        Ok(_) => shutdown::finish(),
         // If the panic propagates up to this catch here there is nothing we can do, this is a terminal
         // failure.
         Err(_) => {
             tracing::error!("unrecoverable kernel panic");
+            // the log output might still sit in the UART transmit buffer, which is normally
+            // drained by interrupts
+            tracing::flush_panic();
             abort()
         }
     }
@@ -149,6 +163,9 @@ fn kmain(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) {
         // set up the basic functionality of the tracing subsystem as early as possible
         tracing::init_early();
 
+        // seed the entropy pool before anything draws randomness from it
+        entropy::init(boot_info.rng_seed);
+
         // initialize a simple bump allocator for allocating memory before our virtual memory subsystem
         // is available
         let allocatable_memories = allocatable_memory_regions(boot_info);
@@ -163,12 +180,28 @@ fn kmain(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) {
 
         let bootargs = bootargs::parse(devtree).unwrap();
 
+        // discover the NUMA topology of the system so the frame allocator can build per-node arenas
+        let topology = topology::init(devtree).unwrap();
+        tracing::debug!("{topology:?}");
+
         // initialize the backtracing subsystem after the allocator has been set up
         // since setting up the symbolization context requires allocation
         backtrace::init(boot_info, bootargs.backtrace);
 
         // fully initialize the tracing subsystem now that we can allocate
-        tracing::init(bootargs.log);
+        tracing::init(bootargs.log, bootargs.log_output);
+
+        // set up the lockup detector before any CPU starts running tasks
+        lockup::init(bootargs.lockup, boot_info.cpu_mask);
+
+        // decide whether tasks are scheduled in parallel or in a reproducible order
+        scheduler::deterministic::init(bootargs.sched, &mut rng);
+
+        // decide what idle CPUs do while waiting for work
+        hotplug::init(bootargs.idle);
+
+        // the network interface is only set up once a network device has been probed
+        net::init(bootargs.net, &mut rng);
 
         // perform global, architecture-specific initialization
         arch::init_early();
@@ -176,7 +209,7 @@ fn kmain(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) {
         // initialize the global frame allocator
         // at this point we have parsed and processed the flattened device tree, so we pass it to the
         // frame allocator for reuse
-        let frame_alloc = frame_alloc::init(boot_alloc, fdt_region_phys);
+        let frame_alloc = frame_alloc::init(boot_alloc, fdt_region_phys, topology);
 
         // initialize the virtual memory subsystem
         mem::init(boot_info, &mut rng, frame_alloc).unwrap();
@@ -186,12 +219,33 @@ fn kmain(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) {
     // (e.g. setting the trap vector and enabling interrupts)
     arch::per_cpu_init_late(device_tree()).unwrap();
 
+    // anchor the wall-clock time now that the CPU-local clock is available
+    time::rtc::init(device_tree()).unwrap();
+
+    // once every CPU has made it into the kernel nothing references the loader anymore, so we can
+    // remove its identity mapping and reclaim its memory
+    static LOADER_RECLAIM: OnceLock<Barrier> = OnceLock::new();
+    let barrier =
+        LOADER_RECLAIM.get_or_init(|| Barrier::new(boot_info.cpu_mask.count_ones() as usize));
+    if barrier.wait().is_leader() {
+        // Safety: all CPUs passed the barrier above
+        unsafe {
+            mem::reclaim_loader_memory(boot_info, frame_alloc::FRAME_ALLOC.get().unwrap()).unwrap();
+        }
+    }
+
     // now that clocks are online we can make the tracing subsystem print out timestamps
     tracing::per_cpu_init_late(Instant::from_ticks(Ticks(boot_ticks)));
 
     // initialize the executor
     let _sched = scheduler::init(boot_info.cpu_mask.count_ones() as usize);
 
+    // bind devices to their drivers, this happens in the background once the CPUs start scheduling
+    driver::init(device_tree(), _sched);
+
+    // keep the entropy pool fresh with entropy from the devices found above
+    entropy::start(_sched);
+
     tracing::info!(
         "Booted in ~{:?} ({:?} in k23)",
         Instant::now().duration_since(Instant::ZERO),
@@ -200,19 +254,33 @@ fn kmain(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) {
 
     cfg_if! {
         if #[cfg(test)] {
-            if cpuid == 0 {
-                _sched.block_on(tests::run_tests()).exit_if_failed();
-                _sched.shutdown();
-            } else {
-                scheduler::Worker::new(_sched, cpuid, &mut rng).run();
+            match (scheduler::deterministic::seed(), cpuid) {
+                (None, 0) => {
+                    _sched.block_on(tests::run_tests()).exit_if_failed();
+                    _sched.shutdown();
+                }
+                (None, _) => scheduler::Worker::new(_sched, cpuid, &mut rng).run(),
+                (Some(seed), 0) => {
+                    if let Some(report) = _sched.run_deterministic(seed, tests::run_tests()) {
+                        report.exit_if_failed();
+                    }
+                    _sched.shutdown();
+                }
+                (Some(_), _) => scheduler::deterministic::park_forever(),
             }
         } else {
-            shell::init(
-                device_tree(),
-                _sched,
-                boot_info.cpu_mask.count_ones() as usize,
-            );
-            scheduler::Worker::new(_sched, cpuid, &mut rng).run();
+            match scheduler::deterministic::seed() {
+                None => {
+                    shell::init(_sched, boot_info.cpu_mask.count_ones() as usize);
+                    scheduler::Worker::new(_sched, cpuid, &mut rng).run();
+                }
+                Some(seed) if cpuid == 0 => {
+                    // all other CPUs are parked, so they won't take part in the shell's barrier
+                    shell::init(_sched, 1);
+                    _sched.run_deterministic(seed, core::future::pending::<()>());
+                }
+                Some(_) => scheduler::deterministic::park_forever(),
+            }
         }
     }
 
@@ -283,7 +351,6 @@ fn kmain(cpuid: usize, boot_info: &'static BootInfo, boot_ticks: u64) {
 
     // - [all][global] parse cmdline
     // - [all][global] `lockup::init()` initialize lockup detector
-    // - [all][global] `topology::init()` initialize the system topology
     // - `kernel_shell_init()`
     // - `userboot_init()`
 }
@@ -6,8 +6,9 @@
 // copied, modified, or distributed except according to those terms.
 
 use crate::arch;
+use crate::entropy::ReseedingRng;
 use crate::mem::address_space_region::AddressSpaceRegion;
-use crate::mem::frame_alloc::FrameAllocator;
+use crate::mem::frame_alloc::{Frame, FrameAllocator};
 use crate::mem::{
     AddressRangeExt, ArchAddressSpace, Flush, PageFaultFlags, Permissions, PhysicalAddress,
     VirtualAddress,
@@ -18,15 +19,14 @@ use alloc::vec;
 use alloc::vec::Vec;
 use anyhow::{bail, ensure};
 use core::alloc::Layout;
-use core::fmt;
 use core::num::NonZeroUsize;
 use core::ops::DerefMut;
 use core::pin::Pin;
 use core::ptr::NonNull;
 use core::range::{Bound, Range, RangeBounds, RangeInclusive};
+use core::{fmt, mem};
 use rand::Rng;
 use rand::distr::Uniform;
-use rand_chacha::ChaCha20Rng;
 
 // const VIRT_ALLOC_ENTROPY: u8 = u8::try_from((arch::VIRT_ADDR_BITS - arch::PAGE_SHIFT as u32) + 1).unwrap();
 const VIRT_ALLOC_ENTROPY: u8 = 27;
@@ -47,7 +47,7 @@ pub struct AddressSpace {
     max_range: RangeInclusive<VirtualAddress>,
     /// The pseudo-random number generator used for address space layout randomization or `None`
     /// if ASLR is disabled.
-    rng: Option<ChaCha20Rng>,
+    rng: Option<ReseedingRng>,
     /// The hardware address space backing this "logical" address space that changes need to be
     /// materialized into in order to take effect.
     pub arch: arch::AddressSpace,
@@ -81,11 +81,10 @@ impl fmt::Debug for AddressSpace {
 
 impl AddressSpace {
     pub fn new_user(
-        asid: u16,
-        rng: Option<ChaCha20Rng>,
+        rng: Option<ReseedingRng>,
         frame_alloc: &'static FrameAllocator,
     ) -> crate::Result<Self> {
-        let (arch, _) = arch::AddressSpace::new(asid, frame_alloc)?;
+        let (arch, _) = arch::AddressSpace::new(frame_alloc)?;
 
         #[allow(tail_expr_drop_order, reason = "")]
         Ok(Self {
@@ -101,7 +100,7 @@ impl AddressSpace {
 
     pub unsafe fn from_active_kernel(
         arch_aspace: arch::AddressSpace,
-        rng: Option<ChaCha20Rng>,
+        rng: Option<ReseedingRng>,
         frame_alloc: &'static FrameAllocator,
     ) -> Self {
         #[allow(tail_expr_drop_order, reason = "")]
@@ -260,6 +259,7 @@ impl AddressSpace {
         // Safety: caller has to ensure invariants are checked
         unsafe {
             self.arch.unmap(
+                self.frame_alloc,
                 range.start,
                 NonZeroUsize::new(range.size()).unwrap(),
                 &mut flush,
@@ -326,6 +326,7 @@ impl AddressSpace {
         // Safety: caller has to ensure invariants are checked
         unsafe {
             self.arch.update_flags(
+                self.frame_alloc,
                 range.start,
                 NonZeroUsize::new(range.size()).unwrap(),
                 new_permissions.into(),
@@ -427,13 +428,18 @@ impl AddressSpace {
         if permissions.is_empty() {
             // Safety: we checked all invariants above
             unsafe {
-                self.arch
-                    .unmap(range.start, NonZeroUsize::new(range.size()).unwrap(), flush)?;
+                self.arch.unmap(
+                    self.frame_alloc,
+                    range.start,
+                    NonZeroUsize::new(range.size()).unwrap(),
+                    flush,
+                )?;
             }
         } else {
             // Safety: we checked all invariants above
             unsafe {
                 self.arch.update_flags(
+                    self.frame_alloc,
                     range.start,
                     NonZeroUsize::new(range.size()).unwrap(),
                     permissions.into(),
@@ -733,6 +739,11 @@ pub struct Batch<'a> {
     range: Range<VirtualAddress>,
     flags: <arch::AddressSpace as ArchAddressSpace>::Flags,
     actions: Vec<BBatchAction>,
+    /// TLB flush accumulated across all actions applied by this batch, so we only need to perform
+    /// a single (potentially cross-CPU) flush at the very end.
+    flush: Flush,
+    /// Frames that are no longer mapped once this batch is applied, see [`Self::queue_free`].
+    retired_frames: Vec<Frame>,
 }
 
 #[derive(Debug)]
@@ -742,21 +753,31 @@ enum BBatchAction {
 
 impl Drop for Batch<'_> {
     fn drop(&mut self) {
-        if !self.actions.is_empty() {
+        if !self.actions.is_empty() || !self.retired_frames.is_empty() {
             tracing::error!("batch was not flushed before dropping");
             // panic_unwind::panic_in_drop!("batch was not flushed before dropping");
+
+            // other CPUs might still access the retired frames through stale TLB entries, leaking
+            // them is the only safe option
+            for frame in self.retired_frames.drain(..) {
+                mem::forget(frame);
+            }
         }
     }
 }
 
 impl<'a> Batch<'a> {
     pub fn new(aspace: &'a mut arch::AddressSpace, frame_alloc: &'static FrameAllocator) -> Self {
+        let flush = aspace.new_flush();
+
         Self {
             aspace,
             frame_alloc,
             range: Range::default(),
             flags: <arch::AddressSpace as ArchAddressSpace>::Flags::empty(),
             actions: vec![],
+            flush,
+            retired_frames: vec![],
         }
     }
 
@@ -774,7 +795,7 @@ impl<'a> Batch<'a> {
 
         tracing::trace!("appending {phys:?} at {virt:?} with flags {flags:?}");
         if self.range.end != virt || self.flags != flags {
-            self.flush()?;
+            self.apply()?;
             self.flags = flags;
             self.range = Range::from(virt..virt.checked_add(len.get()).unwrap());
         } else {
@@ -786,13 +807,74 @@ impl<'a> Batch<'a> {
         Ok(())
     }
 
+    /// Release `frames` once the queued actions have been applied and the TLB has been flushed.
+    ///
+    /// This is used for frames that are still mapped right now, but are replaced by one of the
+    /// queued actions. Freeing them any earlier would allow other CPUs to access the freed frames
+    /// through the old page table entries or their cached translations.
+    pub fn queue_free(&mut self, frames: impl IntoIterator<Item = Frame>) {
+        self.retired_frames.extend(frames);
+    }
+
+    /// Apply all queued actions, then unmap `range` and flush the TLB right away.
+    ///
+    /// Once this returns, no CPU can access the memory previously mapped at `range` anymore. This
+    /// is used before copying frames that are still mapped, so no writes are lost.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure `range` belongs to the region that is being modified through this
+    /// batch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the queued actions could not be applied, the range could not be
+    /// unmapped or the TLB could not be flushed.
+    pub unsafe fn unmap_and_flush(&mut self, range: Range<VirtualAddress>) -> crate::Result<()> {
+        self.apply()?;
+
+        // Safety: ensured by caller
+        unsafe {
+            self.aspace.unmap(
+                self.frame_alloc,
+                range.start,
+                NonZeroUsize::new(range.size()).unwrap(),
+                &mut self.flush,
+            )?;
+        }
+
+        let flush = mem::replace(&mut self.flush, self.aspace.new_flush());
+        flush.flush()
+    }
+
+    /// Apply all queued actions and flush the TLB for all changed addresses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the queued actions could not be applied or the TLB could not be
+    /// flushed.
     pub fn flush(&mut self) -> crate::Result<()> {
+        self.apply()?;
+
+        if self.flush.range().is_some() {
+            let flush = mem::replace(&mut self.flush, self.aspace.new_flush());
+            flush.flush()?;
+        }
+
+        // no CPU can reach the retired frames anymore
+        self.retired_frames.clear();
+
+        Ok(())
+    }
+
+    /// Apply all queued actions to the page tables, but defer the TLB flush until [`Self::flush`]
+    /// is called.
+    fn apply(&mut self) -> crate::Result<()> {
         if self.actions.is_empty() {
             return Ok(());
         }
-        tracing::trace!("flushing batch {:?} {:?}...", self.range, self.actions);
+        tracing::trace!("applying batch {:?} {:?}...", self.range, self.actions);
 
-        let mut flush = self.aspace.new_flush();
         let mut virt = self.range.start;
         for action in self.actions.drain(..) {
             match action {
@@ -804,13 +886,12 @@ impl<'a> Batch<'a> {
                         phys,
                         NonZeroUsize::new(len).unwrap(),
                         self.flags,
-                        &mut flush,
+                        &mut self.flush,
                     )?;
                     virt = virt.checked_add(len).unwrap();
                 },
             }
         }
-        flush.flush()?;
 
         self.range = Range::from(self.range.end..self.range.end);
         Ok(())
@@ -820,3 +901,257 @@ impl<'a> Batch<'a> {
         self.actions.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CPUID;
+    use crate::mem::frame_alloc::FRAME_ALLOC;
+    use crate::scheduler::scheduler;
+    use alloc::sync::Arc;
+    use spin::Mutex;
+
+    type Flags = <arch::AddressSpace as ArchAddressSpace>::Flags;
+
+    /// Returns a new user address space with a read-write region spanning exactly one huge page.
+    fn aspace_with_huge_region() -> (AddressSpace, Range<VirtualAddress>) {
+        let frame_alloc = FRAME_ALLOC.get().unwrap();
+        let mut aspace = AddressSpace::new_user(None, frame_alloc).unwrap();
+
+        let layout = Layout::from_size_align(arch::HUGE_PAGE_SIZE, arch::HUGE_PAGE_SIZE).unwrap();
+        let range = aspace
+            .map(
+                layout,
+                Permissions::READ | Permissions::WRITE,
+                |range, permissions, _batch| {
+                    Ok(AddressSpaceRegion::new_zeroed(
+                        frame_alloc,
+                        range,
+                        permissions,
+                        None,
+                    ))
+                },
+            )
+            .unwrap()
+            .range;
+
+        (aspace, range)
+    }
+
+    fn query(aspace: &mut AddressSpace, addr: VirtualAddress) -> Option<(PhysicalAddress, Flags)> {
+        // Safety: we only read the page tables
+        unsafe { aspace.arch.query(addr) }
+    }
+
+    fn page(range: Range<VirtualAddress>, index: usize) -> VirtualAddress {
+        range.start.checked_add(index * arch::PAGE_SIZE).unwrap()
+    }
+
+    /// Returns whether `range` is mapped to a naturally aligned, physically contiguous block.
+    fn is_huge_block(aspace: &mut AddressSpace, range: Range<VirtualAddress>) -> bool {
+        let Some((base, _)) = query(aspace, range.start) else {
+            return false;
+        };
+
+        base.is_aligned_to(arch::HUGE_PAGE_SIZE)
+            && range
+                .iter()
+                .step_by(arch::PAGE_SIZE)
+                .enumerate()
+                .all(|(idx, addr)| {
+                    query(aspace, addr).is_some_and(|(phys, _)| {
+                        phys == base.checked_add(idx * arch::PAGE_SIZE).unwrap()
+                    })
+                })
+    }
+
+    /// Reads the `u64` at `addr` with `aspace` active on the CPU `cpuid`.
+    async fn read_on(cpuid: usize, aspace: &Arc<Mutex<AddressSpace>>, addr: VirtualAddress) -> u64 {
+        let aspace = aspace.clone();
+        scheduler()
+            .spawn_on(cpuid, async move {
+                // Safety: the user address space also maps the kernel and the worker activates the
+                // kernel address space again before polling the next task
+                unsafe { aspace.lock().activate() }
+                // Safety: the caller ensures `addr` is mapped readable
+                unsafe { addr.as_ptr().cast::<u64>().read_volatile() }
+            })
+            .await
+            .unwrap()
+    }
+
+    #[ktest::test]
+    async fn fault_promotes_to_huge_page() {
+        let (mut aspace, range) = aspace_with_huge_region();
+        let last_page = page(range, arch::HUGE_PAGE_SIZE / arch::PAGE_SIZE - 1);
+
+        // a read fault maps the shared zero frame, so the block can't be backed by a huge page
+        // right away and has to be faulted in page by page
+        aspace.page_fault(last_page, PageFaultFlags::LOAD).unwrap();
+        assert!(!is_huge_block(&mut aspace, range));
+
+        let marker_page = page(range, 1);
+        for addr in range.iter().step_by(arch::PAGE_SIZE) {
+            aspace.page_fault(addr, PageFaultFlags::STORE).unwrap();
+
+            if addr == marker_page {
+                let (phys, _) = query(&mut aspace, addr).unwrap();
+                let marker = VirtualAddress::from_phys(phys).unwrap();
+                // Safety: the frame is owned by the region and mapped through the physmap
+                unsafe {
+                    marker
+                        .as_mut_ptr()
+                        .cast::<u64>()
+                        .write_volatile(0xdead_beef)
+                }
+            }
+        }
+
+        // the fault that made the block fully resident promoted it
+        assert!(is_huge_block(&mut aspace, range));
+
+        // and the contents survived collapsing the block into a new huge frame
+        let (phys, _) = query(&mut aspace, marker_page).unwrap();
+        let marker = VirtualAddress::from_phys(phys).unwrap();
+        // Safety: the frame is owned by the region and mapped through the physmap
+        let value = unsafe { marker.as_ptr().cast::<u64>().read_volatile() };
+        assert_eq!(value, 0xdead_beef);
+    }
+
+    #[ktest::test]
+    async fn partial_protect_and_unmap_split_huge_page() {
+        let (mut aspace, range) = aspace_with_huge_region();
+        let frame_alloc = aspace.frame_alloc;
+
+        // the first write to a vacant block backs it with a huge page
+        aspace
+            .page_fault(range.start, PageFaultFlags::STORE)
+            .unwrap();
+        assert!(is_huge_block(&mut aspace, range));
+        let (base, _) = query(&mut aspace, range.start).unwrap();
+
+        // write-protecting a single page splits the huge page
+        let mut flush = aspace.arch.new_flush();
+        // Safety: the page belongs to the region we just mapped
+        unsafe {
+            aspace
+                .arch
+                .update_flags(
+                    frame_alloc,
+                    page(range, 1),
+                    NonZeroUsize::new(arch::PAGE_SIZE).unwrap(),
+                    Permissions::READ.into(),
+                    &mut flush,
+                )
+                .unwrap();
+        }
+        flush.flush().unwrap();
+
+        let write = Flags::from(Permissions::WRITE);
+        let (_, flags) = query(&mut aspace, page(range, 0)).unwrap();
+        assert!(flags.contains(write));
+        let (_, flags) = query(&mut aspace, page(range, 1)).unwrap();
+        assert!(!flags.contains(write));
+        let (_, flags) = query(&mut aspace, page(range, 2)).unwrap();
+        assert!(flags.contains(write));
+
+        // unmapping a single page leaves the rest of the block mapped
+        let mut flush = aspace.arch.new_flush();
+        // Safety: the page belongs to the region we just mapped
+        unsafe {
+            aspace
+                .arch
+                .unmap(
+                    frame_alloc,
+                    page(range, 2),
+                    NonZeroUsize::new(arch::PAGE_SIZE).unwrap(),
+                    &mut flush,
+                )
+                .unwrap();
+        }
+        flush.flush().unwrap();
+        assert!(query(&mut aspace, page(range, 2)).is_none());
+
+        // the remaining pages still map the same physical memory
+        for idx in [0, 1, 3] {
+            let (phys, _) = query(&mut aspace, page(range, idx)).unwrap();
+            assert_eq!(phys, base.checked_add(idx * arch::PAGE_SIZE).unwrap());
+        }
+    }
+    #[ktest::test]
+    async fn promotion_frees_sub_table_after_remote_flush() {
+        let (mut aspace, range) = aspace_with_huge_region();
+        let frame_alloc = aspace.frame_alloc;
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        let remote = (0..usize::BITS as usize)
+            .find(|cpuid| *cpuid != CPUID.get() && cpu_mask & (1 << cpuid) != 0)
+            .unwrap_or(CPUID.get());
+
+        // a read fault maps the first page through a sub-table of 4KiB leaves
+        aspace
+            .page_fault(range.start, PageFaultFlags::LOAD)
+            .unwrap();
+        assert!(!is_huge_block(&mut aspace, range));
+
+        // let the other CPU walk (and possibly cache) the sub-table
+        let aspace = Arc::new(Mutex::new(aspace));
+        assert_eq!(read_on(remote, &aspace, range.start).await, 0);
+
+        let layout = Layout::from_size_align(arch::HUGE_PAGE_SIZE, arch::HUGE_PAGE_SIZE).unwrap();
+        let huge = frame_alloc.alloc_contiguous_zeroed(layout).unwrap();
+        let base = huge.first().unwrap().addr();
+        let marker = VirtualAddress::from_phys(base)
+            .unwrap()
+            .checked_add(arch::PAGE_SIZE)
+            .unwrap();
+        // Safety: we just allocated the frames, they are accessible through the physmap
+        unsafe {
+            marker
+                .as_mut_ptr()
+                .cast::<u64>()
+                .write_volatile(0xdead_beef)
+        }
+
+        // promoting the range to a huge page unlinks the sub-table
+        {
+            let mut aspace = aspace.lock();
+            let mut flush = aspace.arch.new_flush();
+            // Safety: the range belongs to the region and `huge` outlives the mapping
+            unsafe {
+                aspace
+                    .arch
+                    .map_contiguous(
+                        frame_alloc,
+                        range.start,
+                        base,
+                        NonZeroUsize::new(arch::HUGE_PAGE_SIZE).unwrap(),
+                        Flags::from(Permissions::READ | Permissions::WRITE),
+                        &mut flush,
+                    )
+                    .unwrap();
+            }
+            flush.flush().unwrap();
+            assert!(is_huge_block(&mut aspace, range));
+        }
+
+        // a page the other CPU never accessed has to be translated through the huge page and not
+        // through the released sub-table
+        assert_eq!(read_on(remote, &aspace, page(range, 1)).await, 0xdead_beef);
+
+        let mut aspace = aspace.lock();
+        let mut flush = aspace.arch.new_flush();
+        // Safety: unmap `huge` again before it is released
+        unsafe {
+            aspace
+                .arch
+                .unmap(
+                    frame_alloc,
+                    range.start,
+                    NonZeroUsize::new(arch::HUGE_PAGE_SIZE).unwrap(),
+                    &mut flush,
+                )
+                .unwrap();
+        }
+        flush.flush().unwrap();
+    }
+}
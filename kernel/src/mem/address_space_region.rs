@@ -126,8 +126,8 @@ impl AddressSpaceRegion {
         will_write: bool,
     ) -> crate::Result<()> {
         let vmo_relative_range = Range {
-            start: range.start.checked_sub_addr(self.range.start).unwrap(),
-            end: range.end.checked_sub_addr(self.range.start).unwrap(),
+            start: self.vmo_relative_offset(range.start),
+            end: self.vmo_relative_offset(range.end),
         };
 
         match self.vmo.as_ref() {
@@ -148,9 +148,28 @@ impl AddressSpaceRegion {
                 if will_write {
                     let mut vmo = vmo.write();
 
-                    for addr in range.iter().step_by(arch::PAGE_SIZE) {
+                    let mut addr = range.start;
+                    while addr < range.end {
                         debug_assert!(addr.is_aligned_to(arch::PAGE_SIZE));
-                        let vmo_relative_offset = addr.checked_sub_addr(self.range.start).unwrap();
+                        let vmo_relative_offset = self.vmo_relative_offset(addr);
+
+                        // If the commit covers an entire huge page block, attempt to back it
+                        // by a single huge page first
+                        if let Some(block) = self.huge_page_block(addr)
+                            && block.start == addr
+                            && block.end <= range.end
+                            && let Some(phys) = vmo.require_owned_huge_frame(vmo_relative_offset)
+                        {
+                            batch.queue_map(
+                                addr,
+                                phys,
+                                NonZeroUsize::new(arch::HUGE_PAGE_SIZE).unwrap(),
+                                self.permissions.into(),
+                            )?;
+                            addr = block.end;
+                            continue;
+                        }
+
                         let frame = vmo.require_owned_frame(vmo_relative_offset)?;
                         batch.queue_map(
                             addr,
@@ -158,13 +177,14 @@ impl AddressSpaceRegion {
                             NonZeroUsize::new(arch::PAGE_SIZE).unwrap(),
                             self.permissions.into(),
                         )?;
+                        addr = addr.checked_add(arch::PAGE_SIZE).unwrap();
                     }
                 } else {
                     let mut vmo = vmo.write();
 
                     for addr in range.iter().step_by(arch::PAGE_SIZE) {
                         debug_assert!(addr.is_aligned_to(arch::PAGE_SIZE));
-                        let vmo_relative_offset = addr.checked_sub_addr(self.range.start).unwrap();
+                        let vmo_relative_offset = self.vmo_relative_offset(addr);
                         let frame = vmo.require_read_frame(vmo_relative_offset)?;
                         batch.queue_map(
                             addr,
@@ -192,16 +212,8 @@ impl AddressSpaceRegion {
             }
             Vmo::Paged(vmo) => {
                 let vmo_relative_range = Range {
-                    start: range
-                        .start
-                        .checked_sub_addr(self.range.start)
-                        .and_then(|start| start.checked_add(self.vmo_offset))
-                        .unwrap(),
-                    end: range
-                        .end
-                        .checked_sub_addr(self.range.start)
-                        .and_then(|end| end.checked_add(self.vmo_offset))
-                        .unwrap(),
+                    start: self.vmo_relative_offset(range.start),
+                    end: self.vmo_relative_offset(range.end),
                 };
 
                 let mut vmo = vmo.write();
@@ -258,11 +270,37 @@ impl AddressSpaceRegion {
         // it is always mapped, cannot be paged-out, and also doesn't support COW. This is used to
         // simplify handling of regions like kernel memory which must always be present anyway.
 
-        let vmo_relative_offset = addr.checked_sub_addr(self.range.start).unwrap();
+        let vmo_relative_offset = self.vmo_relative_offset(addr);
+
+        // Large mappings are opportunistically backed by huge pages to reduce TLB pressure. This is
+        // possible when the huge page aligned block around the faulting address is fully contained
+        // within this region.
+        let huge_page_block = self.huge_page_block(addr);
 
         match self.vmo.as_ref() {
             Vmo::Wired => unreachable!("Wired VMO can never page fault"),
             Vmo::Phys(vmo) => {
+                if let Some(block) = huge_page_block {
+                    let block_offset = vmo_relative_offset
+                        .checked_sub(addr.checked_sub_addr(block.start).unwrap())
+                        .unwrap();
+                    let range_phys = vmo.lookup_contiguous(Range::from(
+                        block_offset..block_offset.checked_add(arch::HUGE_PAGE_SIZE).unwrap(),
+                    ));
+
+                    if let Ok(range_phys) = range_phys
+                        && range_phys.start.is_aligned_to(arch::HUGE_PAGE_SIZE)
+                    {
+                        batch.queue_map(
+                            block.start,
+                            range_phys.start,
+                            NonZeroUsize::new(arch::HUGE_PAGE_SIZE).unwrap(),
+                            self.permissions.into(),
+                        )?;
+                        return Ok(());
+                    }
+                }
+
                 let range_phys = vmo
                     .lookup_contiguous(Range::from(
                         vmo_relative_offset
@@ -281,10 +319,54 @@ impl AddressSpaceRegion {
                 if flags.cause_is_write() {
                     let mut vmo = vmo.write();
 
-                    let frame = vmo.require_owned_frame(vmo_relative_offset)?;
+                    let huge_page_block = huge_page_block.map(|block| {
+                        let block_offset = vmo_relative_offset
+                            .checked_sub(addr.checked_sub_addr(block.start).unwrap())
+                            .unwrap();
+                        (block, block_offset)
+                    });
+
+                    // The first write to a vacant block allocates a whole huge page right away
+                    if let Some((block, block_offset)) = huge_page_block
+                        && let Some(phys) = vmo.require_owned_huge_frame(block_offset)
+                    {
+                        batch.queue_map(
+                            block.start,
+                            phys,
+                            NonZeroUsize::new(arch::HUGE_PAGE_SIZE).unwrap(),
+                            self.permissions.into(),
+                        )?;
+                        return Ok(());
+                    }
+
+                    let frame_addr = vmo.require_owned_frame(vmo_relative_offset)?.addr();
+
+                    // Faulting in this frame might have made the block fully resident, in which
+                    // case we can promote it to a huge page
+                    if let Some((block, block_offset)) = huge_page_block
+                        && let Some((phys, old_frames)) =
+                            vmo.promote_huge_frame(block_offset, || {
+                                // The old frames are copied while collapsing the block, so they
+                                // must not be writable anymore.
+                                // Safety: the block belongs to this region and is mapped again
+                                // right below
+                                unsafe { batch.unmap_and_flush(block) }
+                            })?
+                    {
+                        tracing::trace!("promoting {block:?} to huge page");
+                        batch.queue_map(
+                            block.start,
+                            phys,
+                            NonZeroUsize::new(arch::HUGE_PAGE_SIZE).unwrap(),
+                            self.permissions.into(),
+                        )?;
+                        batch.queue_free(old_frames);
+                        return Ok(());
+                    }
+
                     batch.queue_map(
                         addr,
-                        frame.addr(),
+                        frame_addr,
                         NonZeroUsize::new(arch::PAGE_SIZE).unwrap(),
                         self.permissions.into(),
                     )?;
@@ -308,6 +390,29 @@ impl AddressSpaceRegion {
         Ok(())
     }
 
+    /// Returns the offset of `addr` into the backing VMO.
+    fn vmo_relative_offset(&self, addr: VirtualAddress) -> usize {
+        addr.checked_sub_addr(self.range.start)
+            .and_then(|offset| offset.checked_add(self.vmo_offset))
+            .unwrap()
+    }
+
+    /// Returns the [`arch::HUGE_PAGE_SIZE`] aligned block of virtual memory around `addr` if it could
+    /// be mapped using a single huge page.
+    ///
+    /// This is the case when the block lies entirely within this region and its offset into the
+    /// backing VMO is equally aligned.
+    fn huge_page_block(&self, addr: VirtualAddress) -> Option<Range<VirtualAddress>> {
+        let start = addr.align_down(arch::HUGE_PAGE_SIZE);
+        let end = start.checked_add(arch::HUGE_PAGE_SIZE)?;
+        if start < self.range.start || end > self.range.end {
+            return None;
+        }
+
+        (self.vmo_relative_offset(start) % arch::HUGE_PAGE_SIZE == 0)
+            .then_some(Range::from(start..end))
+    }
+
     #[expect(clippy::undocumented_unsafe_blocks, reason = "intrusive tree access")]
     fn update(mut node: NonNull<Self>, left: Option<NonNull<Self>>, right: Option<NonNull<Self>>) {
         let node = unsafe { node.as_mut() };
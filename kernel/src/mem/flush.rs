@@ -7,33 +7,58 @@
 
 use crate::arch;
 use crate::mem::address::VirtualAddress;
+use crate::mem::frame_alloc::Frame;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use anyhow::bail;
 use core::range::Range;
+use core::sync::atomic::AtomicUsize;
 use core::{cmp, mem};
 
 #[must_use]
 pub struct Flush {
     asid: u16,
+    /// Bitmask of CPUs that might hold stale translations for the address space and need to be
+    /// included in the flush.
+    ///
+    /// This is shared with the address space and only read when flushing, so CPUs that activate
+    /// the address space while its page tables are being changed are included too.
+    active_cpus: Arc<AtomicUsize>,
     range: Option<Range<VirtualAddress>>,
+    /// Page table frames that were unlinked from the page tables, see [`Self::retire_pgtable`].
+    retired_pgtables: Vec<Frame>,
 }
 
 impl Drop for Flush {
     fn drop(&mut self) {
-        if self.range.is_some() {
+        if self.range.is_some() || !self.retired_pgtables.is_empty() {
             tracing::error!("dropped Flush without calling ignore/flush");
+
+            // other CPUs might still be walking the retired page tables, leaking them is the only
+            // safe option
+            for frame in self.retired_pgtables.drain(..) {
+                mem::forget(frame);
+            }
         }
     }
 }
 
 impl Flush {
-    pub fn empty(asid: u16) -> Self {
-        Self { asid, range: None }
+    pub fn empty(asid: u16, active_cpus: Arc<AtomicUsize>) -> Self {
+        Self {
+            asid,
+            active_cpus,
+            range: None,
+            retired_pgtables: Vec::new(),
+        }
     }
 
-    pub fn new(asid: u16, range: Range<VirtualAddress>) -> Self {
+    pub fn new(asid: u16, active_cpus: Arc<AtomicUsize>, range: Range<VirtualAddress>) -> Self {
         Self {
             asid,
+            active_cpus,
             range: Some(range),
+            retired_pgtables: Vec::new(),
         }
     }
 
@@ -41,15 +66,40 @@ impl Flush {
         self.range.as_ref()
     }
 
+    /// Release the page table `frame` once the TLB has been flushed.
+    ///
+    /// A CPU may cache non-leaf page table entries and keep walking a page table after it has been
+    /// unlinked, until it executes a fence that covers *all* addresses. Flushing only the range of
+    /// changed leaf entries is not enough, so a `Flush` with retired page tables invalidates the
+    /// entire address space instead.
+    pub fn retire_pgtable(&mut self, frame: Frame) {
+        self.retired_pgtables.push(frame);
+    }
+
     /// Flush the range of virtual addresses from the TLB.
     ///
+    /// If page tables were retired through [`Self::retire_pgtable`] the entire address space is
+    /// flushed instead and the page tables are freed afterward.
+    ///
     /// # Errors
     ///
     /// Returns an error if the range could not be flushed due to an underlying hardware error.
     pub fn flush(mut self) -> crate::Result<()> {
-        if let Some(range) = self.range.take() {
+        let range = self.range.take();
+
+        if !self.retired_pgtables.is_empty() {
+            tracing::trace!(
+                asid = self.asid,
+                pgtables = self.retired_pgtables.len(),
+                "flushing address space"
+            );
+            arch::invalidate_all(self.asid, &self.active_cpus)?;
+
+            // no CPU can reach the retired page tables anymore
+            self.retired_pgtables.clear();
+        } else if let Some(range) = range {
             tracing::trace!(?range, asid = self.asid, "flushing range");
-            arch::invalidate_range(self.asid, range)?;
+            arch::invalidate_range(self.asid, &self.active_cpus, range)?;
         } else {
             tracing::warn!("attempted to flush empty range, ignoring");
         }
@@ -8,6 +8,7 @@
 use super::frame::FrameInfo;
 use crate::arch;
 use crate::mem::address::{AddressRangeExt, PhysicalAddress, VirtualAddress};
+use crate::topology::NodeId;
 use core::alloc::Layout;
 use core::mem::MaybeUninit;
 use core::ptr::NonNull;
@@ -23,16 +24,34 @@ const MAX_ORDER: usize = 11;
 pub struct Arena {
     free_lists: [linked_list::List<FrameInfo>; MAX_ORDER],
     range: Range<PhysicalAddress>,
+    /// The NUMA node this arena's memory belongs to.
+    node: NodeId,
     slots: &'static mut [MaybeUninit<FrameInfo>],
     max_order: usize,
     used_frames: usize,
     total_frames: usize,
+    /// Number of frames handed out to CPUs on the same NUMA node.
+    local_frames: usize,
+    /// Number of frames handed out to CPUs on other NUMA nodes.
+    remote_frames: usize,
+}
+
+/// Allocation statistics of an arena.
+#[derive(Debug, Clone)]
+pub struct ArenaStats {
+    pub range: Range<PhysicalAddress>,
+    pub node: NodeId,
+    pub total_frames: usize,
+    pub used_frames: usize,
+    pub local_frames: usize,
+    pub remote_frames: usize,
 }
 
 impl fmt::Debug for Arena {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Arena")
             .field("range", &self.range)
+            .field("node", &self.node)
             .field(
                 "slots",
                 &format_args!("&[MaybeUninit<FrameInner>; {}]", self.slots.len()),
@@ -48,12 +67,14 @@ impl fmt::Debug for Arena {
             .field("max_order", &self.max_order)
             .field("used_frames", &self.used_frames)
             .field("total_frames", &self.total_frames)
+            .field("local_frames", &self.local_frames)
+            .field("remote_frames", &self.remote_frames)
             .finish()
     }
 }
 
 impl Arena {
-    pub fn from_selection(selection: ArenaSelection) -> Self {
+    pub fn from_selection(selection: ArenaSelection, node: NodeId) -> Self {
         debug_assert!(selection.bookkeeping.size() >= bookkeeping_size(selection.arena.size()));
 
         // Safety: arena selection has ensured the region is valid
@@ -107,11 +128,14 @@ impl Arena {
 
         Self {
             range: selection.arena,
+            node,
             slots,
             free_lists,
             max_order,
             used_frames: 0,
             total_frames,
+            local_frames: 0,
+            remote_frames: 0,
         }
     }
 
@@ -119,6 +143,30 @@ impl Arena {
         arch::PAGE_SIZE << self.max_order
     }
 
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    pub fn stats(&self) -> ArenaStats {
+        ArenaStats {
+            range: self.range,
+            node: self.node,
+            total_frames: self.total_frames,
+            used_frames: self.used_frames,
+            local_frames: self.local_frames,
+            remote_frames: self.remote_frames,
+        }
+    }
+
+    /// Record that `frames` frames of this arena were handed out to a CPU on `node`.
+    pub fn record_allocation(&mut self, node: NodeId, frames: usize) {
+        if node == self.node {
+            self.local_frames += frames;
+        } else {
+            self.remote_frames += frames;
+        }
+    }
+
     pub fn allocate_one(&mut self) -> Option<NonNull<FrameInfo>> {
         let (frame_order, mut frame) = self.free_lists[..=self.max_order]
             .iter_mut()
@@ -120,7 +120,7 @@ impl FrameList {
         let node = self.nodes.entry(&node_offset).or_insert_with(|| {
             Box::pin(FrameListNode {
                 links: wavltree::Links::default(),
-                offset,
+                offset: node_offset,
                 frames: [const { None }; FRAME_LIST_NODE_FANOUT],
             })
         });
@@ -138,7 +138,7 @@ impl FrameList {
         let node = self.nodes.entry(&node_offset).or_insert_with(|| {
             Box::pin(FrameListNode {
                 links: wavltree::Links::default(),
-                offset,
+                offset: node_offset,
                 frames: [const { None }; FRAME_LIST_NODE_FANOUT],
             })
         });
@@ -9,49 +9,59 @@ mod arena;
 mod frame;
 pub mod frame_list;
 
+use crate::CPUID;
 use crate::arch;
 use crate::cpu_local::CpuLocal;
 use crate::mem::bootstrap_alloc::BootstrapAllocator;
 use crate::mem::{PhysicalAddress, VirtualAddress};
+use crate::topology::{NodeId, Topology};
 use alloc::vec::Vec;
+use anyhow::ensure;
 use arena::Arena;
 use arena::select_arenas;
 use core::alloc::Layout;
 use core::cell::RefCell;
 use core::ptr::NonNull;
 use core::range::Range;
-use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::{cmp, fmt, iter, slice};
 use fallible_iterator::FallibleIterator;
+use smallvec::SmallVec;
 use spin::{Mutex, OnceLock};
 
 use crate::mem::frame_alloc::frame_list::FrameList;
+pub use arena::ArenaStats;
 pub use frame::{Frame, FrameInfo};
 
 pub static FRAME_ALLOC: OnceLock<FrameAllocator> = OnceLock::new();
 pub fn init(
     boot_alloc: BootstrapAllocator,
     fdt_region: Range<PhysicalAddress>,
+    topology: &'static Topology,
 ) -> &'static FrameAllocator {
-    FRAME_ALLOC.get_or_init(|| FrameAllocator::new(boot_alloc, fdt_region))
+    FRAME_ALLOC.get_or_init(|| FrameAllocator::new(boot_alloc, fdt_region, topology))
 }
 
 #[derive(Debug)]
 pub struct FrameAllocator {
     /// Global list of arenas that can be allocated from.
     global: Mutex<GlobalFrameAllocator>,
-    max_alignment: usize,
+    /// The largest alignment any arena can satisfy, this only grows when memory is added at runtime.
+    max_alignment: AtomicUsize,
     /// Per-cpu cache of frames to speed up allocation.
     cpu_local_cache: CpuLocal<RefCell<CpuLocalFrameCache>>,
     /// Number of frames - across all cpus - that are in cpu-local caches.
     /// This value must only ever be treated as a hint and should only be used to
     /// produce more accurate frame usage statistics.
     frames_in_caches_hint: AtomicUsize,
+    topology: &'static Topology,
 }
 
 #[derive(Debug)]
 struct GlobalFrameAllocator {
     arenas: Vec<Arena>,
+    /// For each NUMA node, the indices of all arenas ordered by their distance to the node.
+    arena_order: Vec<Vec<usize>>,
 }
 
 #[derive(Debug, Default)]
@@ -69,37 +79,55 @@ pub struct AllocError;
 // === impl FrameAllocator ===
 
 impl FrameAllocator {
-    pub fn new(boot_alloc: BootstrapAllocator, fdt_region: Range<PhysicalAddress>) -> Self {
-        let mut max_alignment = arch::PAGE_SIZE;
-        let mut arenas = Vec::new();
-
-        let phys_regions = boot_alloc
-            .free_regions()
-            .chain(iter::once(fdt_region))
-            .collect();
-        for selection_result in select_arenas(phys_regions).iterator() {
-            match selection_result {
-                Ok(selection) => {
-                    tracing::trace!("selection {selection:?}");
-                    let arena = Arena::from_selection(selection);
-                    tracing::trace!("max arena alignment {}", arena.max_alignment());
-                    max_alignment = cmp::max(max_alignment, arena.max_alignment());
-                    arenas.push(arena);
-                }
-                Err(err) => {
-                    tracing::error!("unable to include RAM region {:?}", err.range);
-                }
-            }
-        }
+    pub fn new(
+        boot_alloc: BootstrapAllocator,
+        fdt_region: Range<PhysicalAddress>,
+        topology: &'static Topology,
+    ) -> Self {
+        let mut global = GlobalFrameAllocator {
+            arenas: Vec::new(),
+            arena_order: Vec::new(),
+        };
+
+        let regions = boot_alloc.free_regions().chain(iter::once(fdt_region));
+        let max_alignment = global.add_regions(regions, topology);
 
         FrameAllocator {
-            global: Mutex::new(GlobalFrameAllocator { arenas }),
-            max_alignment,
+            global: Mutex::new(global),
+            max_alignment: AtomicUsize::new(max_alignment),
             frames_in_caches_hint: AtomicUsize::new(0),
             cpu_local_cache: CpuLocal::new(),
+            topology,
         }
     }
 
+    /// Add the physical memory `region` to the allocator at runtime, e.g. memory that was
+    /// reclaimed from the loader after boot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if (part of) the region is too small to be turned into an arena.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `region` is unused, not already managed by this allocator and
+    /// covered by the physical memory map.
+    pub unsafe fn add_memory(&self, region: Range<PhysicalAddress>) -> crate::Result<()> {
+        tracing::debug!("adding memory {region:?}");
+
+        let mut global = self.global.lock();
+        let arenas_before = global.arenas.len();
+        let max_alignment = global.add_regions(iter::once(region), self.topology);
+        ensure!(
+            global.arenas.len() > arenas_before,
+            "memory region {region:?} is too small to be used"
+        );
+
+        self.max_alignment
+            .fetch_max(max_alignment, Ordering::Relaxed);
+        Ok(())
+    }
+
     /// Allocate a single [`Frame`].
     pub fn alloc_one(&self) -> Result<Frame, AllocError> {
         let mut cpu_local_cache = self.cpu_local_cache.get_or_default().borrow_mut();
@@ -108,7 +136,7 @@ impl FrameAllocator {
             .or_else(|| {
                 let mut global_alloc = self.global.lock();
 
-                global_alloc.allocate_one()
+                global_alloc.allocate_one(self.local_node())
             })
             .ok_or(AllocError)?;
 
@@ -149,7 +177,7 @@ impl FrameAllocator {
                     "CPU-local cache exhausted, refilling {} frames...",
                     layout.size() / arch::PAGE_SIZE
                 );
-                let mut frames = global_alloc.allocate_contiguous(layout)?;
+                let mut frames = global_alloc.allocate_contiguous(self.local_node(), layout)?;
                 cpu_local_cache.free_list.append(&mut frames);
 
                 tracing::trace!("retrying allocation...");
@@ -186,16 +214,79 @@ impl FrameAllocator {
     }
 
     pub fn max_alignment(&self) -> usize {
-        self.max_alignment
+        self.max_alignment.load(Ordering::Relaxed)
+    }
+
+    /// Returns allocation statistics for each arena.
+    pub fn arena_stats(&self) -> Vec<ArenaStats> {
+        self.global.lock().arenas.iter().map(Arena::stats).collect()
+    }
+
+    /// The NUMA node of the calling CPU, allocations prefer arenas from this node.
+    fn local_node(&self) -> NodeId {
+        self.topology.cpu_node(CPUID.get())
     }
 }
 
 // === impl GlobalFrameAllocator ===
 
 impl GlobalFrameAllocator {
-    fn allocate_one(&mut self) -> Option<NonNull<FrameInfo>> {
-        for arena in &mut self.arenas {
+    /// Turn the given physical memory regions into arenas and returns the largest alignment the new
+    /// arenas can satisfy.
+    fn add_regions(
+        &mut self,
+        regions: impl Iterator<Item = Range<PhysicalAddress>>,
+        topology: &Topology,
+    ) -> usize {
+        let mut max_alignment = arch::PAGE_SIZE;
+
+        // Arenas must not span NUMA nodes, so split up the free regions by node first and select
+        // arenas for each node separately.
+        let mut regions_by_node: Vec<SmallVec<[Range<PhysicalAddress>; 4]>> =
+            (0..topology.num_nodes()).map(|_| SmallVec::new()).collect();
+        for region in regions {
+            for (region, node) in split_by_node(region, topology) {
+                regions_by_node[node].push(region);
+            }
+        }
+
+        for (node, phys_regions) in regions_by_node.into_iter().enumerate() {
+            for selection_result in select_arenas(phys_regions).iterator() {
+                match selection_result {
+                    Ok(selection) => {
+                        tracing::trace!("selection {selection:?} node {node}");
+                        let arena = Arena::from_selection(selection, node);
+                        tracing::trace!("max arena alignment {}", arena.max_alignment());
+                        max_alignment = cmp::max(max_alignment, arena.max_alignment());
+                        self.arenas.push(arena);
+                    }
+                    Err(err) => {
+                        tracing::error!("unable to include RAM region {:?}", err.range);
+                    }
+                }
+            }
+        }
+
+        let arenas = &self.arenas;
+        self.arena_order = (0..topology.num_nodes())
+            .map(|node| {
+                let mut order: Vec<usize> = (0..arenas.len()).collect();
+                order.sort_by_key(|idx| {
+                    let arena_node = arenas[*idx].node();
+                    (topology.distance(node, arena_node), arena_node != node)
+                });
+                order
+            })
+            .collect();
+
+        max_alignment
+    }
+
+    fn allocate_one(&mut self, node: NodeId) -> Option<NonNull<FrameInfo>> {
+        for idx in &self.arena_order[node] {
+            let arena = &mut self.arenas[*idx];
             if let Some(frame) = arena.allocate_one() {
+                arena.record_allocation(node, 1);
                 return Some(frame);
             }
         }
@@ -203,9 +294,15 @@ impl GlobalFrameAllocator {
         None
     }
 
-    fn allocate_contiguous(&mut self, layout: Layout) -> Option<linked_list::List<FrameInfo>> {
-        for arena in &mut self.arenas {
+    fn allocate_contiguous(
+        &mut self,
+        node: NodeId,
+        layout: Layout,
+    ) -> Option<linked_list::List<FrameInfo>> {
+        for idx in &self.arena_order[node] {
+            let arena = &mut self.arenas[*idx];
             if let Some(frames) = arena.allocate_contiguous(layout) {
+                arena.record_allocation(node, frames.len());
                 return Some(frames);
             }
         }
@@ -233,25 +330,37 @@ impl CpuLocalFrameCache {
         let mut base = self.free_list.cursor_front();
         'outer: while let Some(base_frame) = base.get() {
             if base_frame.addr().alignment() >= layout.align() {
-                let cursor = base.clone();
-                let mut prev_addr = base_frame.addr();
-
-                let mut c = 0;
-                while let Some(frame) = cursor.get() {
-                    // we found a contiguous block
-                    if c == frames {
-                        break 'outer;
-                    }
-
-                    if frame.addr().checked_sub_addr(prev_addr).unwrap() > arch::PAGE_SIZE {
-                        // frames aren't contiguous, so let's try the next one
-                        tracing::trace!("frames not contiguous, trying next");
-                        continue 'outer;
+                // walk the list from the base frame and check that the following `frames` entries are
+                // physically contiguous. The free list is not sorted so this may fail at any point.
+                let mut cursor = base.clone();
+                let mut expected_addr = base_frame.addr();
+                for _ in 0..frames {
+                    match cursor.get() {
+                        Some(frame) if frame.addr() == expected_addr => {}
+                        _ => {
+                            // frames aren't contiguous, so let's try the next one
+                            tracing::trace!("frames not contiguous, trying next");
+                            index += 1;
+                            base.move_next();
+                            continue 'outer;
+                        }
                     }
 
-                    c += 1;
-                    prev_addr = frame.addr();
+                    expected_addr = expected_addr.checked_add(arch::PAGE_SIZE).unwrap();
+                    cursor.move_next();
                 }
+
+                tracing::trace!("found contiguous block at index {index}");
+
+                // split the cache first at the start of the contiguous block. This will return the contiguous block
+                // plus everything after it
+                let mut split = self.free_list.split_off(index)?;
+                // the split the contiguous block after the number of frames we need
+                // and return the rest back to the cache
+                let mut rest = split.split_off(frames).unwrap();
+                self.free_list.append(&mut rest);
+
+                return Some(split);
             }
 
             tracing::trace!("base frame not aligned, trying next");
@@ -260,18 +369,40 @@ impl CpuLocalFrameCache {
             base.move_next();
         }
 
-        tracing::trace!("found contiguous block at index {index}");
-
-        // split the cache first at the start of the contiguous block. This will return the contiguous block
-        // plus everything after it
-        let mut split = self.free_list.split_off(index)?;
-        // the split the contiguous block after the number of frames we need
-        // and return the rest back to the cache
-        let mut rest = split.split_off(frames).unwrap();
-        self.free_list.append(&mut rest);
+        None
+    }
+}
 
-        Some(split)
+/// Split `region` at NUMA node boundaries. Memory that isn't described by the topology is
+/// attributed to node 0.
+fn split_by_node(
+    region: Range<PhysicalAddress>,
+    topology: &Topology,
+) -> SmallVec<[(Range<PhysicalAddress>, NodeId); 2]> {
+    let mut out = SmallVec::new();
+
+    let mut start = region.start;
+    while start < region.end {
+        let (end, node) = if let Some((range, node)) = topology
+            .memory_ranges()
+            .find(|(range, _)| range.contains(&start))
+        {
+            (cmp::min(range.end, region.end), node)
+        } else {
+            // not covered by any node, extend up to the next described range (if any)
+            let end = topology
+                .memory_ranges()
+                .map(|(range, _)| range.start)
+                .find(|next| *next > start)
+                .map_or(region.end, |next| cmp::min(next, region.end));
+            (end, 0)
+        };
+
+        out.push((Range::from(start..end), node));
+        start = end;
     }
+
+    out
 }
 
 // === impl AllocError ===
@@ -283,3 +414,76 @@ impl fmt::Display for AllocError {
 }
 
 impl core::error::Error for AllocError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topology::topology;
+
+    fn node_ranges() -> [Range<PhysicalAddress>; 2] {
+        let ranges: Vec<_> = topology().memory_ranges().collect();
+        assert_eq!(ranges.len(), 2, "test runner should have two NUMA nodes");
+        [ranges[0].0, ranges[1].0]
+    }
+
+    #[ktest::test]
+    async fn split_by_node_splits_at_node_boundary() {
+        let [node0, node1] = node_ranges();
+
+        let region = Range::from(
+            node0.end.checked_sub(arch::PAGE_SIZE).unwrap()
+                ..node1.start.checked_add(arch::PAGE_SIZE).unwrap(),
+        );
+        let split = split_by_node(region, topology());
+
+        assert_eq!(
+            split.as_slice(),
+            [
+                (Range::from(region.start..node0.end), 0),
+                (Range::from(node1.start..region.end), 1),
+            ]
+        );
+    }
+
+    #[ktest::test]
+    async fn split_by_node_attributes_undescribed_memory_to_node_0() {
+        let [_, node1] = node_ranges();
+
+        let region = Range::from(
+            node1.end.checked_sub(arch::PAGE_SIZE).unwrap()
+                ..node1.end.checked_add(arch::PAGE_SIZE).unwrap(),
+        );
+        let split = split_by_node(region, topology());
+
+        assert_eq!(
+            split.as_slice(),
+            [
+                (Range::from(region.start..node1.end), 1),
+                (Range::from(node1.end..region.end), 0),
+            ]
+        );
+    }
+
+    #[ktest::test]
+    async fn arenas_are_assigned_to_their_node() {
+        let stats = FRAME_ALLOC.get().unwrap().arena_stats();
+
+        for arena in &stats {
+            let last = arena.range.end.checked_sub(arch::PAGE_SIZE).unwrap();
+            assert_eq!(topology().memory_node(arena.range.start), Some(arena.node));
+            assert_eq!(
+                topology().memory_node(last),
+                Some(arena.node),
+                "arena {:?} spans NUMA nodes",
+                arena.range
+            );
+        }
+
+        for node in 0..topology().num_nodes() {
+            assert!(
+                stats.iter().any(|arena| arena.node == node),
+                "node {node} has no arenas"
+            );
+        }
+    }
+}
@@ -272,6 +272,7 @@ impl Mmap {
             // Safety: constructors ensure invariants are maintained
             unsafe {
                 aspace.arch.update_flags(
+                    aspace.frame_alloc,
                     self.range.start,
                     NonZeroUsize::new(self.range.size()).unwrap(),
                     new_permissions.into(),
@@ -17,6 +17,7 @@ mod trap_handler;
 mod vmo;
 
 use crate::arch;
+use crate::entropy::ReseedingRng;
 use crate::mem::frame_alloc::FrameAllocator;
 use alloc::format;
 use alloc::string::ToString;
@@ -24,9 +25,7 @@ use alloc::sync::Arc;
 use core::num::NonZeroUsize;
 use core::range::Range;
 use core::{fmt, slice};
-use loader_api::BootInfo;
-use rand::SeedableRng;
-use rand_chacha::ChaCha20Rng;
+use loader_api::{BootInfo, MemoryRegionKind};
 use spin::{Mutex, OnceLock};
 use xmas_elf::program::Type;
 
@@ -66,7 +65,7 @@ pub fn init(
         let mut aspace = unsafe {
             AddressSpace::from_active_kernel(
                 hw_aspace,
-                Some(ChaCha20Rng::from_rng(rand)),
+                Some(ReseedingRng::from_rng(rand)),
                 frame_alloc,
             )
         };
@@ -82,6 +81,60 @@ pub fn init(
     Ok(())
 }
 
+/// Remove the loader's identity mapping and hand its memory over to the frame allocator.
+///
+/// # Errors
+///
+/// Returns an error if unmapping the loader fails.
+///
+/// # Safety
+///
+/// Must only be called once every CPU has entered the kernel, as no CPU may execute loader code
+/// or otherwise reference loader memory after this.
+pub unsafe fn reclaim_loader_memory(
+    boot_info: &BootInfo,
+    frame_alloc: &'static FrameAllocator,
+) -> crate::Result<()> {
+    let loader_phys = Range::from(
+        PhysicalAddress::new(boot_info.loader_phys.start)
+            ..PhysicalAddress::new(boot_info.loader_phys.end),
+    );
+    tracing::debug!("reclaiming loader memory {loader_phys:?}");
+
+    with_kernel_aspace(|aspace| -> crate::Result<()> {
+        let mut aspace = aspace.lock();
+        let mut flush = aspace.arch.new_flush();
+
+        let virt = VirtualAddress::new(loader_phys.start.get()).unwrap();
+        let len = NonZeroUsize::new(loader_phys.size()).unwrap();
+        // Safety: the loader is identity-mapped (see `identity_map_self` in the loader) and the
+        // caller ensured nobody is using the mapping anymore
+        unsafe {
+            aspace.arch.unmap(frame_alloc, virt, len, &mut flush)?;
+        }
+
+        flush.flush()
+    })?;
+
+    for region in boot_info
+        .memory_regions
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::LoaderReclaimable)
+    {
+        let range = Range::from(
+            PhysicalAddress::new(region.range.start)..PhysicalAddress::new(region.range.end),
+        );
+
+        // Safety: the loader reported this memory as reclaimable and we just removed the identity
+        // mapping. It lies within the physical memory map since the loader was loaded into RAM.
+        if let Err(err) = unsafe { frame_alloc.add_memory(range) } {
+            tracing::warn!("failed to reclaim loader memory: {err}");
+        }
+    }
+
+    Ok(())
+}
+
 fn reserve_wired_regions(aspace: &mut AddressSpace, boot_info: &BootInfo, flush: &mut Flush) {
     // reserve the physical memory map
     aspace
@@ -240,7 +293,7 @@ impl From<PageFaultFlags> for Permissions {
 pub trait ArchAddressSpace {
     type Flags: From<Permissions> + bitflags::Flags;
 
-    fn new(asid: u16, frame_alloc: &FrameAllocator) -> crate::Result<(Self, Flush)>
+    fn new(frame_alloc: &FrameAllocator) -> crate::Result<(Self, Flush)>
     where
         Self: Sized;
     fn from_active(asid: u16) -> (Self, Flush)
@@ -259,6 +312,7 @@ pub trait ArchAddressSpace {
 
     unsafe fn update_flags(
         &mut self,
+        frame_alloc: &FrameAllocator,
         virt: VirtualAddress,
         len: NonZeroUsize,
         new_flags: Self::Flags,
@@ -267,6 +321,7 @@ pub trait ArchAddressSpace {
 
     unsafe fn unmap(
         &mut self,
+        frame_alloc: &FrameAllocator,
         virt: VirtualAddress,
         len: NonZeroUsize,
         flush: &mut Flush,
@@ -278,3 +333,85 @@ pub trait ArchAddressSpace {
 
     fn new_flush(&self) -> Flush;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BOOT_INFO;
+    use crate::mem::frame_alloc::FRAME_ALLOC;
+    use alloc::vec::Vec;
+
+    fn phys_range(range: Range<usize>) -> Range<PhysicalAddress> {
+        Range::from(PhysicalAddress::new(range.start)..PhysicalAddress::new(range.end))
+    }
+
+    fn loader_reclaimable(boot_info: &BootInfo) -> Vec<Range<usize>> {
+        boot_info
+            .memory_regions
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::LoaderReclaimable)
+            .map(|region| region.range)
+            .collect()
+    }
+
+    #[ktest::test]
+    async fn loader_memory_is_split_around_kernel() {
+        let boot_info = BOOT_INFO.get().unwrap();
+        let loader = boot_info.loader_phys;
+        let kernel = boot_info.kernel_phys;
+
+        assert!(loader.start <= kernel.start && kernel.end <= loader.end);
+
+        let expected: Vec<_> = [
+            Range::from(loader.start..kernel.start),
+            Range::from(kernel.end..loader.end),
+        ]
+        .into_iter()
+        .filter(|range| !range.is_empty())
+        .collect();
+
+        assert_eq!(loader_reclaimable(boot_info), expected);
+    }
+
+    #[ktest::test]
+    async fn reclaimed_loader_memory_is_allocatable() {
+        let boot_info = BOOT_INFO.get().unwrap();
+        let kernel = phys_range(boot_info.kernel_phys);
+        let stats = FRAME_ALLOC.get().unwrap().arena_stats();
+
+        // the kernel image must never be handed out
+        for arena in &stats {
+            assert!(
+                !arena.range.is_overlapping(&kernel),
+                "arena {:?} overlaps the kernel image {kernel:?}",
+                arena.range
+            );
+        }
+
+        // every arena carved out of reclaimed memory lies entirely within the reclaimed region
+        let mut reclaimed_frames = 0;
+        for region in loader_reclaimable(boot_info).into_iter().map(phys_range) {
+            for arena in stats
+                .iter()
+                .filter(|arena| arena.range.is_overlapping(&region))
+            {
+                assert!(region.start <= arena.range.start && arena.range.end <= region.end);
+                reclaimed_frames += arena.total_frames;
+            }
+        }
+        assert!(reclaimed_frames > 0);
+    }
+
+    #[ktest::test]
+    async fn add_memory_rejects_empty_region() {
+        let frame_alloc = FRAME_ALLOC.get().unwrap();
+        let arenas_before = frame_alloc.arena_stats().len();
+
+        let addr = PhysicalAddress::new(BOOT_INFO.get().unwrap().loader_phys.start);
+        // Safety: an empty region contains no memory that could be in use
+        let res = unsafe { frame_alloc.add_memory(Range::from(addr..addr)) };
+
+        assert!(res.is_err());
+        assert_eq!(frame_alloc.arena_stats().len(), arenas_before);
+    }
+}
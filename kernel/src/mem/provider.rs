@@ -12,7 +12,6 @@ use alloc::sync::Arc;
 use core::alloc::Layout;
 use core::fmt::Debug;
 use core::iter;
-use core::num::NonZeroUsize;
 use spin::{LazyLock, OnceLock};
 
 pub trait Provider: Debug {
@@ -22,7 +21,7 @@ pub trait Provider: Debug {
     fn get_frames(
         &self,
         at_offset: usize,
-        len: NonZeroUsize,
+        layout: Layout,
         will_write: bool,
     ) -> crate::Result<FrameList>;
     fn free_frame(&self, frame: Frame);
@@ -68,19 +67,17 @@ impl Provider for TheZeroFrame {
     fn get_frames(
         &self,
         _at_offset: usize,
-        len: NonZeroUsize,
+        layout: Layout,
         will_write: bool,
     ) -> crate::Result<FrameList> {
         if will_write {
             self.frame_alloc
-                .alloc_contiguous_zeroed(
-                    Layout::from_size_align(len.get(), arch::PAGE_SIZE).unwrap(),
-                )
+                .alloc_contiguous_zeroed(layout)
                 .map_err(Into::into)
         } else {
             Ok(FrameList::from_iter(iter::repeat_n(
                 self.frame().clone(),
-                len.get(),
+                layout.size() / arch::PAGE_SIZE,
             )))
         }
     }
@@ -16,7 +16,9 @@ use crate::mem::{
     },
 };
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use anyhow::ensure;
+use core::alloc::Layout;
 use core::range::Range;
 use spin::RwLock;
 
@@ -141,6 +143,113 @@ impl PagedVmo {
         Ok(frame)
     }
 
+    /// Attempts to back the [`arch::HUGE_PAGE_SIZE`] block starting at `at_offset` with a single
+    /// naturally aligned, physically contiguous run of owned frames.
+    ///
+    /// Returns the physical base address of the block or `None` if the block is already partially
+    /// resident or no contiguous run could be allocated. In that case callers should fall back to
+    /// requiring individual frames.
+    pub fn require_owned_huge_frame(&mut self, at_offset: usize) -> Option<PhysicalAddress> {
+        debug_assert!(at_offset % arch::HUGE_PAGE_SIZE == 0);
+
+        let is_vacant = (at_offset..at_offset.checked_add(arch::HUGE_PAGE_SIZE).unwrap())
+            .step_by(arch::PAGE_SIZE)
+            .all(|offset| self.frames.get(offset).is_none());
+        if !is_vacant {
+            return None;
+        }
+
+        let layout = Layout::from_size_align(arch::HUGE_PAGE_SIZE, arch::HUGE_PAGE_SIZE).unwrap();
+        let frames = match self.provider.get_frames(at_offset, layout, true) {
+            Ok(frames) => frames,
+            Err(err) => {
+                tracing::trace!("failed to allocate huge frame, falling back to base pages {err}");
+                return None;
+            }
+        };
+
+        let base = frames.first().unwrap().addr();
+        debug_assert!(base.is_aligned_to(arch::HUGE_PAGE_SIZE));
+        for (offset, frame) in (at_offset..).step_by(arch::PAGE_SIZE).zip(frames) {
+            debug_assert!(frame.is_unique());
+            self.frames.insert(offset, frame);
+        }
+
+        Some(base)
+    }
+
+    /// Attempts to promote the fully resident [`arch::HUGE_PAGE_SIZE`] block starting at `at_offset`
+    /// so it can be mapped using a single huge page.
+    ///
+    /// This succeeds right away if the frames of the block are owned and already physically contiguous
+    /// (e.g. because the block was previously mapped as a huge page and got split). Otherwise, the frames
+    /// are *collapsed* into a newly allocated contiguous run, copying their contents over.
+    ///
+    /// Collapsing copies frames that might still be mapped writable, so `before_collapse` is called
+    /// right before the copy and has to make sure nothing can write to the block anymore (e.g. by
+    /// unmapping it and flushing the TLB).
+    ///
+    /// Returns the physical base address of the block and the frames it replaced, or `None` if the
+    /// block cannot be promoted. The replaced frames might still be reachable through stale TLB
+    /// entries, so the caller must only free them once the huge page mapping is in place and the
+    /// TLB has been flushed (see [`Batch::queue_free`](crate::mem::Batch::queue_free)).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `before_collapse` fails.
+    pub fn promote_huge_frame(
+        &mut self,
+        at_offset: usize,
+        before_collapse: impl FnOnce() -> crate::Result<()>,
+    ) -> crate::Result<Option<(PhysicalAddress, Vec<Frame>)>> {
+        debug_assert!(at_offset % arch::HUGE_PAGE_SIZE == 0);
+
+        let offsets = (at_offset..at_offset.checked_add(arch::HUGE_PAGE_SIZE).unwrap())
+            .step_by(arch::PAGE_SIZE);
+
+        // Only blocks where every frame is resident and exclusively owned by this VMO can be promoted,
+        // shared frames (such as the zero frame) must stay mapped read-only for copy-on-write.
+        let Some(base) = self.frames.get(at_offset).map(Frame::addr) else {
+            return Ok(None);
+        };
+        let mut is_contiguous = base.is_aligned_to(arch::HUGE_PAGE_SIZE);
+        for (idx, offset) in offsets.clone().enumerate() {
+            match self.frames.get(offset) {
+                Some(frame) if frame.is_unique() => {
+                    is_contiguous &=
+                        frame.addr() == base.checked_add(idx * arch::PAGE_SIZE).unwrap();
+                }
+                _ => return Ok(None),
+            }
+        }
+
+        if is_contiguous {
+            return Ok(Some((base, Vec::new())));
+        }
+
+        tracing::trace!("collapsing huge frame at offset {at_offset:#x}...");
+        let layout = Layout::from_size_align(arch::HUGE_PAGE_SIZE, arch::HUGE_PAGE_SIZE).unwrap();
+        let Ok(new_frames) = self.frame_alloc.alloc_contiguous(layout) else {
+            return Ok(None);
+        };
+        let new_base = new_frames.first().unwrap().addr();
+
+        before_collapse()?;
+
+        let mut old_frames = Vec::with_capacity(arch::HUGE_PAGE_SIZE / arch::PAGE_SIZE);
+        for (offset, mut new_frame) in offsets.zip(new_frames) {
+            let old_frame = self.frames.get(offset).unwrap();
+            Frame::get_mut(&mut new_frame)
+                .expect("newly allocated frame should be unique")
+                .as_mut_slice()
+                .copy_from_slice(old_frame.as_slice());
+
+            old_frames.push(self.frames.replace(offset, new_frame).unwrap());
+        }
+
+        Ok(Some((new_base, old_frames)))
+    }
+
     pub fn free_frames(&mut self, range: Range<usize>) {
         let mut c = self.frames.cursor_mut(range.start);
 
@@ -24,6 +24,18 @@
 //! Kernel counters are always per-cpu, which means each cpu keeps an individual counter. Methods
 //! on `Counter` can be used to sum events across cpus or even get the maximum or minimum value across
 //! cpus.
+//!
+//! Events that are counted per object, such as the number of times each interrupt fired, are
+//! counted in a [`CounterArray`] declared through the `counter_array!` macro:
+//! ```rust
+//! use crate::metrics::{CounterArray, counter_array};
+//!
+//! static PER_IRQ: CounterArray<1024> = counter_array!("per-irq", 1024);
+//!
+//! fn some_function(irq_num: usize) {
+//!     PER_IRQ.increment(irq_num, 1);
+//! }
+//! ```
 
 use crate::cpu_local::CpuLocal;
 use core::sync::atomic::{AtomicU64, Ordering};
@@ -40,6 +52,18 @@ macro_rules! counter {
     }};
 }
 
+/// Declares a new array of counters.
+#[macro_export]
+macro_rules! counter_array {
+    ($name:expr, $len:expr) => {{
+        #[unsafe(link_section = concat!(".bss.kcounter.", $name))]
+        static ARENA: $crate::cpu_local::CpuLocal<[::core::sync::atomic::AtomicU64; $len]> =
+            $crate::cpu_local::CpuLocal::new();
+
+        CounterArray::new(&ARENA, $name)
+    }};
+}
+
 /// A kernel counter.
 pub struct Counter {
     arena: &'static CpuLocal<AtomicU64>,
@@ -96,6 +120,11 @@ impl Counter {
         Some(self.arena.get()?.load(Ordering::Relaxed))
     }
 
+    /// Get the counter value of the cpu `cpuid`, or `None` if the counter was never written to.
+    pub fn get_for(&self, cpuid: usize) -> Option<u64> {
+        Some(self.arena.get_for(cpuid)?.load(Ordering::Relaxed))
+    }
+
     /// Return the sum of all counters across all cpus.
     pub fn sum_across_all_cpus(&self) -> u64 {
         self.arena.iter().map(|v| v.load(Ordering::Relaxed)).sum()
@@ -119,3 +148,51 @@ impl Counter {
             .unwrap()
     }
 }
+
+/// An array of `N` kernel counters, each of which is per-cpu.
+pub struct CounterArray<const N: usize> {
+    arena: &'static CpuLocal<[AtomicU64; N]>,
+    name: &'static str,
+}
+
+impl<const N: usize> CounterArray<N> {
+    #[doc(hidden)]
+    pub const fn new(arena: &'static CpuLocal<[AtomicU64; N]>, name: &'static str) -> Self {
+        Self { arena, name }
+    }
+
+    /// Allocate the counters of the calling cpu.
+    ///
+    /// They are allocated on first use otherwise, which must not happen in contexts that can't
+    /// allocate, such as interrupt handlers.
+    pub fn init_cpu(&self) {
+        self.local();
+    }
+
+    /// Increment the counter at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn increment(&self, index: usize, value: u64) {
+        self.local()[index].fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Get the value of the counter at `index` for the cpu `cpuid`, or `None` if the cpu never
+    /// wrote to any of the counters.
+    pub fn get_for(&self, cpuid: usize, index: usize) -> Option<u64> {
+        Some(self.arena.get_for(cpuid)?[index].load(Ordering::Relaxed))
+    }
+
+    /// Return the sum of the counter at `index` across all cpus.
+    pub fn sum_across_all_cpus(&self, index: usize) -> u64 {
+        self.arena
+            .iter()
+            .map(|counters| counters[index].load(Ordering::Relaxed))
+            .sum()
+    }
+
+    fn local(&self) -> &[AtomicU64; N] {
+        self.arena.get_or(|| [const { AtomicU64::new(0) }; N])
+    }
+}
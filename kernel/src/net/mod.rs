@@ -0,0 +1,385 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! The TCP/IP network stack.
+//!
+//! The protocol implementations are provided by [`smoltcp`], this module integrates it with the
+//! rest of the kernel: Network drivers hand their device to [`register`], which sets up the
+//! interface and spawns the `net` task that drives it. The task polls the interface whenever a
+//! frame arrives, a socket was used, or one of the protocol timers expires, and otherwise sleeps
+//! on the kernel [`Timer`](crate::time::Timer).
+//!
+//! Kernel code talks to the network through the async [`UdpSocket`], [`TcpListener`] and
+//! [`TcpStream`] types. Only a single, statically configured IPv4 interface is supported for now,
+//! its address is set through the `net` bootarg and defaults to what QEMU's user-mode network
+//! expects:
+//!
+//! ```text
+//! net=10.0.2.15/24,10.0.2.2
+//! ```
+//!
+//! With QEMU, a network device is attached with e.g.
+//! `-netdev user,id=net0 -device virtio-net-device,netdev=net0`.
+
+mod tcp;
+mod udp;
+
+use crate::driver;
+use crate::driver::net::NetDevice;
+use crate::scheduler::scheduler;
+use crate::sync::WaitQueue;
+use crate::time::{Duration, Instant, timeout};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{Context, bail, format_err};
+use core::fmt;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::pin::pin;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use futures::future::{Either, select};
+use rand::RngCore;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{DeviceCapabilities, Medium};
+use smoltcp::socket::{Socket, tcp as smol_tcp};
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Cidr};
+use spin::{Mutex, Once};
+
+pub use tcp::{TcpListener, TcpStream};
+pub use udp::UdpSocket;
+
+/// The first port handed out to sockets that don't ask for a specific one, as suggested by RFC 6335.
+const EPHEMERAL_PORTS_START: u16 = 49152;
+
+static CONFIG: Once<(Config, u64)> = Once::new();
+static STACK: Once<Stack> = Once::new();
+
+/// The configuration of the network interface, parsed from the `net` bootarg.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub address: Ipv4Cidr,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: Ipv4Cidr::new(Ipv4Addr::new(10, 0, 2, 15), 24),
+            gateway: Some(Ipv4Addr::new(10, 0, 2, 2)),
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cidr, gateway) = match s.split_once(',') {
+            Some((cidr, gateway)) => (cidr, Some(gateway.trim())),
+            None => (s, None),
+        };
+        let (addr, prefix_len) = cidr
+            .trim()
+            .split_once('/')
+            .context("network address is missing a prefix length")?;
+
+        let prefix_len: u8 = prefix_len.parse()?;
+        if prefix_len > 32 {
+            bail!("invalid prefix length {prefix_len}");
+        }
+
+        Ok(Self {
+            address: Ipv4Cidr::new(addr.parse()?, prefix_len),
+            gateway: gateway.map(Ipv4Addr::from_str).transpose()?,
+        })
+    }
+}
+
+/// Set the configuration that is used once a network device is registered.
+pub fn init(config: Config, rng: &mut impl RngCore) {
+    CONFIG.call_once(|| (config, rng.next_u64()));
+}
+
+/// Register a network device with the network stack.
+///
+/// The first registered device becomes the network interface, further devices are ignored.
+pub fn register<D: NetDevice + 'static>(device: Arc<D>) {
+    let mut registered = false;
+    let stack = STACK.call_once(|| {
+        registered = true;
+        Stack::new(&*device)
+    });
+
+    if !registered {
+        tracing::warn!("only a single network interface is supported, ignoring device");
+        return;
+    }
+
+    scheduler()
+        .build_task()
+        .name("net")
+        .spawn(run(stack, device));
+}
+
+/// Returns the network stack, waiting for network devices to be probed if necessary.
+async fn stack() -> crate::Result<&'static Stack> {
+    if let Some(stack) = STACK.get() {
+        return Ok(stack);
+    }
+
+    driver::wait_for_probe().await;
+    STACK.get().context("no network interface available")
+}
+
+struct Stack {
+    inner: Mutex<Inner>,
+    /// Set when a socket was used and the interface must be polled to act on it.
+    poll_needed: AtomicBool,
+    /// Woken when `poll_needed` is set.
+    wakeup: WaitQueue,
+}
+
+struct Inner {
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    /// TCP sockets that have been dropped by their owners but are still closing.
+    closing: Vec<SocketHandle>,
+    next_ephemeral_port: u16,
+}
+
+impl Stack {
+    fn new(device: &impl NetDevice) -> Self {
+        let (config, seed) = CONFIG.get().copied().unwrap_or_default();
+
+        let mut iface_config = smoltcp::iface::Config::new(HardwareAddress::Ethernet(
+            EthernetAddress(device.mac_address()),
+        ));
+        iface_config.random_seed = seed;
+
+        let mut phy = Phy {
+            device,
+            pending: None,
+            tx: Vec::new(),
+        };
+        let mut iface = Interface::new(iface_config, &mut phy, timestamp());
+        iface.update_ip_addrs(|addrs| {
+            addrs.push(IpCidr::Ipv4(config.address)).unwrap();
+        });
+        if let Some(gateway) = config.gateway {
+            iface.routes_mut().add_default_ipv4_route(gateway).unwrap();
+        }
+        tracing::info!(
+            "net: address {}, gateway {:?}",
+            config.address,
+            config.gateway
+        );
+
+        Self {
+            inner: Mutex::new(Inner {
+                iface,
+                sockets: SocketSet::new(vec![]),
+                closing: Vec::new(),
+                next_ephemeral_port: EPHEMERAL_PORTS_START,
+            }),
+            poll_needed: AtomicBool::new(false),
+            wakeup: WaitQueue::new(),
+        }
+    }
+
+    /// Make the `net` task poll the interface, must be called after every socket operation that
+    /// may cause packets to be sent.
+    fn poll_now(&self) {
+        self.poll_needed.store(true, Ordering::Release);
+        self.wakeup.wake();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl fmt::Debug for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stack")
+            .field("poll_needed", &self.poll_needed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Inner {
+    fn ephemeral_port(&mut self, protocol: Protocol) -> crate::Result<u16> {
+        for _ in EPHEMERAL_PORTS_START..=u16::MAX {
+            let port = self.next_ephemeral_port;
+            self.next_ephemeral_port = port.checked_add(1).unwrap_or(EPHEMERAL_PORTS_START);
+
+            if !self.is_port_in_use(protocol, port) {
+                return Ok(port);
+            }
+        }
+
+        Err(format_err!("no ephemeral ports left"))
+    }
+
+    fn is_port_in_use(&self, protocol: Protocol, port: u16) -> bool {
+        self.sockets.iter().any(|(_, socket)| match socket {
+            Socket::Udp(socket) => protocol == Protocol::Udp && socket.endpoint().port == port,
+            Socket::Tcp(socket) => {
+                protocol == Protocol::Tcp
+                    && (socket.listen_endpoint().port == port
+                        || socket
+                            .local_endpoint()
+                            .is_some_and(|endpoint| endpoint.port == port))
+            }
+        })
+    }
+
+    /// Remove the sockets in `closing` that have finished closing.
+    fn reap_closing(&mut self) {
+        let sockets = &mut self.sockets;
+        self.closing.retain(|&handle| {
+            let state = sockets.get::<smol_tcp::Socket>(handle).state();
+            if matches!(state, smol_tcp::State::Closed | smol_tcp::State::TimeWait) {
+                sockets.remove(handle);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Drive the interface of `stack`, which sends and receives through `device`.
+async fn run<D: NetDevice>(stack: &'static Stack, device: Arc<D>) {
+    let mut pending = None;
+    let mut tx = Vec::new();
+
+    loop {
+        let delay = {
+            let mut inner = stack.inner.lock();
+            let inner = &mut *inner;
+            let mut phy = Phy {
+                device: &*device,
+                pending: pending.take(),
+                tx: core::mem::take(&mut tx),
+            };
+
+            let now = timestamp();
+            inner.iface.poll(now, &mut phy, &mut inner.sockets);
+            inner.reap_closing();
+
+            pending = phy.pending;
+            tx = phy.tx;
+            inner.iface.poll_delay(now, &inner.sockets)
+        };
+
+        for frame in tx.drain(..) {
+            if let Err(err) = device.transmit(&frame).await {
+                tracing::warn!("{err}");
+            }
+        }
+
+        let poll_needed = stack
+            .wakeup
+            .wait_for(|| stack.poll_needed.swap(false, Ordering::AcqRel));
+        let wait = select(pin!(device.receive()), pin!(poll_needed));
+        let woken = match delay {
+            Some(delay) => timeout(Duration::from_micros(delay.total_micros()), wait)
+                .await
+                .ok(),
+            None => Some(wait.await),
+        };
+
+        match woken {
+            Some(Either::Left((Ok(frame), _))) => pending = Some(frame),
+            Some(Either::Left((Err(err), _))) => {
+                tracing::error!("network device failed, stopping the network stack: {err}");
+                break;
+            }
+            Some(Either::Right(_)) | None => {}
+        }
+    }
+}
+
+/// Adapts a [`NetDevice`] to the synchronous device interface of [`smoltcp`].
+///
+/// Received frames are fetched from the device without blocking, while frames to be sent are
+/// collected and transmitted by the `net` task once the interface has been polled.
+struct Phy<'a, D> {
+    device: &'a D,
+    /// A frame that has been received while the `net` task was waiting.
+    pending: Option<Vec<u8>>,
+    tx: Vec<Vec<u8>>,
+}
+
+struct RxToken(Vec<u8>);
+
+struct TxToken<'a>(&'a mut Vec<Vec<u8>>);
+
+impl<D: NetDevice> smoltcp::phy::Device for Phy<'_, D> {
+    type RxToken<'a>
+        = RxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(
+        &mut self,
+        _timestamp: smoltcp::time::Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let frame = self.pending.take().or_else(|| self.device.try_receive())?;
+        Some((RxToken(frame), TxToken(&mut self.tx)))
+    }
+
+    fn transmit(&mut self, _timestamp: smoltcp::time::Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken(&mut self.tx))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = self.device.max_frame_size();
+        caps
+    }
+}
+
+impl smoltcp::phy::RxToken for RxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.0)
+    }
+}
+
+impl smoltcp::phy::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = vec![0; len];
+        let res = f(&mut frame);
+        self.0.push(frame);
+        res
+    }
+}
+
+fn socket_addr(endpoint: IpEndpoint) -> SocketAddrV4 {
+    let IpAddress::Ipv4(addr) = endpoint.addr;
+    SocketAddrV4::new(addr, endpoint.port)
+}
+
+/// The current time in the representation [`smoltcp`] expects.
+fn timestamp() -> smoltcp::time::Instant {
+    let micros = Instant::now().duration_since(Instant::ZERO).as_micros();
+    smoltcp::time::Instant::from_micros(i64::try_from(micros).unwrap())
+}
@@ -0,0 +1,340 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::net::{Protocol, Stack, socket_addr, stack};
+use alloc::vec;
+use anyhow::{bail, format_err};
+use core::future::poll_fn;
+use core::net::SocketAddrV4;
+use core::task::Poll;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::tcp;
+
+/// The number of bytes each direction of a connection can buffer.
+const BUFFER_SIZE: usize = 64 * 1024;
+/// Connections are aborted if the peer doesn't acknowledge our segments for this long.
+const TIMEOUT: smoltcp::time::Duration = smoltcp::time::Duration::from_secs(60);
+
+fn new_socket() -> tcp::Socket<'static> {
+    let mut socket = tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; BUFFER_SIZE]),
+        tcp::SocketBuffer::new(vec![0; BUFFER_SIZE]),
+    );
+    socket.set_timeout(Some(TIMEOUT));
+    socket
+}
+
+/// A TCP socket listening for incoming connections.
+///
+/// The listener has a backlog of a single connection, further connection attempts are refused
+/// until that connection has been accepted.
+#[derive(Debug)]
+pub struct TcpListener {
+    stack: &'static Stack,
+    handle: SocketHandle,
+    port: u16,
+}
+
+impl TcpListener {
+    /// Start listening on the given local port, or on an unused ephemeral port if `port` is `0`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no network interface or the port is already in use.
+    pub async fn bind(port: u16) -> crate::Result<Self> {
+        let stack = stack().await?;
+        let mut inner = stack.inner.lock();
+
+        let port = if port == 0 {
+            inner.ephemeral_port(Protocol::Tcp)?
+        } else if inner.is_port_in_use(Protocol::Tcp, port) {
+            bail!("TCP port {port} is already in use");
+        } else {
+            port
+        };
+
+        let mut socket = new_socket();
+        socket
+            .listen(port)
+            .map_err(|err| format_err!("failed to listen on TCP port {port}: {err}"))?;
+        let handle = inner.sockets.add(socket);
+
+        Ok(Self {
+            stack,
+            handle,
+            port,
+        })
+    }
+
+    /// The local port this listener is bound to.
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Wait for the next incoming connection.
+    ///
+    /// Returns the established connection together with the address of the peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener couldn't continue listening.
+    pub async fn accept(&mut self) -> crate::Result<(TcpStream, SocketAddrV4)> {
+        poll_fn(|cx| {
+            let mut inner = self.stack.inner.lock();
+            let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+
+            // connections that are reset during the handshake go back to listening by themselves
+            if matches!(socket.state(), tcp::State::Listen | tcp::State::SynReceived) {
+                socket.register_recv_waker(cx.waker());
+                return Poll::Pending;
+            }
+            let peer = socket.remote_endpoint().map(socket_addr);
+
+            // keep listening with a fresh socket while the current one becomes the connection
+            let mut listener = new_socket();
+            if let Err(err) = listener.listen(self.port) {
+                return Poll::Ready(Err(format_err!(
+                    "failed to listen on TCP port {}: {err}",
+                    self.port
+                )));
+            }
+            let stream = TcpStream {
+                stack: self.stack,
+                handle: core::mem::replace(&mut self.handle, inner.sockets.add(listener)),
+            };
+
+            Poll::Ready(match peer {
+                Some(peer) => Ok((stream, peer)),
+                None => Err(format_err!("connection was closed before it was accepted")),
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        self.stack.inner.lock().sockets.remove(self.handle);
+    }
+}
+
+/// A TCP connection.
+///
+/// Dropping the stream closes the connection gracefully in the background.
+#[derive(Debug)]
+pub struct TcpStream {
+    stack: &'static Stack,
+    handle: SocketHandle,
+}
+
+impl TcpStream {
+    /// Open a connection to `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no network interface or the peer refused the connection.
+    pub async fn connect(addr: SocketAddrV4) -> crate::Result<Self> {
+        let stack = stack().await?;
+
+        let handle = {
+            let mut inner = stack.inner.lock();
+            let inner = &mut *inner;
+
+            let port = inner.ephemeral_port(Protocol::Tcp)?;
+            let mut socket = new_socket();
+            socket
+                .connect(inner.iface.context(), addr, port)
+                .map_err(|err| format_err!("failed to connect to {addr}: {err}"))?;
+            inner.sockets.add(socket)
+        };
+        let stream = Self { stack, handle };
+        stack.poll_now();
+
+        poll_fn(|cx| {
+            let mut inner = stack.inner.lock();
+            let socket = inner.sockets.get_mut::<tcp::Socket>(handle);
+
+            match socket.state() {
+                tcp::State::SynSent | tcp::State::SynReceived => {
+                    socket.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                tcp::State::Established => Poll::Ready(Ok(())),
+                _ => Poll::Ready(Err(format_err!("connection to {addr} was refused"))),
+            }
+        })
+        .await?;
+
+        Ok(stream)
+    }
+
+    /// The local address of the connection, `None` if the connection is closed.
+    pub fn local_addr(&self) -> Option<SocketAddrV4> {
+        let inner = self.stack.inner.lock();
+        let socket = inner.sockets.get::<tcp::Socket>(self.handle);
+        socket.local_endpoint().map(socket_addr)
+    }
+
+    /// The address of the peer, `None` if the connection is closed.
+    pub fn peer_addr(&self) -> Option<SocketAddrV4> {
+        let inner = self.stack.inner.lock();
+        let socket = inner.sockets.get::<tcp::Socket>(self.handle);
+        socket.remote_endpoint().map(socket_addr)
+    }
+
+    /// Read received data into `buf`, waiting until at least one byte is available.
+    ///
+    /// Returns `0` once the peer closed its sending half of the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection was reset.
+    pub async fn read(&self, buf: &mut [u8]) -> crate::Result<usize> {
+        let len = poll_fn(|cx| {
+            let mut inner = self.stack.inner.lock();
+            let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+
+            match socket.recv_slice(buf) {
+                Ok(0) if !buf.is_empty() => {
+                    socket.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+                Ok(len) => Poll::Ready(Ok(len)),
+                Err(tcp::RecvError::Finished) => Poll::Ready(Ok(0)),
+                Err(tcp::RecvError::InvalidState) => {
+                    Poll::Ready(Err(format_err!("connection was reset")))
+                }
+            }
+        })
+        .await?;
+
+        // reading made room in the receive window, let the peer know
+        self.stack.poll_now();
+        Ok(len)
+    }
+
+    /// Queue data from `buf` for sending, waiting until at least one byte could be queued.
+    ///
+    /// Returns the number of bytes queued.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sending half of the connection is closed.
+    pub async fn write(&self, buf: &[u8]) -> crate::Result<usize> {
+        let len = poll_fn(|cx| {
+            let mut inner = self.stack.inner.lock();
+            let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+
+            match socket.send_slice(buf) {
+                Ok(0) if !buf.is_empty() => {
+                    socket.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                Ok(len) => Poll::Ready(Ok(len)),
+                Err(tcp::SendError::InvalidState) => {
+                    Poll::Ready(Err(format_err!("connection is closed")))
+                }
+            }
+        })
+        .await?;
+
+        self.stack.poll_now();
+        Ok(len)
+    }
+
+    /// Queue all of `buf` for sending.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sending half of the connection is closed.
+    pub async fn write_all(&self, mut buf: &[u8]) -> crate::Result<()> {
+        while !buf.is_empty() {
+            let len = self.write(buf).await?;
+            buf = &buf[len..];
+        }
+        Ok(())
+    }
+
+    /// Close the sending half of the connection once all queued data has been sent.
+    ///
+    /// The peer may still send data until it closes its half of the connection too.
+    pub fn shutdown(&self) {
+        self.stack
+            .inner
+            .lock()
+            .sockets
+            .get_mut::<tcp::Socket>(self.handle)
+            .close();
+        self.stack.poll_now();
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut inner = self.stack.inner.lock();
+        inner.sockets.get_mut::<tcp::Socket>(self.handle).close();
+        // the `net` task removes the socket once the connection is fully closed
+        inner.closing.push(self.handle);
+        drop(inner);
+
+        self.stack.poll_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::CONFIG;
+    use crate::scheduler::scheduler;
+    use crate::time::{Duration, timeout};
+
+    #[ktest::test]
+    async fn listener_ports() {
+        let a = TcpListener::bind(0).await.unwrap();
+        assert_ne!(a.local_port(), 0);
+        assert!(TcpListener::bind(a.local_port()).await.is_err());
+
+        // TCP and UDP ports are independent of each other
+        crate::net::UdpSocket::bind(a.local_port()).await.unwrap();
+    }
+
+    #[ktest::test]
+    async fn connect_and_accept() {
+        let (config, _) = CONFIG.get().copied().unwrap_or_default();
+
+        let mut listener = TcpListener::bind(0).await.unwrap();
+        let addr = SocketAddrV4::new(config.address.address(), listener.local_port());
+        let server = scheduler().spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+
+            let mut buf = [0; 64];
+            let len = stream.read(&mut buf).await.unwrap();
+            stream.write_all(&buf[..len]).await.unwrap();
+            stream.shutdown();
+
+            // the client closes its half once it got the reply
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+            peer
+        });
+
+        let client = timeout(Duration::from_secs(10), TcpStream::connect(addr))
+            .await
+            .expect("connecting timed out")
+            .unwrap();
+        assert_eq!(client.peer_addr(), Some(addr));
+        let client_addr = client.local_addr().unwrap();
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 64];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+        client.shutdown();
+
+        assert_eq!(server.await.unwrap(), client_addr);
+    }
+}
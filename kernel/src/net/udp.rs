@@ -0,0 +1,185 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::net::{Protocol, Stack, socket_addr, stack};
+use alloc::vec;
+use anyhow::{bail, format_err};
+use core::future::poll_fn;
+use core::net::SocketAddrV4;
+use core::task::Poll;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::udp;
+
+/// The number of datagrams each direction of a socket can buffer.
+const PACKETS: usize = 16;
+/// The number of payload bytes each direction of a socket can buffer.
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// A UDP socket.
+#[derive(Debug)]
+pub struct UdpSocket {
+    stack: &'static Stack,
+    handle: SocketHandle,
+    port: u16,
+}
+
+impl UdpSocket {
+    /// Create a socket bound to the given local port, or to an unused ephemeral port if `port`
+    /// is `0`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no network interface or the port is already in use.
+    pub async fn bind(port: u16) -> crate::Result<Self> {
+        let stack = stack().await?;
+        let mut inner = stack.inner.lock();
+
+        let port = if port == 0 {
+            inner.ephemeral_port(Protocol::Udp)?
+        } else if inner.is_port_in_use(Protocol::Udp, port) {
+            bail!("UDP port {port} is already in use");
+        } else {
+            port
+        };
+
+        let mut socket = udp::Socket::new(
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; PACKETS],
+                vec![0; BUFFER_SIZE],
+            ),
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; PACKETS],
+                vec![0; BUFFER_SIZE],
+            ),
+        );
+        socket
+            .bind(port)
+            .map_err(|err| format_err!("failed to bind UDP port {port}: {err}"))?;
+        let handle = inner.sockets.add(socket);
+
+        Ok(Self {
+            stack,
+            handle,
+            port,
+        })
+    }
+
+    /// The local port this socket is bound to.
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Send `buf` as a single datagram to `addr`, waiting for buffer space if necessary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datagram is larger than the send buffer or `addr` is not a valid
+    /// destination.
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> crate::Result<()> {
+        poll_fn(|cx| {
+            let mut inner = self.stack.inner.lock();
+            let socket = inner.sockets.get_mut::<udp::Socket>(self.handle);
+
+            if buf.len() > socket.payload_send_capacity() {
+                return Poll::Ready(Err(format_err!(
+                    "datagram of {} bytes exceeds the send buffer",
+                    buf.len()
+                )));
+            }
+
+            match socket.send_slice(buf, addr) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(udp::SendError::BufferFull) => {
+                    socket.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                Err(err) => Poll::Ready(Err(format_err!("failed to send to {addr}: {err}"))),
+            }
+        })
+        .await?;
+
+        self.stack.poll_now();
+        Ok(())
+    }
+
+    /// Wait for the next datagram, copy it into `buf` and return its length and sender.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datagram doesn't fit into `buf`, in which case it is discarded.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> crate::Result<(usize, SocketAddrV4)> {
+        poll_fn(|cx| {
+            let mut inner = self.stack.inner.lock();
+            let socket = inner.sockets.get_mut::<udp::Socket>(self.handle);
+
+            match socket.recv_slice(buf) {
+                Ok((len, meta)) => Poll::Ready(Ok((len, socket_addr(meta.endpoint)))),
+                Err(udp::RecvError::Exhausted) => {
+                    socket.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+                Err(err) => Poll::Ready(Err(format_err!("failed to receive datagram: {err}"))),
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        self.stack.inner.lock().sockets.remove(self.handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::CONFIG;
+    use crate::scheduler::scheduler;
+    use crate::time::{Duration, timeout};
+
+    #[ktest::test]
+    async fn bind_ports() {
+        let a = UdpSocket::bind(0).await.unwrap();
+        let b = UdpSocket::bind(0).await.unwrap();
+        assert_ne!(a.local_port(), 0);
+        assert_ne!(a.local_port(), b.local_port());
+
+        assert!(UdpSocket::bind(a.local_port()).await.is_err());
+        let port = a.local_port();
+        drop(a);
+        UdpSocket::bind(port).await.unwrap();
+    }
+
+    #[ktest::test]
+    async fn echo() {
+        let (config, _) = CONFIG.get().copied().unwrap_or_default();
+
+        let server = UdpSocket::bind(0).await.unwrap();
+        let server_addr = SocketAddrV4::new(config.address.address(), server.local_port());
+        let echo = scheduler().spawn(async move {
+            let mut buf = [0; 64];
+            let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+            server.send_to(&buf[..len], peer).await.unwrap();
+            peer
+        });
+
+        let client = UdpSocket::bind(0).await.unwrap();
+        client.send_to(b"hello", server_addr).await.unwrap();
+
+        let mut buf = [0; 64];
+        let (len, peer) = timeout(Duration::from_secs(10), client.recv_from(&mut buf))
+            .await
+            .expect("no reply from the echo socket")
+            .unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(peer, server_addr);
+
+        let client_addr = echo.await.unwrap();
+        assert_eq!(client_addr.port(), client.local_port());
+    }
+}
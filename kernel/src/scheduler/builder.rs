@@ -0,0 +1,141 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::mem::with_kernel_aspace;
+use crate::scheduler::Scheduler;
+use crate::task;
+use crate::task::{Attributes, JoinHandle, Priority, TaskRef};
+use alloc::boxed::Box;
+use core::any::type_name;
+use core::fmt;
+use core::future::Future;
+
+/// Configures a task before spawning it onto the [`Scheduler`].
+///
+/// Returned by [`Scheduler::build_task`].
+#[must_use = "a `TaskBuilder` does nothing unless `spawn` is called"]
+pub struct TaskBuilder {
+    scheduler: &'static Scheduler,
+    attributes: Attributes,
+}
+
+impl TaskBuilder {
+    pub(super) fn new(scheduler: &'static Scheduler) -> Self {
+        Self {
+            scheduler,
+            attributes: Attributes::new(),
+        }
+    }
+
+    /// Set the scheduling class of the task, defaults to [`Priority::Normal`].
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.attributes.priority = priority;
+        self
+    }
+
+    /// Set a human-readable name for the task, which is included in log messages emitted while
+    /// the task is polled, task dumps and lockup reports.
+    pub fn name(mut self, name: &str) -> Self {
+        self.attributes.name = Some(Box::from(name));
+        self
+    }
+
+    /// Restrict the task to the CPUs in the given bitmask, defaults to all CPUs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mask doesn't include any online CPU.
+    pub fn affinity(mut self, mask: usize) -> Self {
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        assert_ne!(
+            mask & cpu_mask,
+            0,
+            "task affinity {mask:#b} doesn't include any online CPU (online {cpu_mask:#b})"
+        );
+
+        // a mask that includes all online CPUs doesn't restrict the task, so don't treat it as pinned
+        self.attributes.affinity = if mask & cpu_mask == cpu_mask {
+            Attributes::ANY_CPU
+        } else {
+            mask
+        };
+        self
+    }
+
+    /// Restrict the task to only ever run on the CPU with the given `cpuid`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no CPU with the given `cpuid`.
+    pub fn pin_to(self, cpuid: usize) -> Self {
+        assert!(cpuid < usize::BITS as usize, "invalid CPU id {cpuid}");
+        self.affinity(1 << cpuid)
+    }
+
+    /// Spawn the configured task, returning a [`JoinHandle`] to await its output.
+    #[track_caller]
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let scheduler = self.scheduler;
+        let (handle, maybe_task) = self.bind(future);
+
+        if let Some(task) = maybe_task {
+            scheduler.schedule_task(task);
+        }
+
+        handle
+    }
+
+    /// Create the configured task without scheduling it.
+    ///
+    /// Returns `None` for the task if the scheduler is shutting down.
+    #[track_caller]
+    pub(super) fn bind<F>(mut self, future: F) -> (JoinHandle<F::Output>, Option<TaskRef>)
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = task::Id::next();
+
+        let loc = core::panic::Location::caller();
+        let span = tracing::trace_span!(
+            "scheduler.spawn",
+            task.tid = id.as_u64(),
+            task.name = self.attributes.name.as_deref(),
+            task.priority = %self.attributes.priority,
+            task.affinity = self.attributes.affinity,
+            task.output = %type_name::<F::Output>(),
+            loc.file = loc.file(),
+            loc.line = loc.line(),
+            loc.col = loc.column(),
+        );
+
+        self.attributes.location = loc;
+
+        with_kernel_aspace(|aspace| {
+            self.scheduler.owned.bind(
+                future,
+                self.scheduler,
+                id,
+                self.attributes,
+                span,
+                aspace.clone(),
+            )
+        })
+    }
+}
+
+impl fmt::Debug for TaskBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskBuilder")
+            .field("attributes", &self.attributes)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Deterministic scheduling for reproducible tests.
+//!
+//! In deterministic mode a single CPU runs the workers of *all* CPUs. In each step it picks a
+//! worker using a seeded pseudo-random number generator and lets it run a single task, so the
+//! interleaving of tasks is entirely determined by the seed. The seed is printed at boot and
+//! whenever the kernel panics, and passing it back through the `sched=deterministic:<seed>`
+//! bootarg replays the exact same schedule.
+//!
+//! Each worker uses the scheduling state of the CPU it stands in for, but everything that is local
+//! to the CPU actually running the tasks (the clock, the current task, task-locals, the lockup
+//! detector's heartbeat) stays with that CPU.
+//!
+//! Interrupts, and therefore timers and device events, are still delivered in real time, so
+//! schedules that depend on them are only reproducible as long as the timing doesn't change. They
+//! are only taken in between ticks though, never while a worker is running a task.
+
+use crate::arch::interrupt;
+use crate::scheduler::{Scheduler, Worker};
+use crate::time::Deadline;
+use crate::{arch, lockup, time};
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use anyhow::Context as _;
+use core::future::Future;
+use core::pin::pin;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use fastrand::FastRand;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use spin::OnceLock;
+
+static SEED: OnceLock<Option<u64>> = OnceLock::new();
+
+/// How tasks are scheduled onto CPUs, selected through the `sched` bootarg.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    /// Every CPU runs its own worker, in parallel.
+    #[default]
+    Parallel,
+    /// A single CPU runs all workers in a pseudo-random but reproducible interleaving. If no seed
+    /// is given, a random one is chosen at boot.
+    Deterministic { seed: Option<u64> },
+}
+
+/// Select the scheduling mode, choosing a random seed from `rng` if necessary.
+pub fn init(mode: Mode, rng: &mut impl RngCore) {
+    SEED.get_or_init(|| match mode {
+        Mode::Parallel => None,
+        Mode::Deterministic { seed } => {
+            let seed = seed.unwrap_or_else(|| rng.next_u64());
+            tracing::info!(
+                "deterministic scheduling with seed {seed:#x}, replay with `sched=deterministic:{seed:#x}`"
+            );
+            Some(seed)
+        }
+    });
+}
+
+/// Returns the seed if the scheduler runs in deterministic mode.
+pub fn seed() -> Option<u64> {
+    SEED.get().copied().flatten()
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "parallel" => Ok(Mode::Parallel),
+            None if s == "deterministic" => Ok(Mode::Deterministic { seed: None }),
+            Some(("deterministic", seed)) => {
+                let seed = if let Some(hex) = seed.strip_prefix("0x") {
+                    u64::from_str_radix(hex, 16)
+                } else {
+                    u64::from_str(seed)
+                }
+                .with_context(|| format!("invalid scheduling seed `{seed}`"))?;
+
+                Ok(Mode::Deterministic { seed: Some(seed) })
+            }
+            _ => anyhow::bail!("unknown scheduling mode `{s}`"),
+        }
+    }
+}
+
+impl Scheduler {
+    /// Run the workers of all CPUs on the calling CPU, in the interleaving determined by `seed`,
+    /// until `future` completes.
+    ///
+    /// `future` is polled on the calling CPU like [`Scheduler::block_on`] would. Returns `None` if
+    /// the scheduler was shut down before the future completed.
+    pub fn run_deterministic<F>(&'static self, seed: u64, future: F) -> Option<F::Output>
+    where
+        F: Future,
+    {
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        let mut workers: Vec<Worker> = (0..self.inboxes.len())
+            .filter(|cpuid| cpu_mask & (1 << cpuid) != 0)
+            .map(|cpuid| Worker::new(self, cpuid, &mut rng))
+            .collect();
+        let num_workers = u32::try_from(workers.len()).unwrap();
+
+        let mut pick = FastRand::from_seed(rng.next_u64());
+
+        let woken = Arc::new(Woken(AtomicBool::new(true)));
+        let waker = Waker::from(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            // Interrupts are only taken in between ticks, so they can't turn and arm timers while
+            // a worker is in the middle of one
+            let done = interrupt::with_disabled(|| {
+                if woken.0.swap(false, Ordering::AcqRel)
+                    && let Poll::Ready(output) = future.as_mut().poll(&mut cx)
+                {
+                    return Some(Some(output));
+                }
+
+                if self.shutdown.load(Ordering::Acquire) {
+                    return Some(None);
+                }
+
+                // Let a random worker run a single task, if it has nothing to do try the next one
+                let start = pick.fastrand_n(num_workers) as usize;
+                let len = workers.len();
+                let progress = (0..len).any(|i| workers[(start + i) % len].tick());
+
+                // Each worker armed the timer interrupt for its own timer while looking for work,
+                // so make sure it is armed for the earliest deadline of all of them
+                let next_deadline = workers
+                    .iter()
+                    .filter_map(|worker| worker.timer().next_deadline())
+                    .min_by_key(|deadline: &Deadline| deadline.ticks);
+                time::arm_timer_interrupt(next_deadline);
+
+                if !progress && !woken.0.load(Ordering::Acquire) {
+                    // Every task is blocked, so we can only make progress once an interrupt
+                    // fires. `wfi` returns as soon as one is pending even though they are
+                    // disabled, it is then taken once they are enabled again.
+                    lockup::park();
+                    // Safety: we are waiting for an interrupt
+                    unsafe {
+                        arch::cpu_park();
+                    }
+                    lockup::unpark();
+                }
+
+                None
+            });
+
+            if let Some(output) = done {
+                return output;
+            }
+        }
+    }
+}
+
+/// Stops a CPU that doesn't participate in deterministic scheduling for good.
+///
+/// The CPU is stopped through the firmware (rather than just parked), so [`shutdown::finish`]
+/// doesn't have to wait for it. Its interrupts would race with the deterministic schedule, so they
+/// stay disabled.
+///
+/// [`shutdown::finish`]: crate::shutdown::finish
+pub fn park_forever() -> ! {
+    arch::cpu_stop()
+}
+
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::yield_now;
+    use alloc::boxed::Box;
+    use spin::Mutex;
+
+    /// Returns the order in which a few tasks that keep yielding make progress when they are
+    /// scheduled with `seed` on a fresh scheduler.
+    fn interleaving(seed: u64) -> Vec<usize> {
+        // CPU ids index the scheduler's per-CPU state, so it has to cover the highest CPU id
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        let num_cpus = usize::try_from(usize::BITS - cpu_mask.leading_zeros()).unwrap();
+        let sched: &'static Scheduler = Box::leak(Box::new(Scheduler::new(num_cpus)));
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let log2 = log.clone();
+        sched
+            .run_deterministic(seed, async move {
+                let handles: Vec<_> = (0..4)
+                    .map(|i| {
+                        let log = log2.clone();
+                        sched.spawn(async move {
+                            for _ in 0..8 {
+                                log.lock().push(i);
+                                yield_now().await;
+                            }
+                        })
+                    })
+                    .collect();
+
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            })
+            .unwrap();
+
+        log.lock().clone()
+    }
+
+    #[ktest::test]
+    async fn same_seed_same_interleaving() {
+        let first = interleaving(0x5eed);
+        assert_eq!(first.len(), 4 * 8);
+        assert_eq!(interleaving(0x5eed), first);
+    }
+}
@@ -42,13 +42,24 @@ impl Idle {
         self.num_searching.load(Ordering::Acquire)
     }
 
+    /// Returns whether the worker on the given CPU is waiting for work.
+    pub fn is_idle(&self, cpuid: usize) -> bool {
+        cpuid < self.num_cores && self.idle_map.is_set(cpuid)
+    }
+
     pub fn transition_worker_to_waiting(&self, worker: &super::Worker) {
         // tracing::trace!("Idle::transition_worker_to_waiting");
 
         // The worker should not be stealing at this point
         debug_assert!(!worker.is_searching);
-        // Check that there are no pending tasks in the global queue
-        debug_assert!(worker.scheduler.run_queue.is_empty());
+        // Check that there are no pending tasks in the global queues
+        debug_assert!(
+            worker
+                .scheduler
+                .run_queues
+                .iter()
+                .all(|run_queue| run_queue.is_empty())
+        );
 
         self.idle_map.set(worker.cpuid);
 
@@ -112,6 +123,20 @@ impl Idle {
         }
     }
 
+    /// Wake up the worker on the given CPU if it is sleeping.
+    pub fn notify_cpu(&self, cpuid: usize) {
+        let mut sleepers = self.sleepers.lock();
+        if let Some(pos) = sleepers.iter().position(|sleeper| *sleeper == cpuid) {
+            sleepers.swap_remove(pos);
+            drop(sleepers);
+
+            // Safety: the worker placed itself into the sleepers list, so sending a wakeup is safe
+            unsafe {
+                arch::cpu_unpark(cpuid);
+            }
+        }
+    }
+
     pub fn notify_all(&self) {
         // tracing::trace!("Idle::notify_all");
         while let Some(worker) = self.sleepers.lock().pop() {
@@ -146,6 +171,11 @@ impl IdleMap {
         self.chunks[chunk].store(next, Ordering::Release);
     }
 
+    fn is_set(&self, index: usize) -> bool {
+        let (chunk, mask) = index_to_mask(index);
+        self.chunks[chunk].load(Ordering::Acquire) & mask != 0
+    }
+
     fn unset(&self, index: usize) {
         let (chunk, mask) = index_to_mask(index);
         let prev = self.chunks[chunk].load(Ordering::Acquire);
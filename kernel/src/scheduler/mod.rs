@@ -43,12 +43,47 @@
 //! 1. The hardware timer will be reset to fire at the new deadline
 //! 2. Any tasks than have registered with the timer will be woken (through their [`Waker`][core::task::Waker]).
 //!
+//! ## Priorities
+//!
+//! Every task belongs to a scheduling class described by its [`Priority`]. Workers keep one local
+//! run queue per class and the scheduler one global run queue per class, a worker will usually only
+//! pick a task of a less urgent class when all queues of the more urgent classes it can reach are
+//! empty. To make sure less urgent tasks aren't starved by a steady stream of more urgent ones,
+//! every class has a budget of tasks ([`CLASS_BUDGETS`]) the worker may pick in a row, once it is
+//! used up the less urgent classes get their turn before the budgets are refilled. This bounds how
+//! long a ready task waits for more urgent ones, while a more urgent task is only ever delayed by
+//! the budgets of the less urgent classes. Priorities are assigned at spawn time through the
+//! [`TaskBuilder`] returned by [`Scheduler::build_task`].
+//!
+//! ## Affinity
+//!
+//! Tasks can be restricted to a subset of CPUs (see [`TaskBuilder::affinity`] and
+//! [`Scheduler::spawn_on`]). Such *pinned* tasks are kept out of the stealable local run queues and
+//! the global run queues entirely: each worker keeps a separate, non-stealable queue of pinned tasks
+//! per priority class, and pinned tasks woken on a CPU they are not allowed to run on are sent to
+//! the *inbox* of a CPU they may run on instead.
+//!
+//! ## CPU hotplug
+//!
+//! CPUs can be taken offline at runtime (see [`crate::hotplug`]). A worker whose CPU was marked
+//! offline stops running tasks, hands everything in its local queues to the global run queues (or
+//! to the inboxes of other CPUs for pinned tasks) and wakes all tasks sleeping on its timer, so
+//! their sleeps move to the timer of the CPU they run on next. Once the CPU comes back online,
+//! the worker continues where it left off. Tasks pinned exclusively to an offline CPU wait in its
+//! inbox until then.
+//!
+//! ## Deterministic mode
+//!
+//! For reproducing concurrency bugs the scheduler can run in [`deterministic`] mode (selected with
+//! the `sched=deterministic[:<seed>]` bootarg), where a single CPU runs the workers of all CPUs in
+//! a seeded pseudo-random interleaving, one task at a time.
+//!
 //! ## Detailed description of current behaviour
 //!
 //! The scheduler has a fixed number of [`Worker`]s one for each CPU of the system. Each `Worker`
-//! maintains their own fixed-size local queue of tasks ([`queue::LOCAL_QUEUE_CAPACITY`] elements).
-//! If more tasks are added to the local run queue than can fit, then half of them are moved to the
-//! global queue to make space.
+//! maintains their own fixed-size local queues of tasks ([`queue::LOCAL_QUEUE_CAPACITY`] elements),
+//! one for each priority class. If more tasks are added to a local run queue than can fit, then
+//! half of them are moved to the global queue of the same priority to make space.
 //!
 //! Workers will prefer tasks from their own local queue and will only pick a task from the global queue
 //! if the local queue is empty, or if it has picked a task from the local queue `global_queue_interval`
@@ -58,73 +93,102 @@
 //! from the local queue of another worker thread. Stealing is done by moving half of the tasks in one
 //! local queue to another local queue.
 //!
+//! All of the above happens for one priority class after another: a worker first consults its local
+//! and the global realtime queue, then its local and the global normal queue and so on. Stealing
+//! likewise first looks at the realtime queues of all other workers before considering less urgent
+//! tasks.
+//!
 //! [`maitake`]: https://github.com/hawkw/mycelium/tree/dd0020892564c77ee4c20ffbc2f7f5b046ad54c8/maitake
 //! [`tokio`]: https://tokio.rs
 //! [`MultiThreadAlt`]: https://docs.rs/tokio/latest/tokio/runtime/struct.Builder.html#method.new_multi_thread_alt
 
+mod builder;
+pub mod deterministic;
 mod idle;
 mod park;
 mod queue;
 mod yield_now;
 
 use crate::cpu_local::CpuLocal;
-use crate::mem::with_kernel_aspace;
 use crate::scheduler::idle::Idle;
 use crate::scheduler::park::ParkToken;
 use crate::scheduler::queue::Overflow;
-use crate::task::{JoinHandle, OwnedTasks, PollResult, Schedule, TaskRef};
+use crate::task::{JoinHandle, OwnedTasks, PollResult, Priority, Schedule, TaskDump, TaskRef};
 use crate::time::Timer;
-use crate::{arch, task};
-use core::any::type_name;
+use crate::{hotplug, lockup, task};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use core::cell::{Ref, RefCell};
 use core::future::Future;
 use core::mem;
 use core::ops::DerefMut;
 use core::pin::pin;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use core::task::{Context, Poll};
 use cpu_local::cpu_local;
 use fastrand::FastRand;
 use rand::RngCore;
 use spin::{Backoff, Barrier, OnceLock};
 
+pub use builder::TaskBuilder;
+pub use yield_now::yield_now;
+
 const DEFAULT_GLOBAL_QUEUE_INTERVAL: u32 = 61;
+/// How many tasks of each priority class a worker may pick before all less urgent classes had their
+/// turn, indexed by [`Priority::index`].
+const CLASS_BUDGETS: [u32; Priority::COUNT] = [32, 8, 1];
 
 static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
 
+cpu_local! {
+    /// The task currently being polled on this CPU.
+    ///
+    /// This is not part of [`Core`], since in deterministic mode one CPU runs the workers (and
+    /// therefore uses the `Core`s) of all CPUs.
+    static CURRENT_TASK: RefCell<Option<TaskRef>> = const { RefCell::new(None) };
+}
+
 pub fn init(num_cores: usize) -> &'static Scheduler {
-    static TASK_STUB: TaskStub = TaskStub::new();
-
-    SCHEDULER.get_or_init(|| Scheduler {
-        cores: CpuLocal::with_capacity(num_cores),
-        remotes: CpuLocal::with_capacity(num_cores),
-        owned: OwnedTasks::new(),
-        // Safety: the static is scoped to this function AND we call `new_with_static_stub` through
-        // `get_or_init` which guarantees the stub will only ever be used once here.
-        run_queue: unsafe { mpsc_queue::MpscQueue::new_with_static_stub(&TASK_STUB.hdr) },
-        shutdown: AtomicBool::new(false),
-        idle: Idle::new(num_cores),
-        shutdown_barrier: Barrier::new(num_cores),
-    })
+    SCHEDULER.get_or_init(|| Scheduler::new(num_cores))
 }
 
 pub fn scheduler() -> &'static Scheduler {
     SCHEDULER.get().expect("scheduler not initialized")
 }
 
+/// Calls `f` with the task currently being polled on this CPU, if any.
+///
+/// Unlike [`Scheduler::current_task`] this works before the scheduler is initialized and doesn't
+/// panic if it interrupts a task switch (reporting no task instead), so it can be used from
+/// interrupt handlers and log output.
+pub(crate) fn with_current_task<R>(f: impl FnOnce(Option<&TaskRef>) -> R) -> R {
+    match CURRENT_TASK.try_borrow() {
+        Ok(current) => f(current.as_ref()),
+        Err(_) => f(None),
+    }
+}
+
 pub struct Scheduler {
     /// Per-CPU core scheduling data
     cores: CpuLocal<RefCell<Core>>,
     /// Per-CPU data that may be accessed by other workers
     remotes: CpuLocal<Remote>,
-    /// The global run queue
-    run_queue: mpsc_queue::MpscQueue<task::Header>,
+    /// The global run queues, one for each priority class
+    run_queues: [mpsc_queue::MpscQueue<task::Header>; Priority::COUNT],
+    /// Pinned tasks that were woken on another CPU, indexed by the CPU they should run on
+    inboxes: Box<[mpsc_queue::MpscQueue<task::Header>]>,
+    /// Where to start looking for an inbox for the next pinned task, so tasks that may run on
+    /// several CPUs are spread over all of them
+    next_inbox: AtomicUsize,
     /// All tasks currently scheduled on this runtime
     owned: OwnedTasks,
     /// Coordinates idle workers
     idle: Idle,
     /// Signal to workers that they should be shutting down.
     shutdown: AtomicBool,
+    /// Bitmask of the CPUs that have been taken offline, see [`crate::hotplug`].
+    offline: AtomicUsize,
     /// Spin barrier used to synchronize shutdown between workers,
     /// see comments in [`Worker::shutdown`] for details.
     shutdown_barrier: Barrier,
@@ -135,15 +199,18 @@ pub struct Scheduler {
 /// Logically this is part of the [`Worker`] struct, but is kept separate to allow access from
 /// other parts of the code. Namely, we need access to the [`Core`] in [`Scheduler::schedule_task`].
 struct Core {
-    /// The worker-local run queue.
-    run_queue: queue::Local,
+    /// The worker-local run queues, one for each priority class.
+    run_queues: [queue::Local; Priority::COUNT],
+    /// Tasks pinned to a set of CPUs that includes this one, one queue for each priority class.
+    ///
+    /// These are kept separate from `run_queues` so they can't be stolen by other workers.
+    pinned: [VecDeque<TaskRef>; Priority::COUNT],
     /// When a task is scheduled from a worker, it is stored in this slot. The
     /// worker will check this slot for a task **before** checking the run
     /// queue. This effectively results in the **last** scheduled task to be run
     /// next (LIFO). This is an optimization for improving locality which
     /// benefits message passing patterns and helps to reduce latency.
     lifo_slot: Option<TaskRef>,
-    current_task: Option<TaskRef>,
 }
 
 /// Per-CPU state accessed by other workers
@@ -154,8 +221,8 @@ struct Core {
 struct Remote {
     /// This workers timer instance.
     timer: Timer,
-    /// Steals tasks from this worker.
-    steal: queue::Steal,
+    /// Steals tasks from this worker, one handle for each priority class.
+    steals: [queue::Steal; Priority::COUNT],
 }
 
 /// A scheduler worker
@@ -179,38 +246,90 @@ pub struct Worker {
     num_seq_local_queue_polls: u32,
     /// How often to check the global queue
     global_queue_interval: u32,
+    /// The number of tasks left that may be picked from each priority class before the less urgent
+    /// classes get their turn, see [`CLASS_BUDGETS`].
+    budgets: [u32; Priority::COUNT],
     /// True if the worker is currently searching for more work. Searching
     /// involves attempting to steal from other workers.
     is_searching: bool,
 }
 
 impl Scheduler {
+    fn new(num_cores: usize) -> Self {
+        fn new_queue() -> mpsc_queue::MpscQueue<task::Header> {
+            let stub: &'static TaskStub = Box::leak(Box::new(TaskStub::new()));
+            // Safety: the stub was just allocated and is never used for anything else
+            unsafe { mpsc_queue::MpscQueue::new_with_static_stub(&stub.hdr) }
+        }
+
+        Self {
+            inboxes: (0..num_cores).map(|_| new_queue()).collect(),
+            next_inbox: AtomicUsize::new(0),
+            cores: CpuLocal::with_capacity(num_cores),
+            remotes: CpuLocal::with_capacity(num_cores),
+            owned: OwnedTasks::new(),
+            run_queues: Priority::ALL.map(|_| new_queue()),
+            shutdown: AtomicBool::new(false),
+            offline: AtomicUsize::new(0),
+            idle: Idle::new(num_cores),
+            shutdown_barrier: Barrier::new(num_cores),
+        }
+    }
+
+    /// Spawn a task with the default settings.
+    ///
+    /// This is a shorthand for `self.build_task().spawn(future)`, use [`Scheduler::build_task`]
+    /// to configure the task before spawning it.
     #[track_caller]
     pub fn spawn<F>(&'static self, future: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send + 'static,
     {
-        let id = task::Id::next();
-
-        let loc = core::panic::Location::caller();
-        let span = tracing::trace_span!(
-            "scheduler.spawn",
-            task.tid = id.as_u64(),
-            task.output = %type_name::<F::Output>(),
-            loc.file = loc.file(),
-            loc.line = loc.line(),
-            loc.col = loc.column(),
-        );
+        self.build_task().spawn(future)
+    }
 
-        let (handle, maybe_task) =
-            with_kernel_aspace(|aspace| self.owned.bind(future, self, id, span, aspace.clone()));
+    /// Returns a [`TaskBuilder`] to configure and spawn a new task.
+    pub fn build_task(&'static self) -> TaskBuilder {
+        TaskBuilder::new(self)
+    }
 
-        if let Some(task) = maybe_task {
-            self.schedule(task);
-        }
+    /// Spawn a task that will only ever run on the CPU with the given `cpuid`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no CPU with the given `cpuid`.
+    #[track_caller]
+    pub fn spawn_on<F>(&'static self, cpuid: usize, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.build_task().pin_to(cpuid).spawn(future)
+    }
+
+    /// Spawn one task per CPU, each pinned to its CPU.
+    ///
+    /// The `make_future` closure is called with the id of each CPU in ascending order to create
+    /// the task for it. The returned handles are in the same order.
+    #[track_caller]
+    pub fn spawn_per_cpu<F>(
+        &'static self,
+        mut make_future: impl FnMut(usize) -> F,
+    ) -> Vec<JoinHandle<F::Output>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
 
-        handle
+        // this is a plain loop rather than an iterator chain so the tasks' spawn location is our
+        // caller and not a closure in here
+        let mut handles = Vec::with_capacity(self.inboxes.len());
+        for cpuid in (0..self.inboxes.len()).filter(|cpuid| cpu_mask & (1 << cpuid) != 0) {
+            handles.push(self.spawn_on(cpuid, make_future(cpuid)));
+        }
+        handles
     }
 
     #[track_caller]
@@ -247,9 +366,45 @@ impl Scheduler {
         }
     }
 
+    /// Returns whether [`Scheduler::shutdown`] has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Returns the bitmask of CPUs that take part in scheduling, i.e. haven't been taken offline.
+    pub fn online_cpus(&self) -> usize {
+        crate::BOOT_INFO.get().unwrap().cpu_mask & !self.offline.load(Ordering::Acquire)
+    }
+
+    pub fn is_cpu_online(&self, cpuid: usize) -> bool {
+        self.online_cpus() & (1 << cpuid) != 0
+    }
+
+    /// Mark the CPU `cpuid` as online or offline.
+    ///
+    /// The worker of a CPU marked offline notices the next time it looks for work, hands over its
+    /// tasks and stops the CPU through [`hotplug::stop_this_cpu`].
+    pub(crate) fn set_cpu_online(&self, cpuid: usize, online: bool) {
+        if online {
+            self.offline.fetch_and(!(1 << cpuid), Ordering::AcqRel);
+        } else {
+            self.offline.fetch_or(1 << cpuid, Ordering::AcqRel);
+            self.idle.notify_cpu(cpuid);
+        }
+    }
+
+    /// Returns whether the CPU `cpuid` is currently idle, waiting for work.
+    pub fn is_cpu_idle(&self, cpuid: usize) -> bool {
+        self.idle.is_idle(cpuid)
+    }
+
     pub fn current_task(&self) -> Option<Ref<'_, TaskRef>> {
-        let core = self.cores.get()?.borrow();
-        Ref::filter_map(core, |core| core.current_task.as_ref()).ok()
+        Ref::filter_map(CURRENT_TASK.borrow(), Option::as_ref).ok()
+    }
+
+    /// Returns a snapshot of every task owned by the scheduler.
+    pub fn dump_tasks(&self) -> Vec<TaskDump> {
+        self.owned.dump()
     }
 
     pub fn cpu_local_timer(&self) -> &Timer {
@@ -258,6 +413,8 @@ impl Scheduler {
 
     fn schedule_task(&self, task: TaskRef) {
         if let Some(core) = self.cores.get()
+            && task.attributes().allows_cpu(crate::CPUID.get())
+            && self.is_cpu_online(crate::CPUID.get())
             && let Ok(mut core) = core.try_borrow_mut()
         {
             self.schedule_local(core.deref_mut(), task);
@@ -267,20 +424,54 @@ impl Scheduler {
     }
 
     fn schedule_local(&self, core: &mut Core, task: TaskRef) {
-        // Push to the LIFO slot
-        let prev = mem::replace(&mut core.lifo_slot, Some(task));
-        if let Some(prev) = prev {
-            core.run_queue.push_back_or_overflow(prev, self);
-        } else {
-            return;
-        }
+        // Push to the LIFO slot, unless that would displace a more urgent task
+        let task = match &core.lifo_slot {
+            Some(prev) if prev.priority() < task.priority() => task,
+            _ => match mem::replace(&mut core.lifo_slot, Some(task)) {
+                Some(prev) => prev,
+                None => return,
+            },
+        };
+
+        self.push_local(core, task);
 
         self.idle.notify_one();
     }
 
+    fn push_local(&self, core: &mut Core, task: TaskRef) {
+        let priority = task.priority().index();
+        if task.attributes().is_pinned() {
+            core.pinned[priority].push_back(task);
+        } else {
+            core.run_queues[priority].push_back_or_overflow(task, &self.run_queues[priority]);
+        }
+    }
+
     fn schedule_remote(&self, task: TaskRef) {
-        self.run_queue.enqueue(task);
-        self.idle.notify_one();
+        let attributes = task.attributes();
+
+        if attributes.is_pinned() {
+            let num_cpus = self.inboxes.len();
+            let start = self.next_inbox.fetch_add(1, Ordering::Relaxed) % num_cpus;
+            let allowed = (0..num_cpus)
+                .map(|i| (start + i) % num_cpus)
+                .filter(|cpuid| attributes.allows_cpu(*cpuid));
+
+            // prefer idle CPUs, then any online CPU. If all allowed CPUs are offline the task
+            // waits in the inbox of one of them until it comes back
+            let cpuid = allowed
+                .clone()
+                .find(|cpuid| self.is_cpu_online(*cpuid) && self.is_cpu_idle(*cpuid))
+                .or_else(|| allowed.clone().find(|cpuid| self.is_cpu_online(*cpuid)))
+                .or_else(|| allowed.clone().next())
+                .expect("task affinity doesn't include any CPU");
+
+            self.inboxes[cpuid].enqueue(task);
+            self.idle.notify_cpu(cpuid);
+        } else {
+            self.run_queues[task.priority().index()].enqueue(task);
+            self.idle.notify_one();
+        }
     }
 }
 
@@ -290,35 +481,41 @@ impl Schedule for &'static Scheduler {
     }
 }
 
-impl Overflow for Scheduler {
+impl Overflow for mpsc_queue::MpscQueue<task::Header> {
     fn push(&self, task: TaskRef) {
-        self.run_queue.enqueue(task);
+        self.enqueue(task);
     }
 
     fn push_batch<I>(&self, iter: I)
     where
         I: Iterator<Item = TaskRef>,
     {
-        self.run_queue.enqueue_many(iter);
+        self.enqueue_many(iter);
     }
 }
 
 impl Worker {
     pub fn new(scheduler: &'static Scheduler, cpuid: usize, rng: &mut impl RngCore) -> Self {
-        let (steal, run_queue) = queue::new();
+        let queues = Priority::ALL.map(|_| queue::new());
+        let steals = queues.each_ref().map(|(steal, _)| steal.clone());
+        let run_queues = queues.map(|(_, local)| local);
 
-        scheduler.cores.get_or(|| {
-            RefCell::new(Core {
-                lifo_slot: None,
-                run_queue,
-                current_task: None,
-            })
-        });
-
-        scheduler.remotes.get_or(|| Remote {
-            steal,
-            timer: Timer::new(),
-        });
+        // Safety: a worker is created either by its own CPU, or in deterministic mode by the CPU
+        // running all workers while the other CPUs are parked
+        unsafe {
+            scheduler.cores.get_or_for(cpuid, || {
+                RefCell::new(Core {
+                    lifo_slot: None,
+                    run_queues,
+                    pinned: Default::default(),
+                })
+            });
+
+            scheduler.remotes.get_or_for(cpuid, || Remote {
+                steals,
+                timer: Timer::new(),
+            });
+        }
 
         Self {
             scheduler,
@@ -327,15 +524,23 @@ impl Worker {
             rng: FastRand::from_seed(rng.next_u64()),
             num_seq_local_queue_polls: 0,
             global_queue_interval: DEFAULT_GLOBAL_QUEUE_INTERVAL,
+            budgets: CLASS_BUDGETS,
         }
     }
 
     pub fn run(&mut self) {
         loop {
-            while let Some(task) = self.next_task() {
+            while self.scheduler.is_cpu_online(self.cpuid)
+                && let Some(task) = self.next_task()
+            {
                 self.run_task(task);
             }
 
+            if !self.scheduler.is_cpu_online(self.cpuid) {
+                self.offline();
+                continue;
+            }
+
             // continue to execute tasks because we possibly unblocked some tasks now by turning
             // the timer
             if self.turn_timer() {
@@ -349,9 +554,12 @@ impl Worker {
             // if we have no tasks to run, we can sleep until an interrupt
             // occurs.
             self.scheduler.idle.transition_worker_to_waiting(self);
-            // Safety: TODO
-            unsafe {
-                arch::cpu_park();
+            // A pinned task might have been sent to us after we last checked our inbox but before
+            // we registered as sleeping, in which case nobody will wake us up for it.
+            if self.scheduler.inboxes[self.cpuid].is_empty() {
+                lockup::park();
+                hotplug::wait_for_interrupt(self.timer().next_deadline());
+                lockup::unpark();
             }
 
             self.scheduler.idle.transition_worker_from_waiting(self);
@@ -360,6 +568,65 @@ impl Worker {
         self.shutdown();
     }
 
+    /// Take this worker's CPU offline, returning once it is back online.
+    fn offline(&mut self) {
+        tracing::debug!("taking CPU {} offline...", self.cpuid);
+
+        // Tasks woken from now on are scheduled on other CPUs (see `Scheduler::schedule_task`), so
+        // once our queues are empty they stay that way.
+        self.hand_over_tasks();
+        let woken = self.timer().wake_all();
+        tracing::trace!("woke {woken} sleeping tasks to move them to other CPUs");
+
+        lockup::park();
+        if let Err(err) = hotplug::stop_this_cpu() {
+            tracing::warn!("failed to take CPU {} offline: {err}", self.cpuid);
+        }
+        lockup::unpark();
+
+        tracing::debug!("CPU {} is online", self.cpuid);
+    }
+
+    /// Move all tasks queued on this worker to other CPUs.
+    fn hand_over_tasks(&mut self) {
+        let mut core = self.core().borrow_mut();
+
+        // Collect the inbox before handing anything over, tasks that may only run on this CPU are
+        // sent right back to it and must not be dequeued again
+        let inbox: Vec<_> =
+            core::iter::from_fn(|| self.scheduler.inboxes[self.cpuid].dequeue()).collect();
+        for task in inbox {
+            self.scheduler.schedule_remote(task);
+        }
+
+        if let Some(task) = core.lifo_slot.take() {
+            self.scheduler.schedule_remote(task);
+        }
+        for run_queue in &mut core.run_queues {
+            while let Some(task) = run_queue.pop() {
+                self.scheduler.schedule_remote(task);
+            }
+        }
+        for pinned in &mut core.pinned {
+            for task in pinned.drain(..) {
+                self.scheduler.schedule_remote(task);
+            }
+        }
+    }
+
+    /// Run a single task, or turn the timer if there is no task to run.
+    ///
+    /// Returns `false` if the worker has nothing to do. This is used by the deterministic mode
+    /// to interleave multiple workers on one CPU, see [`deterministic`].
+    fn tick(&mut self) -> bool {
+        if let Some(task) = self.next_task() {
+            self.run_task(task);
+            true
+        } else {
+            self.turn_timer()
+        }
+    }
+
     fn run_task(&mut self, task: TaskRef) {
         tracing::trace!("Running task {:?}", task);
 
@@ -376,7 +643,10 @@ impl Worker {
                 unsafe { aspace.lock().activate() }
             }
 
-            task.poll()
+            lockup::poll_start(task.id(), task.location());
+            let poll_result = task.poll();
+            lockup::poll_end();
+            poll_result
         });
         match poll_result {
             PollResult::Ready | PollResult::ReadyJoined => {
@@ -394,33 +664,43 @@ impl Worker {
     where
         F: FnOnce(TaskRef) -> R,
     {
-        let mut core = self.scheduler.cores.get().unwrap().borrow_mut();
-        core.current_task = Some(task.clone());
-        drop(core);
+        // restore the previous task afterward, tests may run a nested scheduler from within a task
+        let prev = CURRENT_TASK.replace(Some(task.clone()));
 
         let r = f(task);
 
-        let mut core = self.scheduler.cores.get().unwrap().borrow_mut();
-        (r, core.current_task.take().unwrap())
+        (r, CURRENT_TASK.replace(prev).unwrap())
+    }
+
+    /// Returns this worker's CPU-local scheduling data.
+    ///
+    /// This is looked up by the worker's `cpuid` rather than the calling CPU, which differ in
+    /// deterministic mode.
+    fn core(&self) -> &'static RefCell<Core> {
+        self.scheduler.cores.get_for(self.cpuid).unwrap()
+    }
+
+    /// Returns this worker's timer, see [`Worker::core`].
+    fn timer(&self) -> &'static Timer {
+        &self.scheduler.remotes.get_for(self.cpuid).unwrap().timer
     }
 
     fn turn_timer(&self) -> bool {
-        let (expired, next_deadline) = self.scheduler.cpu_local_timer().turn();
-        if let Some(next_deadline) = next_deadline {
-            riscv::sbi::time::set_timer(next_deadline.ticks.0).unwrap();
-        } else {
-            // Timer interrupts are always IPIs used for sleeping
-            riscv::sbi::time::set_timer(u64::MAX).unwrap();
-        }
+        let expired = self.timer().turn_and_arm();
+        lockup::heartbeat();
         expired > 0
     }
 
     fn next_task(&mut self) -> Option<TaskRef> {
-        let core = self.scheduler.cores.get().unwrap();
-        let mut core = core.borrow_mut();
+        let mut core = self.core().borrow_mut();
 
         self.num_seq_local_queue_polls += 1;
 
+        // Move pinned tasks that were woken on other CPUs into our local pinned queues
+        while let Some(task) = self.scheduler.inboxes[self.cpuid].dequeue() {
+            core.pinned[task.priority().index()].push_back(task);
+        }
+
         // Every `global_queue_interval` ticks we must check the global queue
         // to ensure that tasks in the global run queue make progress too.
         // If we reached a tick where we pull from the global queue that takes precedence.
@@ -429,20 +709,25 @@ impl Worker {
 
             self.turn_timer();
 
-            if let Some(task) = self.next_remote_task() {
-                return Some(task);
+            let task = self.next_task_by_priority(&mut core, |this, core, priority| {
+                this.scheduler.run_queues[priority.index()]
+                    .dequeue()
+                    .or_else(|| this.next_local_task(core, priority))
+            });
+            if task.is_some() {
+                return task;
             }
         }
 
-        // Now comes the "main" part of searching for the next task. We first consult our local run
-        // queue for a task.
-        if let Some(task) = self.next_local_task(&mut core) {
-            return Some(task);
-        }
-
-        // If our local run queue is empty we try to refill it from the global run queue.
-        if let Some(task) = self.next_remote_task_and_refill_queue(&mut core) {
-            return Some(task);
+        // Now comes the "main" part of searching for the next task. For each priority class,
+        // starting with the most urgent one, we first consult our local run queue for a task and
+        // if that is empty try to refill it from the global run queue.
+        let task = self.next_task_by_priority(&mut core, |this, core, priority| {
+            this.next_local_task(core, priority)
+                .or_else(|| this.next_remote_task_and_refill_queue(core, priority))
+        });
+        if task.is_some() {
+            return task;
         }
 
         self.transition_to_searching();
@@ -459,34 +744,87 @@ impl Worker {
         None
     }
 
-    fn next_local_task(&mut self, core: &mut Core) -> Option<TaskRef> {
-        core.lifo_slot.take().or_else(|| core.run_queue.pop())
+    /// Pick a task through `pick`, trying the priority classes from the most to the least urgent.
+    ///
+    /// Classes that used up their budget are skipped, so the less urgent classes get their turn.
+    /// Once no class with budget left has a task, the budgets are refilled.
+    fn next_task_by_priority(
+        &mut self,
+        core: &mut Core,
+        mut pick: impl FnMut(&mut Self, &mut Core, Priority) -> Option<TaskRef>,
+    ) -> Option<TaskRef> {
+        let has_exhausted = self.budgets.contains(&0);
+
+        for refilled in [false, true] {
+            if refilled {
+                if !has_exhausted {
+                    break;
+                }
+                self.budgets = CLASS_BUDGETS;
+            }
+
+            for priority in Priority::ALL {
+                if self.budgets[priority.index()] == 0 {
+                    continue;
+                }
+
+                if let Some(task) = pick(self, core, priority) {
+                    self.budgets[priority.index()] -= 1;
+                    return Some(task);
+                }
+            }
+        }
+
+        None
     }
 
-    fn next_remote_task(&self) -> Option<TaskRef> {
-        self.scheduler.run_queue.dequeue()
+    fn next_local_task(&mut self, core: &mut Core, priority: Priority) -> Option<TaskRef> {
+        if core
+            .lifo_slot
+            .as_ref()
+            .is_some_and(|task| task.priority() == priority)
+        {
+            return core.lifo_slot.take();
+        }
+
+        let pinned = &mut core.pinned[priority.index()];
+        let run_queue = &mut core.run_queues[priority.index()];
+
+        // Alternate between pinned and regular tasks so neither can starve the other
+        if self.num_seq_local_queue_polls % 2 == 0 {
+            pinned.pop_front().or_else(|| run_queue.pop())
+        } else {
+            run_queue.pop().or_else(|| pinned.pop_front())
+        }
     }
 
-    fn next_remote_task_and_refill_queue(&mut self, core: &mut Core) -> Option<TaskRef> {
+    fn next_remote_task_and_refill_queue(
+        &mut self,
+        core: &mut Core,
+        priority: Priority,
+    ) -> Option<TaskRef> {
+        let global = &self.scheduler.run_queues[priority.index()];
+        let local = &mut core.run_queues[priority.index()];
+
         let max = usize::min(
-            core.run_queue.remaining_slots(),
-            usize::max(core.run_queue.max_capacity() / 2, 1),
+            local.remaining_slots(),
+            usize::max(local.max_capacity() / 2, 1),
         );
 
         let n = if self.is_searching {
-            self.scheduler.run_queue.len() / self.scheduler.idle.num_searching() + 1
+            global.len() / self.scheduler.idle.num_searching() + 1
         } else {
-            self.scheduler.run_queue.len() / (self.scheduler.cores.len() + 1)
+            global.len() / (self.scheduler.cores.len() + 1)
         };
 
         let n = usize::min(n, max) + 1;
 
-        let mut tasks = self.scheduler.run_queue.consume().take(n);
+        let mut tasks = global.consume().take(n);
         let ret = tasks.next();
 
         // Safety: we calculated the max from the local queues remaining capacity, it can never overflow
         unsafe {
-            core.run_queue.push_back_unchecked(tasks);
+            local.push_back_unchecked(tasks);
         }
 
         ret
@@ -497,10 +835,6 @@ impl Worker {
 
         debug_assert!(core.lifo_slot.is_none());
 
-        if !core.run_queue.can_steal() {
-            return None;
-        }
-
         let num_workers = u32::try_from(self.scheduler.cores.len()).unwrap();
         let mut backoff = Backoff::new();
 
@@ -512,9 +846,11 @@ impl Worker {
                 return Some(task);
             }
 
-            // Attempt to steal from the global task queue again
-            if let Some(task) = self.next_remote_task_and_refill_queue(core) {
-                return Some(task);
+            // Attempt to steal from the global task queues again
+            for priority in Priority::ALL {
+                if let Some(task) = self.next_remote_task_and_refill_queue(core, priority) {
+                    return Some(task);
+                }
             }
 
             backoff.spin();
@@ -526,20 +862,29 @@ impl Worker {
     fn steal_one_round(&mut self, start: usize, core: &mut Core) -> Option<TaskRef> {
         let num_workers = self.scheduler.cores.len();
 
-        for i in 0..num_workers {
-            let i = (start + i) % num_workers;
-
-            // Don't steal from ourselves! We know we don't have work.
-            if i == self.cpuid {
+        // Look at the most urgent tasks of all other workers first, before considering less urgent
+        // ones.
+        for priority in Priority::ALL {
+            let dst = &mut core.run_queues[priority.index()];
+            if !dst.can_steal() {
                 continue;
             }
 
-            let Some(steal) = self.scheduler.remotes.iter().nth(i) else {
-                // The worker might not be online yet, just advance past
-                continue;
-            };
-            if let Some(task) = steal.steal.steal_into(&mut core.run_queue) {
-                return Some(task);
+            for i in 0..num_workers {
+                let i = (start + i) % num_workers;
+
+                // Don't steal from ourselves! We know we don't have work.
+                if i == self.cpuid {
+                    continue;
+                }
+
+                let Some(remote) = self.scheduler.remotes.iter().nth(i) else {
+                    // The worker might not be online yet, just advance past
+                    continue;
+                };
+                if let Some(task) = remote.steals[priority.index()].steal_into(dst) {
+                    return Some(task);
+                }
             }
         }
 
@@ -568,14 +913,19 @@ impl Worker {
     fn shutdown(&mut self) {
         self.scheduler.owned.close_and_shutdown_all();
 
-        let core = self.scheduler.cores.get().unwrap();
-        let mut core = core.borrow_mut();
+        let mut core = self.core().borrow_mut();
 
         // Drop the LIFO task
         drop(core.lifo_slot.take());
 
-        // Drain tasks from the local queue
-        while core.run_queue.pop().is_some() {}
+        // Drain tasks from the local queues
+        for run_queue in &mut core.run_queues {
+            while run_queue.pop().is_some() {}
+        }
+        for pinned in &mut core.pinned {
+            pinned.clear();
+        }
+        while self.scheduler.inboxes[self.cpuid].dequeue().is_some() {}
 
         // Wait for all workers
         tracing::trace!("waiting for other workers to shut down...");
@@ -588,8 +938,10 @@ impl Worker {
             // cannot call `next_remote_task()` because we already hold the lock.
             //
             // safety: passing in correct `idle::Synced`
-            while let Some(task) = self.scheduler.run_queue.dequeue() {
-                drop(task);
+            for run_queue in &self.scheduler.run_queues {
+                while let Some(task) = run_queue.dequeue() {
+                    drop(task);
+                }
             }
 
             tracing::trace!("scheduler shut down, bye bye...");
@@ -610,3 +962,239 @@ impl TaskStub {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CPUID;
+    use crate::time::{Duration, sleep};
+    use alloc::sync::Arc;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    /// Returns the CPU a task placed on `cpuid` is expected to run on. In deterministic mode the
+    /// calling CPU runs the workers of all CPUs, so every task runs on it.
+    fn expected_cpu(cpuid: usize) -> usize {
+        if deterministic::seed().is_some() {
+            CPUID.get()
+        } else {
+            cpuid
+        }
+    }
+
+    /// Returns a CPU other than the calling one, if there is one.
+    fn other_cpu() -> Option<usize> {
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        (0..usize::BITS as usize)
+            .find(|cpuid| *cpuid != CPUID.get() && cpu_mask & (1 << cpuid) != 0)
+    }
+
+    /// Returns a worker for the calling CPU of a fresh scheduler, so its scheduling decisions can
+    /// be observed in isolation.
+    fn isolated_worker() -> Worker {
+        // CPU ids index the scheduler's per-CPU state, so it has to cover the highest CPU id
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        let num_cpus = usize::try_from(usize::BITS - cpu_mask.leading_zeros()).unwrap();
+        let sched: &'static Scheduler = Box::leak(Box::new(Scheduler::new(num_cpus)));
+        Worker::new(sched, CPUID.get(), &mut ChaCha20Rng::seed_from_u64(0))
+    }
+
+    fn enqueue(worker: &Worker, priority: Priority) {
+        let (_, task) = worker
+            .scheduler
+            .build_task()
+            .priority(priority)
+            .bind(async {});
+        worker.scheduler.run_queues[priority.index()].enqueue(task.unwrap());
+    }
+
+    #[ktest::test]
+    async fn higher_priorities_first() {
+        let mut worker = isolated_worker();
+        for priority in Priority::ALL.into_iter().rev() {
+            enqueue(&worker, priority);
+        }
+
+        for expected in Priority::ALL {
+            let task = worker.next_task().unwrap();
+            assert_eq!(task.priority(), expected);
+            worker.run_task(task);
+        }
+        assert!(worker.next_task().is_none());
+    }
+
+    #[ktest::test]
+    async fn lower_priorities_not_starved() {
+        let mut worker = isolated_worker();
+        enqueue(&worker, Priority::Idle);
+
+        // keep a realtime task ready at all times
+        for _ in 0..=CLASS_BUDGETS[Priority::Realtime.index()] {
+            enqueue(&worker, Priority::Realtime);
+
+            let task = worker.next_task().unwrap();
+            let priority = task.priority();
+            worker.run_task(task);
+            if priority == Priority::Idle {
+                return;
+            }
+        }
+
+        panic!("idle task didn't run while realtime tasks were ready");
+    }
+
+    #[ktest::test]
+    async fn realtime_delay_is_bounded() {
+        let mut worker = isolated_worker();
+
+        // use up the realtime budget, so the less urgent classes are due
+        for _ in 0..CLASS_BUDGETS[Priority::Realtime.index()] {
+            enqueue(&worker, Priority::Realtime);
+            let task = worker.next_task().unwrap();
+            worker.run_task(task);
+        }
+
+        // keep less urgent tasks ready at all times
+        enqueue(&worker, Priority::Realtime);
+        let max_delay =
+            CLASS_BUDGETS[Priority::Normal.index()] + CLASS_BUDGETS[Priority::Idle.index()];
+        for _ in 0..=max_delay {
+            enqueue(&worker, Priority::Normal);
+            enqueue(&worker, Priority::Idle);
+
+            let task = worker.next_task().unwrap();
+            let priority = task.priority();
+            worker.run_task(task);
+            if priority == Priority::Realtime {
+                return;
+            }
+        }
+
+        panic!("realtime task was delayed by more than {max_delay} less urgent tasks");
+    }
+
+    #[ktest::test]
+    async fn global_queue_interval_keeps_priorities() {
+        let mut worker = isolated_worker();
+        enqueue(&worker, Priority::Idle);
+        enqueue(&worker, Priority::Realtime);
+
+        // the next pick is the one that checks the global run queues first
+        worker.num_seq_local_queue_polls = DEFAULT_GLOBAL_QUEUE_INTERVAL - 1;
+        let task = worker.next_task().unwrap();
+        assert_eq!(task.priority(), Priority::Realtime);
+        worker.run_task(task);
+    }
+
+    #[ktest::test]
+    async fn spawn_on_places_task() {
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        for cpuid in (0..usize::BITS as usize).filter(|cpuid| cpu_mask & (1 << cpuid) != 0) {
+            let ran_on = scheduler()
+                .spawn_on(cpuid, async { CPUID.get() })
+                .await
+                .unwrap();
+            assert_eq!(ran_on, expected_cpu(cpuid));
+        }
+    }
+
+    #[ktest::test]
+    async fn spawn_per_cpu_places_tasks() {
+        let handles = scheduler().spawn_per_cpu(|cpuid| async move { (cpuid, CPUID.get()) });
+        assert_eq!(
+            handles.len(),
+            crate::BOOT_INFO.get().unwrap().cpu_mask.count_ones() as usize
+        );
+
+        for handle in handles {
+            let (cpuid, ran_on) = handle.await.unwrap();
+            assert_eq!(ran_on, expected_cpu(cpuid));
+        }
+    }
+
+    #[ktest::test]
+    async fn affinity_spreads_over_mask() {
+        // exclude the calling CPU, so every task goes through `schedule_remote`
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        let mask = cpu_mask & !(1 << CPUID.get());
+        if mask.count_ones() < 2 {
+            return;
+        }
+
+        let handles: Vec<_> = (0..4 * mask.count_ones())
+            .map(|_| {
+                scheduler()
+                    .build_task()
+                    .affinity(mask)
+                    .spawn(async { CPUID.get() })
+            })
+            .collect();
+
+        if deterministic::seed().is_some() {
+            for handle in handles {
+                assert_eq!(handle.await.unwrap(), CPUID.get());
+            }
+            return;
+        }
+
+        let mut used = 0_usize;
+        for handle in handles {
+            let ran_on = handle.await.unwrap();
+            assert_ne!(mask & (1 << ran_on), 0);
+            used |= 1 << ran_on;
+        }
+        assert!(
+            used.count_ones() > 1,
+            "all tasks ran on the same CPU {used:#b}"
+        );
+    }
+
+    #[ktest::test]
+    async fn offline_hands_over_inbox() {
+        let sched = scheduler();
+        let this_cpu = CPUID.get();
+        let Some(target) = other_cpu() else {
+            return;
+        };
+
+        if deterministic::seed().is_some() {
+            // all CPUs are driven by a single one, so they can't be taken offline individually
+            assert!(hotplug::offline(target).is_err());
+            return;
+        }
+
+        // keep the target CPU busy, so it doesn't look at its inbox until it goes offline
+        let started = Arc::new(AtomicBool::new(false));
+        let release = Arc::new(AtomicBool::new(false));
+        let blocker = sched.spawn_on(target, {
+            let started = started.clone();
+            let release = release.clone();
+            async move {
+                started.store(true, Ordering::Release);
+                while !release.load(Ordering::Acquire) {
+                    core::hint::spin_loop();
+                }
+            }
+        });
+        while !started.load(Ordering::Acquire) {
+            sleep(Duration::from_millis(1)).await;
+        }
+
+        let (handle, task) = sched
+            .build_task()
+            .affinity((1 << target) | (1 << this_cpu))
+            .bind(async { CPUID.get() });
+        sched.inboxes[target].enqueue(task.unwrap());
+
+        hotplug::offline(target).unwrap();
+        release.store(true, Ordering::Release);
+        blocker.await.unwrap();
+
+        assert_ne!(handle.await.unwrap(), target);
+
+        while hotplug::state(target) != hotplug::CpuState::Offline {
+            sleep(Duration::from_millis(1)).await;
+        }
+        hotplug::online(target).await.unwrap();
+    }
+}
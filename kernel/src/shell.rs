@@ -15,21 +15,28 @@ const S: &str = r#"
 /_/\_\/____/____/
 "#;
 
-use crate::device_tree::DeviceTree;
-use crate::mem::{Mmap, PhysicalAddress, with_kernel_aspace};
+use crate::arch::device::cpu::with_cpu;
+use crate::driver::Uart;
+use crate::driver::tty::{Input, Tty};
+use crate::hotplug::IdleMode;
+use crate::irq::{IRQ_COUNTS, Irq, NUM_IRQS, TriggerMode};
+use crate::mem::frame_alloc::FRAME_ALLOC;
 use crate::scheduler::{Scheduler, scheduler};
-use crate::{arch, irq};
-use alloc::string::{String, ToString};
+use crate::shutdown::Action;
+use crate::task::trace::framed;
+use crate::{driver, hotplug, irq, shutdown, topology};
+use alloc::string::ToString;
+use alloc::vec::Vec;
 use core::fmt;
 use core::fmt::Write;
-use core::range::Range;
 use core::str::FromStr;
-use fallible_iterator::FallibleIterator;
 use spin::{Barrier, OnceLock};
 
-static COMMANDS: &[Command] = &[PANIC, FAULT, VERSION, SHUTDOWN];
+static COMMANDS: &[Command] = &[
+    PANIC, FAULT, VERSION, SHUTDOWN, REBOOT, NUMA, TASKS, DEVICES, IRQ, CPU,
+];
 
-pub fn init(devtree: &'static DeviceTree, sched: &'static Scheduler, num_cpus: usize) {
+pub fn init(sched: &'static Scheduler, num_cpus: usize) {
     static SYNC: OnceLock<Barrier> = OnceLock::new();
     let barrier = SYNC.get_or_init(|| Barrier::new(num_cpus));
 
@@ -37,75 +44,24 @@ pub fn init(devtree: &'static DeviceTree, sched: &'static Scheduler, num_cpus: u
         tracing::info!("{S}");
         tracing::info!("type `help` to list available commands");
 
-        sched.spawn(async move {
-            let (mut uart, _mmap, irq_num) = init_uart(devtree);
+        sched.build_task().name("shell").spawn(async move {
+            let Some(uart) = framed(driver::wait_for::<Uart>()).await else {
+                tracing::warn!("no UART found, the shell is unavailable");
+                return;
+            };
 
-            let mut line = String::new();
+            let mut tty = Tty::new(uart);
             loop {
-                let res = irq::next_event(irq_num).await;
-                assert!(res.is_ok());
-                let mut newline = false;
-
-                let ch = uart.recv() as char;
-                uart.write_char(ch).unwrap();
-                match ch {
-                    '\n' | '\r' => {
-                        newline = true;
-                        uart.write_str("\n\r").unwrap();
-                    }
-                    '\u{007F}' => {
-                        line.pop();
-                    }
-                    ch => line.push(ch),
-                }
-
-                if newline {
-                    eval(&line);
-                    line.clear();
+                match framed(tty.read_line()).await {
+                    Input::Line(line) => eval(&line),
+                    // the line was discarded, there is nothing running that could be interrupted
+                    Input::Interrupt => {}
                 }
             }
         });
     }
 }
 
-fn init_uart(devtree: &DeviceTree) -> (uart_16550::SerialPort, Mmap, u32) {
-    let s = devtree.find_by_path("/soc/serial").unwrap();
-    assert!(s.is_compatible(["ns16550a"]));
-
-    let clock_freq = s.property("clock-frequency").unwrap().as_u32().unwrap();
-    let mut regs = s.regs().unwrap();
-    let reg = regs.next().unwrap().unwrap();
-    assert!(regs.next().unwrap().is_none());
-    let irq_num = s.property("interrupts").unwrap().as_u32().unwrap();
-
-    let mmap = with_kernel_aspace(|aspace| {
-        // FIXME: this is gross, we're using the PhysicalAddress as an alignment utility :/
-        let size = PhysicalAddress::new(reg.size.unwrap())
-            .checked_align_up(arch::PAGE_SIZE)
-            .unwrap()
-            .get();
-
-        let range_phys = {
-            let start = PhysicalAddress::new(reg.starting_address);
-            Range::from(start..start.checked_add(size).unwrap())
-        };
-
-        Mmap::new_phys(
-            aspace.clone(),
-            range_phys,
-            size,
-            arch::PAGE_SIZE,
-            Some("UART-16550".to_string()),
-        )
-        .unwrap()
-    });
-
-    // Safety: info comes from device tree
-    let uart = unsafe { uart_16550::SerialPort::new(mmap.range().start.get(), clock_freq, 115200) };
-
-    (uart, mmap, irq_num)
-}
-
 pub fn eval(line: &str) {
     if line == "help" {
         tracing::info!(target: "shell", "available commands:");
@@ -161,15 +117,223 @@ const VERSION: Command = Command::new("version")
     });
 
 const SHUTDOWN: Command = Command::new("shutdown")
-    .with_help("exit the kernel and shutdown the machine.")
+    .with_help("exit the kernel and power off the machine.")
+    .with_fn(|_| {
+        shutdown::request(Action::Shutdown, false);
+        Ok(())
+    });
+
+const REBOOT: Command = Command::new("reboot")
+    .with_usage("[--warm]")
+    .with_help("exit the kernel and reboot the machine. with --warm, don't power cycle it.")
+    .with_fn(|mut ctx| {
+        let action = if ctx.parse_bool_flag("--warm") {
+            Action::WarmReboot
+        } else {
+            Action::ColdReboot
+        };
+        shutdown::request(action, false);
+        Ok(())
+    });
+
+const NUMA: Command = Command::new("numa")
+    .with_help("print the NUMA topology and per-arena frame allocation statistics.")
     .with_fn(|_| {
-        tracing::info!("Bye, Bye!");
+        let topology = topology::topology();
+        for node in 0..topology.num_nodes() {
+            let distances = (0..topology.num_nodes())
+                .map(|to| topology.distance(node, to).to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            tracing::info!("node {node}: distances [{distances}]");
+        }
+
+        for stats in FRAME_ALLOC.get().unwrap().arena_stats() {
+            tracing::info!(
+                "arena {:?} node {}: {}/{} frames used, {} local, {} remote",
+                stats.range,
+                stats.node,
+                stats.used_frames,
+                stats.total_frames,
+                stats.local_frames,
+                stats.remote_frames
+            );
+        }
+
+        Ok(())
+    });
 
-        scheduler().shutdown();
+const TASKS: Command = Command::new("tasks")
+    .with_usage("[--trace]")
+    .with_help("list all tasks. with --trace, also print where each task is suspended.")
+    .with_fn(|mut ctx| {
+        let trace = ctx.parse_bool_flag("--trace");
+
+        let dumps = scheduler().dump_tasks();
+        tracing::info!("{} tasks", dumps.len());
+        for dump in dumps {
+            if trace {
+                tracing::info!("{dump:#}");
+            } else {
+                tracing::info!("{dump}");
+            }
+        }
 
         Ok(())
     });
 
+const DEVICES: Command = Command::new("devices")
+    .with_usage("[--drivers]")
+    .with_help(
+        "list all devices bound to a driver. with --drivers, also list all registered drivers.",
+    )
+    .with_fn(|mut ctx| {
+        let list_drivers = ctx.parse_bool_flag("--drivers");
+
+        let bound = driver::bound_devices();
+        tracing::info!("{} devices", bound.len());
+        for bound in bound {
+            tracing::info!("{bound}");
+        }
+
+        if list_drivers {
+            for driver in driver::drivers() {
+                tracing::info!("driver {} compatible {:?}", driver.name, driver.compatible);
+            }
+        }
+
+        Ok(())
+    });
+
+const IRQ: Command = Command::new("irq")
+    .with_usage("[<IRQ> [--priority <PRIORITY>] [--cpus <all|CPU,...>] [--trigger <MODE>]]")
+    .with_help("print per-CPU interrupt statistics, or configure and print the given interrupt.")
+    .with_fn(|mut ctx| {
+        let Some(irq_num) = ctx.parse_optional_u32_hex_or_dec("<IRQ>")? else {
+            for irq_num in 1..NUM_IRQS {
+                let irq = Irq::new(u32::try_from(irq_num).unwrap());
+                if IRQ_COUNTS.sum_across_all_cpus(irq_num) > 0 || irq.config() != Default::default()
+                {
+                    print_irq(irq);
+                }
+            }
+            return Ok(());
+        };
+        let irq = Irq::new(irq_num);
+
+        if let Some(priority) = ctx.parse_optional_flag::<u32>(&["--priority"])? {
+            irq.set_priority(priority).map_err(|err| {
+                tracing::warn!(target: "shell", "{err}");
+                ctx.other_error("failed to set the priority")
+            })?;
+        }
+        if let Some(CpuList(cpus)) = ctx.parse_optional_flag::<CpuList>(&["--cpus"])? {
+            irq.set_affinity(cpus).map_err(|err| {
+                tracing::warn!(target: "shell", "{err}");
+                ctx.other_error("failed to set the CPU set")
+            })?;
+        }
+        if let Some(mode) = ctx.parse_optional_flag::<TriggerMode>(&["--trigger"])? {
+            irq.set_trigger(mode).map_err(|err| {
+                tracing::warn!(target: "shell", "{err}");
+                ctx.other_error("failed to set the trigger mode")
+            })?;
+        }
+
+        print_irq(irq);
+        Ok(())
+    });
+
+fn print_irq(irq: Irq) {
+    let index = usize::try_from(irq.as_u32()).unwrap();
+    let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+    let per_cpu = (0..usize::BITS as usize)
+        .filter(|cpuid| cpu_mask & (1 << cpuid) != 0)
+        .map(|cpuid| {
+            let count = if index < NUM_IRQS {
+                IRQ_COUNTS.get_for(cpuid, index).unwrap_or(0)
+            } else {
+                0
+            };
+            alloc::format!("cpu{cpuid}={count}")
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let config = irq.config();
+    tracing::info!(
+        "irq {}: [{per_cpu}] priority {} cpus {:#b} trigger {}",
+        irq.as_u32(),
+        config.priority.unwrap_or(irq::DEFAULT_PRIORITY),
+        irq.affinity(),
+        config
+            .trigger
+            .map_or_else(|| "default".to_string(), |mode| mode.to_string())
+    );
+}
+
+const CPU: Command = Command::new("cpu")
+    .with_usage("[--idle <wait|suspend>] [<CPU> [--offline|--online]]")
+    .with_help("print the state of all CPUs, change the idle mode or take a CPU offline/online.")
+    .with_fn(|mut ctx| {
+        if let Some(mode) = ctx.parse_optional_flag::<IdleMode>(&["--idle"])? {
+            hotplug::set_idle_mode(mode);
+        }
+
+        if let Some(cpuid) = ctx.parse_optional_u32_hex_or_dec("<CPU>")? {
+            let cpuid = usize::try_from(cpuid).unwrap();
+
+            if ctx.parse_bool_flag("--offline") {
+                hotplug::offline(cpuid).map_err(|err| {
+                    tracing::warn!(target: "shell", "{err}");
+                    ctx.other_error("failed to change the CPU state")
+                })?;
+            } else if ctx.parse_bool_flag("--online") {
+                // the CPU might still be stopping, so wait for it in the background
+                scheduler().spawn(async move {
+                    if let Err(err) = hotplug::online(cpuid).await {
+                        tracing::warn!(target: "shell", "{err}");
+                    }
+                });
+            }
+        }
+
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        for cpuid in (0..usize::BITS as usize).filter(|cpuid| cpu_mask & (1 << cpuid) != 0) {
+            tracing::info!(
+                "cpu {cpuid}: {} ({} suspends)",
+                hotplug::state(cpuid),
+                hotplug::suspend_count(cpuid)
+            );
+        }
+        tracing::info!("idle mode: {}", hotplug::idle_mode());
+        with_cpu(|cpu| tracing::info!("clock: {}", cpu.clock));
+
+        Ok(())
+    });
+
+/// A list of CPUs given on the command line, `all` or comma-separated CPU ids.
+struct CpuList(Option<usize>);
+
+impl FromStr for CpuList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(Self(None));
+        }
+
+        let mut mask = 0_usize;
+        for cpuid in s.split(',') {
+            let cpuid = u32::from_str(cpuid.trim())?;
+            mask |= 1_usize
+                .checked_shl(cpuid)
+                .ok_or_else(|| anyhow::anyhow!("invalid CPU id {cpuid}"))?;
+        }
+        Ok(Self(Some(mask)))
+    }
+}
+
 #[derive(Debug)]
 pub struct Command<'cmd> {
     name: &'cmd str,
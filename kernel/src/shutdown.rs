@@ -0,0 +1,106 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Orderly system shutdown and reboot.
+//!
+//! A shutdown is started through [`request`], which unbinds all drivers and shuts down the
+//! scheduler. Every CPU keeps running tasks until there are none left and then calls [`finish`] on
+//! its way out of the kernel. All CPUs but the primary one (the one with the lowest id) stop
+//! themselves right away, the primary CPU waits for them to be stopped, flushes the log output and
+//! finally resets the system through the firmware.
+//!
+//! If the firmware can't reset the system, we fall back to exiting through semihosting, which
+//! works when running in QEMU.
+
+use crate::scheduler::scheduler;
+use crate::time::{Duration, Instant};
+use crate::{BOOT_INFO, CPUID, arch, driver, hotplug};
+use core::fmt;
+use spin::Once;
+
+/// How long the primary CPU waits for the other CPUs to stop before resetting the system anyway.
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What to do once the kernel has shut down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Power off the machine.
+    Shutdown,
+    /// Power cycle the machine.
+    ColdReboot,
+    /// Reboot without power cycling, which is faster but doesn't reset all hardware.
+    WarmReboot,
+}
+
+static REQUEST: Once<(Action, bool)> = Once::new();
+
+/// Start shutting down the system, `failure` records whether this is because of an error.
+///
+/// This returns right away, the system is reset once all CPUs have run out of tasks. Requests
+/// after the first one are ignored.
+pub fn request(action: Action, failure: bool) {
+    let mut first = false;
+    REQUEST.call_once(|| {
+        first = true;
+        (action, failure)
+    });
+    if !first {
+        return;
+    }
+
+    tracing::info!("{action}...");
+
+    driver::unbind_all();
+    scheduler().shutdown();
+    // offline CPUs have to come back so they can run out of tasks and stop like all others
+    hotplug::online_all();
+}
+
+/// Leave the kernel on the calling CPU, resetting the system once the last CPU is done.
+///
+/// Without an explicit [`request`] the machine is powered off.
+pub fn finish() -> ! {
+    let cpu_mask = BOOT_INFO.get().unwrap().cpu_mask;
+    let primary = usize::try_from(cpu_mask.trailing_zeros()).unwrap();
+    let this_cpu = CPUID.get();
+
+    if this_cpu != primary {
+        arch::cpu_stop();
+    }
+
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    for cpuid in (0..usize::BITS as usize).filter(|cpuid| cpu_mask & (1 << cpuid) != 0) {
+        while cpuid != this_cpu && !arch::is_cpu_stopped(cpuid) {
+            if Instant::now() > deadline {
+                tracing::warn!("CPU {cpuid} didn't stop in time, resetting anyway");
+                break;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    let (action, failure) = REQUEST.get().copied().unwrap_or((Action::Shutdown, false));
+    tracing::info!("Bye, Bye!");
+    tracing::flush();
+
+    if let Err(err) = arch::system_reset(action, failure) {
+        tracing::warn!("system reset failed, falling back to semihosting: {err}");
+        tracing::flush();
+    }
+
+    arch::exit(i32::from(failure))
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Shutdown => "shutting down",
+            Action::ColdReboot => "rebooting",
+            Action::WarmReboot => "rebooting (warm)",
+        })
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Multi-producer, multi-consumer broadcast channels.
+//!
+//! Every message sent on a broadcast channel is received by every [`Receiver`] that existed at the
+//! time it was sent. Messages are stored in a fixed-size ring buffer: sending never waits, instead
+//! the oldest message is overwritten when the buffer is full. Receivers that fall behind by more
+//! than the capacity of the channel observe [`RecvError::Lagged`] and continue with the oldest
+//! message still available.
+
+use crate::sync::WaitQueue;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::fmt;
+use spin::Mutex;
+
+/// Returns a new broadcast channel that retains at most `capacity` messages.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel capacity must be non-zero");
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buffer: (0..capacity).map(|_| None).collect(),
+            tail: 0,
+            senders: 1,
+            receivers: 1,
+        }),
+        rx_wait: WaitQueue::new(),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+/// The sending half of a broadcast channel.
+///
+/// Senders can be cloned to send messages to the same channel from multiple tasks.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiving half of a broadcast channel.
+///
+/// Additional receivers can be created through [`Sender::subscribe`] or by cloning an existing
+/// receiver.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// The sequence number of the next message this receiver will receive.
+    next: u64,
+}
+
+/// The error returned by [`Sender::send`] when there are no receivers.
+///
+/// The message that failed to be sent is returned to the caller.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct SendError<T>(pub T);
+
+/// The error returned by [`Receiver::recv`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RecvError {
+    /// All senders have been dropped and the receiver has seen all messages.
+    Closed,
+    /// The receiver fell behind and the given number of messages were overwritten before they
+    /// could be received. The next call to [`Receiver::recv`] returns the oldest message still
+    /// retained by the channel.
+    Lagged(u64),
+}
+
+/// The error returned by [`Receiver::try_recv`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TryRecvError {
+    /// No new messages have been sent.
+    Empty,
+    /// All senders have been dropped and the receiver has seen all messages.
+    Closed,
+    /// See [`RecvError::Lagged`].
+    Lagged(u64),
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    rx_wait: WaitQueue,
+}
+
+struct State<T> {
+    /// Ring buffer of messages, the message with sequence number `seq` is stored at
+    /// `seq % capacity`.
+    buffer: Box<[Option<T>]>,
+    /// The sequence number of the next message to be sent.
+    tail: u64,
+    senders: usize,
+    receivers: usize,
+}
+
+// === impl Sender ===
+
+impl<T: Clone> Sender<T> {
+    /// Send a message to all current receivers, returning the number of receivers that will
+    /// observe it.
+    ///
+    /// This never waits, if the channel is full the oldest message is overwritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SendError`] containing the message if there are no receivers.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let receivers = {
+            let mut state = self.shared.state.lock();
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+
+            let slot = state.slot(state.tail);
+            state.buffer[slot] = Some(value);
+            state.tail += 1;
+            state.receivers
+        };
+
+        self.shared.rx_wait.wake_all();
+
+        Ok(receivers)
+    }
+
+    /// Create a new receiver that observes all messages sent after this call.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock();
+        state.receivers += 1;
+
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.tail,
+        }
+    }
+
+    /// Returns the number of receivers.
+    pub fn receiver_count(&self) -> usize {
+        self.shared.state.lock().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            // wake all receivers so they can observe the channel closing
+            self.shared.rx_wait.wake_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("shared", &self.shared)
+            .finish()
+    }
+}
+
+// === impl Receiver ===
+
+impl<T: Clone> Receiver<T> {
+    /// Receive the next message, waiting until one is sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecvError::Lagged`] if the receiver fell behind and messages were lost and
+    /// [`RecvError::Closed`] if all senders have been dropped and all messages were received.
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        let shared = &*self.shared;
+        let next = &mut self.next;
+
+        shared
+            .rx_wait
+            .wait_for_value(|| match shared.try_recv(next) {
+                Ok(value) => Some(Ok(value)),
+                Err(TryRecvError::Closed) => Some(Err(RecvError::Closed)),
+                Err(TryRecvError::Lagged(n)) => Some(Err(RecvError::Lagged(n))),
+                Err(TryRecvError::Empty) => None,
+            })
+            .await
+            .expect("broadcast WaitQueue is never closed")
+    }
+
+    /// Attempt to receive the next message without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryRecvError::Empty`] if no new message has been sent, and otherwise the same
+    /// errors as [`Receiver::recv`].
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.shared.try_recv(&mut self.next)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receivers -= 1;
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("shared", &self.shared)
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+// === impl Shared ===
+
+impl<T: Clone> Shared<T> {
+    fn try_recv(&self, next: &mut u64) -> Result<T, TryRecvError> {
+        let state = self.state.lock();
+
+        let capacity = state.buffer.len() as u64;
+        let oldest = state.tail.saturating_sub(capacity);
+        if *next < oldest {
+            let missed = oldest - *next;
+            *next = oldest;
+            return Err(TryRecvError::Lagged(missed));
+        }
+
+        if *next == state.tail {
+            return if state.senders == 0 {
+                Err(TryRecvError::Closed)
+            } else {
+                Err(TryRecvError::Empty)
+            };
+        }
+
+        let value = state.buffer[state.slot(*next)]
+            .clone()
+            .expect("broadcast slot within the retained range is empty");
+        *next += 1;
+
+        Ok(value)
+    }
+}
+
+impl<T> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("Shared")
+            .field("capacity", &state.buffer.len())
+            .field("tail", &state.tail)
+            .field("senders", &state.senders)
+            .field("receivers", &state.receivers)
+            .field("rx_wait", &self.rx_wait)
+            .finish()
+    }
+}
+
+// === impl State ===
+
+impl<T> State<T> {
+    fn slot(&self, seq: u64) -> usize {
+        let capacity = self.buffer.len() as u64;
+        usize::try_from(seq % capacity).unwrap()
+    }
+}
+
+// === impl errors ===
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("channel closed")
+    }
+}
+
+impl<T> core::error::Error for SendError<T> {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Closed => f.pad("channel closed"),
+            RecvError::Lagged(n) => write!(f, "receiver lagged behind by {n} messages"),
+        }
+    }
+}
+
+impl core::error::Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.pad("channel empty"),
+            TryRecvError::Closed => f.pad("channel closed"),
+            TryRecvError::Lagged(n) => write!(f, "receiver lagged behind by {n} messages"),
+        }
+    }
+}
+
+impl core::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+
+    #[ktest::test]
+    async fn all_receivers_see_messages() {
+        let (tx, mut rx1) = channel(4);
+        let mut rx2 = tx.subscribe();
+
+        let handle = scheduler().spawn(async move {
+            let mut sum = 0;
+            while let Ok(value) = rx2.recv().await {
+                sum += value;
+            }
+            sum
+        });
+
+        for i in 1..=3 {
+            assert_eq!(tx.send(i), Ok(2));
+            yield_now().await;
+        }
+        drop(tx);
+
+        assert_eq!(rx1.recv().await, Ok(1));
+        assert_eq!(rx1.recv().await, Ok(2));
+        assert_eq!(rx1.recv().await, Ok(3));
+        assert_eq!(rx1.recv().await, Err(RecvError::Closed));
+        assert_eq!(handle.await.unwrap(), 6);
+    }
+
+    #[ktest::test]
+    async fn lagged() {
+        let (tx, mut rx) = channel(2);
+
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Lagged(3)));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[ktest::test]
+    async fn no_receivers() {
+        let (tx, rx) = channel(1);
+
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+}
@@ -5,8 +5,14 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+pub mod broadcast;
 mod cache_padded;
 mod error;
+pub mod mpsc;
+pub mod mutex;
+pub mod oneshot;
+pub mod rw_lock;
+pub mod semaphore;
 mod wait_cell;
 mod wait_queue;
 mod wake_batch;
@@ -15,5 +21,3 @@ pub use cache_padded::CachePadded;
 pub use error::Closed;
 pub use wait_cell::WaitCell;
 pub use wait_queue::WaitQueue;
-#[expect(unused_imports, reason = "TODO")]
-pub use wake_batch::WakeBatch;
@@ -0,0 +1,476 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Multi-producer, single-consumer channels.
+//!
+//! Messages are stored in the intrusive lock-free [`MpscQueue`] (each message is boxed into its
+//! own queue node) and the receiving task is woken through a [`WaitCell`]. Bounded channels
+//! additionally track their remaining capacity in a [`Semaphore`], which makes senders wait in
+//! FIFO order when the channel is full.
+//!
+//! A channel is closed once the [`Receiver`] is dropped or [closed](Receiver::close), at which
+//! point sending fails, or once all [`Sender`]s are dropped, at which point the receiver fails
+//! with [`Closed`] after all remaining messages were received.
+
+use crate::sync::semaphore::{Semaphore, TryAcquireError};
+use crate::sync::{Closed, WaitCell};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use mpsc_queue::{Linked, Links, MpscQueue};
+
+/// Returns a new bounded channel that can hold at most `capacity` messages at a time.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero or larger than [`Semaphore::MAX_PERMITS`].
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be non-zero");
+    new_channel(Some(Semaphore::new(capacity)))
+}
+
+/// Returns a new unbounded channel.
+///
+/// Sending on an unbounded channel never waits, so care must be taken that the receiver can
+/// keep up with the senders.
+pub fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(None)
+}
+
+/// The sending half of a channel, created by [`channel`] or [`unbounded_channel`].
+///
+/// Senders can be cloned to send messages to the same channel from multiple tasks.
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// The receiving half of a channel, created by [`channel`] or [`unbounded_channel`].
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// The error returned by [`Sender::send`] when the receiver has been dropped or closed.
+///
+/// The message that failed to be sent is returned to the caller.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct SendError<T>(pub T);
+
+/// The error returned by [`Sender::try_send`].
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum TrySendError<T> {
+    /// The channel is at capacity.
+    Full(T),
+    /// The receiver has been dropped or closed.
+    Closed(T),
+}
+
+/// The error returned by [`Receiver::try_recv`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TryRecvError {
+    /// No messages are currently queued.
+    Empty,
+    /// All senders have been dropped and no messages are left in the channel.
+    Closed,
+}
+
+struct Chan<T> {
+    queue: MpscQueue<Node<T>>,
+    rx_wait: WaitCell,
+    /// The remaining capacity of bounded channels.
+    capacity: Option<Semaphore>,
+    senders: AtomicUsize,
+    rx_closed: AtomicBool,
+}
+
+struct Node<T> {
+    links: Links<Node<T>>,
+    value: Option<T>,
+}
+
+fn new_channel<T>(capacity: Option<Semaphore>) -> (Sender<T>, Receiver<T>) {
+    let stub = Box::new(Node {
+        links: Links::new_stub(),
+        value: None,
+    });
+
+    let chan = Arc::new(Chan {
+        queue: MpscQueue::new_with_stub(stub),
+        rx_wait: WaitCell::new(),
+        capacity,
+        senders: AtomicUsize::new(1),
+        rx_closed: AtomicBool::new(false),
+    });
+
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+// === impl Sender ===
+
+impl<T> Sender<T> {
+    /// Send a message, waiting for capacity to become available if the channel is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SendError`] containing the message if the receiver has been dropped or closed.
+    ///
+    /// # Cancellation
+    ///
+    /// Dropping the returned future before it completes drops the message and forfeits the
+    /// task's position in the queue of senders waiting for capacity.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if let Some(capacity) = &self.chan.capacity {
+            match capacity.acquire(1).await {
+                Ok(permit) => permit.forget(),
+                Err(Closed) => return Err(SendError(value)),
+            }
+        }
+
+        self.chan.send(value).map_err(SendError)
+    }
+
+    /// Attempt to send a message without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TrySendError::Full`] if the channel is at capacity and [`TrySendError::Closed`]
+    /// if the receiver has been dropped or closed. Both contain the message that failed to send.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if let Some(capacity) = &self.chan.capacity {
+            match capacity.try_acquire(1) {
+                Ok(permit) => permit.forget(),
+                Err(TryAcquireError::InsufficientPermits) => return Err(TrySendError::Full(value)),
+                Err(TryAcquireError::Closed) => return Err(TrySendError::Closed(value)),
+            }
+        }
+
+        self.chan.send(value).map_err(TrySendError::Closed)
+    }
+
+    /// Returns `true` if the receiver has been dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.chan.rx_closed.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if both senders send to the same channel.
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.chan, &other.chan)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.chan.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // we were the last sender, wake the receiver so it can observe the channel closing
+            self.chan.rx_wait.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").field("chan", &self.chan).finish()
+    }
+}
+
+// === impl Receiver ===
+
+impl<T> Receiver<T> {
+    /// Receive the next message, waiting until one is sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Closed`] if all senders have been dropped and no messages are left in the
+    /// channel.
+    pub async fn recv(&mut self) -> Result<T, Closed> {
+        let chan = &*self.chan;
+        chan.rx_wait
+            .wait_for_value(|| match chan.try_recv() {
+                Ok(value) => Some(Ok(value)),
+                Err(TryRecvError::Closed) => Some(Err(Closed::new())),
+                Err(TryRecvError::Empty) => None,
+            })
+            .await
+            .expect("receiver WaitCell is never closed")
+    }
+
+    /// Attempt to receive the next message without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryRecvError::Empty`] if no messages are queued and [`TryRecvError::Closed`] if
+    /// all senders have been dropped and no messages are left in the channel.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    /// Close the receiving half of the channel without dropping it.
+    ///
+    /// This prevents any further messages from being sent, senders waiting for capacity are
+    /// woken and fail with an error. Messages that were already sent can still be received.
+    pub fn close(&mut self) {
+        self.chan.rx_closed.store(true, Ordering::Release);
+        if let Some(capacity) = &self.chan.capacity {
+            capacity.close();
+        }
+    }
+
+    /// Returns `true` if the channel has been closed, either because all senders have been
+    /// dropped or because the receiver was [closed](Self::close).
+    pub fn is_closed(&self) -> bool {
+        self.chan.rx_closed.load(Ordering::Acquire)
+            || self.chan.senders.load(Ordering::Acquire) == 0
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+        // drop remaining messages now instead of when the last sender goes away
+        while self.chan.queue.dequeue().is_some() {}
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("chan", &self.chan)
+            .finish()
+    }
+}
+
+// === impl Chan ===
+
+impl<T> Chan<T> {
+    /// Dequeue the next message.
+    ///
+    /// This must only be called by the `Receiver` since the queue only supports a single consumer.
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        // Load the sender count *before* looking at the queue, all messages sent by the last
+        // sender are guaranteed to be visible when we observe it being dropped.
+        let closed = self.senders.load(Ordering::Acquire) == 0;
+
+        match self.queue.dequeue() {
+            Some(mut node) => {
+                if let Some(capacity) = &self.capacity {
+                    capacity.add_permits(1);
+                }
+                Ok(node.value.take().expect("channel node without value"))
+            }
+            None if closed => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn send(&self, value: T) -> Result<(), T> {
+        if self.rx_closed.load(Ordering::Acquire) {
+            return Err(value);
+        }
+
+        self.queue.enqueue(Box::new(Node {
+            links: Links::new(),
+            value: Some(value),
+        }));
+        self.rx_wait.wake();
+
+        Ok(())
+    }
+}
+
+impl<T> fmt::Debug for Chan<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chan")
+            .field("queue", &self.queue)
+            .field("rx_wait", &self.rx_wait)
+            .field("capacity", &self.capacity)
+            .field("senders", &self.senders)
+            .field("rx_closed", &self.rx_closed)
+            .finish()
+    }
+}
+
+// === impl Node ===
+
+// Safety: nodes are boxed and never moved while they are part of the queue, and the contained
+// `Links` make sure `Node` doesn't implement `Unpin`.
+unsafe impl<T> Linked for Node<T> {
+    type Handle = Box<Self>;
+
+    fn into_ptr(node: Self::Handle) -> NonNull<Self> {
+        NonNull::from(Box::leak(node))
+    }
+    unsafe fn from_ptr(ptr: NonNull<Self>) -> Self::Handle {
+        // Safety: ensured by the caller
+        unsafe { Box::from_raw(ptr.as_ptr()) }
+    }
+    unsafe fn links(ptr: NonNull<Self>) -> NonNull<Links<Self>>
+    where
+        Self: Sized,
+    {
+        // Safety: ensured by the caller
+        unsafe { NonNull::new_unchecked(&raw mut (*ptr.as_ptr()).links) }
+    }
+}
+
+// === impl errors ===
+
+impl<T> SendError<T> {
+    /// Returns the message that failed to send.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("channel closed")
+    }
+}
+
+impl<T> core::error::Error for SendError<T> {}
+
+impl<T> TrySendError<T> {
+    /// Returns the message that failed to send.
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.debug_tuple("Full").finish_non_exhaustive(),
+            TrySendError::Closed(_) => f.debug_tuple("Closed").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.pad("channel full"),
+            TrySendError::Closed(_) => f.pad("channel closed"),
+        }
+    }
+}
+
+impl<T> core::error::Error for TrySendError<T> {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.pad("channel empty"),
+            TryRecvError::Closed => f.pad("channel closed"),
+        }
+    }
+}
+
+impl core::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+    use alloc::vec::Vec;
+
+    #[ktest::test]
+    async fn unbounded() {
+        let (tx, mut rx) = unbounded_channel();
+
+        for i in 0..16 {
+            tx.send(i).await.unwrap();
+        }
+        drop(tx);
+
+        for i in 0..16 {
+            assert_eq!(rx.recv().await, Ok(i));
+        }
+        assert_eq!(rx.recv().await, Err(Closed::new()));
+    }
+
+    #[ktest::test]
+    async fn bounded_backpressure() {
+        let (tx, mut rx) = channel(2);
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Full(3))));
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        tx.try_send(3).unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[ktest::test]
+    async fn many_senders() {
+        const SENDERS: usize = 4;
+        const MESSAGES: usize = 64;
+
+        let (tx, mut rx) = channel(4);
+
+        let handles: Vec<_> = (0..SENDERS)
+            .map(|_| {
+                let tx = tx.clone();
+                scheduler().spawn(async move {
+                    for i in 0..MESSAGES {
+                        tx.send(i).await.unwrap();
+                        yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut sum = 0;
+        while let Ok(value) = rx.recv().await {
+            sum += value;
+        }
+        assert_eq!(sum, SENDERS * (MESSAGES * (MESSAGES - 1) / 2));
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+
+    #[ktest::test]
+    async fn receiver_closed() {
+        let (tx, rx) = channel(1);
+
+        tx.try_send(1).unwrap();
+        let handle = scheduler().spawn({
+            let tx = tx.clone();
+            async move { tx.send(2).await }
+        });
+
+        yield_now().await;
+        drop(rx);
+
+        assert_eq!(handle.await.unwrap(), Err(SendError(2)));
+        assert!(tx.is_closed());
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Closed(3))));
+    }
+}
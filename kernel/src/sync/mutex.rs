@@ -0,0 +1,292 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::sync::WaitQueue;
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::task::Poll;
+
+/// An asynchronous [mutual exclusion lock][mutex] for protecting shared data.
+///
+/// The data can only be accessed through the [RAII guards] returned from [`lock`] and
+/// [`try_lock`], which guarantees that the data is only ever accessed when the mutex is locked.
+///
+/// # Fairness
+///
+/// This mutex is implemented on top of a [`WaitQueue`] which holds a single stored wakeup while
+/// the mutex is unlocked. Tasks waiting for the lock are queued in first-in, first-out order and
+/// unlocking the mutex hands the lock directly to the task at the front of the queue, so no task
+/// can be starved by others repeatedly acquiring the lock.
+///
+/// # Owned guards
+///
+/// When the mutex is stored in an [`Arc`], [`lock_owned`] returns an [`OwnedMutexGuard`] that
+/// is valid for the `'static` lifetime and can be moved into spawned tasks.
+///
+/// [mutex]: https://en.wikipedia.org/wiki/Mutual_exclusion
+/// [RAII guards]: MutexGuard
+/// [`lock`]: Self::lock
+/// [`try_lock`]: Self::try_lock
+/// [`lock_owned`]: Self::lock_owned
+pub struct Mutex<T: ?Sized> {
+    wait: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// An [RAII] implementation of a "scoped lock" of a [`Mutex`]. When this structure is dropped
+/// (falls out of scope), the lock will be unlocked.
+///
+/// [RAII]: https://rust-unofficial.github.io/patterns/patterns/behavioural/RAII.html
+#[must_use = "if unused, the `Mutex` will immediately unlock"]
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+/// An owned [RAII] implementation of a "scoped lock" of a [`Mutex`]. When this structure is
+/// dropped (falls out of scope), the lock will be unlocked.
+///
+/// This is identical to [`MutexGuard`], except that it holds an [`Arc`] reference to the
+/// [`Mutex`] and is therefore valid for the `'static` lifetime.
+///
+/// [RAII]: https://rust-unofficial.github.io/patterns/patterns/behavioural/RAII.html
+#[must_use = "if unused, the `Mutex` will immediately unlock"]
+pub struct OwnedMutexGuard<T: ?Sized> {
+    mutex: Arc<Mutex<T>>,
+}
+
+// Safety: the mutex guarantees mutual exclusion, so it can be shared between threads if the data
+// can be sent between threads.
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+// Safety: see above
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+// Safety: the guard gives out `&T` and `&mut T`
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+// Safety: the guard gives out `&T` and `&mut T`
+unsafe impl<T: ?Sized + Sync> Sync for OwnedMutexGuard<T> {}
+
+// === impl Mutex ===
+
+impl<T> Mutex<T> {
+    /// Returns a new `Mutex` protecting the provided `data`.
+    ///
+    /// The returned `Mutex` is in an unlocked state, ready for use.
+    pub const fn new(data: T) -> Self {
+        Self {
+            wait: WaitQueue::new_woken(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this `Mutex`, returning the guarded data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Locks this mutex, waiting until it becomes available.
+    ///
+    /// This returns a [`MutexGuard`] that releases the lock when dropped.
+    ///
+    /// # Cancellation
+    ///
+    /// Tasks waiting for the lock are queued in FIFO order. Dropping the returned future before it
+    /// completes forfeits the task's position in the queue.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        self.wait
+            .wait()
+            .await
+            .expect("Mutex's WaitQueue is never closed");
+
+        MutexGuard { mutex: self }
+    }
+
+    /// Attempts to lock this mutex without waiting, returning `None` if it is already locked.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match self.wait.try_wait() {
+            Poll::Ready(Ok(())) => Some(MutexGuard { mutex: self }),
+            Poll::Ready(Err(_)) => unreachable!("Mutex's WaitQueue is never closed"),
+            Poll::Pending => None,
+        }
+    }
+
+    /// Locks this mutex, waiting until it becomes available, returning a guard that is valid for
+    /// the `'static` lifetime.
+    ///
+    /// This is identical to [`Mutex::lock`], except that it requires the `Mutex` to be stored in
+    /// an [`Arc`].
+    pub async fn lock_owned(self: Arc<Self>) -> OwnedMutexGuard<T> {
+        self.wait
+            .wait()
+            .await
+            .expect("Mutex's WaitQueue is never closed");
+
+        OwnedMutexGuard { mutex: self }
+    }
+
+    /// Attempts to lock this mutex without waiting, returning `None` if it is already locked.
+    ///
+    /// This is identical to [`Mutex::try_lock`], except that it requires the `Mutex` to be stored
+    /// in an [`Arc`] and returns a guard that is valid for the `'static` lifetime.
+    pub fn try_lock_owned(self: Arc<Self>) -> Result<OwnedMutexGuard<T>, Arc<Self>> {
+        match self.wait.try_wait() {
+            Poll::Ready(Ok(())) => Ok(OwnedMutexGuard { mutex: self }),
+            Poll::Ready(Err(_)) => unreachable!("Mutex's WaitQueue is never closed"),
+            Poll::Pending => Err(self),
+        }
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the `Mutex` mutably, no actual locking needs to take place.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn unlock(&self) {
+        // hand the lock to the next waiter or store the wakeup if there is none
+        self.wait.wake();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Some(guard) => s.field("data", &&*guard),
+            None => s.field("data", &format_args!("<locked>")),
+        };
+        s.field("wait", &self.wait).finish()
+    }
+}
+
+// === impl MutexGuard ===
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: holding the guard means we have exclusive access
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: holding the guard means we have exclusive access
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// === impl OwnedMutexGuard ===
+
+impl<T: ?Sized> Deref for OwnedMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: holding the guard means we have exclusive access
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: holding the guard means we have exclusive access
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedMutexGuard<T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedMutexGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for OwnedMutexGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+    use alloc::vec::Vec;
+
+    #[ktest::test]
+    async fn lock_unlock() {
+        let mutex = Mutex::new(1);
+
+        let mut guard = mutex.lock().await;
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+    }
+
+    #[ktest::test]
+    async fn contended() {
+        const TASKS: usize = 8;
+        const ITERS: usize = 100;
+
+        let mutex = Arc::new(Mutex::new(0));
+
+        let handles: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let mutex = mutex.clone();
+                scheduler().spawn(async move {
+                    for _ in 0..ITERS {
+                        let mut guard = mutex.clone().lock_owned().await;
+                        let value = *guard;
+                        yield_now().await;
+                        *guard = value + 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(*mutex.lock().await, TASKS * ITERS);
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A channel for sending a single message between two tasks.
+
+use crate::sync::{Closed, WaitCell};
+use alloc::sync::Arc;
+use core::{fmt, mem};
+use spin::Mutex;
+
+/// Returns a new oneshot channel.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State::Empty),
+        rx_wait: WaitCell::new(),
+    });
+
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+/// The sending half of a oneshot channel.
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The receiving half of a oneshot channel.
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The error returned by [`Receiver::try_recv`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TryRecvError {
+    /// No message has been sent yet.
+    Empty,
+    /// The sender was dropped without sending a message or the message was already received.
+    Closed,
+}
+
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    rx_wait: WaitCell,
+}
+
+enum State<T> {
+    Empty,
+    Sent(T),
+    Closed,
+}
+
+// === impl Sender ===
+
+impl<T> Sender<T> {
+    /// Send `value` to the receiver.
+    ///
+    /// # Errors
+    ///
+    /// Returns the value if the receiver has been dropped.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut state = self.inner.state.lock();
+        match *state {
+            State::Empty => {
+                *state = State::Sent(value);
+                drop(state);
+                self.inner.rx_wait.wake();
+                Ok(())
+            }
+            State::Closed => Err(value),
+            State::Sent(_) => unreachable!("oneshot sender used twice"),
+        }
+    }
+
+    /// Returns `true` if the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        matches!(*self.inner.state.lock(), State::Closed)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.lock();
+        if matches!(*state, State::Empty) {
+            *state = State::Closed;
+            drop(state);
+            self.inner.rx_wait.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl Receiver ===
+
+impl<T> Receiver<T> {
+    /// Wait for the message to be sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Closed`] if the sender was dropped without sending a message.
+    pub async fn recv(self) -> Result<T, Closed> {
+        let inner = &*self.inner;
+        inner
+            .rx_wait
+            .wait_for_value(|| match inner.try_take() {
+                Ok(value) => Some(Ok(value)),
+                Err(TryRecvError::Closed) => Some(Err(Closed::new())),
+                Err(TryRecvError::Empty) => None,
+            })
+            .await
+            .expect("oneshot WaitCell is never closed")
+    }
+
+    /// Attempt to receive the message without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryRecvError::Empty`] if no message has been sent yet and
+    /// [`TryRecvError::Closed`] if the sender was dropped without sending a message or the message
+    /// was already received.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.inner.try_take()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // drop a message that was sent but never received here rather than when the sender is
+        // dropped, and make sure the sender can see the channel is closed
+        let state = mem::replace(&mut *self.inner.state.lock(), State::Closed);
+        drop(state);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match *self.inner.state.lock() {
+            State::Empty => "empty",
+            State::Sent(_) => "sent",
+            State::Closed => "closed",
+        };
+        f.debug_struct("Receiver")
+            .field("state", &state)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl Inner ===
+
+impl<T> Inner<T> {
+    fn try_take(&self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock();
+        match mem::replace(&mut *state, State::Closed) {
+            State::Sent(value) => Ok(value),
+            State::Closed => Err(TryRecvError::Closed),
+            State::Empty => {
+                *state = State::Empty;
+                Err(TryRecvError::Empty)
+            }
+        }
+    }
+}
+
+// === impl TryRecvError ===
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.pad("channel empty"),
+            TryRecvError::Closed => f.pad("channel closed"),
+        }
+    }
+}
+
+impl core::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+
+    #[ktest::test]
+    async fn send_recv() {
+        let (tx, rx) = channel();
+
+        let handle = scheduler().spawn(rx.recv());
+        yield_now().await;
+        tx.send(42).unwrap();
+
+        assert_eq!(handle.await.unwrap(), Ok(42));
+    }
+
+    #[ktest::test]
+    async fn sender_dropped() {
+        let (tx, mut rx) = channel::<u32>();
+
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+        assert_eq!(rx.recv().await, Err(Closed::new()));
+    }
+
+    #[ktest::test]
+    async fn receiver_dropped() {
+        let (tx, rx) = channel();
+
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(1), Err(1));
+    }
+}
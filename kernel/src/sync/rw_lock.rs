@@ -0,0 +1,424 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::sync::semaphore::{Semaphore, TryAcquireError};
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// The number of permits the underlying semaphore holds, which also is the maximum number of
+/// concurrent readers.
+const MAX_READERS: usize = Semaphore::MAX_PERMITS;
+
+/// An asynchronous readers-writer lock.
+///
+/// This lock allows any number of readers or at most one writer to access the protected data at
+/// any point in time. Data can only be accessed through the [RAII guards] returned from [`read`]
+/// and [`write`] (and their `try_` and `_owned` variants).
+///
+/// # Fairness
+///
+/// The lock is implemented on top of a fair [`Semaphore`] with [`MAX_READERS`] permits, readers
+/// acquire a single permit while writers acquire all of them. Since the semaphore hands out
+/// permits in FIFO order, a waiting writer blocks all readers that come after it and neither
+/// readers nor writers can be starved.
+///
+/// [RAII guards]: RwLockReadGuard
+/// [`read`]: Self::read
+/// [`write`]: Self::write
+pub struct RwLock<T: ?Sized> {
+    sem: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+/// An [RAII] implementation of a "scoped read lock" of a [`RwLock`]. When this structure is
+/// dropped (falls out of scope), the read lock will be released.
+///
+/// [RAII]: https://rust-unofficial.github.io/patterns/patterns/behavioural/RAII.html
+#[must_use = "if unused, the `RwLock` will immediately unlock"]
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+/// An [RAII] implementation of a "scoped write lock" of a [`RwLock`]. When this structure is
+/// dropped (falls out of scope), the write lock will be released.
+///
+/// [RAII]: https://rust-unofficial.github.io/patterns/patterns/behavioural/RAII.html
+#[must_use = "if unused, the `RwLock` will immediately unlock"]
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+/// An owned version of [`RwLockReadGuard`] that holds an [`Arc`] reference to the [`RwLock`]
+/// and is therefore valid for the `'static` lifetime.
+#[must_use = "if unused, the `RwLock` will immediately unlock"]
+pub struct OwnedRwLockReadGuard<T: ?Sized> {
+    lock: Arc<RwLock<T>>,
+}
+
+/// An owned version of [`RwLockWriteGuard`] that holds an [`Arc`] reference to the [`RwLock`]
+/// and is therefore valid for the `'static` lifetime.
+#[must_use = "if unused, the `RwLock` will immediately unlock"]
+pub struct OwnedRwLockWriteGuard<T: ?Sized> {
+    lock: Arc<RwLock<T>>,
+}
+
+// Safety: the lock guarantees that only one writer or multiple readers access the data, so it can
+// be shared between threads if the data can be both sent and shared between threads.
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+// Safety: see above
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+// Safety: the guard only gives out `&T`
+unsafe impl<T: ?Sized + Sync> Send for RwLockReadGuard<'_, T> {}
+// Safety: the guard only gives out `&T`
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+// Safety: the guard only gives out `&T`
+unsafe impl<T: ?Sized + Send + Sync> Send for OwnedRwLockReadGuard<T> {}
+// Safety: the guard only gives out `&T`
+unsafe impl<T: ?Sized + Send + Sync> Sync for OwnedRwLockReadGuard<T> {}
+// Safety: the guard gives out `&T` and `&mut T`
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+// Safety: the guard gives out `&T` and `&mut T`
+unsafe impl<T: ?Sized + Send + Sync> Sync for OwnedRwLockWriteGuard<T> {}
+
+// === impl RwLock ===
+
+impl<T> RwLock<T> {
+    /// Returns a new `RwLock` protecting the provided `data`.
+    ///
+    /// The returned `RwLock` is in an unlocked state, ready for use.
+    pub const fn new(data: T) -> Self {
+        Self {
+            sem: Semaphore::new(MAX_READERS),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this `RwLock`, returning the guarded data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks this `RwLock` with shared read access, waiting until it can be acquired.
+    ///
+    /// # Cancellation
+    ///
+    /// Dropping the returned future before it completes forfeits the task's position in the
+    /// queue.
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.sem
+            .acquire(1)
+            .await
+            .expect("RwLock semaphore is never closed")
+            .forget();
+
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Locks this `RwLock` with exclusive write access, waiting until it can be acquired.
+    ///
+    /// # Cancellation
+    ///
+    /// Dropping the returned future before it completes forfeits the task's position in the
+    /// queue.
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.sem
+            .acquire(MAX_READERS)
+            .await
+            .expect("RwLock semaphore is never closed")
+            .forget();
+
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Attempts to acquire this `RwLock` for shared read access without waiting.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.try_acquire(1).then(|| RwLockReadGuard { lock: self })
+    }
+
+    /// Attempts to acquire this `RwLock` for exclusive write access without waiting.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.try_acquire(MAX_READERS)
+            .then(|| RwLockWriteGuard { lock: self })
+    }
+
+    /// Locks this `RwLock` with shared read access, returning a guard that is valid for the
+    /// `'static` lifetime.
+    pub async fn read_owned(self: Arc<Self>) -> OwnedRwLockReadGuard<T> {
+        self.sem
+            .acquire(1)
+            .await
+            .expect("RwLock semaphore is never closed")
+            .forget();
+
+        OwnedRwLockReadGuard { lock: self }
+    }
+
+    /// Locks this `RwLock` with exclusive write access, returning a guard that is valid for the
+    /// `'static` lifetime.
+    pub async fn write_owned(self: Arc<Self>) -> OwnedRwLockWriteGuard<T> {
+        self.sem
+            .acquire(MAX_READERS)
+            .await
+            .expect("RwLock semaphore is never closed")
+            .forget();
+
+        OwnedRwLockWriteGuard { lock: self }
+    }
+
+    /// Attempts to acquire this `RwLock` for shared read access without waiting, returning a
+    /// guard that is valid for the `'static` lifetime.
+    pub fn try_read_owned(self: Arc<Self>) -> Result<OwnedRwLockReadGuard<T>, Arc<Self>> {
+        if self.try_acquire(1) {
+            Ok(OwnedRwLockReadGuard { lock: self })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Attempts to acquire this `RwLock` for exclusive write access without waiting, returning
+    /// a guard that is valid for the `'static` lifetime.
+    pub fn try_write_owned(self: Arc<Self>) -> Result<OwnedRwLockWriteGuard<T>, Arc<Self>> {
+        if self.try_acquire(MAX_READERS) {
+            Ok(OwnedRwLockWriteGuard { lock: self })
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the `RwLock` mutably, no actual locking needs to take place.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn try_acquire(&self, permits: usize) -> bool {
+        match self.sem.try_acquire(permits) {
+            Ok(permit) => {
+                permit.forget();
+                true
+            }
+            Err(TryAcquireError::InsufficientPermits) => false,
+            Err(TryAcquireError::Closed) => unreachable!("RwLock semaphore is never closed"),
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("RwLock");
+        match self.try_read() {
+            Some(guard) => s.field("data", &&*guard),
+            None => s.field("data", &format_args!("<locked>")),
+        };
+        s.field("sem", &self.sem).finish()
+    }
+}
+
+// === impl RwLockReadGuard ===
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: holding a read guard means no writer has access
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// === impl RwLockWriteGuard ===
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: holding the write guard means we have exclusive access
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: holding the write guard means we have exclusive access
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(MAX_READERS);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// === impl OwnedRwLockReadGuard ===
+
+impl<T: ?Sized> Deref for OwnedRwLockReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: holding a read guard means no writer has access
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedRwLockReadGuard<T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRwLockReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for OwnedRwLockReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// === impl OwnedRwLockWriteGuard ===
+
+impl<T: ?Sized> Deref for OwnedRwLockWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: holding the write guard means we have exclusive access
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedRwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: holding the write guard means we have exclusive access
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedRwLockWriteGuard<T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(MAX_READERS);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRwLockWriteGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for OwnedRwLockWriteGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+    use alloc::vec::Vec;
+
+    #[ktest::test]
+    async fn readers_share_writers_exclude() {
+        let lock = RwLock::new(1);
+
+        let a = lock.read().await;
+        let b = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        assert_eq!(*a + *b, 2);
+        drop((a, b));
+
+        let mut w = lock.write().await;
+        *w = 2;
+        assert!(lock.try_read().is_none());
+        drop(w);
+
+        assert_eq!(*lock.try_read().unwrap(), 2);
+    }
+
+    #[ktest::test]
+    async fn contended() {
+        const WRITERS: usize = 4;
+        const READERS: usize = 4;
+        const ITERS: usize = 50;
+
+        let lock = Arc::new(RwLock::new(0));
+
+        let mut handles = Vec::new();
+        for _ in 0..WRITERS {
+            let lock = lock.clone();
+            handles.push(scheduler().spawn(async move {
+                for _ in 0..ITERS {
+                    let mut guard = lock.clone().write_owned().await;
+                    let value = *guard;
+                    yield_now().await;
+                    *guard = value + 1;
+                }
+            }));
+        }
+        for _ in 0..READERS {
+            let lock = lock.clone();
+            handles.push(scheduler().spawn(async move {
+                for _ in 0..ITERS {
+                    let guard = lock.clone().read_owned().await;
+                    let value = *guard;
+                    yield_now().await;
+                    assert_eq!(*guard, value);
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(*lock.read().await, WRITERS * ITERS);
+    }
+}
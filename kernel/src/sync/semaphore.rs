@@ -0,0 +1,461 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::sync::{Closed, WaitQueue};
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use core::{fmt, mem};
+use spin::Mutex;
+
+/// An asynchronous [counting semaphore].
+///
+/// A semaphore holds a number of *permits*. Tasks can [`acquire`] one or more permits, waiting
+/// until enough permits are available, and permits are returned to the semaphore when the
+/// [`Permit`] guard is dropped.
+///
+/// # Fairness
+///
+/// The semaphore is fair: tasks acquire permits in the order they started to wait. A task
+/// requesting many permits at the front of the queue will block tasks behind it, even if those
+/// request fewer permits that would be available. This ensures large requests can't be starved by
+/// a stream of smaller ones.
+///
+/// # Implementation Notes
+///
+/// Each task that has to wait draws a ticket and only the task holding the ticket currently being
+/// *served* may acquire permits. The waiting itself is done through a [`WaitQueue`] which is woken
+/// whenever permits are released or a ticket was served. Tasks that stop waiting (because their
+/// future was dropped) give up their ticket so they don't block the tasks behind them.
+///
+/// [counting semaphore]: https://en.wikipedia.org/wiki/Semaphore_(programming)
+/// [`acquire`]: Self::acquire
+pub struct Semaphore {
+    state: Mutex<State>,
+    waiters: WaitQueue,
+}
+
+/// The error returned by [`Semaphore::try_acquire`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TryAcquireError {
+    /// The semaphore has been [closed](Semaphore::close).
+    Closed,
+    /// Not enough permits are available right now.
+    InsufficientPermits,
+}
+
+/// Permits acquired from a [`Semaphore`], released back to the semaphore when dropped.
+#[must_use = "dropping a `Permit` releases the acquired permits back to the `Semaphore`"]
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+/// Permits acquired from a [`Semaphore`] stored in an [`Arc`], released back to the semaphore
+/// when dropped.
+#[must_use = "dropping an `OwnedPermit` releases the acquired permits back to the `Semaphore`"]
+pub struct OwnedPermit {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
+}
+
+struct State {
+    permits: usize,
+    /// The ticket handed out to the next task that needs to wait.
+    next_ticket: u64,
+    /// The ticket of the task that may currently acquire permits.
+    serving: u64,
+    /// Tickets of tasks that stopped waiting before they were served.
+    abandoned: BTreeSet<u64>,
+    closed: bool,
+}
+
+/// Gives up a ticket if the task stops waiting before it was served.
+struct Ticket<'a> {
+    semaphore: &'a Semaphore,
+    ticket: u64,
+    served: bool,
+}
+
+// === impl Semaphore ===
+
+impl Semaphore {
+    /// The maximum number of permits a `Semaphore` can hold.
+    pub const MAX_PERMITS: usize = usize::MAX >> 1;
+
+    /// Returns a new `Semaphore` with `permits` permits available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `permits` is larger than [`Semaphore::MAX_PERMITS`].
+    pub const fn new(permits: usize) -> Self {
+        assert!(permits <= Self::MAX_PERMITS, "too many permits");
+
+        Self {
+            state: Mutex::new(State {
+                permits,
+                next_ticket: 0,
+                serving: 0,
+                abandoned: BTreeSet::new(),
+                closed: false,
+            }),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    /// Acquire `permits` permits, waiting until they become available.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Closed`] if the semaphore is [closed](Self::close) before the permits could be
+    /// acquired.
+    ///
+    /// # Cancellation
+    ///
+    /// Dropping the returned future before it completes forfeits the task's position in the
+    /// queue, no permits will have been acquired.
+    pub async fn acquire(&self, permits: usize) -> Result<Permit<'_>, Closed> {
+        self.acquire_inner(permits).await?;
+        Ok(Permit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    /// Acquire `permits` permits, waiting until they become available, returning an owned
+    /// permit that is valid for the `'static` lifetime.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Closed`] if the semaphore is [closed](Self::close) before the permits could be
+    /// acquired.
+    pub async fn acquire_owned(self: Arc<Self>, permits: usize) -> Result<OwnedPermit, Closed> {
+        self.acquire_inner(permits).await?;
+        Ok(OwnedPermit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    /// Attempt to acquire `permits` permits without waiting.
+    ///
+    /// This will fail if tasks are already waiting for permits, even if enough permits are
+    /// available to satisfy this request, to preserve fairness.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TryAcquireError::InsufficientPermits`] if the permits can't be acquired right now
+    /// and [`TryAcquireError::Closed`] if the semaphore is [closed](Self::close).
+    pub fn try_acquire(&self, permits: usize) -> Result<Permit<'_>, TryAcquireError> {
+        self.try_acquire_inner(permits)?;
+        Ok(Permit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    /// Attempt to acquire `permits` permits without waiting, returning an owned permit that is
+    /// valid for the `'static` lifetime.
+    ///
+    /// # Errors
+    ///
+    /// See [`Semaphore::try_acquire`].
+    pub fn try_acquire_owned(
+        self: Arc<Self>,
+        permits: usize,
+    ) -> Result<OwnedPermit, TryAcquireError> {
+        self.try_acquire_inner(permits)?;
+        Ok(OwnedPermit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    /// Add `permits` new permits to the semaphore.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this would bring the number of permits above [`Semaphore::MAX_PERMITS`].
+    pub fn add_permits(&self, permits: usize) {
+        if permits == 0 {
+            return;
+        }
+
+        {
+            let mut state = self.state.lock();
+            state.permits = state
+                .permits
+                .checked_add(permits)
+                .filter(|permits| *permits <= Self::MAX_PERMITS)
+                .expect("too many permits");
+        }
+
+        self.waiters.wake_all();
+    }
+
+    /// Close the semaphore.
+    ///
+    /// All tasks currently waiting for permits and all future calls to [`acquire`] will return
+    /// [`Closed`]. Permits that were already acquired remain valid.
+    ///
+    /// [`acquire`]: Self::acquire
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.waiters.close();
+    }
+
+    /// Returns `true` if the semaphore has been [closed](Self::close).
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    fn try_acquire_inner(&self, permits: usize) -> Result<(), TryAcquireError> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(TryAcquireError::Closed);
+        }
+
+        if state.serving == state.next_ticket && state.permits >= permits {
+            state.permits -= permits;
+            Ok(())
+        } else {
+            Err(TryAcquireError::InsufficientPermits)
+        }
+    }
+
+    async fn acquire_inner(&self, permits: usize) -> Result<(), Closed> {
+        let ticket = {
+            let mut state = self.state.lock();
+            if state.closed {
+                return Err(Closed::new());
+            }
+
+            if state.serving == state.next_ticket && state.permits >= permits {
+                state.permits -= permits;
+                return Ok(());
+            }
+
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            ticket
+        };
+
+        let mut ticket = Ticket {
+            semaphore: self,
+            ticket,
+            served: false,
+        };
+
+        self.waiters
+            .wait_for(|| {
+                let mut state = self.state.lock();
+                if state.serving == ticket.ticket && state.permits >= permits {
+                    state.permits -= permits;
+                    state.serve_next();
+                    true
+                } else {
+                    false
+                }
+            })
+            .await?;
+        ticket.served = true;
+
+        // the next task in line might be satisfied by the remaining permits
+        self.waiters.wake_all();
+
+        Ok(())
+    }
+
+    fn release(&self, permits: usize) {
+        self.add_permits(permits);
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("Semaphore")
+            .field("permits", &state.permits)
+            .field("waiting", &(state.next_ticket - state.serving))
+            .field("closed", &state.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl State ===
+
+impl State {
+    fn serve_next(&mut self) {
+        self.serving += 1;
+        while self.abandoned.remove(&self.serving) {
+            self.serving += 1;
+        }
+    }
+}
+
+// === impl Ticket ===
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        if self.served {
+            return;
+        }
+
+        let mut state = self.semaphore.state.lock();
+        if state.serving == self.ticket {
+            state.serve_next();
+            drop(state);
+            // let the next task in line check whether it can acquire its permits now
+            self.semaphore.waiters.wake_all();
+        } else {
+            state.abandoned.insert(self.ticket);
+        }
+    }
+}
+
+// === impl Permit ===
+
+impl Permit<'_> {
+    /// Returns the number of permits held by this guard.
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Forget the permits without releasing them back to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release(mem::take(&mut self.permits));
+    }
+}
+
+impl fmt::Debug for Permit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Permit")
+            .field("permits", &self.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl OwnedPermit ===
+
+impl OwnedPermit {
+    /// Returns the number of permits held by this guard.
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Forget the permits without releasing them back to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for OwnedPermit {
+    fn drop(&mut self) {
+        self.semaphore.release(mem::take(&mut self.permits));
+    }
+}
+
+impl fmt::Debug for OwnedPermit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedPermit")
+            .field("permits", &self.permits)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl TryAcquireError ===
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryAcquireError::Closed => f.pad("semaphore closed"),
+            TryAcquireError::InsufficientPermits => f.pad("insufficient permits available"),
+        }
+    }
+}
+
+impl core::error::Error for TryAcquireError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[ktest::test]
+    async fn try_acquire() {
+        let sem = Semaphore::new(2);
+
+        let a = sem.try_acquire(1).unwrap();
+        let b = sem.try_acquire(1).unwrap();
+        assert_eq!(
+            sem.try_acquire(1).unwrap_err(),
+            TryAcquireError::InsufficientPermits
+        );
+
+        drop(a);
+        assert_eq!(sem.available_permits(), 1);
+        drop(b);
+        assert_eq!(sem.available_permits(), 2);
+    }
+
+    #[ktest::test]
+    async fn limits_concurrency() {
+        const TASKS: usize = 8;
+        const PERMITS: usize = 3;
+
+        let sem = Arc::new(Semaphore::new(PERMITS));
+        let active = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let sem = sem.clone();
+                let active = active.clone();
+                scheduler().spawn(async move {
+                    let _permit = sem.acquire_owned(1).await.unwrap();
+                    let prev = active.fetch_add(1, Ordering::SeqCst);
+                    assert!(prev < PERMITS);
+                    yield_now().await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(sem.available_permits(), PERMITS);
+    }
+
+    #[ktest::test]
+    async fn close_wakes_waiters() {
+        let sem = Arc::new(Semaphore::new(0));
+
+        let handle = scheduler().spawn({
+            let sem = sem.clone();
+            async move { sem.acquire_owned(1).await.map(drop) }
+        });
+
+        yield_now().await;
+        sem.close();
+
+        assert_eq!(handle.await.unwrap(), Err(Closed::new()));
+        assert_eq!(sem.try_acquire(1).unwrap_err(), TryAcquireError::Closed);
+    }
+}
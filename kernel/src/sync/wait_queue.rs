@@ -250,8 +250,23 @@ enum Wakeup {
 
 impl WaitQueue {
     pub const fn new() -> Self {
+        Self::make(StateInner::Empty)
+    }
+
+    /// Returns a new `WaitQueue` with a single stored wakeup.
+    ///
+    /// The first call to [`wait`] on this queue will immediately succeed.
+    /// This is used to implement [`Mutex`](super::mutex::Mutex) on top of a `WaitQueue`: the stored
+    /// wakeup represents the unlocked state and unlocking hands the lock directly to the next waiter.
+    ///
+    /// [`wait`]: Self::wait
+    pub(crate) const fn new_woken() -> Self {
+        Self::make(StateInner::Woken)
+    }
+
+    const fn make(state: StateInner) -> Self {
         Self {
-            state: CachePadded(AtomicUsize::new(StateInner::Empty.into_usize())),
+            state: CachePadded(AtomicUsize::new(state.into_usize())),
             queue: Mutex::new(linked_list::List::new()),
         }
     }
@@ -585,7 +600,7 @@ impl WaitQueue {
         }
     }
 
-    fn try_wait(&self) -> Poll<Result<(), Closed>> {
+    pub(crate) fn try_wait(&self) -> Poll<Result<(), Closed>> {
         let mut state = self.load();
         let initial_wake_alls = state.get(State::WAKE_ALLS);
         while state.get(State::INNER) == StateInner::Woken {
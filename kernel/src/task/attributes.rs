@@ -0,0 +1,56 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::task::Priority;
+use alloc::boxed::Box;
+use core::panic::Location;
+
+/// Attributes of a task, fixed when the task is spawned.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Attributes {
+    /// The scheduling class of the task.
+    pub priority: Priority,
+    /// Bitmask of the CPUs the task is allowed to run on.
+    ///
+    /// Tasks whose affinity doesn't include every CPU are *pinned*: they are never placed into
+    /// the global run queues or stolen by other workers.
+    pub affinity: usize,
+    /// The optional human-readable name of the task.
+    pub name: Option<Box<str>>,
+    /// The source location that spawned the task.
+    pub location: &'static Location<'static>,
+}
+
+impl Attributes {
+    /// Affinity mask that allows a task to run on any CPU.
+    pub const ANY_CPU: usize = usize::MAX;
+
+    pub const fn new() -> Self {
+        Self {
+            priority: Priority::Normal,
+            affinity: Self::ANY_CPU,
+            name: None,
+            location: Location::caller(),
+        }
+    }
+
+    /// Returns `true` if the task is restricted to a subset of CPUs.
+    pub const fn is_pinned(&self) -> bool {
+        self.affinity != Self::ANY_CPU
+    }
+
+    /// Returns `true` if the task may run on the given CPU.
+    pub const fn allows_cpu(&self, cpuid: usize) -> bool {
+        cpuid < usize::BITS as usize && self.affinity & (1 << cpuid) != 0
+    }
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
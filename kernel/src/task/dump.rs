@@ -0,0 +1,149 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::task::state::Snapshot;
+use crate::task::trace::Frame;
+use crate::task::{Header, Id, Priority};
+use crate::time::Instant;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+/// A snapshot of a task, as reported by [`Scheduler::dump_tasks`].
+///
+/// The `Display` impl prints a one-line summary of the task, the alternate form (`{:#}`) also
+/// prints the task's async backtrace.
+///
+/// [`Scheduler::dump_tasks`]: crate::scheduler::Scheduler::dump_tasks
+#[derive(Debug, Clone)]
+pub struct TaskDump {
+    pub id: Id,
+    pub name: Option<String>,
+    /// The source location that spawned the task.
+    pub location: &'static Location<'static>,
+    pub priority: Priority,
+    pub state: TaskState,
+    /// The CPU that last polled the task, `None` if it was never polled.
+    pub cpu: Option<usize>,
+    /// The number of times the task has been polled.
+    pub polls: u64,
+    /// The total time spent polling the task.
+    pub busy: Duration,
+    /// The time since the task was last woken, `None` if it was never woken.
+    pub since_wake: Option<Duration>,
+    /// The [`framed`](crate::task::trace::framed) await points the task is currently suspended in, outermost
+    /// frames first.
+    pub frames: Vec<Frame>,
+}
+
+/// The state of a task at the time of a [`TaskDump`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TaskState {
+    /// The task is waiting to be woken.
+    Idle,
+    /// The task has been woken and is waiting in a run queue.
+    Scheduled,
+    /// The task is currently being polled.
+    Running,
+    /// The task has been cancelled but not yet dropped its future.
+    Cancelled,
+    /// The task has completed.
+    Complete,
+}
+
+impl Header {
+    pub(super) fn dump(&self, now: Instant) -> TaskDump {
+        TaskDump {
+            id: self.id,
+            name: self.attributes.name.as_deref().map(String::from),
+            location: self.attributes.location,
+            priority: self.attributes.priority,
+            state: TaskState::from_snapshot(self.state.load(Ordering::Acquire)),
+            cpu: self.stats.last_cpu(),
+            polls: self.stats.polls(),
+            busy: self.stats.busy(),
+            since_wake: self
+                .stats
+                .last_wake()
+                .map(|woken| now.duration_since(woken)),
+            frames: self.frames.lock().clone(),
+        }
+    }
+}
+
+impl TaskState {
+    fn from_snapshot(snapshot: Snapshot) -> Self {
+        if snapshot.get(Snapshot::COMPLETE) {
+            Self::Complete
+        } else if snapshot.get(Snapshot::POLLING) {
+            Self::Running
+        } else if snapshot.get(Snapshot::CANCELLED) {
+            Self::Cancelled
+        } else if snapshot.get(Snapshot::WOKEN) {
+            Self::Scheduled
+        } else {
+            Self::Idle
+        }
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskState::Idle => f.pad("idle"),
+            TaskState::Scheduled => f.pad("scheduled"),
+            TaskState::Running => f.pad("running"),
+            TaskState::Cancelled => f.pad("cancelled"),
+            TaskState::Complete => f.pad("complete"),
+        }
+    }
+}
+
+impl fmt::Display for TaskDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "task {} ({}) {} {}",
+            self.id,
+            self.name.as_deref().unwrap_or("<unnamed>"),
+            self.state,
+            self.priority
+        )?;
+        if let Some(cpu) = self.cpu {
+            write!(f, " cpu={cpu}")?;
+        }
+        write!(f, " polls={} busy={:?}", self.polls, self.busy)?;
+        if let Some(since_wake) = self.since_wake {
+            write!(f, " woken={since_wake:?} ago")?;
+        }
+        write!(f, " spawned at {}", self.location)?;
+
+        if f.alternate() {
+            self.fmt_frames(f, None, 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TaskDump {
+    fn fmt_frames(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        parent: Option<u64>,
+        depth: usize,
+    ) -> fmt::Result {
+        for frame in self.frames.iter().filter(|frame| frame.parent == parent) {
+            write!(f, "\n{:width$}└╼ {}", "", frame.location, width = depth * 2)?;
+            self.fmt_frames(f, Some(frame.id), depth + 1)?;
+        }
+        Ok(())
+    }
+}
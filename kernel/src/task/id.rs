@@ -36,6 +36,10 @@ impl Id {
         self.0
     }
 
+    pub(crate) fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+
     pub fn is_stub(self) -> bool {
         self.0 == 0
     }
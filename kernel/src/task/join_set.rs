@@ -0,0 +1,301 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::scheduler::{TaskBuilder, scheduler};
+use crate::sync::WaitCell;
+use crate::task::{JoinError, JoinHandle};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+/// A collection of tasks spawned onto the [`Scheduler`](crate::scheduler::Scheduler).
+///
+/// Results are returned by [`JoinSet::join_next`] in the order the tasks *complete*, not the order
+/// in which they were spawned. When the `JoinSet` is dropped, all tasks in it are cancelled.
+pub struct JoinSet<T> {
+    /// The handles of all tasks whose results haven't been returned yet, by their key.
+    tasks: BTreeMap<u64, JoinHandle<T>>,
+    completions: Arc<Completions>,
+    next_key: u64,
+}
+
+/// Keys of tasks that completed, in the order they completed.
+struct Completions {
+    keys: Mutex<VecDeque<u64>>,
+    wait: WaitCell,
+}
+
+/// Records the completion of a task in its `JoinSet` when dropped.
+///
+/// This is moved into the spawned future, so it is dropped when the task completes, panics or is
+/// cancelled (even before it was first polled).
+struct CompletionGuard {
+    completions: Arc<Completions>,
+    key: u64,
+}
+
+// === impl JoinSet ===
+
+impl<T> JoinSet<T> {
+    /// Returns a new, empty `JoinSet`.
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            completions: Arc::new(Completions {
+                keys: Mutex::new(VecDeque::new()),
+                wait: WaitCell::new(),
+            }),
+            next_key: 0,
+        }
+    }
+
+    /// Returns the number of tasks in the set.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns `true` if there are no tasks in the set.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+impl<T: Send + 'static> JoinSet<T> {
+    /// Spawn the provided future onto the scheduler and add it to the set.
+    #[track_caller]
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        self.spawn_with(scheduler().build_task(), future);
+    }
+
+    /// Spawn the provided future using the given [`TaskBuilder`] and add it to the set.
+    ///
+    /// This allows members of the set to have names, priorities or an affinity.
+    #[track_caller]
+    pub fn spawn_with<F>(&mut self, builder: TaskBuilder, future: F)
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let key = self.next_key;
+        self.next_key += 1;
+
+        let guard = CompletionGuard {
+            completions: self.completions.clone(),
+            key,
+        };
+        let handle = builder.spawn(async move {
+            let _guard = guard;
+            future.await
+        });
+
+        self.tasks.insert(key, handle);
+    }
+
+    /// Wait until one of the tasks in the set completes and return its output.
+    ///
+    /// Returns `None` if the set is empty.
+    ///
+    /// This method is cancel safe: if the returned future is dropped before it completes, no task
+    /// output is lost.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError<T>>> {
+        loop {
+            if self.tasks.is_empty() {
+                return None;
+            }
+
+            let completions = &*self.completions;
+            let key = completions
+                .wait
+                .wait_for_value(|| completions.keys.lock().front().copied())
+                .await
+                .expect("JoinSet WaitCell is never closed");
+
+            let Some(handle) = self.tasks.get_mut(&key) else {
+                // the task was detached from the set
+                completions.keys.lock().pop_front();
+                continue;
+            };
+
+            let res = handle.await;
+            completions.keys.lock().pop_front();
+            self.tasks.remove(&key);
+
+            return Some(res);
+        }
+    }
+
+    /// Return the output of a completed task in the set, without waiting.
+    ///
+    /// Returns `None` if no task has completed yet or the set is empty.
+    pub fn try_join_next(&mut self) -> Option<Result<T, JoinError<T>>> {
+        let mut cx = Context::from_waker(Waker::noop());
+
+        loop {
+            let key = *self.completions.keys.lock().front()?;
+
+            let Some(handle) = self.tasks.get_mut(&key) else {
+                // the task was detached from the set
+                self.completions.keys.lock().pop_front();
+                continue;
+            };
+
+            // the task might still be in the process of completing
+            let Poll::Ready(res) = Pin::new(handle).poll(&mut cx) else {
+                return None;
+            };
+
+            self.completions.keys.lock().pop_front();
+            self.tasks.remove(&key);
+
+            return Some(res);
+        }
+    }
+
+    /// Wait for all tasks in the set to complete and return their outputs in completion order.
+    ///
+    /// # Panics
+    ///
+    /// If any task panicked, the panic is resumed in the caller. Panics if any task was cancelled.
+    pub async fn join_all(mut self) -> Vec<T> {
+        let mut output = Vec::with_capacity(self.len());
+
+        while let Some(res) = self.join_next().await {
+            match res {
+                Ok(value) => output.push(value),
+                Err(err) if err.is_panic() => panic_unwind::resume_unwind(err.into_panic()),
+                Err(err) => panic!("{err}"),
+            }
+        }
+
+        output
+    }
+
+    /// Cancel all tasks in the set and wait for them to finish.
+    pub async fn shutdown(&mut self) {
+        self.abort_all();
+        while self.join_next().await.is_some() {}
+    }
+}
+
+impl<T> JoinSet<T> {
+    /// Cancel all tasks in the set.
+    ///
+    /// The tasks are not removed from the set, [`JoinSet::join_next`] will return a cancellation
+    /// error for each of them (or their output if they completed before being cancelled).
+    pub fn abort_all(&mut self) {
+        for handle in self.tasks.values() {
+            handle.cancel();
+        }
+    }
+
+    /// Remove all tasks from the set without cancelling them.
+    ///
+    /// The tasks continue to run in the background, but their output can no longer be retrieved.
+    pub fn detach_all(&mut self) {
+        self.tasks.clear();
+    }
+}
+
+impl<T> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        self.abort_all();
+    }
+}
+
+impl<T> fmt::Debug for JoinSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinSet")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl CompletionGuard ===
+
+impl Drop for CompletionGuard {
+    fn drop(&mut self) {
+        self.completions.keys.lock().push_back(self.key);
+        self.completions.wait.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::yield_now;
+    use crate::sync::oneshot;
+    use alloc::vec;
+
+    #[ktest::test]
+    async fn completion_order() {
+        let mut set = JoinSet::new();
+
+        let mut releases = Vec::new();
+        for i in 0..3_usize {
+            let (tx, rx) = oneshot::channel::<()>();
+            releases.push(tx);
+            set.spawn(async move {
+                rx.recv().await.unwrap();
+                i
+            });
+        }
+        assert_eq!(set.len(), 3);
+
+        // release the tasks in reverse order, waiting for each to complete before releasing the
+        // next one, so outputs must arrive in exactly that order
+        let mut outputs = Vec::new();
+        while let Some(tx) = releases.pop() {
+            tx.send(()).unwrap();
+            outputs.push(set.join_next().await.unwrap().unwrap());
+        }
+
+        assert_eq!(outputs, vec![2, 1, 0]);
+        assert!(set.is_empty());
+        assert!(set.join_next().await.is_none());
+    }
+
+    #[ktest::test]
+    async fn panic_is_reported() {
+        let mut set = JoinSet::<()>::new();
+
+        set.spawn(async { panic!("boom") });
+
+        let err = set.join_next().await.unwrap().unwrap_err();
+        assert!(err.is_panic());
+    }
+
+    #[ktest::test]
+    async fn abort_all() {
+        let mut set = JoinSet::<()>::new();
+
+        for _ in 0..4 {
+            set.spawn(async {
+                loop {
+                    yield_now().await;
+                }
+            });
+        }
+        set.abort_all();
+
+        while let Some(res) = set.join_next().await {
+            assert!(res.unwrap_err().is_cancelled());
+        }
+    }
+}
@@ -5,21 +5,33 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+mod attributes;
+mod dump;
 mod id;
 mod join_handle;
+mod join_set;
 mod owned_tasks;
+mod priority;
+pub mod scope;
 mod state;
+mod stats;
+pub mod task_local;
+pub mod trace;
 
 use crate::task::state::{JoinAction, StartPollAction, State, WakeByRefAction, WakeByValAction};
+use crate::task::stats::Stats;
+use crate::task::trace::Frame;
+use crate::time::Instant;
 use crate::util::non_null;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::alloc::{AllocError, Allocator};
 use core::any::type_name;
 use core::cell::UnsafeCell;
 use core::future::Future;
 use core::mem::offset_of;
-use core::panic::AssertUnwindSafe;
+use core::panic::{AssertUnwindSafe, Location};
 use core::pin::Pin;
 use core::ptr::NonNull;
 use core::sync::atomic::Ordering;
@@ -29,9 +41,13 @@ use core::{fmt, mem};
 use crate::mem::AddressSpace;
 use crate::sync::CachePadded;
 use crate::util::maybe_uninit::CheckedMaybeUninit;
+pub use attributes::Attributes;
+pub use dump::{TaskDump, TaskState};
 pub use id::Id;
 pub use join_handle::{JoinError, JoinErrorKind, JoinHandle};
+pub use join_set::JoinSet;
 pub use owned_tasks::OwnedTasks;
+pub use priority::Priority;
 use spin::Mutex;
 
 pub trait Schedule {
@@ -138,6 +154,8 @@ pub(crate) struct Header {
     pub vtable: &'static Vtable,
     /// The task's ID.
     pub id: Id,
+    /// The attributes of this task, such as its priority and name.
+    pub attributes: Attributes,
     /// Links to other tasks in the intrusive global run queue.
     ///
     /// TODO ownership
@@ -148,6 +166,10 @@ pub(crate) struct Header {
     pub owned_tasks_links: linked_list::Links<Header>,
     /// The address space associated with this task
     pub aspace: Option<Arc<Mutex<AddressSpace>>>,
+    /// Runtime statistics reported in task dumps.
+    pub(crate) stats: Stats,
+    /// The `framed` futures this task is currently suspended in, see [`trace`].
+    pub(crate) frames: Mutex<Vec<Frame>>,
     span: tracing::Span,
 }
 
@@ -229,6 +251,7 @@ impl TaskRef {
         future: F,
         scheduler: S,
         task_id: Id,
+        attributes: Attributes,
         span: tracing::Span,
         aspace: Arc<Mutex<AddressSpace>>,
         alloc: A,
@@ -239,7 +262,7 @@ impl TaskRef {
         A: Allocator,
     {
         let ptr = Box::into_raw(Box::try_new_in(
-            Task::new(future, scheduler, task_id, aspace, span),
+            Task::new(future, scheduler, task_id, attributes, aspace, span),
             alloc,
         )?);
 
@@ -265,10 +288,26 @@ impl TaskRef {
         &self.header().state
     }
 
-    pub(super) fn id(&self) -> Id {
+    pub(crate) fn id(&self) -> Id {
         self.header().id
     }
 
+    pub(crate) fn priority(&self) -> Priority {
+        self.header().attributes.priority
+    }
+
+    pub(crate) fn attributes(&self) -> &Attributes {
+        &self.header().attributes
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.header().attributes.name.as_deref()
+    }
+
+    pub(crate) fn location(&self) -> &'static Location<'static> {
+        self.header().attributes.location
+    }
+
     pub fn is_complete(&self) -> bool {
         self.state()
             .load(Ordering::Acquire)
@@ -340,6 +379,7 @@ impl fmt::Debug for TaskRef {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TaskRef")
             .field("id", &self.id())
+            .field("name", &self.name())
             .field("addr", &self.0)
             .finish()
     }
@@ -406,10 +446,13 @@ impl Header {
             state: State::new(),
             vtable: &Self::STATIC_STUB_VTABLE,
             id: Id::stub(),
+            attributes: Attributes::new(),
             run_queue_links: mpsc_queue::Links::new_stub(),
             owned_tasks_links: linked_list::Links::new(),
             span: tracing::Span::none(),
             aspace: None,
+            stats: Stats::new(),
+            frames: Mutex::new(Vec::new()),
         }
     }
 
@@ -534,6 +577,7 @@ where
         future: F,
         scheduler: S,
         task_id: Id,
+        attributes: Attributes,
         aspace: Arc<Mutex<AddressSpace>>,
         span: tracing::Span,
     ) -> Self {
@@ -543,10 +587,13 @@ where
                     state: State::new(),
                     vtable: &Self::TASK_VTABLE,
                     id: task_id,
+                    attributes,
                     run_queue_links: mpsc_queue::Links::new(),
                     owned_tasks_links: linked_list::Links::new(),
                     span,
                     aspace: Some(aspace),
+                    stats: Stats::new(),
+                    frames: Mutex::new(Vec::new()),
                 },
                 scheduler,
             },
@@ -608,8 +655,16 @@ where
 
             // actually poll the task
             let poll = {
+                let _frames = trace::enter_task();
+                let started = Instant::now();
+
                 let cx = Context::from_waker(&waker);
-                this.poll_inner(cx)
+                let poll = this.poll_inner(cx);
+
+                ptr.as_ref()
+                    .stats
+                    .record_poll(crate::CPUID.get(), started.elapsed());
+                poll
             };
 
             let result = this.state().end_poll(poll.is_ready());
@@ -849,6 +904,8 @@ impl<S: Schedule> Schedulable<S> {
     }
 
     unsafe fn schedule(this: TaskRef) {
+        this.header().stats.record_wake();
+
         // Safety: ensured by caller
         unsafe {
             this.header_ptr()
@@ -5,12 +5,14 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use super::{Schedule, TaskRef};
+use super::{Attributes, Schedule, TaskDump, TaskRef};
 use crate::mem::AddressSpace;
 use crate::task;
 use crate::task::id::Id;
 use crate::task::join_handle::JoinHandle;
+use crate::time::Instant;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::future::Future;
 use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
@@ -38,6 +40,7 @@ impl OwnedTasks {
         future: F,
         scheduler: S,
         id: Id,
+        attributes: Attributes,
         span: tracing::Span,
         aspace: Arc<Mutex<AddressSpace>>,
     ) -> (JoinHandle<F::Output>, Option<TaskRef>)
@@ -46,8 +49,16 @@ impl OwnedTasks {
         F::Output: Send + 'static,
         S: Schedule + 'static,
     {
-        let task =
-            TaskRef::try_new_in(future, scheduler, id, span, aspace, alloc::alloc::Global).unwrap();
+        let task = TaskRef::try_new_in(
+            future,
+            scheduler,
+            id,
+            attributes,
+            span,
+            aspace,
+            alloc::alloc::Global,
+        )
+        .unwrap();
         let join = JoinHandle::new(task.clone());
 
         let task = self.bind_inner(task);
@@ -59,6 +70,7 @@ impl OwnedTasks {
         future: F,
         scheduler: S,
         id: Id,
+        attributes: Attributes,
         span: tracing::Span,
         aspace: Arc<Mutex<AddressSpace>>,
     ) -> (JoinHandle<F::Output>, Option<TaskRef>)
@@ -67,8 +79,16 @@ impl OwnedTasks {
         F::Output: 'static,
         S: Schedule + 'static,
     {
-        let task =
-            TaskRef::try_new_in(future, scheduler, id, span, aspace, alloc::alloc::Global).unwrap();
+        let task = TaskRef::try_new_in(
+            future,
+            scheduler,
+            id,
+            attributes,
+            span,
+            aspace,
+            alloc::alloc::Global,
+        )
+        .unwrap();
         let join = JoinHandle::new(task.clone());
 
         let task = self.bind_inner(task);
@@ -102,6 +122,16 @@ impl OwnedTasks {
         }
     }
 
+    /// Returns a snapshot of every task in the list.
+    pub fn dump(&self) -> Vec<TaskDump> {
+        let now = Instant::now();
+        self.list
+            .lock()
+            .iter()
+            .map(|header| header.dump(now))
+            .collect()
+    }
+
     pub fn remove(&self, task: TaskRef) -> Option<TaskRef> {
         let mut list = self.list.lock();
         // Check the closed flag in the lock for ensuring all that tasks
@@ -0,0 +1,53 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::fmt;
+
+/// The scheduling class of a task.
+///
+/// Workers run tasks of a more urgent class before tasks of a less urgent one, regardless of
+/// whether they are found in the local run queue, the global run queue or stolen from another
+/// worker. The only exception is that a worker only picks a bounded number of tasks of a class
+/// before the less urgent classes get their turn, so less urgent tasks are never starved entirely.
+/// Within a class tasks are scheduled in the usual work-stealing fashion.
+///
+/// Since tasks are scheduled cooperatively, a running task is never preempted. Realtime tasks
+/// should therefore do as little work as possible before yielding, otherwise they will delay all
+/// other tasks on the same CPU.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[repr(u8)]
+pub enum Priority {
+    /// Latency-sensitive tasks such as interrupt-driven drivers and timer services.
+    Realtime = 0,
+    /// The default class for all tasks.
+    #[default]
+    Normal = 1,
+    /// Background tasks that should only run when there is nothing else to do.
+    Idle = 2,
+}
+
+impl Priority {
+    /// The number of priority classes.
+    pub const COUNT: usize = 3;
+    /// All priority classes, from the most to the least urgent.
+    pub const ALL: [Priority; Self::COUNT] = [Priority::Realtime, Priority::Normal, Priority::Idle];
+
+    /// Returns the index of this priority class, `0` being the most urgent.
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Realtime => f.pad("realtime"),
+            Priority::Normal => f.pad("normal"),
+            Priority::Idle => f.pad("idle"),
+        }
+    }
+}
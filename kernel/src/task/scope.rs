@@ -0,0 +1,121 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::{Future, poll_fn};
+use core::pin::Pin;
+use core::task::Poll;
+
+/// Run a group of futures that may borrow from the enclosing stack frame to completion.
+///
+/// The closure is called with a [`Scope`] onto which futures can be spawned, and the returned
+/// future completes once every spawned future has completed. Unlike tasks spawned onto the
+/// [`Scheduler`](crate::scheduler::Scheduler), scoped futures don't need to be `'static`.
+///
+/// Scoped futures run *concurrently* with each other, but not in parallel: they are polled by the
+/// future returned from `scope` itself, rather than being scheduled as separate tasks. This is what
+/// makes borrowing sound, even if the `scope` future is leaked the borrowed data can never be
+/// accessed again. Use a [`JoinSet`](crate::task::JoinSet) for work that should run in parallel.
+///
+/// If a scoped future panics, the panic propagates to the caller, dropping all other scoped futures.
+///
+/// # Example
+///
+/// ```ignore
+/// let data = [1, 2, 3, 4, 5, 6, 7, 8];
+/// let mut sums = [0; 2];
+///
+/// task::scope::scope(|s| {
+///     for (chunk, sum) in data.chunks(4).zip(&mut sums) {
+///         s.spawn(async move {
+///             *sum = chunk.iter().sum();
+///         });
+///     }
+/// })
+/// .await;
+///
+/// assert_eq!(sums, [10, 26]);
+/// ```
+pub async fn scope<'env, F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Scope<'env>) -> R,
+{
+    let mut scope = Scope {
+        futures: Vec::new(),
+    };
+    let result = f(&mut scope);
+
+    poll_fn(|cx| {
+        scope
+            .futures
+            .retain_mut(|future| future.as_mut().poll(cx).is_pending());
+
+        if scope.futures.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    result
+}
+
+/// A group of futures spawned through [`scope`].
+pub struct Scope<'env> {
+    futures: Vec<Pin<Box<dyn Future<Output = ()> + Send + 'env>>>,
+}
+
+impl<'env> Scope<'env> {
+    /// Spawn a future onto the scope.
+    ///
+    /// The future may borrow any data that outlives the scope.
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'env,
+    {
+        self.futures.push(Box::pin(future));
+    }
+}
+
+impl fmt::Debug for Scope<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("futures", &self.futures.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::yield_now;
+
+    #[ktest::test]
+    async fn borrows() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut sums = [0; 2];
+
+        let n = scope(|s| {
+            for (chunk, sum) in data.chunks(4).zip(&mut sums) {
+                s.spawn(async move {
+                    for value in chunk {
+                        *sum += value;
+                        yield_now().await;
+                    }
+                });
+            }
+            2
+        })
+        .await;
+
+        assert_eq!(n, 2);
+        assert_eq!(sums, [10, 26]);
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::time::Instant;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+/// Runtime statistics of a task, used for task dumps.
+///
+/// All fields are only updated by the CPU currently polling or waking the task, and read racily
+/// for reporting, so relaxed orderings are sufficient.
+#[derive(Debug)]
+pub(crate) struct Stats {
+    /// The number of times the task has been polled.
+    polls: AtomicU64,
+    /// The total time spent polling the task, in nanoseconds.
+    busy_nanos: AtomicU64,
+    /// The CPU that last polled the task, `usize::MAX` if it was never polled.
+    last_cpu: AtomicUsize,
+    /// The time the task was last woken, in nanoseconds since boot. `0` if it was never woken.
+    last_wake_nanos: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            polls: AtomicU64::new(0),
+            busy_nanos: AtomicU64::new(0),
+            last_cpu: AtomicUsize::new(usize::MAX),
+            last_wake_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record_poll(&self, cpuid: usize, duration: Duration) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.busy_nanos
+            .fetch_add(duration_to_nanos(duration), Ordering::Relaxed);
+        self.last_cpu.store(cpuid, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_wake(&self) {
+        let now = Instant::now().duration_since(Instant::ZERO);
+        self.last_wake_nanos
+            .store(duration_to_nanos(now), Ordering::Relaxed);
+    }
+
+    pub(crate) fn polls(&self) -> u64 {
+        self.polls.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn busy(&self) -> Duration {
+        Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn last_cpu(&self) -> Option<usize> {
+        let cpuid = self.last_cpu.load(Ordering::Relaxed);
+        (cpuid != usize::MAX).then_some(cpuid)
+    }
+
+    pub(crate) fn last_wake(&self) -> Option<Instant> {
+        let nanos = self.last_wake_nanos.load(Ordering::Relaxed);
+        (nanos != 0).then(|| Instant::ZERO + Duration::from_nanos(nanos))
+    }
+}
+
+fn duration_to_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
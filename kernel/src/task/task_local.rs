@@ -0,0 +1,310 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::{fmt, mem};
+use pin_project::{pin_project, pinned_drop};
+
+/// Declare a new task-local storage key of type [`LocalKey`].
+///
+/// Unlike [`cpu_local!`](cpu_local::cpu_local) values, task-local values don't have an initializer
+/// instead they are provided for the duration of a future by [`LocalKey::scope`] (or for the
+/// duration of a closure by [`LocalKey::sync_scope`]). Accessing a task-local outside of a scope
+/// panics.
+///
+/// # Example
+///
+/// ```ignore
+/// crate::task_local! {
+///     pub static REQUEST_ID: u64;
+///
+///     static NAME: String;
+/// }
+///
+/// REQUEST_ID
+///     .scope(42, async {
+///         assert_eq!(REQUEST_ID.get(), 42);
+///     })
+///     .await;
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    // empty (base case for the recursion)
+    () => {};
+    // process multiple declarations
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $crate::__task_local_inner!($(#[$attr])* $vis $name, $t);
+        $crate::task_local!($($rest)*);
+    };
+    // handle a single declaration
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty) => {
+        $crate::__task_local_inner!($(#[$attr])* $vis $name, $t);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __task_local_inner {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $t:ty) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task::task_local::LocalKey<$t> = {
+            ::cpu_local::cpu_local! {
+                static __KEY: ::core::cell::RefCell<::core::option::Option<$t>> =
+                    const { ::core::cell::RefCell::new(::core::option::Option::None) };
+            }
+
+            $crate::task::task_local::LocalKey::new(__KEY)
+        };
+    };
+}
+
+/// A key for task-local data.
+///
+/// Task-local values are stored in CPU-local storage while the future they are scoped over is being
+/// polled and moved back into the future when it yields. This means they follow the task across
+/// CPUs and different tasks never observe each other's values.
+///
+/// Instances of this type are created by the [`task_local!`](crate::task_local) macro.
+pub struct LocalKey<T: 'static> {
+    inner: cpu_local::LocalKey<RefCell<Option<T>>>,
+}
+
+/// The error returned by [`LocalKey::try_with`] when the task-local is accessed outside of a scope.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AccessError(());
+
+/// A future that sets a value of a task-local for the future it wraps.
+///
+/// Returned by [`LocalKey::scope`].
+#[pin_project(PinnedDrop)]
+pub struct TaskLocalFuture<T: 'static, F> {
+    local: &'static LocalKey<T>,
+    slot: Option<T>,
+    #[pin]
+    future: Option<F>,
+}
+
+struct ScopeGuard<'a, T: 'static> {
+    local: &'static LocalKey<T>,
+    slot: &'a mut Option<T>,
+}
+
+// === impl LocalKey ===
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(inner: cpu_local::LocalKey<RefCell<Option<T>>>) -> Self {
+        Self { inner }
+    }
+
+    /// Sets a value `T` as the task-local value for the future `F`.
+    ///
+    /// On completion of `scope`, the task-local will be dropped.
+    pub fn scope<F>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F>
+    where
+        F: Future,
+    {
+        TaskLocalFuture {
+            local: self,
+            slot: Some(value),
+            future: Some(future),
+        }
+    }
+
+    /// Sets a value `T` as the task-local value for the closure `f`.
+    ///
+    /// On completion of `sync_scope`, the task-local will be dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task-local is currently borrowed by [`LocalKey::with`].
+    pub fn sync_scope<F, R>(&'static self, value: T, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let mut slot = Some(value);
+        let _guard = ScopeGuard::enter(self, &mut slot);
+        f()
+    }
+
+    /// Accesses the current task-local and runs the provided closure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called within the context of a future or closure that set a value for this
+    /// task-local through [`LocalKey::scope`] or [`LocalKey::sync_scope`].
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.try_with(f)
+            .expect("cannot access a task-local storage value without setting it first")
+    }
+
+    /// Accesses the current task-local and runs the provided closure.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AccessError`] if not called within the context of a future or closure that set a
+    /// value for this task-local.
+    pub fn try_with<F, R>(&'static self, f: F) -> Result<R, AccessError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.inner.with(|cell| {
+            let value = cell.borrow();
+            value.as_ref().map(f).ok_or(AccessError(()))
+        })
+    }
+}
+
+impl<T: Clone + 'static> LocalKey<T> {
+    /// Returns a copy of the task-local value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called within the context of a future or closure that set a value for this
+    /// task-local.
+    pub fn get(&'static self) -> T {
+        self.with(T::clone)
+    }
+}
+
+impl<T: 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("LocalKey { .. }")
+    }
+}
+
+// === impl TaskLocalFuture ===
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let _guard = ScopeGuard::enter(this.local, this.slot);
+
+        let future = this
+            .future
+            .as_mut()
+            .as_pin_mut()
+            .expect("`TaskLocalFuture` polled after completion");
+
+        let poll = future.poll(cx);
+        if poll.is_ready() {
+            // drop the future while the value is still in scope, it might access it in its `Drop` impl
+            this.future.set(None);
+        }
+        poll
+    }
+}
+
+#[pinned_drop]
+impl<T: 'static, F> PinnedDrop for TaskLocalFuture<T, F> {
+    fn drop(self: Pin<&mut Self>) {
+        let mut this = self.project();
+
+        // make sure the value is in scope when an unfinished future is dropped
+        if this.future.is_some() {
+            let _guard = ScopeGuard::enter(this.local, this.slot);
+            this.future.set(None);
+        }
+    }
+}
+
+impl<T: 'static + fmt::Debug, F> fmt::Debug for TaskLocalFuture<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskLocalFuture")
+            .field("value", &self.slot)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl ScopeGuard ===
+
+impl<'a, T: 'static> ScopeGuard<'a, T> {
+    /// Swap the value in `slot` into the task-local, swapping it back out when the guard is dropped.
+    fn enter(local: &'static LocalKey<T>, slot: &'a mut Option<T>) -> Self {
+        local.inner.with(|cell| {
+            mem::swap(
+                slot,
+                &mut *cell
+                    .try_borrow_mut()
+                    .expect("cannot enter a task-local scope while the task-local is borrowed"),
+            );
+        });
+
+        Self { local, slot }
+    }
+}
+
+impl<T: 'static> Drop for ScopeGuard<'_, T> {
+    fn drop(&mut self) {
+        self.local.inner.with(|cell| {
+            mem::swap(self.slot, &mut *cell.borrow_mut());
+        });
+    }
+}
+
+// === impl AccessError ===
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("task-local value not set")
+    }
+}
+
+impl core::error::Error for AccessError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+
+    crate::task_local! {
+        static VALUE: u32;
+    }
+
+    #[ktest::test]
+    async fn scope() {
+        assert_eq!(VALUE.try_with(|_| ()), Err(AccessError(())));
+
+        VALUE
+            .scope(1, async {
+                assert_eq!(VALUE.get(), 1);
+                yield_now().await;
+                assert_eq!(VALUE.get(), 1);
+
+                VALUE.sync_scope(2, || assert_eq!(VALUE.get(), 2));
+                assert_eq!(VALUE.get(), 1);
+            })
+            .await;
+
+        assert_eq!(VALUE.try_with(|_| ()), Err(AccessError(())));
+    }
+
+    #[ktest::test]
+    async fn isolated_between_tasks() {
+        let handles: [_; 4] = core::array::from_fn(|i| {
+            let i = u32::try_from(i).unwrap();
+            scheduler().spawn(VALUE.scope(i, async move {
+                for _ in 0..8 {
+                    yield_now().await;
+                    assert_eq!(VALUE.get(), i);
+                }
+            }))
+        });
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Async backtraces for task dumps.
+//!
+//! Futures wrapped with [`framed`] record their location in the task that polls them for as long
+//! as they are alive. Since `framed` futures can be nested, the recorded frames form a tree that
+//! shows exactly which await points a task is currently blocked in, see [`TaskDump`].
+//!
+//! [`TaskDump`]: crate::task::TaskDump
+
+use crate::scheduler;
+use crate::task::{Id, TaskRef};
+use core::cell::Cell;
+use core::future::Future;
+use core::panic::Location;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use cpu_local::cpu_local;
+use pin_project::{pin_project, pinned_drop};
+
+cpu_local! {
+    /// The innermost `framed` future currently being polled on this CPU.
+    static CURRENT_FRAME: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// A single await point recorded by a [`framed`] future.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Frame {
+    pub(crate) id: u64,
+    /// The enclosing frame, `None` for frames directly awaited by the task.
+    pub(crate) parent: Option<u64>,
+    /// Where the framed future was created.
+    pub location: &'static Location<'static>,
+}
+
+/// A future that records its location in the backtrace of the task polling it.
+///
+/// Returned by [`framed`].
+#[pin_project(PinnedDrop)]
+#[must_use = "futures do nothing unless `.await`ed or `poll`ed"]
+pub struct Framed<F> {
+    #[pin]
+    future: F,
+    location: &'static Location<'static>,
+    /// The task this frame is registered with and the frame's id.
+    registration: Option<(TaskRef, u64)>,
+}
+
+/// Instrument a future so it shows up in async backtraces of the task awaiting it.
+#[track_caller]
+pub fn framed<F: Future>(future: F) -> Framed<F> {
+    Framed {
+        future,
+        location: Location::caller(),
+        registration: None,
+    }
+}
+
+/// Start recording frames for a new task poll on this CPU, until the returned guard is dropped.
+pub(super) fn enter_task() -> TaskGuard {
+    TaskGuard {
+        prev_frame: CURRENT_FRAME.take(),
+    }
+}
+
+/// Calls `f` with the id and name of the task currently being polled on this CPU, if any.
+///
+/// Can be used from interrupt handlers and log output, see [`scheduler::with_current_task`].
+pub(crate) fn with_current_task<R>(f: impl FnOnce(Option<(Id, Option<&str>)>) -> R) -> R {
+    scheduler::with_current_task(|task| f(task.map(|task| (task.id(), task.name()))))
+}
+
+pub(super) struct TaskGuard {
+    prev_frame: Option<u64>,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        CURRENT_FRAME.set(self.prev_frame);
+    }
+}
+
+// === impl Framed ===
+
+impl<F: Future> Future for Framed<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if this.registration.is_none()
+            && let Some(task) = scheduler::with_current_task(|task| task.cloned())
+        {
+            static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+            task.header().frames.lock().push(Frame {
+                id,
+                parent: CURRENT_FRAME.get(),
+                location: *this.location,
+            });
+            *this.registration = Some((task, id));
+        }
+
+        let prev_frame = CURRENT_FRAME.replace(this.registration.as_ref().map(|(_, id)| *id));
+        let _guard = FrameGuard(prev_frame);
+
+        this.future.poll(cx)
+    }
+}
+
+#[pinned_drop]
+impl<F> PinnedDrop for Framed<F> {
+    fn drop(self: Pin<&mut Self>) {
+        if let Some((task, id)) = self.project().registration.take() {
+            task.header().frames.lock().retain(|frame| frame.id != id);
+        }
+    }
+}
+
+/// Restores the previous `CURRENT_FRAME` when dropped, even if the framed future panics.
+struct FrameGuard(Option<u64>);
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        CURRENT_FRAME.set(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{scheduler, yield_now};
+    use crate::sync::oneshot;
+
+    #[ktest::test]
+    async fn nested_frames() {
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let handle = scheduler().spawn(framed(async move {
+            framed(rx.recv()).await.unwrap();
+        }));
+
+        // wait for the task to block on the receiver
+        let frames = loop {
+            let dump = scheduler()
+                .dump_tasks()
+                .into_iter()
+                .find(|dump| handle == dump.id)
+                .unwrap();
+            if dump.frames.len() == 2 {
+                break dump.frames;
+            }
+            yield_now().await;
+        };
+
+        assert_eq!(frames[0].parent, None);
+        assert_eq!(frames[1].parent, Some(frames[0].id));
+
+        tx.send(()).unwrap();
+        handle.await.unwrap();
+    }
+}
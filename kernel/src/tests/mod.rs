@@ -11,7 +11,7 @@ mod smoke;
 mod spectest;
 mod wast;
 
-use crate::scheduler::scheduler;
+use crate::task::JoinSet;
 use crate::tests::args::Arguments;
 use crate::tests::printer::Printer;
 use crate::{arch, device_tree};
@@ -21,8 +21,6 @@ use core::any::Any;
 use core::ptr::addr_of;
 use core::sync::atomic::{AtomicU64, Ordering};
 use core::{hint, slice};
-use futures::FutureExt;
-use futures::future::try_join_all;
 use ktest::Test;
 
 /// The outcome of performing a single test.
@@ -106,37 +104,30 @@ pub async fn run_tests() -> Conclusion {
     let printer = Arc::new(printer);
     let conclusion = Arc::new(Conclusion::empty());
 
-    let tests = tests.iter().map(|test| {
+    let mut set = JoinSet::new();
+    for test in tests {
         if args.is_ignored(test) {
             printer.print_test(&test.info);
             conclusion.num_ignored.fetch_add(1, Ordering::Release);
-            futures::future::Either::Left(core::future::ready(Ok(())))
         } else {
             let printer = printer.clone();
-            let conclusion = conclusion.clone();
-
-            let h = scheduler()
-                .spawn(async move {
-                    // Print `test foo    ...`, run the test, then print the outcome in
-                    // the same line.
-                    printer.print_test(&test.info);
-                    (test.run)().await;
-                })
-                .inspect(move |res| match res {
-                    Ok(_) => {
-                        conclusion.num_passed.fetch_add(1, Ordering::Release);
-                    }
-                    Err(_) => {
-                        conclusion.num_failed.fetch_add(1, Ordering::Release);
-                    }
-                });
-
-            futures::future::Either::Right(h)
+
+            set.spawn(async move {
+                // Print `test foo    ...`, run the test, then print the outcome in
+                // the same line.
+                printer.print_test(&test.info);
+                (test.run)().await;
+            });
         }
-    });
+    }
 
-    // we handle test failures through `Conclusion` so it is safe to ignore the result here
-    let _ = try_join_all(tests).await;
+    while let Some(res) = set.join_next().await {
+        if res.is_ok() {
+            conclusion.num_passed.fetch_add(1, Ordering::Release);
+        } else {
+            conclusion.num_failed.fetch_add(1, Ordering::Release);
+        }
+    }
 
     printer.print_summary(&conclusion);
 
@@ -20,16 +20,24 @@ pub struct Ticks(pub u64);
 /// A `Clock` consists of a function that returns the hardware clock's current
 /// timestamp in [`Ticks`] (`now()`), and a [`Duration`] that defines the amount
 /// of time represented by a single tick of the clock.
+///
+/// Optionally a clock has a timer, a function that programs the timer interrupt
+/// to fire once the clock reaches a given timestamp (see [`Clock::with_timer`]).
 #[derive(Debug, Clone)]
 pub struct Clock {
     now: fn() -> Ticks,
     tick_duration: Duration,
     name: &'static str,
+    timer: Option<(&'static str, fn(Ticks))>,
 }
 
 impl fmt::Display for Clock {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}, {:?} precision", self.name, self.tick_duration)
+        write!(f, "{}, {:?} precision", self.name, self.tick_duration)?;
+        if let Some(mode) = self.timer_mode() {
+            write!(f, ", {mode} timer")?;
+        }
+        Ok(())
     }
 }
 
@@ -40,6 +48,7 @@ impl Clock {
             now,
             tick_duration,
             name: "<unnamed mystery clock>",
+            timer: None,
         }
     }
 
@@ -52,6 +61,36 @@ impl Clock {
         Self { name, ..self }
     }
 
+    /// Add a timer to this `Clock`, `arm` programs the timer interrupt to fire
+    /// once the clock reaches the given timestamp.
+    ///
+    /// `mode` describes the mechanism used by `arm`, e.g. whether the hardware
+    /// is programmed directly or through firmware.
+    #[must_use]
+    pub const fn with_timer(self, mode: &'static str, arm: fn(Ticks)) -> Self {
+        Self {
+            timer: Some((mode, arm)),
+            ..self
+        }
+    }
+
+    /// Program the timer interrupt to fire once the clock reaches `deadline`,
+    /// replacing any previously programmed deadline.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this `Clock` has no timer.
+    pub(crate) fn arm_timer(&self, deadline: Ticks) {
+        let (_, arm) = self.timer.expect("clock has no timer");
+        arm(deadline);
+    }
+
+    /// Returns how this `Clock`'s timer is programmed, if it has one.
+    #[must_use]
+    pub fn timer_mode(&self) -> Option<&'static str> {
+        self.timer.map(|(mode, _)| mode)
+    }
+
     /// Returns the current `now` timestamp, in [`Ticks`] of this clock's base
     /// tick duration.
     #[must_use]
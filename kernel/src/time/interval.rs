@@ -0,0 +1,189 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::time::{Instant, Sleep, sleep_until};
+use alloc::boxed::Box;
+use core::future::{Future, poll_fn};
+use core::pin::Pin;
+use core::task::{Context, Poll, ready};
+use core::time::Duration;
+
+/// Creates a new [`Interval`] that yields with interval of `period`. The first tick completes
+/// immediately.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now(), period)
+}
+
+/// Creates a new [`Interval`] that yields with interval of `period`, with the first tick
+/// completing at `start`.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(!period.is_zero(), "`period` must be non-zero");
+
+    Interval {
+        delay: Box::pin(sleep_until(start)),
+        period,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+/// Defines the behavior of an [`Interval`] when it misses a tick, i.e. when the next tick is
+/// already due by the time the previous tick is observed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MissedTickBehavior {
+    /// Ticks as fast as possible until caught up, so that the total number of ticks matches the
+    /// time elapsed.
+    #[default]
+    Burst,
+    /// Ticks once immediately, then schedules subsequent ticks `period` after that.
+    Delay,
+    /// Ticks once immediately, then schedules the next tick on the next multiple of `period` from
+    /// the original schedule, skipping all missed ticks.
+    Skip,
+}
+
+/// Yields at a fixed period, returned by [`interval`] and [`interval_at`].
+#[derive(Debug)]
+pub struct Interval {
+    delay: Pin<Box<Sleep<'static>>>,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+impl MissedTickBehavior {
+    fn next_timeout(self, timeout: Instant, now: Instant, period: Duration) -> Instant {
+        match self {
+            MissedTickBehavior::Burst => timeout + period,
+            MissedTickBehavior::Delay => now + period,
+            MissedTickBehavior::Skip => {
+                let behind = now.duration_since(timeout).as_nanos() % period.as_nanos();
+                now + period - Duration::from_nanos(u64::try_from(behind).unwrap())
+            }
+        }
+    }
+}
+
+impl Interval {
+    /// Completes when the next tick of the interval is reached, returning the instant the tick
+    /// was scheduled for.
+    ///
+    /// This method is cancel safe.
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Polls for the next tick of the interval.
+    ///
+    /// Returns `Poll::Pending` and registers the waker from `cx` if the next tick hasn't been
+    /// reached yet.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        ready!(self.delay.as_mut().poll(cx));
+
+        let timeout = self.delay.deadline();
+        let now = Instant::now();
+
+        let next = if now >= timeout + self.period {
+            self.missed_tick_behavior
+                .next_timeout(timeout, now, self.period)
+        } else {
+            timeout + self.period
+        };
+        self.delay.as_mut().reset(next);
+
+        Poll::Ready(timeout)
+    }
+
+    /// Resets the interval so the next tick completes one `period` from now.
+    pub fn reset(&mut self) {
+        self.delay.as_mut().reset(Instant::now() + self.period);
+    }
+
+    /// Resets the interval so the next tick completes immediately.
+    pub fn reset_immediately(&mut self) {
+        self.delay.as_mut().reset(Instant::now());
+    }
+
+    /// Resets the interval so the next tick completes at `deadline`.
+    pub fn reset_at(&mut self, deadline: Instant) {
+        self.delay.as_mut().reset(deadline);
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns the [`MissedTickBehavior`] of the interval.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    /// Sets the [`MissedTickBehavior`] of the interval.
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::sleep;
+
+    #[ktest::test]
+    async fn ticks_periodically() {
+        let start = Instant::now();
+        let mut interval = interval(Duration::from_millis(10));
+
+        let first = interval.tick().await;
+        let second = interval.tick().await;
+        let third = interval.tick().await;
+
+        assert!(first >= start);
+        assert_eq!(second, first + Duration::from_millis(10));
+        assert_eq!(third, first + Duration::from_millis(20));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[ktest::test]
+    async fn missed_ticks() {
+        let period = Duration::from_millis(10);
+
+        let mut burst = interval(period);
+        burst.tick().await;
+        sleep(Duration::from_millis(35)).await;
+        let missed = burst.tick().await;
+        // a burst interval catches up by ticking immediately
+        assert_eq!(burst.tick().await, missed + period);
+
+        let mut skip = interval(period);
+        skip.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let start = skip.tick().await;
+        sleep(Duration::from_millis(35)).await;
+        skip.tick().await;
+        // a skipping interval stays aligned to the original schedule
+        let next = skip.tick().await;
+        assert_eq!(next.duration_since(start).as_nanos() % period.as_nanos(), 0);
+        assert!(next >= start + Duration::from_millis(40));
+
+        let mut delay = interval(period);
+        delay.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        delay.tick().await;
+        sleep(Duration::from_millis(35)).await;
+        delay.tick().await;
+        let before = Instant::now();
+        delay.tick().await;
+        // a delaying interval waits a full period after the missed tick
+        assert!(before.elapsed() + Duration::from_millis(1) >= period);
+    }
+}
@@ -9,6 +9,8 @@
 
 pub mod clock;
 mod instant;
+mod interval;
+pub mod rtc;
 mod sleep;
 mod system_time;
 mod timeout;
@@ -23,8 +25,11 @@ use core::pin::pin;
 use core::task::Context;
 pub use core::time::Duration;
 pub use instant::Instant;
+pub use interval::{Interval, MissedTickBehavior, interval, interval_at};
 pub use sleep::{Sleep, sleep, sleep_until};
+pub use system_time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 pub use timeout::{Elapsed, Timeout, timeout};
+pub(crate) use timer::arm_timer_interrupt;
 pub use timer::{Deadline, Timer};
 
 // #[cfg(test)]
@@ -0,0 +1,222 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Real-time clock support.
+//!
+//! The only supported device right now is the `google,goldfish-rtc` provided by QEMU's `virt`
+//! machine, see <https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT>.
+//!
+//! The RTC is only read once during boot to anchor [`SystemTime`], which is then advanced using the
+//! monotonic [`Clock`](crate::time::Clock). This makes `SystemTime::now` just as cheap as
+//! `Instant::now` and guarantees it never goes backwards. The RTC's alarm is available through
+//! [`Rtc::alarm`].
+
+use crate::device_tree::DeviceTree;
+use crate::driver;
+use crate::irq::Irq;
+use crate::mem::{Mmap, VirtualAddress};
+use crate::sync::Closed;
+use crate::time::{Instant, NANOS_PER_SEC, SystemTime, UNIX_EPOCH};
+use core::future::{Future, poll_fn};
+use core::pin::pin;
+use core::task::Poll;
+use core::time::Duration;
+use spin::{Mutex, OnceLock};
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+const ALARM_LOW: usize = 0x08;
+const ALARM_HIGH: usize = 0x0c;
+const IRQ_ENABLED: usize = 0x10;
+const CLEAR_INTERRUPT: usize = 0x1c;
+
+static RTC: OnceLock<Option<Rtc>> = OnceLock::new();
+static BOOT_TIME: OnceLock<(SystemTime, Instant)> = OnceLock::new();
+
+/// Discover the RTC in the device tree and anchor [`SystemTime`] at the current wall-clock time.
+///
+/// If the system has no supported RTC, the wall-clock time starts at the [`UNIX_EPOCH`] at boot.
+///
+/// # Errors
+///
+/// Returns an error if the RTC's device tree node is malformed.
+#[cold]
+pub fn init(devtree: &DeviceTree) -> crate::Result<()> {
+    let rtc = RTC.get_or_try_init(|| Rtc::from_device_tree(devtree))?;
+
+    BOOT_TIME.get_or_init(|| {
+        let now = rtc.as_ref().map_or_else(
+            || {
+                tracing::warn!("no RTC found, wall-clock time starts at the UNIX epoch");
+                UNIX_EPOCH
+            },
+            Rtc::read_time,
+        );
+        tracing::info!(
+            "wall-clock time at boot: {:?} since the UNIX epoch",
+            now.duration_since(UNIX_EPOCH).unwrap()
+        );
+
+        (now, Instant::now())
+    });
+
+    Ok(())
+}
+
+/// Returns the system's RTC, if it has one.
+pub fn rtc() -> Option<&'static Rtc> {
+    RTC.get()?.as_ref()
+}
+
+/// Returns the current wall-clock time, see [`SystemTime::now`].
+pub(super) fn now() -> SystemTime {
+    let (boot_time, boot_instant) = BOOT_TIME.get().expect("RTC not initialized");
+    *boot_time + boot_instant.elapsed()
+}
+
+/// A `google,goldfish-rtc` real-time clock.
+#[derive(Debug)]
+pub struct Rtc {
+    mmap: Mmap,
+    irq: Irq,
+    /// The time (in nanoseconds since the UNIX epoch) the alarm is currently armed for.
+    armed: Mutex<u64>,
+}
+
+impl Rtc {
+    fn from_device_tree(devtree: &DeviceTree) -> crate::Result<Option<Self>> {
+        let Some((_, dev)) = devtree
+            .descendants()
+            .find(|(_, dev)| dev.is_compatible(["google,goldfish-rtc"]))
+        else {
+            return Ok(None);
+        };
+
+        let mmap = driver::map_reg(dev, 0, "goldfish-rtc")?;
+        let irq = driver::irq(dev, 0)?;
+
+        Ok(Some(Self {
+            mmap,
+            irq,
+            armed: Mutex::new(0),
+        }))
+    }
+
+    /// Read the current wall-clock time from the RTC.
+    ///
+    /// Unlike [`SystemTime::now`] this accesses the device, and the returned time can jump when
+    /// the host's clock is adjusted.
+    pub fn read_time(&self) -> SystemTime {
+        // reading the low half latches the high half, so the order matters here
+        let low = self.read(TIME_LOW);
+        let high = self.read(TIME_HIGH);
+        let nanos = (u64::from(high) << 32_i32) | u64::from(low);
+
+        UNIX_EPOCH
+            + Duration::new(
+                nanos / NANOS_PER_SEC,
+                u32::try_from(nanos % NANOS_PER_SEC).unwrap(),
+            )
+    }
+
+    /// Wait until the wall-clock time has reached `deadline`, using the RTC's alarm interrupt.
+    ///
+    /// Multiple alarms can be pending at the same time, the hardware alarm is always armed for the
+    /// earliest one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interrupt line was closed.
+    pub async fn alarm(&self, deadline: SystemTime) -> Result<(), Closed> {
+        let deadline_nanos = u64::try_from(
+            deadline
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        )
+        .unwrap_or(u64::MAX);
+
+        loop {
+            if self.read_time() >= deadline {
+                return Ok(());
+            }
+
+            // register for the interrupt *before* arming the alarm, so it can't be missed
+            let mut event = pin!(self.irq.next_event());
+            let early = poll_fn(|cx| Poll::Ready(event.as_mut().poll(cx))).await;
+
+            self.arm(deadline_nanos);
+
+            match early {
+                Poll::Ready(res) => res?,
+                Poll::Pending => event.await?,
+            }
+
+            self.write(CLEAR_INTERRUPT, 1);
+        }
+    }
+
+    fn arm(&self, deadline_nanos: u64) {
+        let mut armed = self.armed.lock();
+        let now = self.read_time().duration_since(UNIX_EPOCH).unwrap();
+
+        // only re-arm if the currently armed alarm already fired or fires too late
+        if Duration::from_nanos(*armed) <= now || deadline_nanos < *armed {
+            // writing the low half arms the alarm, so the order matters here
+            self.write(ALARM_HIGH, u32::try_from(deadline_nanos >> 32_i32).unwrap());
+            #[expect(clippy::cast_possible_truncation, reason = "we want the low half")]
+            self.write(ALARM_LOW, deadline_nanos as u32);
+            self.write(IRQ_ENABLED, 1);
+            *armed = deadline_nanos;
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        #[expect(clippy::cast_ptr_alignment, reason = "MMIO regions are page aligned")]
+        let ptr = self.register(offset).as_ptr().cast::<u32>();
+        // Safety: the register is within the mapped MMIO region of the device
+        unsafe { ptr.read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        #[expect(clippy::cast_ptr_alignment, reason = "MMIO regions are page aligned")]
+        let ptr = self.register(offset).as_mut_ptr().cast::<u32>();
+        // Safety: the register is within the mapped MMIO region of the device
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn register(&self, offset: usize) -> VirtualAddress {
+        self.mmap.range().start.checked_add(offset).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{sleep, timeout};
+
+    #[ktest::test]
+    async fn system_time_advances() {
+        let start = SystemTime::now();
+        sleep(Duration::from_millis(10)).await;
+        assert!(start.elapsed().unwrap() >= Duration::from_millis(10));
+    }
+
+    #[ktest::test]
+    async fn alarm() {
+        let Some(rtc) = rtc() else {
+            return;
+        };
+
+        let deadline = rtc.read_time() + Duration::from_millis(20);
+        timeout(Duration::from_secs(1), rtc.alarm(deadline))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(rtc.read_time() >= deadline);
+    }
+}
@@ -6,17 +6,18 @@
 // copied, modified, or distributed except according to those terms.
 
 use crate::arch::device::cpu::with_cpu;
-use crate::scheduler;
 use crate::scheduler::scheduler;
 use crate::sync::WaitCell;
 use crate::time::Instant;
 use crate::time::clock::Ticks;
+use crate::time::timer;
 use crate::time::timer::Timer;
 use core::fmt;
 use core::future::Future;
 use core::marker::PhantomPinned;
 use core::mem::offset_of;
 use core::pin::Pin;
+use core::ptr;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::{Context, Poll, ready};
@@ -32,17 +33,22 @@ pub fn sleep(duration: Duration) -> Sleep<'static> {
 
 pub fn sleep_until(instant: Instant) -> Sleep<'static> {
     let timer = scheduler().cpu_local_timer();
-    let now = with_cpu(|cpu| cpu.clock.now());
-    let duration = instant.duration_since(now);
-    let ticks = with_cpu(|cpu| cpu.clock.duration_to_ticks(duration).unwrap());
 
-    Sleep::new(timer, ticks)
+    Sleep::new_at(timer, instant_to_ticks(instant))
 }
 
+/// A future that completes once its deadline has been reached.
+///
+/// The sleep is registered with the timer of the CPU that polls it. If the task owning the sleep
+/// is stolen by another CPU, the sleep migrates to that CPU's timer the next time it is polled, so
+/// it never depends on a CPU the task no longer runs on. A registered sleep fires even if the CPU
+/// it is registered with is idle, since the CPU's timer interrupt is always armed for the earliest
+/// deadline of its timer.
 #[pin_project(PinnedDrop)]
 #[must_use = "futures do nothing unless `.await`ed or `poll`ed"]
 pub struct Sleep<'t> {
     state: State,
+    /// The timer this sleep is (or was last) registered with.
     timer: &'t Timer,
     ticks: Ticks,
     #[pin]
@@ -69,12 +75,17 @@ pub struct Entry {
 impl<'t> Sleep<'t> {
     pub fn new(timer: &'t Timer, ticks: Ticks) -> Self {
         let now = with_cpu(|cpu| cpu.clock.now_ticks());
-        let deadline = Ticks(now.0 + ticks.0);
+
+        Self::new_at(timer, Ticks(now.0 + ticks.0))
+    }
+
+    fn new_at(timer: &'t Timer, deadline: Ticks) -> Self {
+        let now = with_cpu(|cpu| cpu.clock.now_ticks());
 
         Self {
             state: State::Unregistered,
             timer,
-            ticks,
+            ticks: Ticks(deadline.0.saturating_sub(now.0)),
             entry: Entry {
                 deadline,
                 waker: WaitCell::new(),
@@ -89,6 +100,92 @@ impl<'t> Sleep<'t> {
     pub fn duration(&self) -> Duration {
         with_cpu(|cpu| cpu.clock.ticks_to_duration(self.ticks))
     }
+
+    /// Returns the [`Instant`] at which this `Sleep` future completes.
+    pub fn deadline(&self) -> Instant {
+        Instant::from_ticks(self.entry.deadline)
+    }
+
+    /// Returns `true` if the deadline of this `Sleep` future has been reached.
+    pub fn is_elapsed(&self) -> bool {
+        self.state == State::Completed
+    }
+
+    /// Reset the `Sleep` future to complete at the new `deadline`.
+    ///
+    /// This works regardless of whether the sleep has already completed, and avoids creating a
+    /// new `Sleep` future, e.g. for timeouts that are pushed back whenever some activity happens.
+    pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        let mut me = self.project();
+
+        if *me.state == State::Registered {
+            let mut lock = me.timer.core.lock();
+            if me.entry.is_registered.load(Ordering::Acquire) {
+                lock.cancel(me.entry.as_mut());
+            }
+        }
+
+        let now = with_cpu(|cpu| cpu.clock.now_ticks());
+        let deadline = instant_to_ticks(deadline);
+        *me.ticks = Ticks(deadline.0.saturating_sub(now.0));
+        *me.state = State::Unregistered;
+        // Safety: we only update the deadline, the entry is not moved. The entry is not part of
+        // any timer at this point, so nobody else is accessing it.
+        unsafe {
+            me.entry.as_mut().get_unchecked_mut().deadline = deadline;
+        }
+    }
+
+    /// Register the entry with the calling CPU's timer.
+    ///
+    /// If the sleep is currently registered with another CPU's timer, it is moved over. Returns
+    /// `Poll::Ready` if the deadline has already been reached.
+    fn register(self: Pin<&mut Self>) -> Poll<()> {
+        let mut me = self.project();
+        let timer = scheduler().cpu_local_timer();
+
+        if *me.state == State::Registered {
+            // the task owning this sleep migrated, take the entry off the previous CPU's timer
+            let mut lock = me.timer.core.lock();
+            if !me.entry.is_registered.load(Ordering::Acquire) {
+                // the previous timer fired in the meantime
+                *me.state = State::Completed;
+                return Poll::Ready(());
+            }
+            lock.cancel(me.entry.as_mut());
+            *me.state = State::Unregistered;
+        }
+        *me.timer = timer;
+
+        let mut lock = timer.core.lock();
+
+        // While we are holding the wheel lock, go ahead and advance the
+        // timer, too. This way, the timer wheel gets advanced more
+        // frequently than just when a scheduler tick completes or a
+        // timer IRQ fires, helping to increase timer accuracy.
+        let (_, prev_deadline) = timer.turn_locked(&mut lock);
+
+        // Safety: the timer impl promises to treat the pointer as pinned
+        let ptr = unsafe { NonNull::from(Pin::into_inner_unchecked(me.entry.as_mut())) };
+
+        // Safety: we just created the pointer from a mutable reference
+        if unsafe { lock.register(ptr) }.is_ready() {
+            *me.state = State::Completed;
+            return Poll::Ready(());
+        }
+        *me.state = State::Registered;
+
+        // if this sleep is now the first to expire, the timer interrupt would fire too late
+        let next_deadline = lock.next_deadline();
+        drop(lock);
+        if prev_deadline
+            .is_none_or(|prev| next_deadline.is_some_and(|next| next.ticks < prev.ticks))
+        {
+            timer::arm_timer_interrupt(next_deadline);
+        }
+
+        Poll::Pending
+    }
 }
 
 impl Future for Sleep<'_> {
@@ -96,43 +193,27 @@ impl Future for Sleep<'_> {
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         tracing::trace!(self=?self, "Sleep::poll");
-        let mut me = self.as_mut().project();
-
-        match me.state {
-            State::Unregistered => {
-                let mut lock = me.timer.core.lock();
-
-                // While we are holding the wheel lock, go ahead and advance the
-                // timer, too. This way, the timer wheel gets advanced more
-                // frequently than just when a scheduler tick completes or a
-                // timer IRQ fires, helping to increase timer accuracy.
-                me.timer.turn_locked(&mut lock);
-
-                // Safety: the timer impl promises to treat the pointer as pinned
-                let ptr = unsafe { NonNull::from(Pin::into_inner_unchecked(me.entry.as_mut())) };
-
-                // Safety: we just created the pointer from a mutable reference
-                match unsafe { lock.register(ptr) } {
-                    Poll::Ready(()) => {
-                        *me.state = State::Completed;
-                        return Poll::Ready(());
-                    }
-                    Poll::Pending => {
-                        *me.state = State::Registered;
-                        drop(lock);
-                    }
-                }
+
+        match self.state {
+            State::Unregistered => ready!(self.as_mut().register()),
+            State::Registered if !ptr::eq(self.timer, scheduler().cpu_local_timer()) => {
+                ready!(self.as_mut().register());
             }
-            State::Registered if me.entry.is_registered.load(Ordering::Acquire) => {}
-            _ => return Poll::Ready(()),
+            State::Registered => {}
+            State::Completed => return Poll::Ready(()),
         }
 
-        let _poll = ready!(me.entry.waker.poll_wait(cx));
-        debug_assert!(
-            _poll.is_err(),
-            "a Sleep's WaitCell should only be woken by closing"
-        );
-        Poll::Ready(())
+        let me = self.project();
+        loop {
+            if !me.entry.is_registered.load(Ordering::Acquire) {
+                *me.state = State::Completed;
+                return Poll::Ready(());
+            }
+
+            // the cell might still hold a wakeup from before the sleep was reset, in which case
+            // we need to check again and re-register our waker
+            let _ = ready!(me.entry.waker.poll_wait(cx));
+        }
     }
 }
 
@@ -169,6 +250,14 @@ impl fmt::Debug for Sleep<'_> {
     }
 }
 
+fn instant_to_ticks(instant: Instant) -> Ticks {
+    with_cpu(|cpu| {
+        cpu.clock
+            .duration_to_ticks(instant.duration_since(Instant::ZERO))
+            .unwrap()
+    })
+}
+
 impl Entry {
     pub(super) fn fire(&self) {
         let was_registered =
@@ -177,6 +266,11 @@ impl Entry {
         tracing::trace!(was_registered = was_registered.is_ok(), "firing sleep!");
         self.waker.wake();
     }
+
+    /// Wake the task waiting for this entry without completing the sleep.
+    pub(super) fn wake(&self) {
+        self.waker.wake();
+    }
 }
 
 // Safety: TODO
@@ -197,3 +291,76 @@ unsafe impl linked_list::Linked for Entry {
         .cast()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::timeout;
+    use alloc::boxed::Box;
+    use core::future::poll_fn;
+
+    #[ktest::test]
+    async fn reset() {
+        let start = Instant::now();
+        let mut sleep = Box::pin(sleep(Duration::from_secs(60)));
+
+        sleep.as_mut().reset(start + Duration::from_millis(10));
+        timeout(Duration::from_secs(1), sleep.as_mut())
+            .await
+            .unwrap();
+        assert!(sleep.is_elapsed());
+
+        // a completed sleep can be reused
+        sleep
+            .as_mut()
+            .reset(Instant::now() + Duration::from_millis(10));
+        assert!(!sleep.is_elapsed());
+        timeout(Duration::from_secs(1), sleep.as_mut())
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[ktest::test]
+    async fn fires_on_idle_cpus() {
+        // every CPU but the one running the test will be idle while its sleep is pending
+        let handles =
+            scheduler().spawn_per_cpu(|_| async { sleep(Duration::from_millis(10)).await });
+
+        timeout(Duration::from_secs(1), async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[ktest::test]
+    async fn migrates_with_task() {
+        let cpu_mask = crate::BOOT_INFO.get().unwrap().cpu_mask;
+        let num_cpus = usize::try_from(usize::BITS - cpu_mask.leading_zeros()).unwrap();
+
+        for cpuid in (0..num_cpus).filter(|cpuid| cpu_mask & (1 << cpuid) != 0) {
+            // register the sleep with this CPU's timer...
+            let mut sleep = Box::pin(sleep(Duration::from_millis(10)));
+            poll_fn(|cx| {
+                assert!(sleep.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+
+            // ...and complete it on another CPU, as if the task had been stolen
+            let handle = scheduler().spawn_on(cpuid, async move {
+                sleep.as_mut().await;
+                ptr::eq(sleep.timer, scheduler().cpu_local_timer())
+            });
+
+            let migrated = timeout(Duration::from_secs(1), handle)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(migrated);
+        }
+    }
+}
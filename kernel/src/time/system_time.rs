@@ -5,6 +5,7 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use crate::time::rtc;
 use core::fmt;
 use core::ops::{Add, AddAssign, Sub, SubAssign};
 use core::time::Duration;
@@ -22,48 +23,12 @@ pub struct SystemTime(Duration);
 pub struct SystemTimeError(Duration);
 
 impl SystemTime {
-    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    /// Returns the current wall-clock time.
+    ///
+    /// The time is read from the RTC once during boot and advanced using the monotonic clock
+    /// afterward, so unlike the RTC itself it never goes backwards.
     pub fn now() -> Self {
-        // Only device supported right now is "google,goldfish-rtc"
-        // https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT
-
-        // machine_info().
-        // let rtc = MACHINE_INFO
-        //     .get()
-        //     .unwrap()
-        //     .mmio_devices
-        //     .iter()
-        //     .find(|region| region.compatible.contains(&"google,goldfish-rtc"))
-        //     .unwrap();
-
-        // Safety: MMIO device access
-        // let time_ns = unsafe {
-        //     assert!(rtc.regions[0].start.is_aligned_to(4));
-        //
-        //     #[expect(clippy::cast_ptr_alignment, reason = "checked above")]
-        //     let time_low = AtomicPtr::new(rtc.regions[0].start.as_mut_ptr().cast::<u32>());
-        //     #[expect(clippy::cast_ptr_alignment, reason = "checked above")]
-        //     let time_high = AtomicPtr::new(
-        //         rtc.regions[0]
-        //             .start
-        //             .checked_add(0x04)
-        //             .unwrap()
-        //             .as_mut_ptr()
-        //             .cast::<u32>(),
-        //     );
-        //
-        //     let low = time_low.load(Ordering::Relaxed).read_volatile();
-        //     let high = time_high.load(Ordering::Relaxed).read_volatile();
-        //
-        //     (u64::from(high) << 32_i32) | u64::from(low)
-        // };
-        //
-        // SystemTime(Duration::new(
-        //     time_ns / NANOS_PER_SEC,
-        //     (time_ns % NANOS_PER_SEC) as u32,
-        // ))
-
-        todo!()
+        rtc::now()
     }
 
     pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, SystemTimeError> {
@@ -6,6 +6,7 @@
 // copied, modified, or distributed except according to those terms.
 
 use crate::arch::device::cpu::with_cpu;
+use crate::lockup;
 use crate::time::Clock;
 use crate::time::clock::Ticks;
 use crate::time::sleep::Entry;
@@ -133,6 +134,39 @@ impl Timer {
         }
     }
 
+    /// Turn the timer wheel and arm the calling CPU's timer interrupt for the next deadline.
+    ///
+    /// This must only be called on the CPU that owns this timer. Returns the number of expired
+    /// entries.
+    pub fn turn_and_arm(&self) -> usize {
+        let (expired, next_deadline) = self.turn();
+        arm_timer_interrupt(next_deadline);
+        expired
+    }
+
+    /// Returns the next deadline of this timer, `None` if no entries are registered.
+    pub fn next_deadline(&self) -> Option<Deadline> {
+        self.core.lock().next_deadline()
+    }
+
+    /// Wake the tasks of all registered entries without firing the entries.
+    ///
+    /// This is used when the CPU owning this timer goes offline: each woken task moves its sleep
+    /// to the timer of the CPU it is polled on next, see [`Sleep`](super::Sleep). Returns the
+    /// number of woken entries.
+    pub fn wake_all(&self) -> usize {
+        let lock = self.core.lock();
+
+        let mut woken = 0;
+        for wheel in &lock.wheels {
+            for entry in wheel.slots.iter().flat_map(|slot| slot.iter()) {
+                entry.wake();
+                woken += 1;
+            }
+        }
+        woken
+    }
+
     fn cancel(&self, entry: Pin<&mut Entry>) {
         let mut lock = self.core.lock();
         lock.cancel(entry);
@@ -145,6 +179,15 @@ impl Timer {
     }
 }
 
+/// Arm the calling CPU's timer interrupt for the given deadline.
+pub(crate) fn arm_timer_interrupt(next_deadline: Option<Deadline>) {
+    // Timer interrupts are always IPIs used for sleeping, unless the lockup detector needs
+    // to run its checks
+    let deadline = next_deadline.map_or(u64::MAX, |deadline| deadline.ticks.0);
+    let deadline = Ticks(lockup::clamp_timer_deadline(deadline));
+    with_cpu(|cpu| cpu.clock.arm_timer(deadline));
+}
+
 impl Core {
     const WHEELS: usize = Wheel::BITS;
     const MAX_SLEEP_TICKS: u64 = (1 << (Wheel::BITS * Self::WHEELS)) - 1;
@@ -240,7 +283,7 @@ impl Core {
         (expired, next_deadline)
     }
 
-    fn next_deadline(&self) -> Option<Deadline> {
+    pub(super) fn next_deadline(&self) -> Option<Deadline> {
         self.wheels.iter().find_map(|wheel| {
             let next_deadline = wheel.next_deadline(self.now)?;
             Some(next_deadline)
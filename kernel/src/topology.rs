@@ -0,0 +1,232 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! System topology as described by the device tree.
+//!
+//! Currently this only covers NUMA: which memory ranges and CPUs belong to which node and how far
+//! apart nodes are from each other. The bindings are described in the Linux kernel documentation
+//! under `Documentation/devicetree/bindings/numa.txt`. Systems that don't describe their NUMA
+//! topology are treated as having a single node that contains all CPUs and memory.
+
+use crate::device_tree::{Device, DeviceTree};
+use crate::mem::PhysicalAddress;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::ensure;
+use core::range::Range;
+use core::str::FromStr;
+use fallible_iterator::FallibleIterator;
+use spin::OnceLock;
+
+/// Identifier of a NUMA node.
+pub type NodeId = usize;
+
+/// The distance of a node to itself.
+pub const LOCAL_DISTANCE: u32 = 10;
+/// The distance between two different nodes if the device tree doesn't provide a distance map.
+pub const REMOTE_DISTANCE: u32 = 20;
+
+static TOPOLOGY: OnceLock<Topology> = OnceLock::new();
+
+#[cold]
+pub fn init(devtree: &DeviceTree) -> crate::Result<&'static Topology> {
+    TOPOLOGY.get_or_try_init(|| Topology::from_device_tree(devtree))
+}
+
+pub fn topology() -> &'static Topology {
+    TOPOLOGY.get().expect("topology not initialized")
+}
+
+#[derive(Debug)]
+pub struct Topology {
+    num_nodes: usize,
+    /// The NUMA node of each CPU, indexed by CPU id.
+    cpu_nodes: Vec<NodeId>,
+    /// Physical memory ranges and the NUMA node they belong to.
+    memory: Vec<(Range<PhysicalAddress>, NodeId)>,
+    /// `num_nodes * num_nodes` matrix of node distances.
+    distances: Vec<u32>,
+}
+
+impl Topology {
+    fn from_device_tree(devtree: &DeviceTree) -> crate::Result<Self> {
+        let mut num_nodes = 1;
+
+        let mut memory = Vec::new();
+        for dev in devtree.children().filter(|dev| dev.name.name == "memory") {
+            let node = numa_node_id(dev)?;
+            num_nodes = num_nodes.max(node + 1);
+
+            let mut regs = dev.regs().unwrap();
+            while let Some(reg) = regs.next()? {
+                let start = PhysicalAddress::new(reg.starting_address);
+                let end = start.checked_add(reg.size.unwrap_or(0)).unwrap();
+                memory.push((Range::from(start..end), node));
+            }
+        }
+        memory.sort_unstable_by_key(|(range, _)| range.start);
+
+        let mut cpu_nodes = Vec::new();
+        let cpus = devtree
+            .find_by_path("/cpus")
+            .expect("required /cpus node not in device tree");
+        for cpu in cpus.children().filter(|dev| dev.name.name == "cpu") {
+            let cpuid =
+                usize::from_str(cpu.name.unit_address.expect("CPU is missing unit address"))
+                    .expect("CPU unit address is not an integer");
+            let node = numa_node_id(cpu)?;
+            num_nodes = num_nodes.max(node + 1);
+
+            if cpu_nodes.len() <= cpuid {
+                cpu_nodes.resize(cpuid + 1, 0);
+            }
+            cpu_nodes[cpuid] = node;
+        }
+
+        let mut distances = vec![REMOTE_DISTANCE; num_nodes * num_nodes];
+        for node in 0..num_nodes {
+            distances[node * num_nodes + node] = LOCAL_DISTANCE;
+        }
+
+        if let Some(map) = devtree.find_by_path("/distance-map")
+            && map.is_compatible(["numa-distance-map-v1"])
+            && let Some(matrix) = map.property("distance-matrix")
+        {
+            let raw = matrix.raw();
+            ensure!(
+                raw.len() % 12 == 0,
+                "distance-matrix must consist of <from to distance> triplets"
+            );
+
+            // Entries that aren't explicitly given are assumed to be symmetric
+            let mut explicit = vec![false; num_nodes * num_nodes];
+            for entry in raw.array_chunks::<12>() {
+                let [from, to, distance] = [&entry[0..4], &entry[4..8], &entry[8..12]]
+                    .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()));
+                let from = NodeId::try_from(from)?;
+                let to = NodeId::try_from(to)?;
+
+                ensure!(
+                    from < num_nodes && to < num_nodes,
+                    "distance-matrix references unknown node"
+                );
+
+                distances[from * num_nodes + to] = distance;
+                explicit[from * num_nodes + to] = true;
+                if !explicit[to * num_nodes + from] {
+                    distances[to * num_nodes + from] = distance;
+                }
+            }
+        }
+
+        Ok(Self {
+            num_nodes,
+            cpu_nodes,
+            memory,
+            distances,
+        })
+    }
+
+    /// Returns the number of NUMA nodes in the system.
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+
+    /// Returns the NUMA node the given CPU belongs to.
+    pub fn cpu_node(&self, cpuid: usize) -> NodeId {
+        self.cpu_nodes.get(cpuid).copied().unwrap_or(0)
+    }
+
+    /// Returns the NUMA node of the given physical address or `None` if the device tree
+    /// doesn't describe the memory at this address.
+    pub fn memory_node(&self, addr: PhysicalAddress) -> Option<NodeId> {
+        self.memory
+            .iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(_, node)| *node)
+    }
+
+    /// Returns the physical memory ranges described by the device tree, sorted by address.
+    pub fn memory_ranges(&self) -> impl Iterator<Item = (Range<PhysicalAddress>, NodeId)> + '_ {
+        self.memory.iter().copied()
+    }
+
+    /// Returns the distance between two NUMA nodes.
+    pub fn distance(&self, from: NodeId, to: NodeId) -> u32 {
+        self.distances[from * self.num_nodes + to]
+    }
+
+    /// Returns all NUMA nodes sorted by their distance to `from`, starting with `from` itself.
+    pub fn nodes_by_distance(&self, from: NodeId) -> Vec<NodeId> {
+        let mut nodes: Vec<_> = (0..self.num_nodes).collect();
+        nodes.sort_by_key(|to| (self.distance(from, *to), *to != from));
+        nodes
+    }
+}
+
+fn numa_node_id(dev: &Device) -> crate::Result<NodeId> {
+    match dev.property("numa-node-id") {
+        Some(prop) => Ok(prop.as_usize()?),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_tree::device_tree;
+    use crate::mem::MIB;
+
+    // The test runner starts QEMU with two NUMA nodes: CPUs 0-3 and the first 128MiB of RAM on
+    // node 0, CPUs 4-7 and the second 128MiB on node 1, and a distance of 20 between them.
+
+    #[ktest::test]
+    async fn parses_numa_nodes() {
+        let topology = Topology::from_device_tree(device_tree()).unwrap();
+
+        assert_eq!(topology.num_nodes(), 2);
+        for cpuid in 0..8 {
+            assert_eq!(topology.cpu_node(cpuid), cpuid / 4);
+        }
+
+        let ranges: Vec<_> = topology.memory_ranges().collect();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].1, 0);
+        assert_eq!(ranges[1].1, 1);
+        assert_eq!(ranges[0].0.end, ranges[1].0.start);
+        for (range, node) in ranges {
+            assert_eq!(range.end.checked_sub_addr(range.start).unwrap(), 128 * MIB);
+            assert_eq!(topology.memory_node(range.start), Some(node));
+            assert_eq!(
+                topology.memory_node(range.end.checked_sub(1).unwrap()),
+                Some(node)
+            );
+        }
+
+        let end = topology.memory_ranges().last().unwrap().0.end;
+        assert_eq!(topology.memory_node(end), None);
+    }
+
+    #[ktest::test]
+    async fn parses_distance_map() {
+        let devtree = device_tree();
+        let map = devtree
+            .find_by_path("/distance-map")
+            .expect("test runner should describe node distances");
+        assert!(map.is_compatible(["numa-distance-map-v1"]));
+
+        let topology = Topology::from_device_tree(devtree).unwrap();
+
+        assert_eq!(topology.distance(0, 0), LOCAL_DISTANCE);
+        assert_eq!(topology.distance(1, 1), LOCAL_DISTANCE);
+        assert_eq!(topology.distance(0, 1), 20);
+        assert_eq!(topology.distance(1, 0), 20);
+
+        assert_eq!(topology.nodes_by_distance(0), [0, 1]);
+        assert_eq!(topology.nodes_by_distance(1), [1, 0]);
+    }
+}
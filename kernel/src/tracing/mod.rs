@@ -15,12 +15,17 @@ mod registry;
 mod writer;
 
 use crate::CPUID;
+use crate::driver::Uart;
+use crate::task;
 use crate::time::Instant;
-use crate::tracing::writer::{MakeWriter, Semihosting};
+use crate::tracing::writer::{Console, MakeWriter};
 pub use ::tracing::*;
+use alloc::sync::Arc;
+use anyhow::bail;
 use color::{Color, SetColor};
 use core::fmt;
 use core::fmt::Write;
+use core::str::FromStr;
 pub use filter::Filter;
 use registry::Registry;
 use spin::OnceLock;
@@ -45,7 +50,7 @@ cpu_local! {
 pub fn init_early() {
     let subscriber = SUBSCRIBER.get_or_init(|| Subscriber {
         // level_filter,
-        output: Output::new(Semihosting::new()),
+        output: Output::new(Console::new()),
         lateinit: OnceLock::new(),
     });
     ::log::set_logger(subscriber).unwrap();
@@ -55,8 +60,30 @@ pub fn init_early() {
     dispatch::set_global_default(dispatch).unwrap();
 }
 
+/// Where log output goes once the subsystem is fully initialized.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogOutput {
+    /// Print through semihosting, requires a debugger or emulator that implements it.
+    #[default]
+    Semihosting,
+    /// Print to the first UART, output is buffered until the UART driver is bound.
+    Uart,
+}
+
+impl FromStr for LogOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "semihosting" => Ok(LogOutput::Semihosting),
+            "uart" => Ok(LogOutput::Uart),
+            _ => bail!("unknown log output `{s}`"),
+        }
+    }
+}
+
 /// Fully initialize the subsystem, after this point tracing [`Span`]s will be processed as well.
-pub fn init(filter: Filter) {
+pub fn init(filter: Filter, output: LogOutput) {
     let subscriber = SUBSCRIBER
         .get()
         .expect("tracing::init must be called after tracing::init_early");
@@ -73,6 +100,31 @@ pub fn init(filter: Filter) {
     subscriber
         .lateinit
         .get_or_init(|| (Registry::default(), filter));
+
+    if output == LogOutput::Uart {
+        subscriber.output.make_writer.use_uart();
+    }
+}
+
+/// Make `uart` available for log output, see [`LogOutput::Uart`].
+pub fn register_uart(uart: Arc<Uart>) {
+    if let Some(subscriber) = SUBSCRIBER.get() {
+        subscriber.output.make_writer.set_uart(uart);
+    }
+}
+
+/// Wait until all log output has been written out, e.g. before the system shuts down.
+pub fn flush() {
+    if let Some(subscriber) = SUBSCRIBER.get() {
+        subscriber.output.make_writer.flush();
+    }
+}
+
+/// Write out all buffered log output from a fatal panic, see [`Console::flush_panic`].
+pub fn flush_panic() {
+    if let Some(subscriber) = SUBSCRIBER.get() {
+        subscriber.output.make_writer.flush_panic();
+    }
 }
 
 /// Perform late, per-CPU initialization. This will enable the proper printing of timestamps and
@@ -83,7 +135,7 @@ pub fn per_cpu_init_late(time_base: Instant) {
 
 struct Subscriber {
     // level_filter: LevelFilter,
-    output: Output<Semihosting>,
+    output: Output<Console>,
     lateinit: OnceLock<(Registry, Filter)>,
 }
 
@@ -161,6 +213,7 @@ impl Collect for Subscriber {
 
         let _ = write_level(&mut writer, *meta.level());
         let _ = write_cpu(&mut writer);
+        let _ = write_task(&mut writer);
         let _ = write_timestamp(&mut writer);
         // let _ = writer.indent(IndentKind::Event);
         let _ = write!(
@@ -297,6 +350,18 @@ where
     w.write_fmt(format_args!("[CPU {}]", CPUID.get()))
 }
 
+#[inline]
+fn write_task<W>(w: &mut W) -> fmt::Result
+where
+    W: Write,
+{
+    task::trace::with_current_task(|task| match task {
+        Some((id, Some(name))) => w.write_fmt(format_args!("[task {id} {name}]")),
+        Some((id, None)) => w.write_fmt(format_args!("[task {id}]")),
+        None => Ok(()),
+    })
+}
+
 #[inline]
 fn write_timestamp<W>(w: &mut W) -> fmt::Result
 where
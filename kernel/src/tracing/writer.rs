@@ -5,11 +5,15 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use crate::driver::Uart;
 use crate::tracing::color::{AnsiEscapes, Color, SetColor};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::cell::UnsafeCell;
 use core::fmt::{Arguments, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::{cmp, fmt};
-use spin::{ReentrantMutex, ReentrantMutexGuard};
+use spin::{Mutex, OnceLock, ReentrantMutex, ReentrantMutexGuard};
 use tracing_core::Metadata;
 
 pub trait MakeWriter<'a> {
@@ -199,3 +203,125 @@ impl Write for SemihostingWriter<'_> {
         this.write_fmt(args)
     }
 }
+
+/// The number of bytes of log output kept while waiting for the UART to show up.
+const EARLY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The kernel log console.
+///
+/// Log output goes to semihosting until [`Console::use_uart`] is called. From then on it is
+/// buffered until a UART is registered through [`Console::set_uart`], which is only known after
+/// drivers have been probed.
+pub struct Console {
+    semihosting: Semihosting,
+    use_uart: AtomicBool,
+    uart: OnceLock<Arc<Uart>>,
+    /// Keeps log lines from different harts apart, reentrant so logging from within a log
+    /// statement doesn't deadlock.
+    uart_lock: ReentrantMutex<()>,
+    early: Mutex<Vec<u8>>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            semihosting: Semihosting::new(),
+            use_uart: AtomicBool::new(false),
+            uart: OnceLock::new(),
+            uart_lock: ReentrantMutex::new(()),
+            early: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Send all further output to the UART.
+    pub fn use_uart(&self) {
+        self.use_uart.store(true, Ordering::Release);
+    }
+
+    /// Set the UART that output is written to, flushing any output that was buffered until now.
+    pub fn set_uart(&self, uart: Arc<Uart>) {
+        let _guard = self.uart_lock.lock();
+
+        let uart = self.uart.get_or_init(|| uart);
+        let early = core::mem::take(&mut *self.early.lock());
+        uart.write_lossy(&early);
+    }
+
+    /// Wait until all buffered output has been written out.
+    pub fn flush(&self) {
+        let _guard = self.uart_lock.lock();
+        if let Some(uart) = self.uart.get() {
+            uart.flush_blocking();
+        }
+    }
+
+    /// Write out all buffered output without taking any locks that might already be held by the
+    /// calling hart, see [`Uart::try_flush_blocking`].
+    pub fn flush_panic(&self) {
+        if let Some(uart) = self.uart.get() {
+            uart.try_flush_blocking();
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Console {
+    type Writer = AnsiEscapes<ConsoleWriter<'a>>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        let writer = if self.use_uart.load(Ordering::Acquire) {
+            ConsoleWriter::Uart(UartWriter {
+                console: self,
+                _guard: self.uart_lock.lock(),
+            })
+        } else {
+            ConsoleWriter::Semihosting(SemihostingWriter(self.semihosting.0.lock()))
+        };
+
+        AnsiEscapes::new(writer)
+    }
+}
+
+pub enum ConsoleWriter<'a> {
+    Semihosting(SemihostingWriter<'a>),
+    Uart(UartWriter<'a>),
+}
+
+impl Write for ConsoleWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self {
+            ConsoleWriter::Semihosting(writer) => writer.write_str(s),
+            ConsoleWriter::Uart(writer) => writer.write_str(s),
+        }
+    }
+}
+
+pub struct UartWriter<'a> {
+    console: &'a Console,
+    _guard: ReentrantMutexGuard<'a, ()>,
+}
+
+impl UartWriter<'_> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if let Some(uart) = self.console.uart.get() {
+            uart.write_lossy(bytes);
+        } else if let Some(mut early) = self.console.early.try_lock() {
+            let len = bytes.len().min(EARLY_BUFFER_SIZE - early.len());
+            early.extend_from_slice(&bytes[..len]);
+        }
+    }
+}
+
+impl Write for UartWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // terminals expect CRLF line endings
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.write_bytes(first.as_bytes());
+        }
+        for line in lines {
+            self.write_bytes(b"\r\n");
+            self.write_bytes(line.as_bytes());
+        }
+        Ok(())
+    }
+}
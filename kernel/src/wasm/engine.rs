@@ -5,15 +5,14 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use crate::arch;
+use crate::entropy::ReseedingRng;
 use crate::wasm::compile::Compiler;
 use crate::wasm::cranelift::CraneliftCompiler;
 use crate::wasm::type_registry::TypeRegistry;
 use alloc::sync::Arc;
 use core::sync::atomic::AtomicU64;
 use cranelift_codegen::settings::{Configurable, Flags};
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaCha20Rng;
+use rand::Rng;
 use spin::{Mutex, MutexGuard};
 
 /// Global context for the runtime.
@@ -28,8 +27,7 @@ pub struct Engine(Arc<EngineInner>);
 pub struct EngineInner {
     compiler: CraneliftCompiler,
     type_registry: TypeRegistry,
-    rng: Option<Mutex<ChaCha20Rng>>,
-    asid_alloc: Mutex<arch::AsidAllocator>,
+    rng: Option<Mutex<ReseedingRng>>,
     epoch_counter: AtomicU64,
 }
 
@@ -48,7 +46,6 @@ impl Default for Engine {
             compiler: CraneliftCompiler::new(target_isa),
             type_registry: TypeRegistry::default(),
             rng: None,
-            asid_alloc: Mutex::new(arch::AsidAllocator::new()),
             epoch_counter: AtomicU64::new(0),
         }))
     }
@@ -68,8 +65,7 @@ impl Engine {
         Self(Arc::new(EngineInner {
             compiler: CraneliftCompiler::new(target_isa),
             type_registry: TypeRegistry::default(),
-            rng: Some(Mutex::new(ChaCha20Rng::from_rng(rng))),
-            asid_alloc: Mutex::new(arch::AsidAllocator::new()),
+            rng: Some(Mutex::new(ReseedingRng::from_rng(rng))),
             epoch_counter: AtomicU64::new(0),
         }))
     }
@@ -87,11 +83,7 @@ impl Engine {
         &self.0.type_registry
     }
 
-    pub fn allocate_asid(&self) -> u16 {
-        let mut alloc = self.0.asid_alloc.lock();
-        alloc.alloc().expect("out of address space identifiers")
-    }
-    pub fn rng(&self) -> Option<MutexGuard<'_, ChaCha20Rng>> {
+    pub fn rng(&self) -> Option<MutexGuard<'_, ReseedingRng>> {
         Some(self.0.rng.as_ref()?.lock())
     }
     pub fn epoch_counter(&self) -> &AtomicU64 {
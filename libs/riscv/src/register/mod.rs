@@ -16,11 +16,16 @@
 pub mod satp;
 pub mod scause;
 pub mod scounteren;
+pub mod seed;
 pub mod sepc;
 pub mod sie;
 pub mod sip;
+pub mod sireg;
+pub mod siselect;
 pub mod sscratch;
 pub mod sstatus;
+pub mod stimecmp;
+pub mod stopei;
 pub mod stval;
 pub mod stvec;
 pub mod time;
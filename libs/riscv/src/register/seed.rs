@@ -0,0 +1,72 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Entropy Source Register (Zkr extension)
+
+/// Seed register
+#[derive(Clone, Copy)]
+pub struct Seed {
+    bits: usize,
+}
+
+/// The status of the entropy source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opst {
+    /// The entropy source is running its built-in self test.
+    Bist,
+    /// No entropy is available right now, the read should be retried later.
+    Wait,
+    /// The register contains 16 bits of entropy.
+    Es16,
+    /// The entropy source has failed unrecoverably.
+    Dead,
+}
+
+/// Polls the entropy source.
+///
+/// The seed CSR must be accessed with a read-write instruction, a plain CSR read raises an illegal
+/// instruction exception. In S-mode the access additionally has to be enabled by M-mode through
+/// `mseccfg.SSEED`.
+///
+/// **WARNING**: panics on non-`riscv` targets.
+#[inline]
+pub fn read() -> Seed {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))] {
+            let bits: usize;
+            unsafe {
+                ::core::arch::asm!("csrrw {0}, 0x015, x0", out(reg) bits);
+            }
+            Seed { bits }
+        } else {
+            unimplemented!()
+        }
+    }
+}
+
+impl Seed {
+    /// Returns the status of the entropy source.
+    #[inline]
+    pub fn opst(&self) -> Opst {
+        match (self.bits >> 30) & 0b11 {
+            0b00 => Opst::Bist,
+            0b01 => Opst::Wait,
+            0b10 => Opst::Es16,
+            _ => Opst::Dead,
+        }
+    }
+
+    /// Returns the entropy bits, only meaningful if [`Self::opst`] is [`Opst::Es16`].
+    #[inline]
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "We actually want to truncate"
+    )]
+    pub fn entropy(&self) -> u16 {
+        self.bits as u16
+    }
+}
@@ -0,0 +1,15 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! sireg register (Smcsrind/Sscsrind extensions)
+//!
+//! Aliases the register selected by [`siselect`](super::siselect).
+
+use crate::register::{read_csr_as_usize, write_csr_as_usize};
+
+read_csr_as_usize!(0x151);
+write_csr_as_usize!(0x151);
@@ -0,0 +1,16 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! siselect register (Smcsrind/Sscsrind extensions)
+//!
+//! Selects the register that is accessed through [`sireg`](super::sireg), used among other
+//! things to access the registers of the hart's IMSIC interrupt file.
+
+use crate::register::{read_csr_as_usize, write_csr_as_usize};
+
+read_csr_as_usize!(0x150);
+write_csr_as_usize!(0x150);
@@ -0,0 +1,43 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! stopei register (Ssaia extension)
+//!
+//! Reports the highest-priority pending and enabled interrupt of the hart's S-level IMSIC
+//! interrupt file.
+
+/// Supervisor top external interrupt register
+#[derive(Clone, Copy)]
+pub struct Stopei {
+    bits: usize,
+}
+
+/// Claims the highest-priority pending and enabled interrupt, clearing its pending bit.
+///
+/// **WARNING**: panics on non-`riscv` targets.
+#[inline]
+pub fn claim() -> Stopei {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))] {
+            let bits: usize;
+            unsafe {
+                ::core::arch::asm!("csrrw {0}, 0x15c, x0", out(reg) bits);
+            }
+            Stopei { bits }
+        } else {
+            unimplemented!()
+        }
+    }
+}
+
+impl Stopei {
+    /// Returns the identity of the claimed interrupt, `0` if no interrupt was pending.
+    #[inline]
+    pub fn identity(&self) -> u32 {
+        u32::try_from((self.bits >> 16) & 0x7ff).unwrap()
+    }
+}
@@ -18,12 +18,62 @@ pub fn hart_start(hartid: usize, start_address: usize, opaque: usize) -> super::
     Ok(())
 }
 
+/// Stop the calling hart, returning its ownership to the SBI implementation.
+///
+/// On success this call doesn't return.
+///
+/// # Errors
+///
+/// Returns an error if the SBI call fails.
+#[inline]
+pub fn hart_stop() -> super::Result<()> {
+    sbi_call!(ext: EID_HSM, func: 1)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+}
+
+/// Returns the current state of the hart `hartid`.
+///
+/// # Errors
+///
+/// Returns an error if the SBI call fails, e.g. because `hartid` is invalid.
+#[inline]
+pub fn hart_get_status(hartid: usize) -> super::Result<HartState> {
+    match sbi_call!(ext: EID_HSM, func: 2, "a0": hartid)? {
+        0 => Ok(HartState::Started),
+        1 => Ok(HartState::Stopped),
+        2 => Ok(HartState::StartPending),
+        3 => Ok(HartState::StopPending),
+        4 => Ok(HartState::Suspended),
+        5 => Ok(HartState::SuspendPending),
+        6 => Ok(HartState::ResumePending),
+        state => Err(super::Error::Other(isize::try_from(state)?)),
+    }
+}
+
 #[repr(u32)]
 pub enum SuspendType {
+    /// The hart keeps all of its state and [`hart_suspend`] returns once it is resumed.
     DefaultRetentive = 0,
+    /// The hart may lose all of its state. It is resumed at the given resume address in
+    /// supervisor mode with the MMU turned off, `a0` holding its hart id and `a1` the opaque value.
     DefaultNonRetentive = 0x80000000,
 }
 
+/// Suspend the calling hart until an interrupt is pending for it.
+///
+/// For non-retentive suspend types this call only returns on error, see [`SuspendType`].
+///
 /// # Errors
 ///
 /// Returns an error if the SBI call fails.
@@ -33,6 +83,6 @@ pub fn hart_suspend(
     resume_address: usize,
     opaque: usize,
 ) -> super::Result<()> {
-    sbi_call!(ext: EID_HSM, func: 0, "a0": suspend_type as u32 as usize, "a1": resume_address, "a2": opaque)?;
+    sbi_call!(ext: EID_HSM, func: 3, "a0": suspend_type as u32 as usize, "a1": resume_address, "a2": opaque)?;
     Ok(())
 }
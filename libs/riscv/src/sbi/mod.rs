@@ -20,6 +20,7 @@ mod error;
 pub mod hsm;
 pub mod ipi;
 pub mod rfence;
+pub mod srst;
 pub mod time;
 
 const EID_BASE: usize = 0x10;
@@ -0,0 +1,42 @@
+// Copyright 2025 Jonas Kruckenberg
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! System Reset Extension
+
+use super::{EID_SRST, sbi_call};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ResetType {
+    /// Power off the system.
+    Shutdown = 0,
+    /// Power cycle the system, resetting all hardware.
+    ColdReboot = 1,
+    /// Reboot the system, resetting only the harts and some of the hardware.
+    WarmReboot = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+/// Reset the system, taking all harts down with it.
+///
+/// On success this call doesn't return.
+///
+/// # Errors
+///
+/// Returns an error if the SBI call fails, e.g. because the reset type isn't supported by the
+/// platform.
+#[inline]
+pub fn system_reset(reset_type: ResetType, reset_reason: ResetReason) -> super::Result<()> {
+    sbi_call!(ext: EID_SRST, func: 0, "a0": reset_type as u32 as usize, "a1": reset_reason as u32 as usize)?;
+    Ok(())
+}
@@ -30,8 +30,23 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Interrupt enable flags
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InterruptFlags: u8 {
+        /// Received data is available
+        const RECEIVED = 1;
+        /// The transmitter holding register (and FIFO) is empty
+        const TRANSMIT_EMPTY = 1 << 1;
+    }
+}
+
+/// The depth of the transmit and receive FIFOs.
+pub const FIFO_SIZE: usize = 16;
+
 pub struct SerialPort {
     data: AtomicPtr<u8>,
+    int_en: AtomicPtr<u8>,
     line_sts: AtomicPtr<u8>,
 }
 
@@ -87,6 +102,7 @@ impl SerialPort {
 
             Self {
                 data: AtomicPtr::new(base_pointer),
+                int_en: AtomicPtr::new(base_pointer.add(1)),
                 line_sts: AtomicPtr::new(base_pointer.add(5)),
             }
         }
@@ -121,6 +137,54 @@ impl SerialPort {
         }
     }
 
+    /// Fill the transmit FIFO with up to [`FIFO_SIZE`] bytes from `buf` if it is empty.
+    ///
+    /// Returns the number of bytes written, `0` if the transmitter is still busy. Unlike
+    /// [`Self::send`] this doesn't translate backspace.
+    pub fn fill_fifo(&mut self, buf: &[u8]) -> usize {
+        if !self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY) {
+            return 0;
+        }
+
+        let self_data = self.data.load(Ordering::Relaxed);
+        let len = buf.len().min(FIFO_SIZE);
+        for &byte in &buf[..len] {
+            // Safety: the FIFO was empty, so it has room for `FIFO_SIZE` bytes
+            unsafe {
+                self_data.write_volatile(byte);
+            }
+        }
+        len
+    }
+
+    /// Read a single byte if one is available.
+    pub fn try_recv(&mut self) -> Option<u8> {
+        if !self.line_sts().contains(LineStsFlags::INPUT_FULL) {
+            return None;
+        }
+
+        // Safety: there is received data available
+        unsafe { Some(self.data.load(Ordering::Relaxed).read_volatile()) }
+    }
+
+    /// Returns the enabled interrupts.
+    pub fn interrupts(&mut self) -> InterruptFlags {
+        // Safety: it is always safe to read the interrupt enable register
+        unsafe {
+            InterruptFlags::from_bits_truncate(self.int_en.load(Ordering::Relaxed).read_volatile())
+        }
+    }
+
+    /// Set the enabled interrupts.
+    pub fn set_interrupts(&mut self, flags: InterruptFlags) {
+        // Safety: DLAB is never left enabled, so this always addresses the interrupt enable register
+        unsafe {
+            self.int_en
+                .load(Ordering::Relaxed)
+                .write_volatile(flags.bits());
+        }
+    }
+
     pub fn recv(&mut self) -> u8 {
         let self_data = self.data.load(Ordering::Relaxed);
         let mut boff = Backoff::new();
@@ -38,6 +38,9 @@ pub struct BootInfo {
     ///
     /// This field can be used by the kernel to perform introspection of its own ELF file.
     pub kernel_phys: Range<usize>, // PhysicalAddress
+    /// Physical memory region occupied by the loader image, this range is identity-mapped when the
+    /// kernel is entered.
+    pub loader_phys: Range<usize>, // PhysicalAddress
 
     pub rng_seed: [u8; 32],
 }
@@ -57,6 +60,7 @@ impl BootInfo {
             tls_template: None,
             kernel_virt: Default::default(),
             kernel_phys: Default::default(),
+            loader_phys: Default::default(),
             rng_seed: [0; 32],
         }
     }
@@ -129,6 +133,17 @@ pub enum MemoryRegionKind {
     Loader,
     /// The memory region containing the flattened device tree (FDT).
     FDT,
+    /// Memory reserved by the firmware, either through the FDT memory reservation block or
+    /// `/reserved-memory` nodes.
+    ///
+    /// This memory must _never_ be used by the kernel.
+    Reserved,
+    /// Memory occupied by the loader image itself (excluding the embedded kernel ELF).
+    ///
+    /// This memory is still identity-mapped when the kernel is entered and CPUs might still be
+    /// executing loader code, so it must not be used until all CPUs have entered the kernel and the
+    /// identity mapping has been removed. After that it can be used like [`Usable`][MemoryRegionKind::Usable] memory.
+    LoaderReclaimable,
 }
 
 impl MemoryRegionKind {
@@ -159,6 +174,11 @@ impl fmt::Display for BootInfo {
             "{:<23} : {:#x}..{:#x}",
             "KERNEL PHYS", self.kernel_phys.start, self.kernel_phys.end
         )?;
+        writeln!(
+            f,
+            "{:<23} : {:#x}..{:#x}",
+            "LOADER PHYS", self.loader_phys.start, self.loader_phys.end
+        )?;
         if let Some(tls) = self.tls_template.as_ref() {
             writeln!(
                 f,
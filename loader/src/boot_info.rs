@@ -23,6 +23,7 @@ pub fn prepare_boot_info(
     loader_phys: Range<usize>,
     kernel_phys: Range<usize>,
     fdt_phys: Range<usize>,
+    reserved: &[Range<usize>],
     hart_mask: usize,
     rng_seed: [u8; 32],
 ) -> crate::Result<*mut BootInfo> {
@@ -32,8 +33,14 @@ pub fn prepare_boot_info(
     )?;
     let page = physical_address_offset.checked_add(frame).unwrap();
 
-    let memory_regions =
-        init_boot_info_memory_regions(page, frame_alloc, fdt_phys, loader_phys, kernel_phys);
+    let memory_regions = init_boot_info_memory_regions(
+        page,
+        frame_alloc,
+        fdt_phys,
+        reserved,
+        loader_phys,
+        kernel_phys,
+    );
 
     let mut boot_info = BootInfo::new(memory_regions);
     boot_info.physical_address_offset = physical_address_offset;
@@ -41,6 +48,7 @@ pub fn prepare_boot_info(
     boot_info.tls_template = maybe_tls_template;
     boot_info.kernel_virt = kernel_virt;
     boot_info.kernel_phys = kernel_phys;
+    boot_info.loader_phys = loader_phys;
     boot_info.cpu_mask = hart_mask;
     boot_info.rng_seed = rng_seed;
 
@@ -55,6 +63,7 @@ fn init_boot_info_memory_regions(
     page: usize,
     frame_alloc: FrameAllocator,
     fdt_phys: Range<usize>,
+    reserved: &[Range<usize>],
     loader_phys: Range<usize>,
     kernel_phys: Range<usize>,
 ) -> MemoryRegions {
@@ -92,16 +101,33 @@ fn init_boot_info_memory_regions(
     // Most of the memory occupied by the loader is not needed once the kernel is running,
     // but the kernel itself lies somewhere in the loader memory.
     //
-    // We can still mark the range before and after the kernel as usable.
+    // The range before and after the kernel can be reclaimed by the kernel once all CPUs are done
+    // executing loader code.
     push_region(MemoryRegion {
         range: Range::from(loader_phys.start..kernel_phys.start),
-        kind: MemoryRegionKind::Usable,
+        kind: MemoryRegionKind::LoaderReclaimable,
     });
     push_region(MemoryRegion {
         range: Range::from(kernel_phys.end..loader_phys.end),
-        kind: MemoryRegionKind::Usable,
+        kind: MemoryRegionKind::LoaderReclaimable,
     });
 
+    // Report memory reserved by the firmware so the kernel knows to stay away from it. Firmware
+    // commonly reserves the FDT too, which we report separately below.
+    for region in reserved {
+        let before = Range::from(region.start..region.end.min(fdt_phys.start));
+        let after = Range::from(region.start.max(fdt_phys.end)..region.end);
+
+        for range in [before, after] {
+            if !range.is_empty() {
+                push_region(MemoryRegion {
+                    range,
+                    kind: MemoryRegionKind::Reserved,
+                });
+            }
+        }
+    }
+
     // Report the flattened device tree as a separate region.
     push_region(MemoryRegion {
         range: fdt_phys,
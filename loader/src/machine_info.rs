@@ -25,6 +25,9 @@ pub struct MachineInfo<'dt> {
     pub fdt: &'dt [u8],
     /// Address ranges we may use for allocation
     pub memories: ArrayVec<Range<usize>, 16>,
+    /// Address ranges reserved by the firmware through the memory reservation block or
+    /// `/reserved-memory` nodes. Sorted, non-overlapping, and page-aligned.
+    pub reserved: ArrayVec<Range<usize>, 16>,
     /// The RNG seed passed to us by the previous stage loader.
     pub rng_seed: Option<&'dt [u8]>,
     /// A bitfield where each bit corresponds to a CPU in the system.
@@ -141,13 +144,34 @@ impl MachineInfo<'_> {
             log::trace!("applying reservation {region:#x?}");
 
             exclude_region(region);
+            reserved_memory.push(region);
         }
 
         // Apply memory reservations
-        for reservation in reserved_memory {
+        for reservation in &reserved_memory {
             log::trace!("applying reservation {reservation:#x?}");
 
-            exclude_region(reservation);
+            exclude_region(*reservation);
+        }
+
+        // page-align the reservations outwards (the usable memory regions are aligned inwards below
+        // so they can't overlap) and merge overlapping ones
+        let mut reserved: ArrayVec<Range<usize>, 16> = ArrayVec::new();
+        reserved_memory.retain(|region| region.end > region.start);
+        reserved_memory.sort_unstable_by_key(|region| region.start);
+        for region in reserved_memory {
+            let region = Range::from(
+                align_down(region.start, PAGE_SIZE)
+                    ..checked_align_up(region.end, PAGE_SIZE).unwrap(),
+            );
+
+            if let Some(last) = reserved.last_mut()
+                && region.start <= last.end
+            {
+                last.end = last.end.max(region.end);
+            } else {
+                reserved.push(region);
+            }
         }
 
         // remove memory regions that are left as zero-sized from the previous step
@@ -176,6 +200,7 @@ impl MachineInfo<'_> {
         Ok(MachineInfo {
             fdt: fdt_slice,
             memories,
+            reserved,
             rng_seed,
             hart_mask,
         })
@@ -216,6 +241,13 @@ impl fmt::Display for MachineInfo<'_> {
         for (idx, r) in self.memories.iter().enumerate() {
             writeln!(f, "MEMORY REGION {:<4}: {:#x}..{:#x}", idx, r.start, r.end)?;
         }
+        for (idx, r) in self.reserved.iter().enumerate() {
+            writeln!(
+                f,
+                "RESERVED REGION {:<2}: {:#x}..{:#x}",
+                idx, r.start, r.end
+            )?;
+        }
 
         Ok(())
     }
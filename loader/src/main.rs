@@ -186,6 +186,7 @@ fn do_global_init(hartid: usize, opaque: *const c_void) -> GlobalInitResult {
         Range::from(self_regions.executable.start..self_regions.read_write.end),
         kernel.phys_range(),
         fdt_phys,
+        &minfo.reserved,
         minfo.hart_mask,
         rng_seed,
     )